@@ -677,6 +677,8 @@ mod tests {
             expires_at: None,
             reference: None,
             reference_hash: None,
+            score: None,
+            attestation: None,
         }
     }
 
@@ -736,7 +738,7 @@ mod tests {
             ],
             None,
         ) {
-            Err(Error::RequiredDeposit(36000000000000000000000)) => (),
+            Err(Error::RequiredDeposit(80000000000000000000000)) => (),
             Ok(_) => panic!("expected RequiredDeposit, got: Ok"),
             Err(x) => panic!("expected RequiredDeposit, got: {:?}", x),
         };
@@ -765,7 +767,7 @@ mod tests {
             Err(x) => panic!("expected NotAuthorized, got: {:?}", x),
         };
 
-        ctx.attached_deposit = 19000000000000000000000;
+        ctx.attached_deposit = 41000000000000000000000;
         testing_env!(ctx.clone());
         ctr.sbt_mint_many(
             vec![(alice(), vec![mk_meteadata(1), mk_meteadata(cls2)])],
@@ -773,7 +775,7 @@ mod tests {
         )?;
 
         // deposit increases because we are minting more tokens
-        ctx.attached_deposit = 37000000000000000000000;
+        ctx.attached_deposit = 81000000000000000000000;
         testing_env!(ctx);
         ctr.sbt_mint_many(
             vec![
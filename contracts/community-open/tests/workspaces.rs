@@ -153,6 +153,8 @@ async fn init(
         expires_at: None,
         reference: None,
         reference_hash: None,
+        score: None,
+        attestation: None,
     };
 
     let res = minter
@@ -444,6 +446,8 @@ async fn sbt_mint_many() -> anyhow::Result<()> {
             expires_at: None,
             reference: None,
             reference_hash: None,
+            score: None,
+            attestation: None,
         },
         TokenMetadata {
             class: 2,
@@ -451,6 +455,8 @@ async fn sbt_mint_many() -> anyhow::Result<()> {
             expires_at: None,
             reference: None,
             reference_hash: None,
+            score: None,
+            attestation: None,
         },
     ];
 
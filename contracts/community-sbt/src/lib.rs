@@ -661,6 +661,8 @@ mod tests {
             expires_at: None,
             reference: None,
             reference_hash: None,
+            score: None,
+            attestation: None,
         }
     }
 
@@ -720,7 +722,7 @@ mod tests {
             ],
             None,
         ) {
-            Err(MintError::RequiredDeposit(36000000000000000000000)) => (),
+            Err(MintError::RequiredDeposit(80000000000000000000000)) => (),
             Ok(_) => panic!("expected RequiredDeposit, got: Ok"),
             Err(x) => panic!("expected RequiredDeposit, got: {:?}", x),
         };
@@ -749,7 +751,7 @@ mod tests {
             Err(x) => panic!("expected NotAuthorized, got: {:?}", x),
         };
 
-        ctx.attached_deposit = 19000000000000000000000;
+        ctx.attached_deposit = 41000000000000000000000;
         testing_env!(ctx.clone());
         ctr.sbt_mint_many(
             vec![(alice(), vec![mk_meteadata(1), mk_meteadata(cls2)])],
@@ -757,7 +759,7 @@ mod tests {
         )?;
 
         // deposit increases because we are minting more tokens
-        ctx.attached_deposit = 37000000000000000000000;
+        ctx.attached_deposit = 81000000000000000000000;
         testing_env!(ctx);
         ctr.sbt_mint_many(
             vec![
@@ -3,7 +3,7 @@ use near_sdk::{Balance, Gas};
 pub const MICRO_NEAR: Balance = 1_000_000_000_000_000_000;
 pub const MILI_NEAR: Balance = 1000 * MICRO_NEAR;
 
-pub const MINT_COST: Balance = 9 * MILI_NEAR; // 0.009 NEAR. Storage cost is 0.843N from running contract
+pub const MINT_COST: Balance = 20 * MILI_NEAR; // 0.02 NEAR. Covers the registry storage of a max size token, see `TOKEN_UPDATES_STORAGE`
 pub const BAN_COST: Balance = 5 * MILI_NEAR;
 
 pub const MINT_GAS: Gas = Gas(7 * Gas::ONE_TERA.0);
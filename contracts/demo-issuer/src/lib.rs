@@ -74,6 +74,8 @@ impl Contract {
             expires_at: Some(now_ms + self.sbt_ttl_ms),
             reference: None,
             reference_hash: None,
+            score: None,
+            attestation: None,
         };
 
         if let Some(memo) = memo {
@@ -1,7 +1,10 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, near_bindgen, require, AccountId, Balance, PanicOnDefault};
+use near_sdk::{
+    env, near_bindgen, require, AccountId, Balance, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    PromiseResult,
+};
 
 use sbt::*;
 
@@ -9,6 +12,10 @@ pub const MILI_NEAR: Balance = 1_000_000_000_000_000_000_000;
 pub const REG_HUMAN_DEPOSIT: Balance = 3 * MILI_NEAR;
 /// maximum time for proposal voting in milliseconds.
 pub const VOTING_DURATION: u64 = 20_000;
+/// gas reserved for each registry `sbts` query done by the SBT freshness check.
+pub const SBTS_QUERY_GAS: Gas = Gas(5 * Gas::ONE_TERA.0);
+/// gas reserved for the `on_sbts_fresh` callback.
+pub const ON_SBTS_FRESH_GAS: Gas = Gas(5 * Gas::ONE_TERA.0);
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
@@ -32,13 +39,17 @@ impl Contract {
         }
     }
 
+    /// Records the `iah_proof` for the `caller`. Returns false if the caller already has a
+    /// recorded proof.
+    /// If `payload.max_age_ms` is set, the proof tokens are queried from the registry and
+    /// the call fails if any of the tokens was issued more than `max_age_ms` milliseconds ago.
     #[payable]
     pub fn register_human_token(
         &mut self,
         caller: AccountId,
         iah_proof: SBTs,
         payload: RegisterHumanPayload,
-    ) -> bool {
+    ) -> PromiseOrValue<bool> {
         env::log_str(&format!(
             "register token for {}, memo={}",
             caller, payload.memo
@@ -55,6 +66,60 @@ impl Contract {
                 "bad response, expected non empty token list"
             );
         }
+        if self.used_tokens.contains_key(&caller) {
+            return PromiseOrValue::Value(false);
+        }
+        let max_age_ms = match payload.max_age_ms {
+            None => {
+                self.used_tokens.insert(&caller, &iah_proof);
+                return PromiseOrValue::Value(true);
+            }
+            Some(max_age_ms) => max_age_ms,
+        };
+
+        let mut query: Option<Promise> = None;
+        for (issuer, tokens) in &iah_proof {
+            let p = ext_registry::ext(self.registry.clone())
+                .with_static_gas(SBTS_QUERY_GAS)
+                .sbts(issuer.clone(), tokens.clone(), Some(true));
+            query = Some(match query {
+                None => p,
+                Some(q) => q.and(p),
+            });
+        }
+        PromiseOrValue::Promise(
+            query.unwrap().then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(ON_SBTS_FRESH_GAS)
+                    .on_sbts_fresh(caller, iah_proof, max_age_ms),
+            ),
+        )
+    }
+
+    /// Callback for the `register_human_token` freshness check. Expects one `sbts` query
+    /// result per `iah_proof` issuer. Panics if any token is not valid or was issued more
+    /// than `max_age_ms` milliseconds ago.
+    #[private]
+    pub fn on_sbts_fresh(&mut self, caller: AccountId, iah_proof: SBTs, max_age_ms: u64) -> bool {
+        require!(
+            env::promise_results_count() as usize == iah_proof.len(),
+            "unexpected number of registry responses"
+        );
+        let min_issued_at = env::block_timestamp_ms().saturating_sub(max_age_ms);
+        for i in 0..iah_proof.len() {
+            let tokens: Vec<Option<Token>> = match env::promise_result(i as u64) {
+                PromiseResult::Successful(value) => near_sdk::serde_json::from_slice(&value)
+                    .unwrap_or_else(|_| env::panic_str("bad registry response")),
+                _ => env::panic_str("registry sbts query failed"),
+            };
+            for t in tokens {
+                let fresh = t
+                    .and_then(|t| t.metadata.issued_at)
+                    .map_or(false, |issued_at| issued_at >= min_issued_at);
+                require!(fresh, "stale SBT proof");
+            }
+        }
+        // the proof could be recorded by another call while we were waiting for the response
         if self.used_tokens.contains_key(&caller) {
             return false;
         }
@@ -113,6 +178,9 @@ impl Contract {
 pub struct RegisterHumanPayload {
     pub memo: String,
     pub numbers: Vec<u32>,
+    /// If set, all proof tokens must be issued at most `max_age_ms` milliseconds ago.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_ms: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -130,7 +198,8 @@ pub(crate) fn expected_vec_payload() -> Vec<u32> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use near_sdk::{test_utils::VMContextBuilder, testing_env, VMContext};
+    use near_sdk::test_utils::{get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig, VMContext};
 
     fn alice() -> AccountId {
         AccountId::new_unchecked("alice.near".to_string())
@@ -157,6 +226,48 @@ mod tests {
         (ctx, ctr)
     }
 
+    fn value(r: PromiseOrValue<bool>) -> bool {
+        match r {
+            PromiseOrValue::Value(v) => v,
+            PromiseOrValue::Promise(_) => panic!("expected value, got promise"),
+        }
+    }
+
+    fn mk_token(token: TokenId, issued_at: Option<u64>) -> Token {
+        Token {
+            token,
+            issuer_id: 1,
+            owner: alice(),
+            metadata: TokenMetadata {
+                class: 1,
+                issued_at,
+                expires_at: None,
+                reference: None,
+                reference_hash: None,
+                score: None,
+                attestation: None,
+            },
+            metadata_version: 1,
+        }
+    }
+
+    /// sets the context to the `on_sbts_fresh` callback with the given `sbts` responses.
+    fn callback_env(ctx: &mut VMContext, now_ms: u64, responses: Vec<Vec<Option<Token>>>) {
+        ctx.predecessor_account_id = ctx.current_account_id.clone();
+        ctx.block_timestamp = now_ms * 1_000_000;
+        let results = responses
+            .iter()
+            .map(|r| PromiseResult::Successful(near_sdk::serde_json::to_vec(r).unwrap()))
+            .collect();
+        testing_env!(
+            ctx.clone(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results
+        );
+    }
+
     #[test]
     fn register_human_token() {
         let (_, mut ctr) = setup(registry(), REG_HUMAN_DEPOSIT);
@@ -165,12 +276,17 @@ mod tests {
         let payload = RegisterHumanPayload {
             memo: "checking alice".to_owned(),
             numbers: expected_vec_payload(),
+            max_age_ms: None,
         };
-        assert!(ctr.register_human_token(alice(), tokens.clone(), payload.clone()));
+        assert!(value(ctr.register_human_token(
+            alice(),
+            tokens.clone(),
+            payload.clone()
+        )));
         assert_eq!(ctr.used_tokens.get(&alice()).unwrap(), tokens);
 
         assert!(
-            !ctr.register_human_token(alice(), vec![(issuer1(), vec![2])], payload),
+            !value(ctr.register_human_token(alice(), vec![(issuer1(), vec![2])], payload)),
             "second call for the same user should return false"
         );
         assert_eq!(
@@ -192,7 +308,88 @@ mod tests {
             RegisterHumanPayload {
                 memo: "registering alice".to_owned(),
                 numbers: expected_vec_payload(),
+                max_age_ms: None,
             },
         );
     }
+
+    #[test]
+    fn register_human_token_max_age() {
+        let (mut ctx, mut ctr) = setup(registry(), REG_HUMAN_DEPOSIT);
+
+        let tokens = vec![(issuer1(), vec![1, 4])];
+        let payload = RegisterHumanPayload {
+            memo: "checking alice".to_owned(),
+            numbers: expected_vec_payload(),
+            max_age_ms: Some(1000),
+        };
+        match ctr.register_human_token(alice(), tokens.clone(), payload) {
+            PromiseOrValue::Promise(_) => (),
+            PromiseOrValue::Value(_) => panic!("expected the registry query promise"),
+        };
+        // the proof is recorded only in the callback
+        assert!(ctr.used_tokens.get(&alice()).is_none());
+        // one sbts query and the callback
+        assert_eq!(get_created_receipts().len(), 2);
+
+        callback_env(
+            &mut ctx,
+            5000,
+            vec![vec![
+                Some(mk_token(1, Some(4000))),
+                Some(mk_token(4, Some(4500))),
+            ]],
+        );
+        assert!(ctr.on_sbts_fresh(alice(), tokens.clone(), 1000));
+        assert_eq!(ctr.used_tokens.get(&alice()).unwrap(), tokens);
+    }
+
+    #[test]
+    #[should_panic(expected = "stale SBT proof")]
+    fn on_sbts_fresh_stale() {
+        let (mut ctx, mut ctr) = setup(registry(), REG_HUMAN_DEPOSIT);
+        callback_env(
+            &mut ctx,
+            5000,
+            vec![vec![
+                Some(mk_token(1, Some(3999))),
+                Some(mk_token(4, Some(4500))),
+            ]],
+        );
+        ctr.on_sbts_fresh(alice(), vec![(issuer1(), vec![1, 4])], 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "stale SBT proof")]
+    fn on_sbts_fresh_invalid_token() {
+        let (mut ctx, mut ctr) = setup(registry(), REG_HUMAN_DEPOSIT);
+        // expired or missing tokens are returned as None by the registry
+        callback_env(&mut ctx, 5000, vec![vec![None]]);
+        ctr.on_sbts_fresh(alice(), vec![(issuer1(), vec![1])], 1000);
+    }
+
+    #[test]
+    fn register_human_token_registry_proof() {
+        let (mut ctx, mut ctr) = setup(issuer1(), 20 * MILI_NEAR);
+        let mut reg = ::registry::Contract::new(registry(), issuer1(), vec![1], vec![]);
+        reg.sbt_mint(vec![(alice(), vec![mk_token(1, None).metadata])]);
+
+        // the registry proof is passed to the dapp without re-shaping
+        let proof: SBTs = reg.is_human_detailed(alice()).unwrap();
+        assert_eq!(proof, reg.is_human(alice(), None));
+        ctx.predecessor_account_id = registry();
+        ctx.attached_deposit = REG_HUMAN_DEPOSIT;
+        testing_env!(ctx);
+        let payload = RegisterHumanPayload {
+            memo: "registry proof".to_owned(),
+            numbers: expected_vec_payload(),
+            max_age_ms: None,
+        };
+        assert!(value(ctr.register_human_token(
+            alice(),
+            proof.clone(),
+            payload
+        )));
+        assert_eq!(ctr.used_tokens.get(&alice()).unwrap(), proof);
+    }
 }
@@ -5,6 +5,8 @@ use sbt::{SBTs, TokenMetadata};
 use serde_json::json;
 
 use human_checker::{RegisterHumanPayload, VotePayload, VOTING_DURATION};
+use near_sdk::json_types::U128;
+use registry::storage::IsHumanCallResult;
 
 const REGISTER_HUMAN_TOKEN: &str = "register_human_token";
 const MSECOND : u64 = 1000;
@@ -30,6 +32,23 @@ impl Suite {
         Ok(res)
     }
 
+    pub async fn is_human_call_checked(
+        &self,
+        caller: &Account,
+        function: &str,
+        payload: &RegisterHumanPayload,
+    ) -> anyhow::Result<ExecutionFinalResult> {
+        let res = caller
+        .call(self.registry.id(), "is_human_call_checked")
+        .args_json(json!({"ctr": self.human_checker.id(), "function": function, "payload": serde_json::to_string(payload).unwrap()}))
+        .deposit(parse_near!("0.01 N"))
+        .max_gas()
+        .transact()
+        .await?;
+        println!(">>> is_human_call_checked logs {:?}\n", res.logs());
+        Ok(res)
+    }
+
     pub async fn is_human_call_lock(
         &self,
         caller: &Account,
@@ -115,6 +134,8 @@ async fn init(
                 expires_at: None,
                 reference: None,
                 reference_hash: None,
+                score: None,
+                attestation: None,
             }],
         ),
         (
@@ -125,6 +146,8 @@ async fn init(
                 expires_at: None,
                 reference: None,
                 reference_hash: None,
+                score: None,
+                attestation: None,
             }],
         ),
     ];
@@ -157,6 +180,7 @@ async fn is_human_call() -> anyhow::Result<()> {
     let payload = RegisterHumanPayload {
         memo: "registering alice".to_owned(),
         numbers: vec![2, 3, 5, 7, 11],
+        max_age_ms: None,
     };
 
     let suite = Suite {
@@ -232,3 +256,113 @@ async fn is_human_call() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn is_human_call_checked() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let (registry, human_checker, alice, _, john, issuer) = init(&worker).await?;
+    let issuer_id = near_sdk::AccountId::try_from(issuer.id().as_str().to_owned())?;
+
+    let suite = Suite {
+        registry,
+        human_checker,
+    };
+    let payload = RegisterHumanPayload {
+        memo: "registering alice".to_owned(),
+        numbers: vec![2, 3, 5, 7, 11],
+        max_age_ms: None,
+    };
+
+    // successful recipient call
+    let r = suite.is_human_call_checked(&alice, REGISTER_HUMAN_TOKEN, &payload).await?;
+    assert!(r.is_success());
+    let result: IsHumanCallResult = r.json()?;
+    assert_eq!(
+        result,
+        IsHumanCallResult {
+            is_human: true,
+            callback_succeeded: true,
+            refunded: U128(0),
+        }
+    );
+    assert_eq!(
+        suite.query_sbts(&alice).await?,
+        Some(vec![(issuer_id, vec![1])])
+    );
+
+    // failed recipient call (wrong payload): the transaction succeeds and the deposit is
+    // refunded
+    let bad_payload = RegisterHumanPayload {
+        memo: "wrong payload".to_owned(),
+        numbers: vec![1],
+        max_age_ms: None,
+    };
+    let r = suite.is_human_call_checked(&alice, REGISTER_HUMAN_TOKEN, &bad_payload).await?;
+    assert!(r.is_success());
+    let result: IsHumanCallResult = r.json()?;
+    assert!(result.is_human);
+    assert!(!result.callback_succeeded);
+    assert_eq!(result.refunded, U128(parse_near!("0.01 N")));
+
+    // not a human: fails in the registry
+    let r = suite.is_human_call_checked(&john, REGISTER_HUMAN_TOKEN, &payload).await?;
+    assert!(r.is_failure());
+    let failure_str = format!("{:?}", r.failures());
+    assert!(failure_str.contains("caller is not a human"), "{}", failure_str);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn is_human_call_max_age() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let (registry, human_checker, alice, _, john, issuer) = init(&worker).await?;
+    let issuer_id = near_sdk::AccountId::try_from(issuer.id().as_str().to_owned())?;
+
+    // mint a fresh IAH token to john (`issued_at` is set by the registry to "now")
+    let res = issuer
+        .call(registry.id(), "sbt_mint")
+        .args_json(json!({ "token_spec": [(john.id(), vec![TokenMetadata {
+            class: 1,
+            issued_at: None,
+            expires_at: None,
+            reference: None,
+            reference_hash: None,
+            score: None,
+            attestation: None,
+        }])] }))
+        .deposit(parse_near!("0.1 N"))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_success());
+
+    let suite = Suite {
+        registry,
+        human_checker,
+    };
+    let payload = RegisterHumanPayload {
+        memo: "registering with fresh proof".to_owned(),
+        numbers: vec![2, 3, 5, 7, 11],
+        max_age_ms: Some(3_600 * MSECOND),
+    };
+
+    // alice token was issued at 0: stale
+    let r = suite.is_human_call(&alice, &payload).await?;
+    assert!(r.is_failure());
+    let failure_str = format!("{:?}", r.failures());
+    assert!(failure_str.contains("stale SBT proof"), "{}", failure_str);
+    assert_eq!(suite.query_sbts(&alice).await?, None);
+
+    // john token was just minted: fresh
+    let r = suite.is_human_call(&john, &payload).await?;
+    assert!(r.is_success());
+    let result: bool = r.json()?;
+    assert!(result, "should register tokens to john");
+    assert_eq!(
+        suite.query_sbts(&john).await?,
+        Some(vec![(issuer_id, vec![3])])
+    );
+
+    Ok(())
+}
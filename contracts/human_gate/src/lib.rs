@@ -0,0 +1,158 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupSet;
+use near_sdk::{env, near_bindgen, AccountId, Gas, PanicOnDefault, Promise};
+
+use sbt::*;
+
+/// gas reserved for the `on_greet` callback.
+pub const ON_GREET_GAS: Gas = Gas(5 * Gas::ONE_TERA.0);
+
+/// Example contract using the `HumanGate` helper: only humans can be greeted.
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    pub gate: HumanGate,
+    pub greeted: LookupSet<AccountId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new(registry: AccountId) -> Self {
+        Self {
+            gate: HumanGate::new(registry),
+            greeted: LookupSet::new(b"g"),
+        }
+    }
+
+    /// Greets the caller. Must be called through `registry.is_human_call`:
+    /// `registry.is_human_call({"ctr": "<this contract>", "function": "greet_human", "payload": "{}"})`.
+    pub fn greet_human(&mut self, caller: AccountId, iah_proof: SBTs) -> bool {
+        self.gate.require_human_call(&iah_proof);
+        self.greeted.insert(&caller)
+    }
+
+    /// Greets the predecessor using the `require_human` callback pattern: queries the
+    /// registry and greets the predecessor in the `on_greet` callback, if it's a human.
+    pub fn greet(&mut self) -> Promise {
+        let caller = env::predecessor_account_id();
+        self.gate.is_human(caller.clone()).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(ON_GREET_GAS)
+                .on_greet(caller),
+        )
+    }
+
+    /// Callback of `greet`. Returns true if the caller was greeted for the first time.
+    #[private]
+    pub fn on_greet(&mut self, caller: AccountId) -> bool {
+        if !is_human_result() {
+            env::log_str(&format!("{} is not a human", caller));
+            return false;
+        }
+        self.greeted.insert(&caller)
+    }
+
+    /**********
+     * QUERIES
+     **********/
+
+    pub fn greeted(&self, account: AccountId) -> bool {
+        self.greeted.contains(&account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::serde_json;
+    use near_sdk::test_utils::{self, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+
+    fn registry() -> AccountId {
+        AccountId::new_unchecked("registry.near".to_string())
+    }
+
+    fn alice() -> AccountId {
+        AccountId::new_unchecked("alice.near".to_string())
+    }
+
+    fn gate() -> AccountId {
+        AccountId::new_unchecked("gate.near".to_string())
+    }
+
+    fn setup(predecessor: &AccountId, results: Vec<PromiseResult>) -> Contract {
+        let ctx = VMContextBuilder::new()
+            .predecessor_account_id(predecessor.clone())
+            .current_account_id(gate())
+            .build();
+        testing_env!(
+            ctx,
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results
+        );
+        Contract::new(registry())
+    }
+
+    fn proof() -> SBTs {
+        vec![(registry(), vec![1])]
+    }
+
+    #[test]
+    fn greet_human() {
+        let mut ctr = setup(&registry(), vec![]);
+        assert!(ctr.greet_human(alice(), proof()));
+        assert!(!ctr.greet_human(alice(), proof()));
+        assert!(ctr.greeted(alice()));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called by registry")]
+    fn greet_human_not_registry() {
+        let mut ctr = setup(&alice(), vec![]);
+        ctr.greet_human(alice(), proof());
+    }
+
+    #[test]
+    #[should_panic(expected = "not a human")]
+    fn greet_human_no_proof() {
+        let mut ctr = setup(&registry(), vec![]);
+        ctr.greet_human(alice(), vec![]);
+    }
+
+    #[test]
+    fn greet() {
+        let mut ctr = setup(&alice(), vec![]);
+        let _ = ctr.greet();
+        assert_eq!(test_utils::get_created_receipts().len(), 2);
+
+        let res = serde_json::to_vec(&proof()).unwrap();
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(gate())
+                .current_account_id(gate())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(res)]
+        );
+        assert!(ctr.on_greet(alice()));
+        assert!(ctr.greeted(alice()));
+    }
+
+    #[test]
+    fn on_greet_not_human() {
+        let empty = serde_json::to_vec(&SBTs::new()).unwrap();
+        let mut ctr = setup(&gate(), vec![PromiseResult::Successful(empty)]);
+        assert!(!ctr.on_greet(alice()));
+        assert!(!ctr.greeted(alice()));
+
+        setup(&gate(), vec![PromiseResult::Failed]);
+        assert!(!ctr.on_greet(alice()));
+        assert!(!ctr.greeted(alice()));
+    }
+}
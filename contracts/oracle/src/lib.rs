@@ -200,6 +200,8 @@ impl Contract {
             expires_at: Some(now_ms + self.sbt_ttl_ms),
             reference: None,
             reference_hash: None,
+            score: None,
+            attestation: None,
         });
         //KYC token to be minted. Class is set to `2` to differentiate the token
         if claim.verified_kyc {
@@ -209,6 +211,8 @@ impl Contract {
                 expires_at: Some(now_ms + self.sbt_ttl_ms),
                 reference: None,
                 reference_hash: None,
+                score: None,
+                attestation: None,
             });
         }
 
@@ -285,6 +289,8 @@ impl Contract {
                 expires_at: Some(now + self.sbt_ttl_ms),
                 reference: None,
                 reference_hash: None,
+                score: None,
+                attestation: None,
             };
             ext_registry::ext(self.registry.clone())
                 .with_attached_deposit(MINT_COST)
@@ -378,6 +384,8 @@ impl Contract {
                     expires_at: Some(end),
                     reference: None,
                     reference_hash: None,
+                    score: None,
+                    attestation: None,
                 }],
             ));
         }
@@ -541,7 +549,7 @@ pub mod tests {
 
     #[test]
     #[should_panic(
-        expected = "Requires attached deposit at least 9000000000000000000000 yoctoNEAR"
+        expected = "Requires attached deposit at least 20000000000000000000000 yoctoNEAR"
     )]
     fn mint_not_enough_storage_deposit() {
         let signer = acc_claimer();
@@ -556,7 +564,7 @@ pub mod tests {
 
     #[test]
     #[should_panic(
-        expected = "Requires attached deposit at least 18000000000000000000000 yoctoNEAR"
+        expected = "Requires attached deposit at least 40000000000000000000000 yoctoNEAR"
     )]
     fn mint_with_kyc_not_enough_storage_deposit() {
         let signer = acc_claimer();
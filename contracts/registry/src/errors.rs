@@ -1,15 +1,35 @@
 use near_sdk::env::panic_str;
 use near_sdk::FunctionError;
 
+/// Stable error code prefixing the panic message when a transaction reserved for issuers
+/// is called by an account which is not a registered issuer.
+pub const ERR_NOT_ISSUER: &str = "ERR_NOT_ISSUER";
+
 #[cfg_attr(not(target_arch = "wasm32"), derive(PartialEq, Debug))]
 pub enum IsHumanCallErr {
     NotHuman,
+    /// not enough gas attached, the value is the minimum required prepaid gas.
+    NotEnoughGas(u64),
+    /// `valid_until_ms` is in the past.
+    Expired,
+    /// the function name is empty or longer than `MAX_FUNCTION_NAME_LEN`.
+    InvalidFunctionName,
 }
 
 impl FunctionError for IsHumanCallErr {
     fn panic(&self) -> ! {
         match self {
             IsHumanCallErr::NotHuman => panic_str("caller is not a human"),
+            IsHumanCallErr::NotEnoughGas(required) => {
+                panic_str(&format!("attach at least {} gas", required))
+            }
+            IsHumanCallErr::Expired => {
+                panic_str("is_human_call expired: valid_until_ms is in the past")
+            }
+            IsHumanCallErr::InvalidFunctionName => panic_str(&format!(
+                "function name must be non empty and at most {} characters long",
+                crate::MAX_FUNCTION_NAME_LEN
+            )),
         }
     }
 }
@@ -17,6 +37,8 @@ impl FunctionError for IsHumanCallErr {
 #[cfg_attr(not(target_arch = "wasm32"), derive(PartialEq, Debug))]
 pub enum SoulTransferErr {
     TransferLocked,
+    SelfTransfer,
+    OngoingIsHumanCall,
 }
 
 impl FunctionError for SoulTransferErr {
@@ -25,6 +47,12 @@ impl FunctionError for SoulTransferErr {
             SoulTransferErr::TransferLocked => {
                 panic_str("soul transfer not possible: owner has a transfer lock")
             }
+            SoulTransferErr::SelfTransfer => {
+                panic_str("soul transfer not possible: recipient must be different than owner")
+            }
+            SoulTransferErr::OngoingIsHumanCall => {
+                panic_str("soul transfer not possible: owner has an ongoing is_human_call")
+            }
         }
     }
 }
@@ -1,12 +1,18 @@
-use near_sdk::{serde::Serialize, serde_json::json, AccountId};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::{env, serde::Serialize, serde_json::json, AccountId};
 use sbt::{EventPayload, NearEvent};
 
 use crate::storage::AccountFlag;
 
+/// Standard name of the registry specific (not NEP-393) events.
+pub const IAH_STANDARD: &str = "i_am_human";
+/// Version of the registry specific events schema.
+pub const IAH_VERSION: &str = "1.0.0";
+
 fn emit_iah_event<T: Serialize>(event: EventPayload<T>) {
     NearEvent {
-        standard: "i_am_human",
-        version: "1.0.0",
+        standard: IAH_STANDARD,
+        version: IAH_VERSION,
         event,
     }
     .emit();
@@ -39,6 +45,82 @@ pub(crate) fn emit_transfer_lock(account: AccountId, locked_until: u64) {
     });
 }
 
+/// Emitted instead of the NEP-393 `mint` event for issuers with compact events enabled.
+/// `tokens_hash` is the sha256 hash of the minted token IDs (sorted, u64 little endian).
+pub(crate) fn emit_mint_compact(issuer: &AccountId, owners: usize, tokens: &[u64], event_seq: u64) {
+    let bytes: Vec<u8> = tokens.iter().flat_map(|t| t.to_le_bytes()).collect();
+    let tokens_hash: Base64VecU8 = env::sha256(&bytes).into();
+    emit_iah_event(EventPayload {
+        event: "mint_compact",
+        data: json!({
+            "issuer": issuer,
+            "owners": owners,
+            "tokens": tokens.len(),
+            "tokens_hash": tokens_hash,
+            "event_seq": event_seq,
+        }),
+    });
+}
+
+/// Emitted when a token of a transferable class is transferred with `sbt_transfer`.
+pub(crate) fn emit_sbt_transfer(
+    issuer: &AccountId,
+    token: u64,
+    from: &AccountId,
+    to: &AccountId,
+    event_seq: u64,
+) {
+    emit_iah_event(EventPayload {
+        event: "sbt_transfer",
+        data: json!({
+            "issuer": issuer,
+            "token": token,
+            "from": from,
+            "to": to,
+            "event_seq": event_seq,
+        }),
+    });
+}
+
+/// Emitted when an issuer atomically replaces the `old_token` with the `new_token`
+/// using `sbt_reissue`.
+pub(crate) fn emit_sbt_reissue(
+    issuer: &AccountId,
+    owner: &AccountId,
+    old_token: u64,
+    new_token: u64,
+    event_seq: u64,
+) {
+    emit_iah_event(EventPayload {
+        event: "sbt_reissue",
+        data: json!({
+            "issuer": issuer,
+            "owner": owner,
+            "old_token": old_token,
+            "new_token": new_token,
+            "event_seq": event_seq,
+        }),
+    });
+}
+
+/// Emitted when the authority moves the issuer `issuer_id` from the `old_account` to the
+/// `new_account` using `admin_rebind_issuer`.
+pub(crate) fn emit_issuer_rebind(old_account: &AccountId, new_account: &AccountId, issuer_id: u32) {
+    emit_iah_event(EventPayload {
+        event: "issuer_rebind",
+        data: json!({ "old_account": old_account, "new_account": new_account, "issuer_id": issuer_id }),
+    });
+}
+
+/// Emitted when a soul transfer doesn't complete in the first call (the transfer continues
+/// in subsequent calls). The `soul_transfer` event is emitted once the transfer completes.
+pub(crate) fn emit_soul_transfer_started(from: &AccountId, to: &AccountId) {
+    emit_iah_event(EventPayload {
+        event: "soul_transfer_started",
+        data: json!({ "from": from, "to": to }),
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use near_sdk::test_utils;
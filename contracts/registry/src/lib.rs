@@ -1,13 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LazyOption, LookupMap, TreeMap, UnorderedMap, UnorderedSet};
+use near_sdk::json_types::{Base64VecU8, U128};
 use near_sdk::serde_json::value::RawValue;
-use near_sdk::{env, near_bindgen, require, serde_json, AccountId, Gas, PanicOnDefault, Promise};
+use near_sdk::{
+    env, near_bindgen, require, serde_json, AccountId, Gas, PanicOnDefault, Promise, PromiseResult,
+};
 
 use sbt::*;
 
 use crate::errors::*;
+use crate::registry::MAX_LIMIT;
 use crate::storage::*;
 
 pub mod errors;
@@ -17,6 +21,40 @@ pub mod registry;
 pub mod storage;
 
 const IS_HUMAN_GAS: Gas = Gas(12 * Gas::ONE_TERA.0);
+/// gas reserved by `is_human_call` to schedule and execute the `on_is_human_call` callback.
+const IS_HUMAN_CALLBACK_GAS: Gas = Gas(20 * Gas::ONE_TERA.0);
+/// gas attached to the `on_is_human_call` callback.
+const ON_IS_HUMAN_CALL_GAS: Gas = Gas(10 * Gas::ONE_TERA.0);
+/// gas attached to `on_is_human_call_failed`.
+const IS_HUMAN_FAILED_GAS: Gas = Gas(2 * Gas::ONE_TERA.0);
+/// min gas forwarded to the recipient of `is_human_call` and `is_human_call_lock`.
+pub const MIN_IS_HUMAN_CALL_GAS: Gas = Gas(5 * Gas::ONE_TERA.0);
+/// default amount of tokens transferred in a single `sbt_soul_transfer` call.
+const SOUL_TX_BATCH: u32 = 20;
+/// max amount of contracts notified about a completed soul transfer.
+pub const MAX_SOUL_TX_HOOKS: usize = 5;
+/// gas attached to each `on_soul_transfer` hook call.
+const SOUL_TX_HOOK_GAS: Gas = Gas(5 * Gas::ONE_TERA.0);
+/// max length (in bytes) of the token metadata `reference`.
+pub const MAX_REFERENCE_LEN: usize = 512;
+/// max length (in bytes) of the token metadata `attestation`.
+pub const MAX_ATTESTATION_LEN: usize = 128;
+/// max amount of entries stored in a token history, see `sbt_history`.
+pub const MAX_TOKEN_HISTORY: usize = 10;
+/// storage (in bytes) of a full token history and an expire time added to the token. It is
+/// prepaid by the mint deposit, so the token updates done without a deposit (renew, revoke,
+/// recovery) don't use the registry balance. Reference updates can grow the token storage
+/// up to `MAX_REFERENCE_LEN`, so `sbt_update_token_references` charges its storage growth.
+pub const TOKEN_UPDATES_STORAGE: u64 = 160;
+/// max amount of accounts accepted by `count_unique_humans`.
+pub const MAX_HUMANS_QUERY: usize = 100;
+/// max length of the function name called by `is_human_call*` (the NEAR protocol limit).
+pub const MAX_FUNCTION_NAME_LEN: usize = 256;
+/// max `grace_ms` accepted by `is_human` and `is_human_call`: 7 days.
+pub const MAX_HUMAN_GRACE_MS: u64 = 7 * 24 * 3600 * 1000;
+/// gas reserved by `sbt_recover` for the work done after the tokens loop (supply updates,
+/// event, storage check).
+const RECOVER_GAS_RESERVE: Gas = Gas(10 * Gas::ONE_TERA.0);
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
@@ -28,7 +66,7 @@ pub struct Contract {
     pub sbt_issuers: UnorderedMap<AccountId, IssuerId>,
     pub issuer_id_map: LookupMap<IssuerId, AccountId>, // reverse index
     /// store ongoing soul transfers by "old owner"
-    pub(crate) ongoing_soul_tx: LookupMap<AccountId, IssuerTokenId>,
+    pub(crate) ongoing_soul_tx: LookupMap<AccountId, SoulTxCursor>,
 
     /// map accounts -> unix timestamp in milliseconds until when any soul transfer is blocked
     /// for the given account.
@@ -44,6 +82,12 @@ pub struct Contract {
     pub(crate) supply_by_owner: LookupMap<(AccountId, IssuerId), u64>,
     pub(crate) supply_by_class: LookupMap<(IssuerId, ClassId), u64>,
     pub(crate) supply_by_issuer: LookupMap<IssuerId, u64>,
+    /// sorted list of issuer classes with non zero supply.
+    pub(crate) issuer_classes: LookupMap<IssuerId, Vec<ClassId>>,
+    /// total amount of tokens (across all issuers) per owner.
+    pub(crate) supply_by_owner_total: LookupMap<AccountId, u64>,
+    /// number of distinct accounts holding at least one token.
+    pub(crate) total_souls: u64,
 
     /// maps user balance key to tokenID
     pub(crate) balances: TreeMap<BalanceKey, TokenId>,
@@ -53,9 +97,65 @@ pub struct Contract {
     pub(crate) next_token_ids: LookupMap<IssuerId, TokenId>,
     pub(crate) next_issuer_id: IssuerId,
 
-    /// tuple of (required issuer, [required list of classes]) that represents mandatory
-    /// requirements to be verified as human for `is_human` and `is_human_call` methods.
-    pub(crate) iah_sbts: (AccountId, Vec<ClassId>),
+    /// list of (IAH issuer, [required list of classes]) pairs used by `is_human` and
+    /// `is_human_call` methods. An account is a human if it has tokens of all required
+    /// classes of any of the IAH issuers.
+    pub(crate) iah_sbts: Vec<(AccountId, Vec<ClassId>)>,
+
+    /// max amount of tokens transferred in a single `sbt_soul_transfer` call.
+    pub(crate) soul_tx_batch: u32,
+
+    /// set of (issuer, class) pairs which can only be minted to allowlisted accounts.
+    pub(crate) allowlisted_classes: LookupMap<(IssuerId, ClassId), ()>,
+    /// accounts allowed to receive a token of an allowlisted (issuer, class).
+    pub(crate) class_allowlist: LookupMap<(IssuerId, ClassId, AccountId), ()>,
+    /// contracts notified (`on_soul_transfer(from, to)`) when a soul transfer completes.
+    pub(crate) soul_tx_hooks: LazyOption<Vec<AccountId>>,
+    /// next token to process by an ongoing `admin_expire_issuer`.
+    pub(crate) issuer_expire_cursor: LookupMap<IssuerId, TokenId>,
+    /// scope of the accounts banned by `admin_ban_with_scope`. Banned accounts without an
+    /// entry have the `BanScope::TransferOnly` scope.
+    pub(crate) ban_scope: LookupMap<AccountId, BanScope>,
+    /// set of (issuer, class) pairs which tokens can be transferred by the owner using
+    /// `sbt_transfer`. By default classes are soulbound.
+    pub(crate) transferable_classes: LookupMap<(IssuerId, ClassId), ()>,
+    /// set of (issuer, class) pairs which tokens are not moved by `sbt_soul_transfer`.
+    pub(crate) pinned_classes: LookupMap<(IssuerId, ClassId), ()>,
+    /// outcome of the tokens processed so far by an ongoing soul transfer.
+    pub(crate) soul_tx_counts: LookupMap<AccountId, SoulTransferCounts>,
+    /// issuers paused by the authority: they can't mint nor renew tokens.
+    pub(crate) paused_issuers: LookupMap<IssuerId, ()>,
+    /// issuers which require the owner consent for the non burn `sbt_revoke_by_owner`.
+    pub(crate) revocation_consent_required: LookupMap<IssuerId, ()>,
+    /// (owner, issuer) revocation consents given with `allow_revocation`.
+    pub(crate) revocation_consents: LookupMap<(AccountId, IssuerId), ()>,
+    /// issuers which opted out from the sha256 (32 bytes) `reference_hash` length check.
+    pub(crate) reference_hash_opt_out: LookupMap<IssuerId, ()>,
+    /// last `MAX_TOKEN_HISTORY` updates of a token, see `sbt_history`.
+    pub(crate) token_history: LookupMap<IssuerTokenId, Vec<TokenChange>>,
+    /// ongoing `admin_rebuild_supplies` per issuer.
+    pub(crate) supply_rebuild: LookupMap<IssuerId, SupplyRebuild>,
+    /// issuers default expiry policy. Issuers without an entry use `ExpiryPolicy::NeverExpire`.
+    pub(crate) expiry_policy: LookupMap<IssuerId, ExpiryPolicy>,
+    /// number of `is_human_call` calls in flight per caller. Soul transfers of the caller are
+    /// blocked until the `on_is_human_call` callback clears the marker.
+    pub(crate) ongoing_is_human_calls: LookupMap<AccountId, u32>,
+    /// issuers which opted in for the compact Mint events, see `set_compact_events`.
+    pub(crate) compact_events: LookupMap<IssuerId, ()>,
+    /// account to which the key account transferred its soul (one hop).
+    pub(crate) soul_successors: LookupMap<AccountId, AccountId>,
+    /// last account which transferred its soul to the key account (one hop).
+    pub(crate) soul_predecessors: LookupMap<AccountId, AccountId>,
+    /// (issuer, account) pairs: accounts approved by the issuer to renew its tokens with
+    /// `sbt_renew_as`.
+    pub(crate) renewers: LookupMap<(IssuerId, AccountId), ()>,
+    /// next token to process by an ongoing `sbt_revoke_class`.
+    pub(crate) class_revoke_cursor: LookupMap<(IssuerId, ClassId), TokenId>,
+    /// sequence number of the last emitted Mint, Recover, Renew, Revoke, Burn or
+    /// TokenReference event.
+    pub(crate) event_seq: u64,
+    /// ongoing `migrate` backfill, `None` when the backfill is done.
+    pub(crate) migration_backfill: Option<MigrationBackfill>,
 }
 
 // Implement the contract structure
@@ -63,7 +163,8 @@ pub struct Contract {
 impl Contract {
     /// Contract constructor.
     /// `iah_issuer`: required issuer for is_human check.
-    /// `iah_classes`: required list of classes for is_human check.
+    /// `iah_classes`: required list of classes for is_human check. Must not be empty, it's
+    /// sorted and deduplicated.
     #[init]
     pub fn new(
         authority: AccountId,
@@ -71,10 +172,7 @@ impl Contract {
         iah_classes: Vec<ClassId>,
         authorized_flaggers: Vec<AccountId>,
     ) -> Self {
-        require!(
-            !iah_classes.is_empty(),
-            "iah_classes must be a non empty list"
-        );
+        let iah_classes = normalize_iah_classes(iah_classes);
         let mut contract = Self {
             authority,
             sbt_issuers: UnorderedMap::new(StorageKey::SbtIssuers),
@@ -84,17 +182,44 @@ impl Contract {
             supply_by_owner: LookupMap::new(StorageKey::SupplyByOwner),
             supply_by_class: LookupMap::new(StorageKey::SupplyByClass),
             supply_by_issuer: LookupMap::new(StorageKey::SupplyByIssuer),
+            issuer_classes: LookupMap::new(StorageKey::IssuerClasses),
+            supply_by_owner_total: LookupMap::new(StorageKey::SupplyByOwnerTotal),
+            total_souls: 0,
+            migration_backfill: None,
             balances: TreeMap::new(StorageKey::Balances),
             issuer_tokens: LookupMap::new(StorageKey::IssuerTokens),
             next_token_ids: LookupMap::new(StorageKey::NextTokenId),
             next_issuer_id: 1,
             ongoing_soul_tx: LookupMap::new(StorageKey::OngoingSoultTx),
-            iah_sbts: (iah_issuer.clone(), iah_classes),
+            iah_sbts: vec![(iah_issuer.clone(), iah_classes)],
             flagged: LookupMap::new(StorageKey::Flagged),
             authorized_flaggers: LazyOption::new(
                 StorageKey::AdminsFlagged,
                 Some(&authorized_flaggers),
             ),
+            soul_tx_batch: SOUL_TX_BATCH,
+            allowlisted_classes: LookupMap::new(StorageKey::AllowlistedClasses),
+            class_allowlist: LookupMap::new(StorageKey::ClassAllowlist),
+            soul_tx_hooks: LazyOption::new(StorageKey::SoulTxHooks, None),
+            issuer_expire_cursor: LookupMap::new(StorageKey::IssuerExpireCursor),
+            ban_scope: LookupMap::new(StorageKey::BanScope),
+            transferable_classes: LookupMap::new(StorageKey::TransferableClasses),
+            pinned_classes: LookupMap::new(StorageKey::PinnedClasses),
+            soul_tx_counts: LookupMap::new(StorageKey::SoulTxCounts),
+            paused_issuers: LookupMap::new(StorageKey::PausedIssuers),
+            revocation_consent_required: LookupMap::new(StorageKey::RevocationConsentRequired),
+            revocation_consents: LookupMap::new(StorageKey::RevocationConsents),
+            reference_hash_opt_out: LookupMap::new(StorageKey::ReferenceHashOptOut),
+            token_history: LookupMap::new(StorageKey::TokenHistory),
+            ongoing_is_human_calls: LookupMap::new(StorageKey::OngoingIsHumanCalls),
+            expiry_policy: LookupMap::new(StorageKey::ExpiryPolicy),
+            supply_rebuild: LookupMap::new(StorageKey::SupplyRebuild),
+            compact_events: LookupMap::new(StorageKey::CompactEvents),
+            soul_successors: LookupMap::new(StorageKey::SoulSuccessors),
+            soul_predecessors: LookupMap::new(StorageKey::SoulPredecessors),
+            renewers: LookupMap::new(StorageKey::Renewers),
+            class_revoke_cursor: LookupMap::new(StorageKey::ClassRevokeCursor),
+            event_seq: 0,
         };
         contract._add_sbt_issuer(&iah_issuer);
         contract
@@ -108,10 +233,17 @@ impl Contract {
         self.sbt_issuers.keys().collect()
     }
 
+    /// Returns list of token metadata schema versions supported by the registry. Query
+    /// responses have `metadata_version` set to one of them.
+    pub fn supported_metadata_versions(&self) -> Vec<u8> {
+        vec![1, 2]
+    }
+
     /// Returns IAH class set: required token classes to be approved as a human by the
-    /// `is_human`.
+    /// `is_human`. An account is a human if it has tokens of all classes of any of the
+    /// listed issuers.
     pub fn iah_class_set(&self) -> ClassSet {
-        vec![self.iah_sbts.clone()]
+        self.iah_sbts.clone()
     }
 
     #[inline]
@@ -119,2976 +251,10573 @@ impl Contract {
         self.banlist.contains(account)
     }
 
-    /// Returns account status if it was flagged. Returns None if the account was not flagged.
-    pub fn account_flagged(&self, account: AccountId) -> Option<AccountFlag> {
-        self.flagged.get(&account)
+    /// Same as `sbt_supply_by_class`, but distinguishes an unregistered issuer (returns
+    /// `None`) from a class without supply (returns `Some(0)`).
+    pub fn sbt_supply_by_class_checked(&self, issuer: AccountId, class: ClassId) -> Option<u64> {
+        let issuer_id = self.sbt_issuers.get(&issuer)?;
+        Some(self.supply_by_class.get(&(issuer_id, class)).unwrap_or(0))
     }
 
-    /// Returns empty list if the account is NOT a human according to the IAH protocol.
-    /// Otherwise returns list of SBTs (identifed by issuer and list of token IDs) proving
-    /// the `account` humanity.
-    pub fn is_human(&self, account: AccountId) -> SBTs {
-        self._is_human(&account)
+    /// Returns the supply of each of the requested `classes` of the `issuer`, in the same
+    /// order. Returns 0 for classes without supply and for an unregistered issuer.
+    /// Panics if more than MAX_LIMIT classes are provided.
+    pub fn sbt_supply_by_classes(&self, issuer: AccountId, classes: Vec<ClassId>) -> Vec<u64> {
+        require!(
+            classes.len() <= MAX_LIMIT as usize,
+            format!("max {} classes can be queried", MAX_LIMIT)
+        );
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return vec![0; classes.len()],
+            Some(id) => id,
+        };
+        classes
+            .into_iter()
+            .map(|c| self.supply_by_class.get(&(issuer_id, c)).unwrap_or(0))
+            .collect()
     }
 
-    /// Returns `true` if an account is considered human, and `false` otherwise.
-    /// We DO NOT RECOMMEND using this function. You SHOULD use `is_human` instead. Returning
-    /// bool may create wrong practices. Humanity will be a metric, not a true/false.
-    /// Each "client" should have his own criteria and asses the humanity proof
-    /// (e.g. check for KYC SBTs, liveness, ...).
-    pub fn is_human_bool(&self, account: AccountId) -> bool {
-        !self._is_human(&account).is_empty()
+    /// Returns the `account` token of the given `class` issued by the `issuer` (including an
+    /// expired one), or `None` if the account doesn't have such token.
+    /// Returns `None` if the account has an ongoing soul transfer.
+    pub fn sbt_by_owner_class(
+        &self,
+        account: AccountId,
+        issuer: AccountId,
+        class: ClassId,
+    ) -> Option<Token> {
+        if self.ongoing_soul_tx.contains_key(&account) {
+            return None;
+        }
+        let issuer_id = self.sbt_issuers.get(&issuer)?;
+        let token = self
+            .balances
+            .get(&balance_key(account, issuer_id, ClassKey(class)))?;
+        self.issuer_tokens
+            .get(&IssuerTokenId::new(issuer_id, token))
+            .map(|t| t.to_token(issuer_id, token))
     }
 
-    fn _is_human(&self, account: &AccountId) -> SBTs {
-        if self.flagged.get(account) == Some(AccountFlag::Blacklisted) || self._is_banned(account) {
+    /// Returns the `owner` tokens issued by the `issuer`: same as `sbt_tokens_by_owner` with
+    /// the `issuer` filter and default arguments (expired tokens are excluded), but without
+    /// the `(issuer, tokens)` grouping.
+    /// Returns an empty list if the `issuer` is not registered, the `owner` has an ongoing soul
+    /// transfer or is banned with `BanScope::Full`.
+    pub fn sbt_tokens_of(&self, issuer: AccountId, owner: AccountId) -> Vec<OwnedToken> {
+        if self.sbt_issuers.get(&issuer).is_none() {
             return vec![];
         }
-        let issuer = Some(self.iah_sbts.0.clone());
-        let mut proof: Vec<TokenId> = Vec::new();
-        // check if user has tokens from all classes
-        for cls in &self.iah_sbts.1 {
-            let tokens = self.sbt_tokens_by_owner(
-                account.clone(),
-                issuer.clone(),
-                Some(*cls),
-                Some(1),
-                None,
-            );
-            // we need to check class, because the query can return a "next" token if a user
-            // doesn't have the token of requested class.
-            if tokens.is_empty() || tokens[0].1[0].metadata.class != *cls {
-                return vec![];
-            }
-            proof.push(tokens[0].1[0].token)
+        match self
+            .sbt_tokens_by_owner(owner, Some(issuer), None, None, None)
+            .pop()
+        {
+            None => vec![],
+            Some((_, tokens)) => tokens,
         }
-        vec![(self.iah_sbts.0.clone(), proof)]
     }
 
-    pub fn get_authority(self) -> AccountId {
-        self.authority
+    /// Resumable `sbt_tokens_by_owner` across all issuers: lists the `account` tokens grouped
+    /// by issuer (in the issuer ID and class order), starting after the `cursor` (from the
+    /// beginning if not set). Returns the page and the opaque cursor to pass as `cursor` to get
+    /// the next page, or `None` when the end is reached. The cursor has the same encoding as in
+    /// `dump_balances`.
+    /// `limit` is a hard total of the returned tokens across all issuers, so the page can be cut
+    /// off mid-issuer: the remaining tokens of that issuer are returned in the next page, under
+    /// the same issuer. Expired tokens are skipped (and not counted) unless `with_expired` is
+    /// set, so the last page can be empty.
+    /// Returns an empty page for accounts with an ongoing soul transfer or banned with
+    /// `BanScope::Full`.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    /// Panics if the `cursor` is malformed or is not a cursor of the `account`.
+    pub fn sbt_tokens_by_owner_page(
+        &self,
+        account: AccountId,
+        cursor: Option<String>,
+        limit: Option<u32>,
+        with_expired: Option<bool>,
+    ) -> (Vec<(AccountId, Vec<OwnedToken>)>, Option<String>) {
+        let after = match cursor {
+            None => balance_key(account.clone(), 0, ClassKey(0)),
+            Some(cursor) => match decode_balance_cursor(&cursor) {
+                Some(key) if key.owner == account => key,
+                _ => env::panic_str("malformed cursor"),
+            },
+        };
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        if self.ongoing_soul_tx.contains_key(&account)
+            || self.ban_scope.get(&account) == Some(BanScope::Full)
+        {
+            return (vec![], None);
+        }
+        let (tokens, last_key) =
+            self.owner_tokens_after(after, false, limit, with_expired.unwrap_or(false));
+        // we peek the next entry to know if there is a next page.
+        let next = last_key
+            .filter(|key| {
+                matches!(self.balances.iter_from(key.clone()).next(),
+                    Some((k, _)) if k.owner == account)
+            })
+            .map(|key| encode_balance_cursor(&key));
+        (tokens, next)
     }
 
-    pub fn authorized_flaggers(self) -> Vec<AccountId> {
-        self.authorized_flaggers.get().unwrap_or_default()
+    /// Returns the account to which the `account` transferred its soul with a completed
+    /// `sbt_soul_transfer`, or `None`. Only one hop is returned: to follow a chain of
+    /// transfers, query the successor of the returned account.
+    pub fn soul_successor(&self, account: AccountId) -> Option<AccountId> {
+        self.soul_successors.get(&account)
     }
 
-    //
-    // Transactions
-    //
+    /// Returns the last account which transferred its soul to the `account` with a completed
+    /// `sbt_soul_transfer`, or `None`. Only one hop is returned, see `soul_successor`.
+    pub fn soul_predecessor(&self, account: AccountId) -> Option<AccountId> {
+        self.soul_predecessors.get(&account)
+    }
 
-    /// sbt_mint_iah is a wrapper around `sbt_mint` and `is_human`. It mints SBTs only when
-    /// all recipients are humans. Panics if one of the recipients is not a human.
-    #[payable]
-    pub fn sbt_mint_iah(
-        &mut self,
-        token_spec: Vec<(AccountId, Vec<TokenMetadata>)>,
-    ) -> Vec<TokenId> {
-        let issuer = &env::predecessor_account_id();
-        for ts in &token_spec {
-            require!(
-                !self._is_human(&ts.0).is_empty(),
-                format!("{} is not a human", &ts.0)
-            );
-        }
-        self._sbt_mint(issuer, token_spec)
+    /// Returns the `attestation` of the `token` issued by the `issuer`, or `None` if the token
+    /// doesn't exist or has no attestation. The attestation is not verified by the registry.
+    pub fn sbt_attestation(&self, issuer: AccountId, token: TokenId) -> Option<Base64VecU8> {
+        let issuer_id = self.sbt_issuers.get(&issuer)?;
+        self.issuer_tokens
+            .get(&IssuerTokenId::new(issuer_id, token))?
+            .metadata
+            .latest()
+            .attestation
     }
 
-    /// Transfers atomically all SBT tokens from one account to another account.
-    /// The caller must be an SBT holder and the `recipient` must not be a banned account.
-    /// Transfers the account flag from the owner to the recipient.
-    /// Fails when:
-    /// + `recipient` is banned;
-    /// + there is a potential conflict between the caller's and recipient's flag,
-    ///   specifically when one account is `Blacklisted` and the other is `Verified`;
-    /// Bans the caller account. NOTE: call can try to do soul_transfer to himself. This
-    /// sounds irrationally, and allows to ban himself/herself.
-    /// Emits:
-    /// + `Ban` event for the caller at the beginning of the process.
-    /// + `SoulTransfer` event only once all the tokens from the caller were transferred
-    ///    and at least one token was transferred (caller had at least 1 sbt).
-    /// Returns the amount of tokens transferred and a boolean: `true` if the whole
-    /// process has finished, `false` when the process has not finished and should be
-    /// continued by a subsequent call.
-    /// + User must keep calling the `sbt_soul_transfer` until `true` is returned.
-    /// + If caller does not have any tokens, nothing will be transfered, the caller
-    ///   will be banned and `Ban` event will be emitted.
-    /// See https://github.com/near/NEPs/pull/393 for more details and rationality about
-    /// soul transfer.
-    #[payable]
-    #[handle_result]
-    pub fn sbt_soul_transfer(
-        &mut self,
-        recipient: AccountId,
-        #[allow(unused_variables)] memo: Option<String>,
-    ) -> Result<(u32, bool), SoulTransferErr> {
-        // TODO: test what is the max safe amount of updates
-        self._sbt_soul_transfer(recipient, 20)
+    /// Returns, for each of the `classes` (in the same order), if the `account` holds a token
+    /// (including an expired one) of the class issued by the `issuer`.
+    /// Returns all `false` for an unknown issuer or if the account has an ongoing soul transfer.
+    /// Panics if more than MAX_LIMIT classes are provided.
+    pub fn sbt_holds_classes(
+        &self,
+        account: AccountId,
+        issuer: AccountId,
+        classes: Vec<ClassId>,
+    ) -> Vec<bool> {
+        require!(
+            classes.len() <= MAX_LIMIT as usize,
+            format!("max {} classes can be queried", MAX_LIMIT)
+        );
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            Some(id) if !self.ongoing_soul_tx.contains_key(&account) => id,
+            _ => return vec![false; classes.len()],
+        };
+        classes
+            .into_iter()
+            .map(|c| {
+                self.balances
+                    .contains_key(&balance_key(account.clone(), issuer_id, ClassKey(c)))
+            })
+            .collect()
     }
 
-    pub(crate) fn _transfer_flag(&mut self, from: &AccountId, recipient: &AccountId) {
-        if let Some(flag_from) = self.flagged.get(from) {
-            if let Some(flag_to) = self.flagged.get(recipient) {
-                require!(
-                    flag_from == flag_to,
-                    "can't transfer soul when there is a flag conflict"
-                )
-            } else {
-                self.flagged.insert(recipient, &flag_from);
-            }
+    /// Returns the score of the `token` issued by the `issuer`, or `None` if the token doesn't
+    /// exist or has no score.
+    pub fn sbt_score(&self, issuer: AccountId, token: TokenId) -> Option<u32> {
+        let issuer_id = self.sbt_issuers.get(&issuer)?;
+        self.issuer_tokens
+            .get(&IssuerTokenId::new(issuer_id, token))?
+            .metadata
+            .score()
+    }
+
+    /// Returns the `issuer` tokens of the given `class` with `score >= min_score` (including
+    /// expired tokens). Tokens without a score are skipped.
+    /// Uses the `sbt_tokens` paging: scans at most `limit` (default `MAX_LIMIT`) token IDs
+    /// starting from `from_token` (default 1), so the result can have less than `limit`
+    /// tokens even if there are more matching tokens. To continue, call again with
+    /// `from_token + limit`.
+    pub fn sbt_tokens_by_score(
+        &self,
+        issuer: AccountId,
+        class: ClassId,
+        min_score: u32,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+    ) -> Vec<Token> {
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return vec![],
+            Some(i) => i,
+        };
+        let from_token = from_token.unwrap_or(1);
+        require!(from_token > 0, "from_token, if set, must be >= 1");
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let max_id = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        if max_id < from_token {
+            return vec![];
         }
+        let max_id = std::cmp::min(
+            max_id.saturating_add(1),
+            from_token.saturating_add(limit as u64),
+        );
+        (from_token..max_id)
+            .filter_map(|token| {
+                let t = self
+                    .issuer_tokens
+                    .get(&IssuerTokenId::new(issuer_id, token))?;
+                let score = t.metadata.score()?;
+                if t.metadata.class_id() != class || score < min_score {
+                    return None;
+                }
+                Some(t.to_token(issuer_id, token))
+            })
+            .collect()
     }
 
-    // execution of the sbt_soul_transfer in this function to parametrize `max_updates` in
-    // order to facilitate tests.
-    #[handle_result]
-    pub(crate) fn _sbt_soul_transfer(
-        &mut self,
-        recipient: AccountId,
-        limit: usize,
-    ) -> Result<(u32, bool), SoulTransferErr> {
-        let owner = env::predecessor_account_id();
-        let transfer_lock = self.transfer_lock.get(&owner).unwrap_or(0);
-        if transfer_lock >= env::block_timestamp_ms() {
-            return Err(SoulTransferErr::TransferLocked);
+    /// Same as `sbt_tokens`, but every returned token is paired with the `issuer` account.
+    /// Handy when merging pages of tokens from many issuers.
+    pub fn sbt_tokens_with_issuer(
+        &self,
+        issuer: AccountId,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+        with_expired: Option<bool>,
+    ) -> Vec<(AccountId, Token)> {
+        self.sbt_tokens(issuer.clone(), from_token, limit, with_expired)
+            .into_iter()
+            .map(|t| (issuer.clone(), t))
+            .collect()
+    }
+
+    /// Same as `sbt_tokens`, but returns the `from_token` of the next page, so the caller
+    /// doesn't need to compute it. `next_from_token` is `None` when the end is reached.
+    /// NOTE: like `sbt_tokens`, the function scans `limit` token IDs, so a page can have less
+    /// than `limit` tokens (or be empty) when tokens are burned or expired.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    pub fn sbt_tokens_page(
+        &self,
+        issuer: AccountId,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+        with_expired: Option<bool>,
+    ) -> TokenPage {
+        let tokens = self.sbt_tokens(issuer.clone(), from_token, limit, with_expired);
+        let last = match self.sbt_issuers.get(&issuer) {
+            None => 0,
+            Some(issuer_id) => self.next_token_ids.get(&issuer_id).unwrap_or(0),
+        };
+        let next = from_token
+            .unwrap_or(1)
+            .saturating_add(limit.unwrap_or(MAX_LIMIT) as u64);
+        TokenPage {
+            tokens,
+            next_from_token: if next <= last { Some(next) } else { None },
         }
+    }
 
-        let (resumed, start) = self.transfer_continuation(&owner, &recipient, true);
-        if !resumed {
-            self._transfer_flag(&owner, &recipient);
+    /// Query valid (not expired) tokens of the given `class` issued by the `issuer`.
+    /// Paging works the same way as in `sbt_tokens`: the function scans at most `limit` token
+    /// IDs starting from `from_token` (default 1), so an empty list doesn't mean there are
+    /// no more tokens.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    pub fn sbt_valid_tokens_by_class(
+        &self,
+        issuer: AccountId,
+        class: ClassId,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+    ) -> Vec<Token> {
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return vec![],
+            Some(i) => i,
+        };
+        let from_token = from_token.unwrap_or(1);
+        require!(from_token > 0, "from_token, if set, must be >= 1");
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let max_id = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        if max_id < from_token {
+            return vec![];
         }
+        let max_id = std::cmp::min(
+            max_id.saturating_add(1),
+            from_token.saturating_add(limit as u64),
+        );
 
-        let batch: Vec<(BalanceKey, TokenId)> = self
-            .balances
-            .iter_from(BalanceKey {
-                owner: owner.clone(),
-                issuer_id: start.issuer_id,
-                class_id: start.token,
+        let now = env::block_timestamp_ms();
+        (from_token..max_id)
+            .filter_map(|token| {
+                self.issuer_tokens
+                    .get(&IssuerTokenId::new(issuer_id, token))
+                    .filter(|t| t.metadata.class_id() == class && is_token_valid_at(t, now))
+                    .map(|t| t.to_token(issuer_id, token))
             })
-            .take(limit)
-            .collect();
+            .collect()
+    }
 
-        let mut key_new = BalanceKey {
-            owner: recipient.clone(),
-            issuer_id: 0,
-            class_id: 0,
+    /// Query tokens of the `issuer` issued in the `[from_ms, to_ms]` time range (inclusive,
+    /// unix time in milliseconds). Expired tokens are included, tokens without `issued_at`
+    /// are excluded.
+    /// Paging works the same way as in `sbt_tokens`: the function scans at most `limit` token
+    /// IDs starting from `from_token` (default 1), so an empty list doesn't mean there are
+    /// no more tokens. Continue with `from_token = previous from_token + limit` until
+    /// `from_token > sbt_supply(issuer)`.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    pub fn sbt_tokens_by_issued_range(
+        &self,
+        issuer: AccountId,
+        from_ms: u64,
+        to_ms: u64,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+    ) -> Vec<Token> {
+        require!(from_ms <= to_ms, "from_ms must not be bigger than to_ms");
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return vec![],
+            Some(i) => i,
         };
-        let mut prev_issuer: IssuerId = 0;
-        let mut token_counter = 0;
-        for (key, token_id) in &batch {
-            if key.owner != owner {
-                break;
-            }
-            token_counter += 1;
+        let from_token = from_token.unwrap_or(1);
+        require!(from_token > 0, "from_token, if set, must be >= 1");
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let max_id = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        if max_id < from_token {
+            return vec![];
+        }
+        let max_id = std::cmp::min(
+            max_id.saturating_add(1),
+            from_token.saturating_add(limit as u64),
+        );
 
-            if prev_issuer != key.issuer_id {
-                prev_issuer = key.issuer_id;
-                // update user token supply map
-                if let Some(s) = self.supply_by_owner.remove(&(owner.clone(), prev_issuer)) {
-                    let key = &(recipient.clone(), prev_issuer);
-                    let supply_to = self.supply_by_owner.get(key).unwrap_or(0);
-                    self.supply_by_owner.insert(key, &(s + supply_to));
+        let mut resp = Vec::new();
+        for token in from_token..max_id {
+            if let Some(t) = self
+                .issuer_tokens
+                .get(&IssuerTokenId::new(issuer_id, token))
+            {
+                match t.metadata.issued_at() {
+                    Some(i) if i >= from_ms && i <= to_ms => {
+                        resp.push(t.to_token(issuer_id, token))
+                    }
+                    _ => (),
                 }
             }
+        }
+        resp
+    }
 
-            key_new.issuer_id = key.issuer_id;
-            key_new.class_id = key.class_id;
-            // One use can have max one toke of a (issuer, class) pair. We don't allow users
-            // to overwrite each other tokens. Recipient or sender should firstly burn his SBT
-            // to avoid conflicts.
-            if self.balances.insert(&key_new, token_id).is_some() {
-                env::panic_str(&format!(
-                    "recipient already has an SBT of issuer={}, class={}; source_token_id={}",
-                    self.issuer_by_id(key.issuer_id),
-                    key.class_id,
-                    token_id
-                ));
+    /// Returns the most recent `issuer` tokens, with `issued_at >= since_ms`, ordered from
+    /// the most recent (highest token ID). The scan goes down from `from_token` (default: the
+    /// last minted token) and stops at the first token issued before `since_ms`.
+    /// Tokens without `issued_at` are excluded. Expired tokens are included.
+    /// NOTE: the function scans at most `limit` token IDs, so a page can have less than `limit`
+    /// tokens. To continue, call again with the returned `next_from_token`, which is `None`
+    /// when the scan is finished.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    pub fn recent_tokens(
+        &self,
+        issuer: AccountId,
+        since_ms: u64,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+    ) -> TokenPage {
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let mut page = TokenPage {
+            tokens: Vec::new(),
+            next_from_token: None,
+        };
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return page,
+            Some(i) => i,
+        };
+        let last = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        let from_token = from_token.map_or(last, |t| std::cmp::min(t, last));
+        let to_token = from_token.saturating_sub(limit as u64);
+        for token in (to_token + 1..=from_token).rev() {
+            if let Some(t) = self
+                .issuer_tokens
+                .get(&IssuerTokenId::new(issuer_id, token))
+            {
+                match t.metadata.issued_at() {
+                    None => continue,
+                    Some(i) if i < since_ms => return page,
+                    _ => page.tokens.push(t.to_token(issuer_id, token)),
+                }
             }
-            self.balances.remove(key);
-
-            let i_key = IssuerTokenId {
-                issuer_id: key.issuer_id,
-                token: *token_id,
-            };
-            let mut td = self.issuer_tokens.get(&i_key).unwrap();
-            td.owner = recipient.clone();
-            self.issuer_tokens.insert(&i_key, &td);
         }
+        if to_token > 0 {
+            page.next_from_token = Some(to_token);
+        }
+        page
+    }
 
-        let completed = token_counter != limit;
-        if completed {
-            if resumed {
-                // insert is happening when we need to continue, so don't need to remove if
-                // the process finishes in the same transaction.
-                self.ongoing_soul_tx.remove(&owner);
+    /// Returns `(class, supply)` pairs of the `issuer` classes with non zero supply, ordered
+    /// by class, starting from `from_class` (inclusive, default 1).
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    /// Panics during the migration backfill (see `admin_migration_backfill`).
+    pub fn issuer_class_supplies(
+        &self,
+        issuer: AccountId,
+        from_class: Option<ClassId>,
+        limit: Option<u32>,
+    ) -> Vec<(ClassId, u64)> {
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return vec![],
+            Some(i) => i,
+        };
+        let from_class = from_class.unwrap_or(1);
+        require!(from_class > 0, "from_class, if set, must be >= 1");
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        self.assert_backfilled();
+        let classes = self.issuer_classes.get(&issuer_id).unwrap_or_default();
+        let start = classes.partition_point(|c| *c < from_class);
+        classes[start..]
+            .iter()
+            .take(limit as usize)
+            .map(|c| (*c, self.supply_by_class.get(&(issuer_id, *c)).unwrap_or(0)))
+            .collect()
+    }
+
+    /// Maintenance query: lists registered issuers with zero supply (never minted or all
+    /// tokens were burned), which are candidates for a cleanup.
+    /// The function scans at most `limit` issuer IDs starting from `from_id` (default 1), so
+    /// an empty list doesn't mean there are no more empty issuers.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    pub fn empty_issuers(&self, from_id: Option<IssuerId>, limit: Option<u32>) -> Vec<AccountId> {
+        let from_id = from_id.unwrap_or(1);
+        require!(from_id > 0, "from_id, if set, must be >= 1");
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let end = std::cmp::min(self.next_issuer_id, from_id.saturating_add(limit));
+        (from_id..end)
+            .filter(|id| self.supply_by_issuer.get(id).unwrap_or(0) == 0)
+            .filter_map(|id| self.issuer_id_map.get(&id))
+            .collect()
+    }
+
+    /// Monitoring query to check that token and issuer ID counters can't reuse an existing
+    /// ID, and that the supply counters are consistent. For every registered issuer checks:
+    /// + issuer ID is smaller than `next_issuer_id` and the reverse index is consistent;
+    /// + the next token ID, which will be assigned by the `next_token_ids` counter, is not
+    ///   used yet;
+    /// + the issuer supply equals the sum of the issuer class supplies.
+    ///
+    /// The issuer checks are done only for the first page (`from_key` not set). Additionally,
+    /// for a page of the ownership records (see `dump_balances`), checks that no token ID is
+    /// bigger than the issuer token ID counter. Returns the check result and the cursor to
+    /// pass as `from_key` to check the next page, or `None` when the end is reached.
+    /// If limit is not specified, default is used: MAX_LIMIT. Limit is capped at MAX_LIMIT.
+    /// Panics if the `from_key` cursor is malformed, or during the migration backfill.
+    pub fn check_id_invariants(
+        &self,
+        from_key: Option<String>,
+        limit: Option<u32>,
+    ) -> (bool, Option<String>) {
+        self.assert_backfilled();
+        let issuers_ok = from_key.is_some()
+            || self.sbt_issuers.iter().all(|(issuer, issuer_id)| {
+                let next_token = self.next_token_ids.get(&issuer_id).unwrap_or(0) + 1;
+                let classes_supply: u64 = self
+                    .issuer_classes
+                    .get(&issuer_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|class| self.supply_by_class.get(&(issuer_id, class)).unwrap_or(0))
+                    .sum();
+                issuer_id < self.next_issuer_id
+                    && self.issuer_id_map.get(&issuer_id) == Some(issuer)
+                    && self
+                        .issuer_tokens
+                        .get(&IssuerTokenId::new(issuer_id, next_token))
+                        .is_none()
+                    && self.supply_by_issuer.get(&issuer_id).unwrap_or(0) == classes_supply
+            });
+        let (records, next) = self.dump_balances(from_key, limit);
+        let tokens_ok = records.into_iter().all(|(_, issuer_id, _, token)| {
+            token <= self.next_token_ids.get(&issuer_id).unwrap_or(0)
+        });
+        (issuers_ok && tokens_ok, next)
+    }
+
+    /// Bulk version of `is_banned`: returns ban status for each account, in the same order.
+    /// Panics if more than MAX_LIMIT accounts are provided.
+    pub fn is_banned_bulk(&self, accounts: Vec<AccountId>) -> Vec<bool> {
+        require!(
+            accounts.len() <= MAX_LIMIT as usize,
+            format!("max {} accounts can be queried", MAX_LIMIT)
+        );
+        accounts.iter().map(|a| self._is_banned(a)).collect()
+    }
+
+    /// Returns the number of distinct issuers the `account` holds at least one token from
+    /// (including expired tokens). Returns 0 if the account has an ongoing soul transfer.
+    pub fn issuer_diversity(&self, account: AccountId) -> u32 {
+        if self.ongoing_soul_tx.contains_key(&account) {
+            return 0;
+        }
+        let mut count = 0;
+        let mut prev_issuer = 0;
+        // issuer IDs start from 1, so (account, 0, 0) is before all account balance keys.
+        for (key, _) in self
+            .balances
+            .iter_from(balance_key(account.clone(), 0, ClassKey(0)))
+        {
+            if key.owner != account {
+                break;
             }
-            // we emit the event only once the operation is completed and only if some tokens were
-            // transferred
-            if resumed || token_counter > 0 {
-                emit_soul_transfer(&owner, &recipient);
+            if key.issuer_id != prev_issuer {
+                count += 1;
+                prev_issuer = key.issuer_id;
             }
-        } else {
-            let last = &batch[token_counter - 1];
-            self.ongoing_soul_tx.insert(
-                &owner,
-                &IssuerTokenId {
-                    issuer_id: last.0.issuer_id,
-                    token: last.0.class_id, // we reuse IssuerTokenId type here (to not generate new code), but we store class_id instead of token here.
-                },
-            );
         }
+        count
+    }
 
-        Ok((token_counter as u32, completed))
+    /// Returns all token IDs held by `account`, grouped by issuer. Unlike `sbt_tokens_by_owner`,
+    /// it includes expired tokens and works during an ongoing soul transfer, so the authority
+    /// can be used to assess the impact of banning an account.
+    /// If limit is not specified, default is used: MAX_LIMIT. Limit is capped at MAX_LIMIT.
+    pub fn tokens_held(&self, account: AccountId, limit: Option<u32>) -> SBTs {
+        let limit = std::cmp::min(limit.unwrap_or(MAX_LIMIT), MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+
+        let mut resp = SBTs::new();
+        let mut prev_issuer = 0;
+        for (key, token_id) in self
+            .balances
+            .iter_from(balance_key(account.clone(), 0, ClassKey(0)))
+            .take_while(|(key, _)| key.owner == account)
+            .take(limit as usize)
+        {
+            if prev_issuer != key.issuer_id {
+                resp.push((self.issuer_by_id(key.issuer_id), Vec::new()));
+                prev_issuer = key.issuer_id;
+            }
+            resp.last_mut().unwrap().1.push(token_id);
+        }
+        resp
     }
 
-    /// Checks if the `predecessor_account_id` is a human. If yes, then calls, passing the
-    /// provided deposit:
-    ///
-    ///    ctr.function({caller: predecessor_account_id(),
-    ///                 iah_proof: SBTs,
-    ///                 payload: payload})
-    ///
-    /// `payload` must be a JSON string, and it will be passed through the default interface,
-    /// hence it will be JSON deserialized when using SDK.
-    /// Panics if the predecessor is not a human.
-    #[payable]
-    #[handle_result]
-    pub fn is_human_call(
-        &mut self,
-        ctr: AccountId,
-        function: String,
-        payload: String,
-    ) -> Result<Promise, IsHumanCallErr> {
-        let caller = env::predecessor_account_id();
-        let iah_proof = self._is_human(&caller);
-        if iah_proof.is_empty() {
-            return Err(IsHumanCallErr::NotHuman);
-        }
-
-        let args = IsHumanCallbackArgs {
-            caller,
-            iah_proof,
-            payload: &RawValue::from_string(payload).unwrap(),
+    /// Exports the registry ownership records: `(owner, issuer_id, class, token)` tuples in the
+    /// `balances` order (owner, issuer ID, class), starting after the `from_key` cursor (from
+    /// the beginning if not set). Returns the page and the opaque cursor to pass as `from_key`
+    /// to get the next page, or `None` when the end is reached.
+    /// Useful for indexers bootstrapping from a cold contract.
+    /// If limit is not specified, default is used: MAX_LIMIT. Limit is capped at MAX_LIMIT.
+    /// Panics if the `from_key` cursor is malformed.
+    pub fn dump_balances(
+        &self,
+        from_key: Option<String>,
+        limit: Option<u32>,
+    ) -> (Vec<BalanceRecord>, Option<String>) {
+        let limit = std::cmp::min(limit.unwrap_or(MAX_LIMIT), MAX_LIMIT) as usize;
+        require!(limit > 0, "limit must be bigger than 0");
+        let iter: Box<dyn Iterator<Item = (BalanceKey, TokenId)>> = match from_key {
+            None => Box::new(self.balances.iter()),
+            Some(cursor) => match decode_balance_cursor(&cursor) {
+                Some(key) => Box::new(self.balances.iter_from(key)),
+                None => env::panic_str("malformed from_key"),
+            },
         };
-        Ok(Promise::new(ctr).function_call(
-            function,
-            serde_json::to_vec(&args).unwrap(),
-            env::attached_deposit(),
-            env::prepaid_gas() - IS_HUMAN_GAS,
-        ))
+        // we take one more entry to know if there is a next page.
+        let mut entries: Vec<(BalanceKey, TokenId)> = iter.take(limit + 1).collect();
+        let next = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|(key, _)| encode_balance_cursor(key))
+        } else {
+            None
+        };
+        let entries = entries
+            .into_iter()
+            .map(|(key, token)| (key.owner, key.issuer_id, key.class_id.0, token))
+            .collect();
+        (entries, next)
     }
 
-    /// Apps should use this function to ask a user to lock his account for soul transfer.
-    /// This is useful when a dapp relays on user account ID (rather set of potential SBTs)
-    /// being a unique human over a period of time (there is no soul transfer in between).
-    /// Example use cases: voting, staking, games.
-    /// Dapps should make it clear that they extend user lock for a given amount of time.
-    /// Parameters are similar to `is_human_call`:
-    /// * `ctr` and `function`: the contract function we will call, passing the provided deposit,
-    ///   if and only if the caller has a valid humanity proof.
-    ///
-    ///    ctr.function({caller: predecessor_account_id(),
-    ///                 locked_until: time_in_ms_until_when_the_account_is_locked,
-    ///                 iah_proof: SBTs,
-    ///                 payload: payload})
-    ///
-    ///   Note the additional arguments provided to the recipient function call, that are not
-    ///   present in `is_human_call`:
-    ///   - `locked_until`: time in milliseconds (duration) until when the account is locked for soul
-    ///      transfers. It may be bigger than `now + lock_duration` (this is a case when there
-    ///      is already an existing lock with a longer duration).
-    ///   - `iah_proof` will be set to an empty list if `with_proof=false`.
-    /// * `payload`: must be a JSON string, and it will be passed through the default interface.
-    /// * `lock_duration`: duration in milliseconds to extend the predecessor account lock for
-    ///    soul transfers. Can be zero, if no lock is needed.
-    /// * `with_proof`: when false - doesn't send iah_proof (SBTs) to the contract call.
-    /// Emits transfer_lock if the transfer_lock is extended.
-    /// Panics if the predecessor is not a human.
-    #[payable]
-    #[handle_result]
-    pub fn is_human_call_lock(
-        &mut self,
-        ctr: AccountId,
-        function: String,
-        payload: String,
-        lock_duration: u64,
-        with_proof: bool,
-    ) -> Result<Promise, IsHumanCallErr> {
-        let caller = env::predecessor_account_id();
-        let proof = self._is_human(&caller);
-        if proof.is_empty() {
-            return Err(IsHumanCallErr::NotHuman);
+    /// Exports all the registry tokens: iterates issuers in the issuer ID order and the issuer
+    /// tokens in the token ID order, starting after the `cursor` (from the beginning if not
+    /// set). Returns the page of `(issuer, token)` pairs and the opaque cursor to pass to get
+    /// the next page, or `None` when the end is reached.
+    /// NOTE: like `sbt_tokens`, the function scans `limit` token IDs, so a page can have less
+    /// than `limit` tokens (or be empty) when tokens are burned. Expired tokens are included.
+    /// If limit is not specified, default is used: MAX_LIMIT. Limit is capped at MAX_LIMIT.
+    /// Panics if the `cursor` is malformed.
+    pub fn all_tokens(
+        &self,
+        cursor: Option<String>,
+        limit: Option<u32>,
+    ) -> (Vec<(AccountId, Token)>, Option<String>) {
+        let mut limit = std::cmp::min(limit.unwrap_or(MAX_LIMIT), MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let (mut issuer_id, mut token) = match cursor {
+            None => (1, 1),
+            Some(cursor) => match decode_token_cursor(&cursor) {
+                Some((issuer_id, last)) => (issuer_id, last.saturating_add(1)),
+                None => env::panic_str("malformed cursor"),
+            },
+        };
+        let mut resp = Vec::new();
+        // last scanned position
+        let mut last = None;
+        while issuer_id < self.next_issuer_id {
+            let max_id = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+            if let Some(issuer) = self.issuer_id_map.get(&issuer_id) {
+                while token <= max_id {
+                    if limit == 0 {
+                        return (resp, last.map(|(i, t)| encode_token_cursor(i, t)));
+                    }
+                    if let Some(t) = self
+                        .issuer_tokens
+                        .get(&IssuerTokenId::new(issuer_id, token))
+                    {
+                        resp.push((issuer.clone(), t.to_token(issuer_id, token)));
+                    }
+                    last = Some((issuer_id, token));
+                    token += 1;
+                    limit -= 1;
+                }
+            }
+            issuer_id += 1;
+            token = 1;
         }
+        (resp, None)
+    }
 
-        let now = env::block_timestamp_ms();
-        let mut lock = self.transfer_lock.get(&caller).unwrap_or(now);
-        if lock_duration > 0 && lock < now + lock_duration {
-            lock = now + lock_duration;
-            self.transfer_lock.insert(&caller, &lock);
-            events::emit_transfer_lock(caller.clone(), lock)
+    /// Lists banned accounts, starting from the `from_index` (default 0) position of the
+    /// banlist. If limit is not specified, default is used: MAX_LIMIT. Limit is capped at
+    /// MAX_LIMIT.
+    pub fn banned_accounts(&self, from_index: Option<u64>, limit: Option<u32>) -> Vec<AccountId> {
+        let limit = std::cmp::min(limit.unwrap_or(MAX_LIMIT), MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        self.banlist
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// Returns the number of banned accounts.
+    pub fn banned_count(&self) -> u64 {
+        self.banlist.len()
+    }
+
+    /// Returns the ban scope of the account, or None if the account is not banned.
+    pub fn ban_scope(&self, account: AccountId) -> Option<BanScope> {
+        if !self._is_banned(&account) {
+            return None;
         }
+        Some(
+            self.ban_scope
+                .get(&account)
+                .unwrap_or(BanScope::TransferOnly),
+        )
+    }
 
-        let args = IsHumanLockCallbackArgs {
-            caller,
-            locked_until: lock,
-            iah_proof: if with_proof { Some(proof) } else { None },
-            payload: &RawValue::from_string(payload).unwrap(),
-        };
+    /// Returns account status if it was flagged. Returns None if the account was not flagged.
+    pub fn account_flagged(&self, account: AccountId) -> Option<AccountFlag> {
+        self.flagged.get(&account)
+    }
 
-        Ok(Promise::new(ctr).function_call(
-            function,
-            serde_json::to_vec(&args).unwrap(),
-            env::attached_deposit(),
-            env::prepaid_gas() - IS_HUMAN_GAS,
-        ))
+    /// Returns empty list if the account is NOT a human according to the IAH protocol.
+    /// Otherwise returns list of SBTs (identifed by issuer and list of token IDs) proving
+    /// the `account` humanity.
+    /// Optional `grace_ms` (default 0, max `MAX_HUMAN_GRACE_MS`) allows IAH tokens expired
+    /// less than `grace_ms` milliseconds ago to still prove the humanity (eg: renewal in
+    /// progress).
+    pub fn is_human(&self, account: AccountId, grace_ms: Option<u64>) -> SBTs {
+        self._is_human_with_grace(&account, assert_human_grace(grace_ms))
     }
 
-    // NOTE: we are using IssuerTokenId to return Issuer and ClassId. This works as expected
-    // and doesn't create API conflict because this is a crate private function. The reason we
-    // do it is to avoid another struct creation and save the bytes.
-    pub(crate) fn start_transfer_with_continuation(
-        &mut self,
-        owner: &AccountId,
-        recipient: &AccountId,
-        ban_owner: bool,
-    ) -> IssuerTokenId {
-        self.assert_not_banned(recipient);
-        if ban_owner {
-            // we only ban the source account in the soul transfer
-            // insert into banlist and assure the owner is not already banned.
-            require!(
-                self.banlist.insert(owner),
-                "from account is banned. Cannot start the transfer"
-            );
-            Nep393Event::Ban(vec![owner]).emit();
+    /// Returns the first IAH token (issuer, token ID, class) proving the `account` humanity,
+    /// or `None` if the account is not a human (see `is_human`) or has an ongoing soul
+    /// transfer. Useful for UIs to show which credential makes the account a human.
+    pub fn human_proof(&self, account: AccountId) -> Option<(AccountId, TokenId, ClassId)> {
+        if self.ongoing_soul_tx.contains_key(&account) {
+            return None;
         }
+        let (issuer, tokens) = self._is_human(&account).pop()?;
+        let (_, classes) = self.iah_sbts.iter().find(|(i, _)| *i == issuer)?;
+        Some((issuer, tokens[0], classes[0]))
+    }
 
-        IssuerTokenId {
-            issuer_id: 0,
-            token: 0, // NOTE: this is class ID
+    /// Returns the SBTs (issuer and list of token IDs) which satisfied the humanity check
+    /// (the same proof as passed by `is_human_call`), or `None` if the account is not a human
+    /// or has an ongoing soul transfer.
+    pub fn is_human_detailed(&self, account: AccountId) -> Option<SBTs> {
+        if self.ongoing_soul_tx.contains_key(&account) {
+            return None;
+        }
+        let proof = self._is_human(&account);
+        if proof.is_empty() {
+            return None;
         }
+        Some(proof)
     }
 
-    // If it is the first iteration of the soul transfer, bans the source account, otherwise returns the last transfered token
-    fn transfer_continuation(
-        &mut self,
-        from: &AccountId,
-        to: &AccountId,
-        ban_owner: bool,
-    ) -> (bool, IssuerTokenId) {
-        match self.ongoing_soul_tx.get(from) {
-            // starting the process
-            None => (
-                false,
-                self.start_transfer_with_continuation(from, to, ban_owner),
-            ),
-            // resuming sbt_recover process
-            Some(s) => (true, s),
+    /// Returns the soonest expire time (unix time in milliseconds) of the IAH tokens proving
+    /// the `account` humanity (the `is_human` proof), so wallets can warn users before their
+    /// humanity lapses.
+    /// Non expiring proof tokens are skipped. Returns `None` if none of the proof tokens
+    /// expires (humanity doesn't lapse), if the account is not a human or if it has an
+    /// ongoing soul transfer. Use `is_human` to distinguish these cases.
+    pub fn human_expires_at(&self, account: AccountId) -> Option<u64> {
+        if self.ongoing_soul_tx.contains_key(&account) {
+            return None;
+        }
+        let mut expires_at: Option<u64> = None;
+        for (issuer, tokens) in self._is_human(&account) {
+            let issuer_id = self.sbt_issuers.get(&issuer).unwrap();
+            for token in tokens {
+                if let Some(e) = self.get_token(issuer_id, token).metadata.expires_at() {
+                    expires_at = Some(expires_at.map_or(e, |x| x.min(e)));
+                }
+            }
         }
+        expires_at
     }
 
-    // sbt_recover execution with `limit` parameter in
-    // order to facilitate tests.
-    fn _sbt_recover(&mut self, from: AccountId, to: AccountId, limit: usize) -> (u32, bool) {
-        let storage_start = env::storage_usage();
-        let issuer = env::predecessor_account_id();
-        let issuer_id = self.assert_issuer(&issuer);
-        self.assert_not_banned(&to);
-        // get the last transfered token and don't ban the owner.
-        let (resumed, start) = self.transfer_continuation(&from, &to, false);
+    /// Returns `true` if an account is considered human, and `false` otherwise.
+    /// We DO NOT RECOMMEND using this function. You SHOULD use `is_human` instead. Returning
+    /// bool may create wrong practices. Humanity will be a metric, not a true/false.
+    /// Each "client" should have his own criteria and asses the humanity proof
+    /// (e.g. check for KYC SBTs, liveness, ...).
+    pub fn is_human_bool(&self, account: AccountId) -> bool {
+        !self._is_human(&account).is_empty()
+    }
 
-        let mut tokens_recovered = 0;
-        let mut class_ids = Vec::new();
+    /// Returns `true` if the `account` is a human (see `is_human`) and holds a valid (not
+    /// expired) token of the `class` issued by the `issuer`. Returns `false` otherwise, also
+    /// for an unknown issuer or if the account has an ongoing soul transfer.
+    /// Gates "verified human who is also a member" with a single cross contract read.
+    pub fn is_human_with_class(
+        &self,
+        account: AccountId,
+        issuer: AccountId,
+        class: ClassId,
+    ) -> bool {
+        if self.ongoing_soul_tx.contains_key(&account) || self._is_human(&account).is_empty() {
+            return false;
+        }
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return false,
+            Some(i) => i,
+        };
+        let now = env::block_timestamp_ms();
+        self.balances
+            .get(&balance_key(account, issuer_id, ClassKey(class)))
+            .and_then(|token| {
+                self.issuer_tokens
+                    .get(&IssuerTokenId::new(issuer_id, token))
+            })
+            .map_or(false, |t| is_token_valid_at(&t, now))
+    }
 
-        let mut last_token_transfered = BalanceKey {
-            owner: from.clone(),
-            issuer_id,
-            class_id: 0,
+    /// Returns how many of the provided (deduplicated) `accounts` are currently human
+    /// according to `is_human`. Accounts with an ongoing soul transfer are not counted.
+    /// Useful to verify a threshold (e.g. quorum) of distinct humans.
+    /// Panics if more than `MAX_HUMANS_QUERY` accounts are provided.
+    pub fn count_unique_humans(&self, accounts: Vec<AccountId>) -> u32 {
+        require!(
+            accounts.len() <= MAX_HUMANS_QUERY,
+            format!("max {} accounts can be queried", MAX_HUMANS_QUERY)
+        );
+        let accounts: HashSet<AccountId> = accounts.into_iter().collect();
+        accounts
+            .iter()
+            .filter(|a| !self.ongoing_soul_tx.contains_key(a) && !self._is_human(a).is_empty())
+            .count() as u32
+    }
+
+    /// Returns humanity metrics:
+    /// * `total_iah_holders`: cheap estimate based on the supply counters: for each IAH issuer
+    ///   the minimum supply of its required classes, summed over all IAH issuers. Expired tokens
+    ///   are included and accounts human through many IAH issuers are counted many times.
+    /// * `valid_iah_holders`: computed only when an IAH `issuer` is provided, by scanning at
+    ///   most `limit` token IDs of the issuer starting from `from_token` (default 1): counts
+    ///   the token owners whose humanity proof (`is_human`) is provided by that issuer.
+    ///   Summing the results of all pages (see `next_from_token`) of all IAH issuers gives the
+    ///   number of valid humans.
+    /// If limit is not specified, default is used: MAX_LIMIT.
+    /// Panics if the `issuer` is not an IAH issuer.
+    pub fn humanity_stats(
+        &self,
+        issuer: Option<AccountId>,
+        from_token: Option<u64>,
+        limit: Option<u32>,
+    ) -> HumanityStats {
+        let total_iah_holders = self
+            .iah_sbts
+            .iter()
+            .filter_map(|(issuer, classes)| {
+                let issuer_id = self.sbt_issuers.get(issuer)?;
+                classes
+                    .iter()
+                    .map(|c| self.supply_by_class.get(&(issuer_id, *c)).unwrap_or(0))
+                    .min()
+            })
+            .sum();
+        let mut stats = HumanityStats {
+            total_iah_holders,
+            valid_iah_holders: None,
+            next_from_token: None,
+        };
+        let issuer = match issuer {
+            None => return stats,
+            Some(i) => i,
         };
+        let classes = match self.iah_sbts.iter().find(|(i, _)| *i == issuer) {
+            Some((_, classes)) if !classes.is_empty() => classes,
+            _ => env::panic_str("not an IAH issuer"),
+        };
+        let from_token = from_token.unwrap_or(1);
+        require!(from_token > 0, "from_token, if set, must be >= 1");
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        let issuer_id = self.sbt_issuers.get(&issuer).unwrap_or(0);
+        let last = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        let end = std::cmp::min(
+            last.saturating_add(1),
+            from_token.saturating_add(limit as u64),
+        );
 
-        for (key, token) in self
-            .balances
-            .iter_from(balance_key(from.clone(), issuer_id, start.token))
-            .take(limit)
-        {
-            if key.owner != from || key.issuer_id != issuer_id {
-                break;
+        // every holder has exactly one token of the first required class.
+        let mut valid = 0;
+        for token in from_token..end {
+            let t = match self
+                .issuer_tokens
+                .get(&IssuerTokenId::new(issuer_id, token))
+            {
+                Some(t) if t.metadata.class_id() == classes[0] => t,
+                _ => continue,
+            };
+            if self.ongoing_soul_tx.contains_key(&t.owner) {
+                continue;
             }
-            tokens_recovered += 1;
-            let mut t = self.get_token(key.issuer_id, token);
+            if matches!(self._is_human(&t.owner).first(), Some((i, _)) if *i == issuer) {
+                valid += 1;
+            }
+        }
+        stats.valid_iah_holders = Some(valid);
+        if end <= last {
+            stats.next_from_token = Some(end);
+        }
+        stats
+    }
 
-            class_ids.push(t.metadata.class_id());
+    fn _is_human(&self, account: &AccountId) -> SBTs {
+        self._is_human_with_grace(account, 0)
+    }
 
-            t.owner = to.clone();
-            self.issuer_tokens
-                .insert(&IssuerTokenId { issuer_id, token }, &t);
-            last_token_transfered = key;
+    /// Same as `_is_human`, but IAH tokens expired less than `grace_ms` milliseconds ago are
+    /// considered valid.
+    fn _is_human_with_grace(&self, account: &AccountId, grace_ms: u64) -> SBTs {
+        if self.flagged.get(account) == Some(AccountFlag::Blacklisted) || self._is_banned(account) {
+            return vec![];
         }
-
-        // update user balances
-        let mut old_balance_key = balance_key(from.clone(), issuer_id, 0);
-        let mut new_balance_key = balance_key(to.clone(), issuer_id, 0);
-        for class_id in class_ids {
-            old_balance_key.class_id = class_id;
-            let token_id = self.balances.remove(&old_balance_key).unwrap();
-            new_balance_key.class_id = class_id;
-            self.balances.insert(&new_balance_key, &token_id);
+        // the first IAH issuer satisfied by the account provides the proof.
+        for (issuer, classes) in &self.iah_sbts {
+            if let Some(proof) = self.iah_issuer_proof(account, issuer, classes, grace_ms) {
+                return vec![(issuer.clone(), proof)];
+            }
         }
+        vec![]
+    }
 
-        // update supply_by_owner map. We can't do it in the loop above becuse we can't modify
-        // self.balances while iterating over it
-        let supply_key = &(from.clone(), issuer_id);
-        let old_supply_from = self.supply_by_owner.remove(supply_key).unwrap_or(0);
-        if old_supply_from != tokens_recovered {
-            self.supply_by_owner.insert(
-                &(from.clone(), issuer_id),
-                &(old_supply_from - tokens_recovered),
+    /// Returns the `account` tokens of all `classes` of the `issuer`, or None if the account
+    /// doesn't have a valid token (or a token expired less than `grace_ms` ago) of one of the
+    /// classes.
+    fn iah_issuer_proof(
+        &self,
+        account: &AccountId,
+        issuer: &AccountId,
+        classes: &[ClassId],
+        grace_ms: u64,
+    ) -> Option<Vec<TokenId>> {
+        let valid_at = env::block_timestamp_ms().saturating_sub(grace_ms);
+        let mut proof: Vec<TokenId> = Vec::new();
+        // check if user has tokens from all classes
+        for cls in classes {
+            let tokens = self._sbt_tokens_by_owner(
+                account.clone(),
+                Some(issuer.clone()),
+                Some(*cls),
+                Some(1),
+                Some(true),
             );
+            // we need to check class, because the query can return a "next" token if a user
+            // doesn't have the token of requested class.
+            if tokens.is_empty() || tokens[0].1[0].metadata.class != *cls {
+                return None;
+            }
+            if tokens[0].1[0]
+                .metadata
+                .expires_at
+                .map_or(false, |e| e <= valid_at)
+            {
+                return None;
+            }
+            proof.push(tokens[0].1[0].token)
         }
-        let supply_key = &(to.clone(), issuer_id);
-        let old_supply_to = self.supply_by_owner.get(supply_key).unwrap_or(0);
-        self.supply_by_owner
-            .insert(supply_key, &(old_supply_to + tokens_recovered));
+        Some(proof)
+    }
 
-        let completed = tokens_recovered != limit as u64;
-        if completed {
-            if resumed {
-                // insert is happening when we need to continue, so don't need to remove if
-                // the process finishes in the same transaction.
-                self.ongoing_soul_tx.remove(&from);
-            }
-            // we emit the event only once the operation is completed and only if some tokens were
-            // recovered
-            if resumed || tokens_recovered > 0 {
-                // emit Recover event
-                SbtRecover {
-                    issuer: &issuer,
-                    old_owner: &from,
-                    new_owner: &to,
+    /// Returns false if the `issuer` enabled an allowlist for the `class` and the `account`
+    /// is not on it, or if the `issuer` is not registered. Otherwise returns true.
+    pub fn is_class_allowed(&self, issuer: AccountId, class: ClassId, account: AccountId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self._is_class_allowed(issuer_id, class, &account),
+        }
+    }
+
+    /// Pre-flight check for minting a token of the `issuer` `class` to the `owner`.
+    /// Returns all rules which would block the mint: unknown issuer, invalid class, paused
+    /// issuer, banned owner, owner already having a token of the class and the class allowlist.
+    pub fn can_mint(&self, issuer: AccountId, owner: AccountId, class: ClassId) -> CanMintResult {
+        let mut reasons = Vec::new();
+        if class == 0 {
+            reasons.push(MintSkipReason::InvalidClass);
+        }
+        match self.sbt_issuers.get(&issuer) {
+            None => {
+                reasons.push(MintSkipReason::UnknownIssuer);
+                if self.banlist.contains(&owner) {
+                    reasons.push(MintSkipReason::Banned);
                 }
-                .emit();
             }
-        } else {
-            self.ongoing_soul_tx.insert(
-                &from,
-                &IssuerTokenId {
-                    issuer_id: last_token_transfered.issuer_id,
-                    token: last_token_transfered.class_id, // we reuse IssuerTokenId type here (to not generate new code), but we store class_id instead of token here.
-                },
-            );
+            Some(issuer_id) => reasons.extend(self.mint_blockers(issuer_id, &owner, class)),
         }
-        // storage check
-        // we are using checked_sub, since the storage can decrease and we are running of risk of underflow
-        let storage_usage = env::storage_usage();
-        if storage_usage > storage_start {
-            let required_deposit =
-                (storage_usage - storage_start) as u128 * env::storage_byte_cost();
-            require!(
-                env::attached_deposit() >= required_deposit,
-                format!(
-                    "not enough NEAR storage depost, required: {}",
-                    required_deposit
-                )
-            );
+        CanMintResult {
+            allowed: reasons.is_empty(),
+            reasons,
         }
-        (tokens_recovered as u32, completed)
     }
 
-    /// Method to burn all caller tokens (from all issuers).
-    /// The method must be called repeatedly until true is returned.
-    /// Not all tokens may be burned in a single call due to the gas limitation - in that case
-    /// `false` is returned.
-    /// The burn event is emitted for all the tokens burned.
-    pub fn sbt_burn_all(&mut self) -> bool {
-        self._sbt_burn_all(25)
+    /// Returns the reasons (in the check order) why a token of the `class` can't be minted
+    /// to the `owner`: paused issuer, banned owner, duplicated class, not allowlisted owner.
+    fn mint_blockers(
+        &self,
+        issuer_id: IssuerId,
+        owner: &AccountId,
+        class: ClassId,
+    ) -> Vec<MintSkipReason> {
+        let mut reasons = Vec::new();
+        if self._is_issuer_paused(issuer_id) {
+            reasons.push(MintSkipReason::IssuerPaused);
+        }
+        if self.banlist.contains(owner) {
+            reasons.push(MintSkipReason::Banned);
+        }
+        if self
+            .balances
+            .contains_key(&balance_key(owner.clone(), issuer_id, ClassKey(class)))
+        {
+            reasons.push(MintSkipReason::DuplicateClass);
+        }
+        if !self._is_class_allowed(issuer_id, class, owner) {
+            reasons.push(MintSkipReason::NotAllowed);
+        }
+        reasons
     }
 
-    /// Allows user to burn any of his tokens.
-    /// The burn event is emitted for all  tokens burned.
-    /// Panics if user has ongoing soul transfer or ongoing recovery or doesn't own a listed
-    /// token.
-    pub fn sbt_burn(
-        &mut self,
-        issuer: AccountId,
-        tokens: Vec<TokenId>,
-        #[allow(unused_variables)] memo: Option<String>,
-    ) {
-        let owner = env::predecessor_account_id();
-        require!(
-            !self.ongoing_soul_tx.contains_key(&owner),
-            "can't burn tokens while in soul_transfer"
-        );
-
-        let issuer_id = self.assert_issuer(&issuer);
-        let token_len = tokens.len() as u64;
-        let mut token_ids = HashSet::new();
-        for tid in tokens.iter() {
-            require!(
-                !token_ids.contains(tid),
-                format!("duplicated token_id in tokens: {}", tid)
-            );
-            token_ids.insert(tid);
-
-            let ct_key = &IssuerTokenId {
-                issuer_id,
-                token: *tid,
-            };
-            let t = self
-                .issuer_tokens
-                .get(ct_key)
-                .unwrap_or_else(|| panic!("tokenID={} not found", tid));
-            require!(
-                t.owner == owner,
-                &format!("not an owner of tokenID={}", tid)
-            );
-
-            self.issuer_tokens.remove(ct_key);
-            let class_id = t.metadata.v1().class;
-            self.balances
-                .remove(&balance_key(owner.clone(), issuer_id, class_id));
+    fn _is_class_allowed(&self, issuer_id: IssuerId, class: ClassId, account: &AccountId) -> bool {
+        !self.allowlisted_classes.contains_key(&(issuer_id, class))
+            || self
+                .class_allowlist
+                .contains_key(&(issuer_id, class, account.clone()))
+    }
 
-            // update supply by class
-            let key = (issuer_id, class_id);
-            let mut supply = self.supply_by_class.get(&key).unwrap();
-            supply -= 1;
-            self.supply_by_class.insert(&key, &supply);
+    /// Returns true if tokens of the `issuer` `class` can be transferred using `sbt_transfer`.
+    pub fn is_class_transferable(&self, issuer: AccountId, class: ClassId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self.transferable_classes.contains_key(&(issuer_id, class)),
         }
-
-        // update supply by owner
-        let key = (owner, issuer_id);
-        let mut supply = self.supply_by_owner.get(&key).unwrap();
-        supply -= token_len;
-        self.supply_by_owner.insert(&key, &supply);
-
-        // update total supply by issuer
-        let mut supply = self.supply_by_issuer.get(&issuer_id).unwrap();
-        supply -= token_len;
-        self.supply_by_issuer.insert(&issuer_id, &supply);
-
-        SbtTokensEvent { issuer, tokens }.emit_burn();
     }
 
-    //
-    // Authority
-    //
+    /// Returns true if tokens of the `issuer` `class` are pinned: they are not moved by
+    /// `sbt_soul_transfer`, see `LockedPolicy`.
+    pub fn is_class_pinned(&self, issuer: AccountId, class: ClassId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self.pinned_classes.contains_key(&(issuer_id, class)),
+        }
+    }
 
-    /// returns false if the `issuer` contract was already registered.
-    pub fn admin_add_sbt_issuer(&mut self, issuer: AccountId) -> bool {
-        self.assert_authority();
-        self._add_sbt_issuer(&issuer)
+    /// Returns true if the `issuer` was paused by the authority, see `admin_pause_issuer`.
+    pub fn is_issuer_paused(&self, issuer: AccountId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self._is_issuer_paused(issuer_id),
+        }
     }
 
-    pub fn change_admin(&mut self, new_admin: AccountId) {
-        self.assert_authority();
-        self.authority = new_admin;
+    /// The issuer pause check shared by the mint and renew methods, `can_mint` and
+    /// `is_issuer_paused`.
+    fn _is_issuer_paused(&self, issuer_id: IssuerId) -> bool {
+        self.paused_issuers.contains_key(&issuer_id)
     }
 
-    pub fn admin_set_authorized_flaggers(&mut self, authorized_flaggers: Vec<AccountId>) {
-        self.assert_authority();
-        self.authorized_flaggers.set(&authorized_flaggers);
+    /// Returns true if the `issuer` requires the owner consent to revoke (without burning) the
+    /// owner tokens with `sbt_revoke_by_owner`.
+    pub fn is_revocation_consent_required(&self, issuer: AccountId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self.revocation_consent_required.contains_key(&issuer_id),
+        }
     }
 
-    /// Returns true if account was added. Returns false if account was already authorized.
-    pub fn admin_add_authorized_flagger(&mut self, account: AccountId) -> bool {
-        self.assert_authority();
-        match self.authorized_flaggers.get() {
-            None => {
-                self.authorized_flaggers.set(&vec![account]);
-                true
-            }
-            Some(mut a) => {
-                if a.contains(&account) {
-                    false
-                } else {
-                    a.push(account);
-                    self.authorized_flaggers.set(&a);
-                    true
-                }
+    /// Returns the number of `owner` tokens issued by the `issuer`, including expired ones:
+    /// the amount of tokens a burning `sbt_revoke_by_owner` would affect (possibly over
+    /// multiple calls). A revoke without burning only affects the non expired tokens.
+    /// Returns 0 if the `issuer` is not registered.
+    pub fn revoke_by_owner_preview(&self, owner: AccountId, issuer: AccountId) -> u64 {
+        if self.sbt_issuers.get(&issuer).is_none() {
+            return 0;
+        }
+        let mut count = 0;
+        let mut from_class = None;
+        loop {
+            let tokens = match self
+                ._sbt_tokens_by_owner(
+                    owner.clone(),
+                    Some(issuer.clone()),
+                    from_class,
+                    None,
+                    Some(true),
+                )
+                .pop()
+            {
+                None => break,
+                Some((_, tokens)) => tokens,
+            };
+            count += tokens.len() as u64;
+            if tokens.len() < MAX_LIMIT as usize {
+                break;
             }
+            from_class = tokens.last().map(|t| t.metadata.class + 1);
         }
+        count
     }
 
-    /// Sets a flag for every account in the `accounts` list, overwriting if needed.
-    /// Panics if a caller is not flagged.
-    /// Panics if any of the account is blacklisted.
-    pub fn admin_flag_accounts(
-        &mut self,
-        flag: AccountFlag,
-        accounts: Vec<AccountId>,
-        #[allow(unused_variables)] memo: String,
-    ) {
-        self.assert_authorized_flagger();
-        for a in &accounts {
-            self.assert_not_banned(a);
-            self.flagged.insert(a, &flag);
+    /// Returns true if the `account` is approved to renew the `issuer` tokens, see
+    /// `approve_renewer`.
+    pub fn is_renewer(&self, issuer: AccountId, account: AccountId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self.renewers.contains_key(&(issuer_id, account)),
         }
-        events::emit_iah_flag_accounts(flag, accounts);
     }
 
-    /// removes flag from the provided account list.
-    /// Panics if an account is not currently flagged.
-    pub fn admin_unflag_accounts(
-        &mut self,
-        accounts: Vec<AccountId>,
-        #[allow(unused_variables)] memo: String,
-    ) {
-        self.assert_authorized_flagger();
-        for a in &accounts {
-            require!(self.flagged.remove(a).is_some());
+    /// Returns true if the `owner` allowed the `issuer` to revoke his tokens, see
+    /// `allow_revocation`.
+    pub fn has_revocation_consent(&self, owner: AccountId, issuer: AccountId) -> bool {
+        match self.sbt_issuers.get(&issuer) {
+            None => false,
+            Some(issuer_id) => self.revocation_consents.contains_key(&(owner, issuer_id)),
         }
-        events::emit_iah_unflag_accounts(accounts);
     }
 
-    //
-    // Internal
-    //
+    /// Returns the last `MAX_TOKEN_HISTORY` updates (reference, class, expire time, recovery)
+    /// of the token, oldest first. Returns an empty list for unknown tokens and tokens
+    /// without updates.
+    pub fn sbt_history(&self, issuer: AccountId, token: TokenId) -> Vec<TokenChange> {
+        match self.sbt_issuers.get(&issuer) {
+            None => vec![],
+            Some(issuer_id) => self
+                .token_history
+                .get(&IssuerTokenId::new(issuer_id, token))
+                .unwrap_or_default(),
+        }
+    }
 
-    /// Queries a given token. Panics if token doesn't exist
-    pub(crate) fn get_token(&self, issuer_id: IssuerId, token: TokenId) -> TokenData {
-        self.issuer_tokens
-            .get(&IssuerTokenId { issuer_id, token })
-            .unwrap_or_else(|| panic!("token {} not found", token))
+    /// Returns the `issuer` default expiry policy, see `set_default_expiry_policy`.
+    /// Returns None if the issuer is not registered.
+    pub fn default_expiry_policy(&self, issuer: AccountId) -> Option<ExpiryPolicy> {
+        let issuer_id = self.sbt_issuers.get(&issuer)?;
+        Some(
+            self.expiry_policy
+                .get(&issuer_id)
+                .unwrap_or(ExpiryPolicy::NeverExpire),
+        )
     }
 
-    /// updates the internal token counter based on how many tokens we want to mint (num), and
-    /// returns the first valid TokenId for newly minted tokens.
-    pub(crate) fn next_token_id(&mut self, issuer_id: IssuerId, num: u64) -> TokenId {
-        let tid = self.next_token_ids.get(&issuer_id).unwrap_or(0);
-        self.next_token_ids.insert(&issuer_id, &(tid + num));
-        tid + 1
+    /// Returns the `event_seq` of the last emitted Mint, Recover, Renew, Revoke, Burn or
+    /// TokenReference event.
+    pub fn last_event_seq(&self) -> u64 {
+        self.event_seq
     }
 
-    #[inline]
-    pub(crate) fn assert_authorized_flagger(&self) {
-        let caller = env::predecessor_account_id();
-        let a = self.authorized_flaggers.get();
-        if a.is_none() || !a.unwrap().contains(&caller) {
-            env::panic_str("not authorized");
-        }
+    /// Returns the number of distinct accounts holding at least one token (including
+    /// expired and revoked, but not burned tokens).
+    /// Panics during the migration backfill (see `admin_migration_backfill`).
+    pub fn total_souls(&self) -> u64 {
+        self.assert_backfilled();
+        self.total_souls
     }
 
-    #[inline]
-    pub(crate) fn assert_not_banned(&self, owner: &AccountId) {
-        require!(
-            !self.banlist.contains(owner),
-            format!("account {} is banned", owner)
-        );
+    /// Returns the live registry configuration.
+    pub fn config(&self) -> RegistryConfig {
+        RegistryConfig {
+            authority: self.authority.clone(),
+            authorized_flaggers: self.authorized_flaggers.get().unwrap_or_default(),
+            iah_sbts: self.iah_sbts.clone(),
+            soul_tx_batch: self.soul_tx_batch,
+            max_query_limit: MAX_LIMIT,
+        }
     }
 
-    /// note: use issuer_id() if you need issuer_id
-    pub(crate) fn assert_issuer(&self, issuer: &AccountId) -> IssuerId {
-        // TODO: use Result rather than panic
-        self.sbt_issuers
-            .get(issuer)
-            .expect("must be called by a registered SBT Issuer")
+    /// Returns the registry contract storage usage in bytes.
+    pub fn storage_usage_bytes(&self) -> u64 {
+        env::storage_usage()
     }
 
-    pub(crate) fn issuer_by_id(&self, id: IssuerId) -> AccountId {
-        self.issuer_id_map
-            .get(&id)
-            .expect("internal error: inconsistent sbt issuer map")
+    /// Returns the NEAR amount (in yoctoNEAR) staked to cover the registry storage usage.
+    pub fn storage_balance(&self) -> U128 {
+        U128(env::storage_usage() as u128 * env::storage_byte_cost())
     }
 
-    pub(crate) fn assert_authority(&self) {
-        require!(
-            self.authority == env::predecessor_account_id(),
-            "not an admin"
-        )
+    pub fn get_authority(self) -> AccountId {
+        self.authority
     }
 
-    fn _add_sbt_issuer(&mut self, issuer: &AccountId) -> bool {
-        if self.sbt_issuers.get(issuer).is_some() {
-            return false;
-        }
-        self.sbt_issuers.insert(issuer, &self.next_issuer_id);
-        self.issuer_id_map.insert(&self.next_issuer_id, issuer);
-        self.next_issuer_id += 1;
-        true
+    pub fn authorized_flaggers(self) -> Vec<AccountId> {
+        self.authorized_flaggers.get().unwrap_or_default()
     }
 
-    fn _sbt_renew(&mut self, issuer: AccountId, tokens: Vec<TokenId>, expires_at: u64) {
-        let issuer_id = self.assert_issuer(&issuer);
-        for token in &tokens {
-            let token = *token;
-            let mut t = self.get_token(issuer_id, token);
-            self.assert_not_banned(&t.owner);
-            let mut m = t.metadata.v1();
-            m.expires_at = Some(expires_at);
-            t.metadata = m.into();
-            self.issuer_tokens
-                .insert(&IssuerTokenId { issuer_id, token }, &t);
-        }
-        SbtTokensEvent { issuer, tokens }.emit_renew();
+    /// Returns list of contracts notified when a soul transfer completes.
+    pub fn soul_tx_hooks(&self) -> Vec<AccountId> {
+        self.soul_tx_hooks.get().unwrap_or_default()
     }
 
-    fn _sbt_mint(
+    //
+    // Transactions
+    //
+
+    /// sbt_mint_iah is a wrapper around `sbt_mint` and `is_human`. It mints SBTs only when
+    /// all recipients are humans. Panics if one of the recipients is not a human.
+    #[payable]
+    pub fn sbt_mint_iah(
         &mut self,
-        issuer: &AccountId,
         token_spec: Vec<(AccountId, Vec<TokenMetadata>)>,
     ) -> Vec<TokenId> {
-        let storage_start = env::storage_usage();
-        let storage_deposit = env::attached_deposit();
+        let issuer = &env::predecessor_account_id();
+        for ts in &token_spec {
+            require!(
+                !self._is_human(&ts.0).is_empty(),
+                format!("{} is not a human", &ts.0)
+            );
+        }
+        self._sbt_mint(issuer, token_spec)
+    }
 
+    /// Same as `sbt_mint`, but mints the tokens at the explicit token IDs assigned by the
+    /// issuer (eg: a deterministic ID scheme of an off chain system): `token_spec_with_ids`
+    /// is a list of (token ID, owner, metadata) triples. The issuer token ID counter is
+    /// advanced to the biggest ID, so the next `sbt_mint` continues after it.
+    /// Token IDs must be unique and bigger than all the issuer token IDs assigned so far
+    /// (also by `sbt_mint`), so a token ID is never reused, even if the token was burned.
+    /// NOTE: skipped IDs create gaps which can't be filled later, and the `sbt_tokens` paging
+    /// (scanning token IDs) returns empty pages for big gaps.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover the
+    /// registry storage cost. Emits `Mint` event.
+    #[payable]
+    pub fn sbt_mint_at(
+        &mut self,
+        token_spec_with_ids: Vec<(TokenId, AccountId, TokenMetadata)>,
+    ) -> Vec<TokenId> {
+        let issuer = &env::predecessor_account_id();
         let issuer_id = self.assert_issuer(issuer);
-        let mut num_tokens = 0;
-        for el in token_spec.iter() {
-            num_tokens += el.1.len() as u64;
+        self.assert_issuer_not_paused(issuer_id);
+        let last = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        let mut max_id = last;
+        let mut ids = HashSet::new();
+        for (token, _, _) in &token_spec_with_ids {
+            require!(
+                *token > last && ids.insert(*token),
+                format!("token ID {} is already used or reserved", token)
+            );
+            max_id = std::cmp::max(max_id, *token);
         }
-        let mut token = self.next_token_id(issuer_id, num_tokens);
-        let ret_token_ids = (token..token + num_tokens).collect();
-        let mut supply_by_class = HashMap::new();
-        let mut per_recipient: HashMap<AccountId, Vec<TokenId>> = HashMap::new();
-        let now = env::block_timestamp_ms();
+        self.next_token_ids.insert(&issuer_id, &max_id);
+        let token_spec = token_spec_with_ids
+            .into_iter()
+            .map(|(token, owner, metadata)| (owner, vec![(token, metadata)]))
+            .collect();
+        self.mint_tokens(issuer, issuer_id, token_spec)
+    }
 
+    /// Same as `sbt_mint`, but instead of panicking, skips tokens which can't be minted:
+    /// tokens for banned recipients, duplicated classes (the recipient already has a token
+    /// of the class or the class is repeated in the batch) and allowlisted classes for not
+    /// allowed recipients.
+    /// Returns outcome for every token metadata, in the `token_spec` order.
+    /// Panics on invalid metadata (zero class or invalid reference).
+    /// The `Mint` event is emitted only if at least one token was minted.
+    #[payable]
+    pub fn sbt_mint_report(
+        &mut self,
+        token_spec: Vec<(AccountId, Vec<TokenMetadata>)>,
+    ) -> Vec<MintOutcome> {
+        let issuer = &env::predecessor_account_id();
+        let issuer_id = self.assert_issuer(issuer);
+        self.assert_issuer_not_paused(issuer_id);
+        let mut outcomes = Vec::new();
+        let mut to_mint = Vec::new();
+        let mut in_batch = HashSet::new();
         for (owner, metadatas) in token_spec {
-            // no need to check ongoing_soult_tx, because it will automatically ban the source account
-            self.assert_not_banned(&owner);
-
-            let recipient_tokens = per_recipient.entry(owner.clone()).or_default();
-            let metadatas_len = metadatas.len();
-
-            for mut metadata in metadatas {
+            let mut owner_metadatas = Vec::new();
+            for metadata in metadatas {
                 require!(metadata.class > 0, "Class must be > 0");
-                if metadata.issued_at.is_none() {
-                    metadata.issued_at = Some(now);
-                }
-                let prev = self.balances.insert(
-                    &balance_key(owner.clone(), issuer_id, metadata.class),
-                    &token,
-                );
-                require!(
-                    prev.is_none(),
-                    format! {"{} already has SBT of class {}", owner, metadata.class}
+                Self::assert_metadata_size(&metadata);
+                self.assert_reference_hash(
+                    issuer_id,
+                    &metadata.reference,
+                    &metadata.reference_hash,
                 );
-
-                // update supply by class
-                match supply_by_class.get_mut(&metadata.class) {
+                let reason = self
+                    .mint_blockers(issuer_id, &owner, metadata.class)
+                    .into_iter()
+                    .next()
+                    .or_else(|| {
+                        (!in_batch.insert((owner.clone(), metadata.class)))
+                            .then_some(MintSkipReason::DuplicateClass)
+                    });
+                match reason {
+                    Some(r) => outcomes.push(MintOutcome::Skipped(r)),
                     None => {
-                        supply_by_class.insert(metadata.class, 1);
+                        // token ID is set once the tokens are minted
+                        outcomes.push(MintOutcome::Minted(0));
+                        owner_metadatas.push(metadata);
                     }
-                    Some(s) => *s += 1,
-                };
-
-                self.issuer_tokens.insert(
-                    &IssuerTokenId { issuer_id, token },
-                    &TokenData {
-                        owner: owner.clone(),
-                        metadata: metadata.into(),
-                    },
-                );
-                recipient_tokens.push(token);
-
-                token += 1;
+                }
+            }
+            if !owner_metadatas.is_empty() {
+                to_mint.push((owner, owner_metadatas));
             }
-
-            // update supply by owner
-            let skey = (owner, issuer_id);
-            let sowner = self.supply_by_owner.get(&skey).unwrap_or(0) + metadatas_len as u64;
-            self.supply_by_owner.insert(&skey, &sowner);
         }
-
-        for (cls, new_supply) in supply_by_class {
-            let key = (issuer_id, cls);
-            let s = self.supply_by_class.get(&key).unwrap_or(0) + new_supply;
-            self.supply_by_class.insert(&key, &s);
+        if to_mint.is_empty() {
+            return outcomes;
         }
 
-        let new_supply = self.supply_by_issuer.get(&issuer_id).unwrap_or(0) + num_tokens;
-        self.supply_by_issuer.insert(&issuer_id, &new_supply);
-
-        let mut minted: Vec<(&AccountId, &Vec<TokenId>)> = per_recipient.iter().collect();
-        minted.sort_by(|a, b| a.0.cmp(b.0));
-        SbtMint {
-            issuer,
-            tokens: minted,
+        let mut minted = self._sbt_mint(issuer, to_mint).into_iter();
+        for o in outcomes.iter_mut() {
+            if let MintOutcome::Minted(t) = o {
+                *t = minted.next().unwrap();
+            }
         }
-        .emit();
+        outcomes
+    }
 
-        let required_deposit =
-            (env::storage_usage() - storage_start) as u128 * env::storage_byte_cost();
-        require!(
-            storage_deposit >= required_deposit,
-            format!(
-                "not enough NEAR storage deposit, required: {}",
-                required_deposit
-            )
-        );
+    /// Transfers atomically all SBT tokens from one account to another account.
+    /// The caller must be an SBT holder and the `recipient` must not be a banned account.
+    /// Token IDs are preserved: only the token owner changes.
+    /// Transfers the account flag from the owner to the recipient.
+    /// Fails when:
+    /// + `recipient` is banned;
+    /// + there is a potential conflict between the caller's and recipient's flag,
+    ///   specifically when one account is `Blacklisted` and the other is `Verified`;
+    /// + `recipient` is the caller;
+    /// Bans the caller account.
+    /// Emits:
+    /// + `Ban` event for the caller at the beginning of the process.
+    /// + `SoulTransfer` event only once all the tokens from the caller were transferred
+    ///    and at least one token was transferred (caller had at least 1 sbt).
+    /// Returns the amount of tokens transferred and a boolean: `true` if the whole
+    /// process has finished, `false` when the process has not finished and should be
+    /// continued by a subsequent call.
+    /// + User must keep calling the `sbt_soul_transfer` until `true` is returned.
+    /// + If caller does not have any tokens, nothing will be transfered, the caller
+    ///   will be banned and `Ban` event will be emitted.
+    /// Tokens of pinned classes (see `set_class_pinned`) are not transferred: depending on the
+    /// `locked_policy` (default `Skip`) they are left in the caller account or burned (emitting
+    /// `Burn` event). The `SoulTransfer` event reports the amount of `transferred`, `skipped`
+    /// and `burned` tokens. The returned amount counts only the transferred tokens.
+    /// See https://github.com/near/NEPs/pull/393 for more details and rationality about
+    /// soul transfer.
+    #[payable]
+    #[handle_result]
+    pub fn sbt_soul_transfer(
+        &mut self,
+        recipient: AccountId,
+        #[allow(unused_variables)] memo: Option<String>,
+        locked_policy: Option<LockedPolicy>,
+    ) -> Result<(u32, bool), SoulTransferErr> {
+        self._sbt_soul_transfer(
+            recipient,
+            self.soul_tx_batch as usize,
+            locked_policy.unwrap_or_default(),
+        )
+    }
 
-        ret_token_ids
+    pub(crate) fn _transfer_flag(&mut self, from: &AccountId, recipient: &AccountId) {
+        if let Some(flag_from) = self.flagged.get(from) {
+            if let Some(flag_to) = self.flagged.get(recipient) {
+                require!(
+                    flag_from == flag_to,
+                    "can't transfer soul when there is a flag conflict"
+                )
+            } else {
+                self.flagged.insert(recipient, &flag_from);
+            }
+        }
     }
 
-    /// Method to help parametrize the sbt_burn_all.
-    /// limit indicates the number of tokens that will be burned in one call
-    pub(crate) fn _sbt_burn_all(&mut self, limit: u32) -> bool {
+    // execution of the sbt_soul_transfer in this function to parametrize `max_updates` in
+    // order to facilitate tests.
+    #[handle_result]
+    pub(crate) fn _sbt_soul_transfer(
+        &mut self,
+        recipient: AccountId,
+        limit: usize,
+        locked_policy: LockedPolicy,
+    ) -> Result<(u32, bool), SoulTransferErr> {
         let owner = env::predecessor_account_id();
-        require!(
-            !self.ongoing_soul_tx.contains_key(&owner),
-            "can't burn tokens while in soul_transfer"
-        );
-        let mut tokens_burned: u32 = 0;
+        if owner == recipient {
+            return Err(SoulTransferErr::SelfTransfer);
+        }
+        let transfer_lock = self.transfer_lock.get(&owner).unwrap_or(0);
+        if transfer_lock >= env::block_timestamp_ms() {
+            return Err(SoulTransferErr::TransferLocked);
+        }
+        if self.ongoing_is_human_calls.contains_key(&owner) {
+            return Err(SoulTransferErr::OngoingIsHumanCall);
+        }
 
-        let issuer_token_pair_vec =
-            self.sbt_tokens_by_owner(owner.clone(), None, None, Some(limit), Some(true));
-        for (issuer, tokens) in issuer_token_pair_vec.iter() {
-            let mut token_ids = Vec::new();
-            let issuer_id = self.assert_issuer(issuer);
-            let mut tokens_burned_per_issuer: u64 = 0;
-            for t in tokens.iter() {
-                token_ids.push(t.token);
-                self.issuer_tokens.remove(&IssuerTokenId {
-                    issuer_id,
-                    token: t.token,
-                });
-                let class_id = t.metadata.class;
-                self.balances
-                    .remove(&balance_key(owner.clone(), issuer_id, class_id));
+        let (resumed, start) = self.transfer_continuation(&owner, &recipient, true);
+        if !resumed {
+            self._transfer_flag(&owner, &recipient);
+        }
 
-                // update supply by class
-                let key = (issuer_id, class_id);
-                let mut supply = self.supply_by_class.get(&key).unwrap();
-                supply -= 1;
-                self.supply_by_class.insert(&key, &supply);
-                tokens_burned_per_issuer += 1;
-                tokens_burned += 1;
-                if tokens_burned >= limit {
-                    break;
-                }
+        let batch: Vec<(BalanceKey, TokenId)> = self
+            .balances
+            .iter_from(start.balance_key(owner.clone()))
+            .take(limit)
+            .collect();
+
+        let mut key_new = BalanceKey {
+            owner: recipient.clone(),
+            issuer_id: 0,
+            class_id: ClassKey(0),
+        };
+        // outcome of the tokens processed in this batch
+        let mut batch_counts = SoulTransferCounts::default();
+        // tokens moved and burned from the current issuer
+        let (mut moved, mut burned) = (0, 0);
+        let mut burned_tokens: Vec<TokenId> = Vec::new();
+        let mut prev_issuer: IssuerId = 0;
+        let mut token_counter = 0;
+        for (key, token_id) in &batch {
+            if key.owner != owner {
+                break;
             }
+            token_counter += 1;
 
-            // update supply by owner
-            let key = (owner.clone(), issuer_id);
-            let mut supply = self.supply_by_owner.get(&key).unwrap();
-            supply -= tokens_burned_per_issuer;
-            self.supply_by_owner.insert(&key, &supply);
+            if prev_issuer != key.issuer_id {
+                self.move_owner_supply(&owner, &recipient, prev_issuer, moved, burned);
+                self.emit_pinned_burn(prev_issuer, burned_tokens);
+                (moved, burned, burned_tokens) = (0, 0, Vec::new());
+                prev_issuer = key.issuer_id;
+            }
 
-            // update total supply by issuer
-            let mut supply = self.supply_by_issuer.get(&issuer_id).unwrap();
-            supply -= tokens_burned_per_issuer;
-            self.supply_by_issuer.insert(&issuer_id, &supply);
+            let i_key = IssuerTokenId::new(key.issuer_id, *token_id);
+            if self
+                .pinned_classes
+                .contains_key(&(key.issuer_id, key.class_id.0))
+            {
+                match locked_policy {
+                    LockedPolicy::Skip => batch_counts.skipped += 1,
+                    LockedPolicy::Burn => {
+                        self.balances.remove(key);
+                        self.issuer_tokens.remove(&i_key);
+                        self.token_history.remove(&i_key);
+                        let supply_class = self
+                            .supply_by_class
+                            .get(&(key.issuer_id, key.class_id.0))
+                            .unwrap_or(1);
+                        self.set_class_supply(key.issuer_id, key.class_id.0, supply_class - 1);
+                        let supply_issuer = self.supply_by_issuer.get(&key.issuer_id).unwrap_or(1);
+                        self.supply_by_issuer
+                            .insert(&key.issuer_id, &(supply_issuer - 1));
+                        burned_tokens.push(*token_id);
+                        burned += 1;
+                        batch_counts.burned += 1;
+                    }
+                }
+                continue;
+            }
 
-            SbtTokensEvent {
-                issuer: issuer.to_owned(),
-                tokens: token_ids.clone(),
+            key_new.issuer_id = key.issuer_id;
+            key_new.class_id = key.class_id;
+            // One use can have max one toke of a (issuer, class) pair. We don't allow users
+            // to overwrite each other tokens. Recipient or sender should firstly burn his SBT
+            // to avoid conflicts.
+            if self.balances.insert(&key_new, token_id).is_some() {
+                env::panic_str(&format!(
+                    "recipient already has an SBT of issuer={}, class={}; source_token_id={}",
+                    self.issuer_by_id(key.issuer_id),
+                    key.class_id.0,
+                    token_id
+                ));
             }
-            .emit_burn();
-            if tokens_burned >= limit {
-                return false;
+            self.balances.remove(key);
+
+            let mut td = self.issuer_tokens.get(&i_key).unwrap();
+            td.owner = recipient.clone();
+            self.issuer_tokens.insert(&i_key, &td);
+            moved += 1;
+            batch_counts.transferred += 1;
+        }
+        self.move_owner_supply(&owner, &recipient, prev_issuer, moved, burned);
+        self.emit_pinned_burn(prev_issuer, burned_tokens);
+        self.sub_owner_total(
+            &owner,
+            (batch_counts.transferred + batch_counts.burned) as u64,
+        );
+        self.add_owner_total(&recipient, batch_counts.transferred as u64);
+
+        let transferred = batch_counts.transferred;
+        let mut counts = batch_counts;
+        if resumed {
+            if let Some(c) = self.soul_tx_counts.get(&owner) {
+                counts.transferred += c.transferred;
+                counts.skipped += c.skipped;
+                counts.burned += c.burned;
             }
         }
-        true
-    }
-
-    /// Helper function for `sbt_revoke_by_owner`
-    fn sbt_token_ids_by_owner(
-        &self,
-        account: AccountId,
-        issuer_id: u32,
-        limit: u32,
-    ) -> Vec<(TokenId, ClassId)> {
-        let first_key = balance_key(account.clone(), issuer_id, 0);
 
-        assert!(limit > 0, "limit must be bigger than 0");
+        let completed = token_counter != limit;
+        if completed {
+            if resumed {
+                // insert is happening when we need to continue, so don't need to remove if
+                // the process finishes in the same transaction.
+                self.ongoing_soul_tx.remove(&owner);
+                self.soul_tx_counts.remove(&owner);
+            }
+            self.soul_successors.insert(&owner, &recipient);
+            self.soul_predecessors.insert(&recipient, &owner);
+            // we emit the event only once the operation is completed and only if some tokens were
+            // processed
+            if resumed || token_counter > 0 {
+                SoulTransfer {
+                    from: &owner,
+                    to: &recipient,
+                    transferred: Some(counts.transferred),
+                    skipped: Some(counts.skipped),
+                    burned: Some(counts.burned),
+                }
+                .emit();
+                self.notify_soul_tx_hooks(&owner, &recipient);
+            }
+        } else {
+            if !resumed {
+                events::emit_soul_transfer_started(&owner, &recipient);
+            }
+            let last = &batch[token_counter - 1];
+            self.ongoing_soul_tx
+                .insert(&owner, &SoulTxCursor::from(&last.0));
+            self.soul_tx_counts.insert(&owner, &counts);
+        }
 
-        self.balances
-            .iter_from(first_key)
-            .take_while(|(key, _)| key.owner == account && key.issuer_id == issuer_id)
-            .map(|(key, token_id)| (token_id, key.class_id))
-            .take(limit as usize)
-            .collect()
+        Ok((transferred, completed))
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::ops::Mul;
-
-    use cost::MILI_NEAR;
-    use near_sdk::json_types::Base64VecU8;
-    use near_sdk::test_utils::{self, VMContextBuilder};
-    use near_sdk::{testing_env, Balance, Gas, VMContext};
-    use sbt::*;
 
-    use pretty_assertions::assert_eq;
-
-    use super::*;
+    /// Checks if the `predecessor_account_id` is a human. If yes, then calls, passing the
+    /// provided deposit:
+    ///
+    ///    ctr.function({caller: predecessor_account_id(),
+    ///                 iah_proof: SBTs,
+    ///                 payload: payload})
+    ///
+    /// `payload` must be a JSON string, and it will be passed through the default interface,
+    /// hence it will be JSON deserialized when using SDK.
+    /// The registry keeps `IS_HUMAN_GAS` for itself and `IS_HUMAN_CALLBACK_GAS` for the
+    /// `on_is_human_call` callback, and forwards the remaining prepaid gas.
+    /// Fails early with `NotEnoughGas` if less than `MIN_IS_HUMAN_CALL_GAS` would be forwarded.
+    /// Until the callback is executed, soul transfers of the caller are blocked, so the
+    /// humanity proof can't move to another account while the call is in flight. Issuer
+    /// revocations are not blocked. The
+    /// callback runs on both success and failure of the recipient call, so a failed call
+    /// doesn't leave the caller locked.
+    /// The callback returns the recipient call result.
+    /// Optional `nonce` and `valid_until_ms` (unix time in milliseconds) are passed to the
+    /// recipient, which can use them to protect against replayed payloads. Fails early with
+    /// `Expired` if `valid_until_ms` is in the past.
+    /// Fails early with `InvalidFunctionName` if the `function` is empty or longer than
+    /// `MAX_FUNCTION_NAME_LEN`.
+    /// If the recipient call fails, the deposit is refunded to `refund_to` (default: the
+    /// caller) by the callback. This way a relayer paying on behalf of a user can get the
+    /// refund. When the call fails early (panics), the deposit is returned to the predecessor.
+    /// Optional `grace_ms` is the same as in `is_human`: IAH tokens expired less than
+    /// `grace_ms` milliseconds ago still prove the caller humanity.
+    /// Panics if the predecessor is not a human.
+    #[payable]
+    #[handle_result]
+    pub fn is_human_call(
+        &mut self,
+        ctr: AccountId,
+        function: String,
+        payload: String,
+        nonce: Option<u64>,
+        valid_until_ms: Option<u64>,
+        refund_to: Option<AccountId>,
+        grace_ms: Option<u64>,
+    ) -> Result<Promise, IsHumanCallErr> {
+        self._is_human_call(
+            ctr,
+            function,
+            payload,
+            nonce,
+            valid_until_ms,
+            refund_to,
+            grace_ms,
+            false,
+        )
+    }
 
-    fn alice() -> AccountId {
-        AccountId::new_unchecked("alice.near".to_string())
+    /// Same as `is_human_call`, but the callback doesn't propagate the recipient call result.
+    /// Instead it returns `IsHumanCallResult`: `{"is_human": true, "callback_succeeded":
+    /// bool, "refunded": "<yoctoNEAR>"}`, so the caller learns, in one decoded value, that
+    /// the humanity check passed and how the recipient call went. A failed recipient call
+    /// doesn't fail the transaction: `callback_succeeded` is false and the deposit is
+    /// refunded to `refund_to` (`refunded` is the refunded amount).
+    /// Fails early, the same way as `is_human_call`, if the caller is not a human.
+    #[payable]
+    #[handle_result]
+    pub fn is_human_call_checked(
+        &mut self,
+        ctr: AccountId,
+        function: String,
+        payload: String,
+        nonce: Option<u64>,
+        valid_until_ms: Option<u64>,
+        refund_to: Option<AccountId>,
+        grace_ms: Option<u64>,
+    ) -> Result<Promise, IsHumanCallErr> {
+        self._is_human_call(
+            ctr,
+            function,
+            payload,
+            nonce,
+            valid_until_ms,
+            refund_to,
+            grace_ms,
+            true,
+        )
     }
 
-    fn alice2() -> AccountId {
-        AccountId::new_unchecked("alice.nea".to_string())
-    }
-
-    fn bob() -> AccountId {
-        AccountId::new_unchecked("bob.near".to_string())
-    }
+    fn _is_human_call(
+        &mut self,
+        ctr: AccountId,
+        function: String,
+        payload: String,
+        nonce: Option<u64>,
+        valid_until_ms: Option<u64>,
+        refund_to: Option<AccountId>,
+        grace_ms: Option<u64>,
+        checked: bool,
+    ) -> Result<Promise, IsHumanCallErr> {
+        if let Some(valid_until_ms) = valid_until_ms {
+            if valid_until_ms < env::block_timestamp_ms() {
+                return Err(IsHumanCallErr::Expired);
+            }
+        }
+        validate_function_name(&function)?;
+        let caller = env::predecessor_account_id();
+        let iah_proof = self._is_human_with_grace(&caller, assert_human_grace(grace_ms));
+        if iah_proof.is_empty() {
+            return Err(IsHumanCallErr::NotHuman);
+        }
 
-    fn carol() -> AccountId {
-        AccountId::new_unchecked("carol.near".to_string())
+        let args = IsHumanCallbackArgs {
+            caller: caller.clone(),
+            iah_proof,
+            payload: &RawValue::from_string(payload).unwrap(),
+            nonce,
+            valid_until_ms,
+        };
+        let args = serde_json::to_vec(&args).unwrap();
+        let deposit = env::attached_deposit();
+        let gas = forwarded_gas(IS_HUMAN_GAS + IS_HUMAN_CALLBACK_GAS)?;
+        let n = self.ongoing_is_human_calls.get(&caller).unwrap_or(0);
+        self.ongoing_is_human_calls.insert(&caller, &(n + 1));
+        let refund_to = refund_to.unwrap_or_else(|| caller.clone());
+        let callback = Self::ext(env::current_account_id()).with_static_gas(ON_IS_HUMAN_CALL_GAS);
+        let callback = if checked {
+            callback.on_is_human_call_checked(caller, refund_to, U128(deposit))
+        } else {
+            callback.on_is_human_call(caller, refund_to, U128(deposit))
+        };
+        Ok(Promise::new(ctr)
+            .function_call(function, args, deposit, gas)
+            .then(callback))
     }
 
-    fn dan() -> AccountId {
-        AccountId::new_unchecked("dan.near".to_string())
+    /// `is_human_call` callback: clears the `caller` in flight marker and returns the
+    /// recipient call result. If the recipient call failed, the marker is cleared as well,
+    /// the `deposit` is refunded to `refund_to` and the failure is propagated by the
+    /// `on_is_human_call_failed` call.
+    #[private]
+    pub fn on_is_human_call(&mut self, caller: AccountId, refund_to: AccountId, deposit: U128) {
+        self.end_is_human_call(&caller);
+        match env::promise_result(0) {
+            PromiseResult::Successful(value) => env::value_return(&value),
+            _ => {
+                if deposit.0 > 0 {
+                    Promise::new(refund_to).transfer(deposit.0);
+                }
+                Self::ext(env::current_account_id())
+                    .with_static_gas(IS_HUMAN_FAILED_GAS)
+                    .on_is_human_call_failed()
+                    .as_return();
+            }
+        }
     }
 
-    fn issuer1() -> AccountId {
-        AccountId::new_unchecked("sbt.n".to_string())
+    #[private]
+    pub fn on_is_human_call_failed(&self) {
+        env::panic_str("is_human_call: recipient call failed")
     }
 
-    fn issuer2() -> AccountId {
-        AccountId::new_unchecked("sbt.ne".to_string())
+    /// `is_human_call_checked` callback: clears the `caller` in flight marker, refunds the
+    /// `deposit` to `refund_to` if the recipient call failed, and returns the call summary.
+    #[private]
+    pub fn on_is_human_call_checked(
+        &mut self,
+        caller: AccountId,
+        refund_to: AccountId,
+        deposit: U128,
+    ) -> IsHumanCallResult {
+        self.end_is_human_call(&caller);
+        let callback_succeeded = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        let mut refunded = 0;
+        if !callback_succeeded && deposit.0 > 0 {
+            Promise::new(refund_to).transfer(deposit.0);
+            refunded = deposit.0;
+        }
+        IsHumanCallResult {
+            is_human: true,
+            callback_succeeded,
+            refunded: U128(refunded),
+        }
     }
 
-    fn issuer3() -> AccountId {
-        AccountId::new_unchecked("sbt.nea".to_string())
+    /// Returns true if the `account` has an `is_human_call` in flight.
+    pub fn has_ongoing_is_human_call(&self, account: AccountId) -> bool {
+        self.ongoing_is_human_calls.contains_key(&account)
     }
 
-    fn issuer4() -> AccountId {
-        AccountId::new_unchecked("sbt4.near".to_string())
-    }
+    /// Apps should use this function to ask a user to lock his account for soul transfer.
+    /// This is useful when a dapp relays on user account ID (rather set of potential SBTs)
+    /// being a unique human over a period of time (there is no soul transfer in between).
+    /// Example use cases: voting, staking, games.
+    /// Dapps should make it clear that they extend user lock for a given amount of time.
+    /// Parameters are similar to `is_human_call`:
+    /// * `ctr` and `function`: the contract function we will call, passing the provided deposit,
+    ///   if and only if the caller has a valid humanity proof.
+    ///
+    ///    ctr.function({caller: predecessor_account_id(),
+    ///                 locked_until: time_in_ms_until_when_the_account_is_locked,
+    ///                 iah_proof: SBTs,
+    ///                 payload: payload})
+    ///
+    ///   Note the additional arguments provided to the recipient function call, that are not
+    ///   present in `is_human_call`:
+    ///   - `locked_until`: time in milliseconds (duration) until when the account is locked for soul
+    ///      transfers. It may be bigger than `now + lock_duration` (this is a case when there
+    ///      is already an existing lock with a longer duration).
+    ///   - `iah_proof` will be set to an empty list if `with_proof=false`.
+    /// * `payload`: must be a JSON string, and it will be passed through the default interface.
+    /// * `lock_duration`: duration in milliseconds to extend the predecessor account lock for
+    ///    soul transfers. Can be zero, if no lock is needed.
+    /// * `with_proof`: when false - doesn't send iah_proof (SBTs) to the contract call.
+    /// Emits transfer_lock if the transfer_lock is extended.
+    /// Fails with `InvalidFunctionName` if the `function` is empty or longer than
+    /// `MAX_FUNCTION_NAME_LEN`.
+    /// Panics if the predecessor is not a human.
+    #[payable]
+    #[handle_result]
+    pub fn is_human_call_lock(
+        &mut self,
+        ctr: AccountId,
+        function: String,
+        payload: String,
+        lock_duration: u64,
+        with_proof: bool,
+    ) -> Result<Promise, IsHumanCallErr> {
+        validate_function_name(&function)?;
+        let caller = env::predecessor_account_id();
+        let proof = self._is_human(&caller);
+        if proof.is_empty() {
+            return Err(IsHumanCallErr::NotHuman);
+        }
+        let gas = forwarded_gas(IS_HUMAN_GAS)?;
 
-    fn admins_flagged() -> Vec<AccountId> {
-        vec![AccountId::new_unchecked("admin_flagged.near".to_string())]
-    }
+        let now = env::block_timestamp_ms();
+        let mut lock = self.transfer_lock.get(&caller).unwrap_or(now);
+        if lock_duration > 0 && lock < now + lock_duration {
+            lock = now + lock_duration;
+            self.transfer_lock.insert(&caller, &lock);
+            events::emit_transfer_lock(caller.clone(), lock)
+        }
 
-    #[inline]
-    fn fractal_mainnet() -> AccountId {
-        AccountId::new_unchecked("fractal.i-am-human.near".to_string())
-    }
+        let args = IsHumanLockCallbackArgs {
+            caller,
+            locked_until: lock,
+            iah_proof: if with_proof { Some(proof) } else { None },
+            payload: &RawValue::from_string(payload).unwrap(),
+        };
 
-    fn admin() -> AccountId {
-        AccountId::new_unchecked("sbt.near".to_string())
+        Ok(Promise::new(ctr).function_call(
+            function,
+            serde_json::to_vec(&args).unwrap(),
+            env::attached_deposit(),
+            gas,
+        ))
     }
 
-    fn mk_metadata(class: ClassId, expires_at: Option<u64>) -> TokenMetadata {
-        TokenMetadata {
-            class,
-            issued_at: Some(START),
-            expires_at,
-            reference: Some("abc".to_owned()),
-            reference_hash: Some(vec![61, 61].into()),
+    // If needed, bans the owner and returns the initial soul transfer cursor.
+    pub(crate) fn start_transfer_with_continuation(
+        &mut self,
+        owner: &AccountId,
+        recipient: &AccountId,
+        ban_owner: bool,
+    ) -> SoulTxCursor {
+        self.assert_not_banned(recipient);
+        if ban_owner {
+            // we only ban the source account in the soul transfer
+            // insert into banlist and assure the owner is not already banned.
+            require!(
+                self.banlist.insert(owner),
+                "from account is banned. Cannot start the transfer"
+            );
+            Nep393Event::Ban(vec![owner]).emit();
         }
+
+        SoulTxCursor::default()
     }
 
-    fn mk_token(token: TokenId, owner: AccountId, metadata: TokenMetadata) -> Token {
-        Token {
-            token,
-            owner,
-            metadata,
+    // If it is the first iteration of the soul transfer, bans the source account, otherwise returns the last transfered token
+    fn transfer_continuation(
+        &mut self,
+        from: &AccountId,
+        to: &AccountId,
+        ban_owner: bool,
+    ) -> (bool, SoulTxCursor) {
+        match self.ongoing_soul_tx.get(from) {
+            // starting the process
+            None => (
+                false,
+                self.start_transfer_with_continuation(from, to, ban_owner),
+            ),
+            // resuming sbt_recover process
+            Some(s) => (true, s),
         }
     }
 
-    fn mk_owned_token(token: TokenId, metadata: TokenMetadata) -> OwnedToken {
-        OwnedToken { token, metadata }
-    }
+    // sbt_recover execution with `limit` parameter in
+    // order to facilitate tests.
+    // The tokens loop stops early (and the process must be continued by a subsequent call)
+    // when the remaining gas is not enough to finalize the recovery.
+    fn _sbt_recover(&mut self, from: AccountId, to: AccountId, limit: usize) -> (u32, bool) {
+        require!(from != to, "from and to must be different accounts");
+        let gas_start = env::used_gas();
+        let storage_start = env::storage_usage();
+        let issuer = env::predecessor_account_id();
+        let issuer_id = self.assert_issuer(&issuer);
+        self.assert_not_banned(&to);
+        // get the last transfered token and don't ban the owner.
+        let (resumed, start) = self.transfer_continuation(&from, &to, false);
 
-    fn mk_balance_key(owner: AccountId, issuer_id: IssuerId, class_id: ClassId) -> BalanceKey {
-        BalanceKey {
-            owner,
+        let mut tokens_recovered = 0;
+        let mut out_of_gas = false;
+        // max gas used to recover a single token
+        let mut token_gas = 0;
+
+        let mut last_token_transfered = BalanceKey {
+            owner: from.clone(),
             issuer_id,
-            class_id,
+            class_id: ClassKey(0),
+        };
+
+        // We can't modify self.balances while iterating over it, so we firstly collect the batch.
+        let batch: Vec<(BalanceKey, TokenId)> = self
+            .balances
+            .iter_from(balance_key(from.clone(), issuer_id, start.class_id))
+            .take(limit)
+            .collect();
+        let mut new_balance_key = balance_key(to.clone(), issuer_id, ClassKey(0));
+        for (key, token) in batch {
+            if key.owner != from || key.issuer_id != issuer_id {
+                break;
+            }
+            // Stop early if there is not enough gas to recover the next token and finalize.
+            // We always recover at least one token to make a progress.
+            let gas_start = env::used_gas().0;
+            if tokens_recovered > 0
+                && env::prepaid_gas().0.saturating_sub(gas_start)
+                    < RECOVER_GAS_RESERVE.0 + 2 * token_gas
+            {
+                out_of_gas = true;
+                break;
+            }
+            tokens_recovered += 1;
+            let mut t = self.get_token(key.issuer_id, token);
+            t.owner = to.clone();
+            self.issuer_tokens
+                .insert(&IssuerTokenId::new(issuer_id, token), &t);
+            self.record_token_change(issuer_id, token, TokenField::Recovery);
+
+            // update user balances. Same as in the soul transfer, we don't overwrite the
+            // recipient tokens: a user can have max one token of an (issuer, class) pair.
+            self.balances.remove(&key);
+            new_balance_key.class_id = key.class_id;
+            if self.balances.insert(&new_balance_key, &token).is_some() {
+                env::panic_str(&format!(
+                    "recipient already has an SBT of issuer={}, class={}; source_token_id={}",
+                    issuer, key.class_id.0, token
+                ));
+            }
+            last_token_transfered = key;
+            token_gas = token_gas.max(env::used_gas().0 - gas_start);
         }
-    }
 
-    fn mk_batch_metadata(n: u64) -> Vec<TokenMetadata> {
-        let mut batch_metadata: Vec<TokenMetadata> = Vec::new();
-        for i in 1..=n {
-            batch_metadata.push(mk_metadata(i, Some(START + i)))
+        // update supply_by_owner map.
+        let supply_key = &(from.clone(), issuer_id);
+        let old_supply_from = self.supply_by_owner.remove(supply_key).unwrap_or(0);
+        if old_supply_from != tokens_recovered {
+            self.supply_by_owner.insert(
+                &(from.clone(), issuer_id),
+                &(old_supply_from - tokens_recovered),
+            );
         }
-        batch_metadata
-    }
+        let supply_key = &(to.clone(), issuer_id);
+        let old_supply_to = self.supply_by_owner.get(supply_key).unwrap_or(0);
+        self.supply_by_owner
+            .insert(supply_key, &(old_supply_to + tokens_recovered));
+        self.sub_owner_total(&from, tokens_recovered);
+        self.add_owner_total(&to, tokens_recovered);
 
-    fn max_gas() -> Gas {
-        Gas::ONE_TERA.mul(300)
+        let completed = !out_of_gas && tokens_recovered != limit as u64;
+        if completed {
+            if resumed {
+                // insert is happening when we need to continue, so don't need to remove if
+                // the process finishes in the same transaction.
+                self.ongoing_soul_tx.remove(&from);
+            }
+            // we emit the event only once the operation is completed and only if some tokens were
+            // recovered
+            if resumed || tokens_recovered > 0 {
+                // emit Recover event
+                SbtRecover {
+                    issuer: &issuer,
+                    old_owner: &from,
+                    new_owner: &to,
+                    event_seq: Some(self.next_event_seq()),
+                }
+                .emit();
+            }
+        } else {
+            self.ongoing_soul_tx
+                .insert(&from, &SoulTxCursor::from(&last_token_transfered));
+        }
+        // structured log (not an event) to help clients sizing the gas of the subsequent
+        // calls: `token_gas` is the max gas used to recover a single token.
+        env::log_str(&format!(
+            r#"recover_stats: {{"tokens":{},"gas_used":{},"token_gas":{},"completed":{}}}"#,
+            tokens_recovered,
+            (env::used_gas() - gas_start).0,
+            token_gas,
+            completed
+        ));
+        // storage check
+        // we are using checked_sub, since the storage can decrease and we are running of risk of underflow
+        let storage_usage = env::storage_usage();
+        if storage_usage > storage_start {
+            let required_deposit =
+                (storage_usage - storage_start) as u128 * env::storage_byte_cost();
+            require!(
+                env::attached_deposit() >= required_deposit,
+                format!(
+                    "not enough NEAR storage depost, required: {}",
+                    required_deposit
+                )
+            );
+        }
+        (tokens_recovered as u32, completed)
     }
 
-    const MSECOND: u64 = 1_000_000; // milisecond in ns
-    const START: u64 = 10;
-    const MINT_DEPOSIT: Balance = 9 * MILI_NEAR;
+    /// Method to burn all caller tokens (from all issuers).
+    /// The method must be called repeatedly until true is returned.
+    /// Not all tokens may be burned in a single call due to the gas limitation - in that case
+    /// `false` is returned.
+    /// The burn event is emitted for all the tokens burned.
+    pub fn sbt_burn_all(&mut self) -> bool {
+        self._sbt_burn_all(25)
+    }
 
-    fn setup(predecessor: &AccountId, deposit: Balance) -> (VMContext, Contract) {
-        let mut ctx = VMContextBuilder::new()
-            .predecessor_account_id(admin())
+    /// Allows user to burn any of his tokens.
+    /// The burn event is emitted for all  tokens burned.
+    /// Panics if user has ongoing soul transfer or ongoing recovery or doesn't own a listed
+    /// token. An empty `tokens` list is a no-op.
+    pub fn sbt_burn(
+        &mut self,
+        issuer: AccountId,
+        tokens: Vec<TokenId>,
+        #[allow(unused_variables)] memo: Option<String>,
+    ) {
+        let owner = env::predecessor_account_id();
+        require!(
+            !self.ongoing_soul_tx.contains_key(&owner),
+            "can't burn tokens while in soul_transfer"
+        );
+
+        let issuer_id = self.assert_issuer(&issuer);
+        if tokens.is_empty() {
+            return;
+        }
+        let token_len = tokens.len() as u64;
+        let mut token_ids = HashSet::new();
+        for tid in tokens.iter() {
+            require!(
+                !token_ids.contains(tid),
+                format!("duplicated token_id in tokens: {}", tid)
+            );
+            token_ids.insert(tid);
+
+            let ct_key = &IssuerTokenId::new(issuer_id, *tid);
+            let t = self
+                .issuer_tokens
+                .get(ct_key)
+                .unwrap_or_else(|| panic!("tokenID={} not found", tid));
+            require!(
+                t.owner == owner,
+                &format!("not an owner of tokenID={}", tid)
+            );
+
+            self.issuer_tokens.remove(ct_key);
+            self.token_history.remove(ct_key);
+            let class_id = t.metadata.latest().class;
+            self.balances
+                .remove(&balance_key(owner.clone(), issuer_id, ClassKey(class_id)));
+
+            // update supply by class
+            let supply = self.supply_by_class.get(&(issuer_id, class_id)).unwrap();
+            self.set_class_supply(issuer_id, class_id, supply - 1);
+        }
+
+        // update supply by owner
+        self.sub_owner_total(&owner, token_len);
+        let key = (owner, issuer_id);
+        let mut supply = self.supply_by_owner.get(&key).unwrap();
+        supply -= token_len;
+        self.supply_by_owner.insert(&key, &supply);
+
+        // update total supply by issuer
+        let mut supply = self.supply_by_issuer.get(&issuer_id).unwrap();
+        supply -= token_len;
+        self.supply_by_issuer.insert(&issuer_id, &supply);
+
+        SbtTokensEvent {
+            issuer,
+            tokens,
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(self.next_event_seq()),
+        }
+        .emit_burn();
+    }
+
+    /// Enables an allowlist for the `class` of the calling issuer and adds `accounts` to it.
+    /// Once enabled, tokens of that class can only be minted to the allowlisted accounts.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_class_allowlist(&mut self, class: ClassId, accounts: Vec<AccountId>) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        require!(class > 0, "Class must be > 0");
+
+        self.allowlisted_classes.insert(&(issuer_id, class), &());
+        for a in accounts {
+            self.class_allowlist.insert(&(issuer_id, class, a), &());
+        }
+
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Sets if tokens of the `class` of the calling issuer can be transferred by their owners
+    /// (using `sbt_transfer`). By default all classes are soulbound (not transferable).
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_class_transferable(&mut self, class: ClassId, transferable: bool) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        require!(class > 0, "Class must be > 0");
+        if transferable {
+            self.transferable_classes.insert(&(issuer_id, class), &());
+        } else {
+            self.transferable_classes.remove(&(issuer_id, class));
+        }
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Sets if tokens of the `class` of the calling issuer are pinned to their owner: a soul
+    /// transfer doesn't move them, and depending on the `LockedPolicy` they are left in the
+    /// source account or burned. By default classes are not pinned.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_class_pinned(&mut self, class: ClassId, pinned: bool) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        require!(class > 0, "Class must be > 0");
+        if pinned {
+            self.pinned_classes.insert(&(issuer_id, class), &());
+        } else {
+            self.pinned_classes.remove(&(issuer_id, class));
+        }
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Batch version of `sbt_renew`: every element of `renewals` is a pair of token IDs and
+    /// their new expire time (unix timestamp in miliseconds). Emits one `Renew` event per
+    /// pair (skipped if no token of the pair was changed).
+    /// Must be called by the tokens issuer, and enough NEAR must be attached to cover the
+    /// storage growth (eg: previously non expiring tokens get an expire time). The excess
+    /// deposit is refunded.
+    #[payable]
+    pub fn sbt_renew_many(&mut self, renewals: Vec<(Vec<TokenId>, u64)>) {
+        let storage_start = env::storage_usage();
+        let issuer = env::predecessor_account_id();
+        let issuer_id = self.assert_issuer(&issuer);
+        self.assert_issuer_not_paused(issuer_id);
+        for (tokens, expires_at) in renewals {
+            self.renew_tokens(issuer.clone(), issuer_id, tokens, expires_at);
+        }
+        Self::charge_storage_deposit(storage_start);
+    }
+
+    /// Approves the `account` to renew the caller tokens with `sbt_renew_as`. Useful for
+    /// services handling subscriptions renewals separately from the issuer key.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn approve_renewer(&mut self, account: AccountId) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        self.renewers.insert(&(issuer_id, account), &());
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Revokes the `account` approval to renew the caller tokens, see `approve_renewer`.
+    /// Must be called by a registered issuer.
+    pub fn revoke_renewer(&mut self, account: AccountId) {
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        self.renewers.remove(&(issuer_id, account));
+    }
+
+    /// Renews the `issuer` tokens on behalf of the issuer. Same as `sbt_renew_many`:
+    /// every element of `renewals` is a pair of token IDs and their new expire time.
+    /// Must be called by an account approved by the issuer with `approve_renewer`, and
+    /// enough NEAR must be attached to cover the storage growth. The excess deposit is
+    /// refunded.
+    #[payable]
+    pub fn sbt_renew_as(&mut self, issuer: AccountId, renewals: Vec<(Vec<TokenId>, u64)>) {
+        let storage_start = env::storage_usage();
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            Some(id) => id,
+            None => env::panic_str("issuer is not registered"),
+        };
+        require!(
+            self.renewers
+                .contains_key(&(issuer_id, env::predecessor_account_id())),
+            "caller is not an approved renewer of the issuer"
+        );
+        self.assert_issuer_not_paused(issuer_id);
+        for (tokens, expires_at) in renewals {
+            self.renew_tokens(issuer.clone(), issuer_id, tokens, expires_at);
+        }
+        Self::charge_storage_deposit(storage_start);
+    }
+
+    /// Sets if the calling issuer requires the owner consent (see `allow_revocation`) to
+    /// revoke, without burning, the owner tokens with `sbt_revoke_by_owner`. Useful for
+    /// credentials which shouldn't be unilaterally revoked. By default the consent is not
+    /// required.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_revocation_consent_required(&mut self, required: bool) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        if required {
+            self.revocation_consent_required.insert(&issuer_id, &());
+        } else {
+            self.revocation_consent_required.remove(&issuer_id);
+        }
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Allows the `issuer` to revoke (without burning) the caller tokens with
+    /// `sbt_revoke_by_owner`, when the issuer requires the owner consent. The consent is
+    /// cleared once all the tokens are revoked.
+    /// Enough NEAR must be attached to cover the storage cost.
+    #[payable]
+    pub fn allow_revocation(&mut self, issuer: AccountId) {
+        let storage_start = env::storage_usage();
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            Some(id) => id,
+            None => env::panic_str("issuer is not registered"),
+        };
+        self.revocation_consents
+            .insert(&(env::predecessor_account_id(), issuer_id), &());
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// By default, when a token has both `reference` and `reference_hash`, the hash must be
+    /// a sha256 hash (32 bytes). Issuers using other hash functions can opt out from
+    /// that check, in which case a 32 or 64 bytes hash is accepted.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_reference_hash_opt_out(&mut self, opt_out: bool) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        if opt_out {
+            self.reference_hash_opt_out.insert(&issuer_id, &());
+        } else {
+            self.reference_hash_opt_out.remove(&issuer_id);
+        }
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Enables (or disables) compact Mint events for the calling issuer: instead of the
+    /// NEP-393 `mint` event listing all the minted tokens, the registry emits a `mint_compact`
+    /// event with the number of recipients and tokens and a hash of the token IDs. Useful
+    /// for issuers minting big batches. Indexers can reconstruct the tokens with the queries.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_compact_events(&mut self, compact: bool) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        if compact {
+            self.compact_events.insert(&issuer_id, &());
+        } else {
+            self.compact_events.remove(&issuer_id);
+        }
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Sets the caller default expiry policy, applied to the newly minted tokens without
+    /// `expires_at`. Explicit token `expires_at` always overrides the policy.
+    /// Must be called by a registered issuer, and enough NEAR must be attached to cover
+    /// the storage cost.
+    #[payable]
+    pub fn set_default_expiry_policy(&mut self, policy: ExpiryPolicy) {
+        let storage_start = env::storage_usage();
+        let issuer_id = self.assert_issuer(&env::predecessor_account_id());
+        if policy == ExpiryPolicy::NeverExpire {
+            self.expiry_policy.remove(&issuer_id);
+        } else {
+            self.expiry_policy.insert(&issuer_id, &policy);
+        }
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Transfers a single token of a transferable class (see `set_class_transferable`) from
+    /// the caller to the `recipient`. Soulbound tokens can only be moved with a soul transfer.
+    /// Enough NEAR must be attached to cover the storage cost.
+    /// Panics if:
+    /// + the caller is not the token owner, is banned, has a transfer lock (see
+    ///   `is_human_call_lock`), an ongoing `is_human_call` or an ongoing soul transfer;
+    /// + the token class is not transferable;
+    /// + the `recipient` is the caller, is banned, is not allowed to receive the class or
+    ///   already has a token of the same issuer and class.
+    /// Emits `sbt_transfer` event.
+    #[payable]
+    pub fn sbt_transfer(&mut self, issuer: AccountId, token: TokenId, recipient: AccountId) {
+        let storage_start = env::storage_usage();
+        let owner = env::predecessor_account_id();
+        require!(owner != recipient, "recipient must be different than owner");
+        require!(
+            !self.ongoing_soul_tx.contains_key(&owner),
+            "can't transfer tokens while in soul_transfer"
+        );
+        require!(
+            self.transfer_lock.get(&owner).unwrap_or(0) < env::block_timestamp_ms(),
+            "can't transfer tokens: owner has a transfer lock"
+        );
+        require!(
+            !self.ongoing_is_human_calls.contains_key(&owner),
+            "can't transfer tokens: owner has an ongoing is_human_call"
+        );
+        self.assert_not_banned(&owner);
+        self.assert_not_banned(&recipient);
+        let issuer_id = self.assert_issuer(&issuer);
+
+        let key = IssuerTokenId::new(issuer_id, token);
+        let mut t = self.get_token(issuer_id, token);
+        require!(
+            t.owner == owner,
+            format!("not an owner of tokenID={}", token)
+        );
+        let class = t.metadata.class_id();
+        require!(
+            self.transferable_classes.contains_key(&(issuer_id, class)),
+            format!("tokens of class {} are not transferable", class)
+        );
+        require!(
+            self._is_class_allowed(issuer_id, class, &recipient),
+            format!(
+                "{} is not allowed to receive SBT of class {}",
+                recipient, class
+            )
+        );
+
+        self.balances
+            .remove(&balance_key(owner.clone(), issuer_id, ClassKey(class)));
+        let prev = self.balances.insert(
+            &balance_key(recipient.clone(), issuer_id, ClassKey(class)),
+            &token,
+        );
+        require!(
+            prev.is_none(),
+            format!("{} already has SBT of class {}", recipient, class)
+        );
+        t.owner = recipient.clone();
+        self.issuer_tokens.insert(&key, &t);
+
+        self.move_owner_supply(&owner, &recipient, issuer_id, 1, 0);
+        self.sub_owner_total(&owner, 1);
+        self.add_owner_total(&recipient, 1);
+
+        let event_seq = self.next_event_seq();
+        events::emit_sbt_transfer(&issuer, token, &owner, &recipient, event_seq);
+        Self::assert_storage_deposit(storage_start);
+    }
+
+    /// Deprecates the caller `class`: revokes, without burning, all the class tokens by setting
+    /// their `expires_at = now`. The tokens stay in storage, and can be queried with
+    /// `with_expired=true`. Already expired tokens are not modified.
+    /// The process is resumable: every call scans at most `limit` issuer token IDs. Must be
+    /// called repeatedly until `true` is returned.
+    /// Returns the number of tokens revoked in this call and a boolean: `true` if the process
+    /// has finished.
+    /// Must be called by a registered issuer.
+    /// Emits `Revoke` event, with the `reason` (default `Unspecified`), for the tokens revoked
+    /// in the call.
+    pub fn sbt_revoke_class(
+        &mut self,
+        class: ClassId,
+        limit: u32,
+        reason: Option<RevokeReason>,
+    ) -> (u32, bool) {
+        let issuer = env::predecessor_account_id();
+        let issuer_id = self.assert_issuer(&issuer);
+        require!(class > 0, "Class must be > 0");
+        require!(limit > 0, "limit must be bigger than 0");
+        let cursor_key = &(issuer_id, class);
+        let start = self.class_revoke_cursor.get(cursor_key).unwrap_or(1);
+        let last = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        let end = std::cmp::min(last.saturating_add(1), start.saturating_add(limit as u64));
+        let now = env::block_timestamp_ms();
+
+        let mut revoked = Vec::new();
+        for token in start..end {
+            let key = IssuerTokenId::new(issuer_id, token);
+            if let Some(mut t) = self.issuer_tokens.get(&key) {
+                if t.metadata.class_id() != class || !is_token_valid_at(&t, now) {
+                    continue;
+                }
+                let mut m = t.metadata.latest();
+                m.expires_at = Some(now);
+                t.metadata = m.into();
+                self.issuer_tokens.insert(&key, &t);
+                self.record_token_change(issuer_id, token, TokenField::Expiry);
+                revoked.push(token);
+            }
+        }
+
+        let finished = end > last;
+        if finished {
+            self.class_revoke_cursor.remove(cursor_key);
+        } else {
+            self.class_revoke_cursor.insert(cursor_key, &end);
+        }
+        let num_revoked = revoked.len() as u32;
+        if !revoked.is_empty() {
+            SbtTokensEvent {
+                issuer,
+                tokens: revoked,
+                reason: Some(reason.unwrap_or_default()),
+                expires_at: None,
+                renewals: None,
+                event_seq: Some(self.next_event_seq()),
+            }
+            .emit_revoke();
+        }
+        (num_revoked, finished)
+    }
+
+    /// Same as the non burn `sbt_revoke`, but the tokens stay valid during a grace period:
+    /// their `expires_at` is set to `now + grace_ms`, giving the holders time to act (eg:
+    /// renew, export). Tokens already expiring before the end of the grace period keep their
+    /// expire time.
+    /// Must be called by the tokens issuer.
+    /// Emits `Revoke` event with the `reason` (default `Unspecified`) and the `expires_at`
+    /// set to the end of the grace period. The event lists only the updated tokens, and it's
+    /// not emitted when no token was updated.
+    pub fn sbt_revoke_with_grace(
+        &mut self,
+        tokens: Vec<TokenId>,
+        grace_ms: u64,
+        reason: Option<RevokeReason>,
+    ) {
+        let issuer = env::predecessor_account_id();
+        let issuer_id = self.assert_issuer(&issuer);
+        if tokens.is_empty() {
+            return;
+        }
+        let expires_at = env::block_timestamp_ms().saturating_add(grace_ms);
+        let mut revoked = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let mut t = self.get_token(issuer_id, token);
+            if t.metadata.expires_at().map_or(false, |e| e <= expires_at) {
+                continue;
+            }
+            let mut m = t.metadata.latest();
+            m.expires_at = Some(expires_at);
+            t.metadata = m.into();
+            self.issuer_tokens
+                .insert(&IssuerTokenId::new(issuer_id, token), &t);
+            self.record_token_change(issuer_id, token, TokenField::Expiry);
+            revoked.push(token);
+        }
+        if revoked.is_empty() {
+            return;
+        }
+        SbtTokensEvent {
+            issuer,
+            tokens: revoked,
+            reason: Some(reason.unwrap_or_default()),
+            expires_at: Some(expires_at),
+            renewals: None,
+            event_seq: Some(self.next_event_seq()),
+        }
+        .emit_revoke();
+    }
+
+    /// Atomically burns the caller issued `token` and mints a new token, with the new
+    /// `metadata`, to the same owner. Useful to rotate a credential (eg: new expiry or
+    /// reference) without a risk of partial failure of separate revoke and mint calls.
+    /// The owner and issuer supplies don't change, class supply changes when the new
+    /// metadata has a different class. Like in `sbt_mint`, the issuer default expiry policy
+    /// applies when the new `metadata` has no `expires_at`.
+    /// Panics if the owner already has a token of the new class (other than `token`).
+    /// Must be called by the issuer of the token, and enough NEAR must be attached to cover
+    /// the storage cost.
+    /// Returns the new token ID. Emits `sbt_reissue` event.
+    #[payable]
+    pub fn sbt_reissue(&mut self, token: TokenId, metadata: TokenMetadata) -> TokenId {
+        let storage_start = env::storage_usage();
+        let issuer = env::predecessor_account_id();
+        let issuer_id = self.assert_issuer(&issuer);
+        self.assert_issuer_not_paused(issuer_id);
+        let old = self.get_token(issuer_id, token);
+        let owner = old.owner;
+        // no need to check ongoing_soult_tx, because it will automatically ban the source account
+        self.assert_not_banned(&owner);
+        require!(metadata.class > 0, "Class must be > 0");
+        Self::assert_metadata_size(&metadata);
+        self.assert_reference_hash(issuer_id, &metadata.reference, &metadata.reference_hash);
+        require!(
+            self._is_class_allowed(issuer_id, metadata.class, &owner),
+            format!(
+                "{} is not allowed to receive SBT of class {}",
+                owner, metadata.class
+            )
+        );
+
+        // burn the old token
+        let old_class = old.metadata.class_id();
+        self.balances
+            .remove(&balance_key(owner.clone(), issuer_id, ClassKey(old_class)));
+        self.issuer_tokens
+            .remove(&IssuerTokenId::new(issuer_id, token));
+        let history = self
+            .token_history
+            .remove(&IssuerTokenId::new(issuer_id, token));
+        let supply = self
+            .supply_by_class
+            .get(&(issuer_id, old_class))
+            .unwrap_or(0);
+        self.set_class_supply(issuer_id, old_class, supply - 1);
+
+        // mint the new one, the same way as `sbt_mint`. Owner and issuer supply stay the same.
+        let mut metadata = metadata;
+        let expiry_policy = self.expiry_policy.get(&issuer_id);
+        Self::set_mint_times(&mut metadata, env::block_timestamp_ms(), expiry_policy);
+        let new_token = self.next_token_id(issuer_id, 1);
+        let supply = self
+            .supply_by_class
+            .get(&(issuer_id, metadata.class))
+            .unwrap_or(0);
+        self.set_class_supply(issuer_id, metadata.class, supply + 1);
+        let prev = self.balances.insert(
+            &balance_key(owner.clone(), issuer_id, ClassKey(metadata.class)),
+            &new_token,
+        );
+        require!(
+            prev.is_none(),
+            format! {"{} already has SBT of class {}", owner, metadata.class}
+        );
+        let prev = self.issuer_tokens.insert(
+            &IssuerTokenId::new(issuer_id, new_token),
+            &TokenData {
+                owner: owner.clone(),
+                metadata: metadata.into(),
+            },
+        );
+        require!(
+            prev.is_none(),
+            format!("internal error: token {} already exists", new_token)
+        );
+        if let Some(history) = history {
+            self.token_history
+                .insert(&IssuerTokenId::new(issuer_id, new_token), &history);
+        }
+        self.record_token_change(issuer_id, new_token, TokenField::Class);
+
+        let event_seq = self.next_event_seq();
+        events::emit_sbt_reissue(&issuer, &owner, token, new_token, event_seq);
+        Self::assert_storage_deposit(storage_start);
+        new_token
+    }
+
+    //
+    // Authority
+    //
+
+    /// returns false if the `issuer` contract was already registered.
+    pub fn admin_add_sbt_issuer(&mut self, issuer: AccountId) -> bool {
+        self.assert_authority();
+        self._add_sbt_issuer(&issuer)
+    }
+
+    /// Moves the issuer registered under the `old_account` to the `new_account` (e.g. after
+    /// a key compromise), keeping the same issuer ID: all the issuer tokens, balances,
+    /// supplies and settings remain valid and are managed by the `new_account`. The IAH
+    /// issuers list is updated as well.
+    /// Panics if the `old_account` is not an issuer or the `new_account` is already an issuer.
+    /// Emits `issuer_rebind` event.
+    /// Must be called by the authority.
+    pub fn admin_rebind_issuer(&mut self, old_account: AccountId, new_account: AccountId) {
+        self.assert_authority();
+        require!(
+            self.sbt_issuers.get(&new_account).is_none(),
+            "new_account is already an issuer"
+        );
+        let issuer_id = match self.sbt_issuers.remove(&old_account) {
+            Some(id) => id,
+            None => env::panic_str("old_account is not an issuer"),
+        };
+        self.sbt_issuers.insert(&new_account, &issuer_id);
+        self.issuer_id_map.insert(&issuer_id, &new_account);
+        for (issuer, _) in self.iah_sbts.iter_mut() {
+            if *issuer == old_account {
+                *issuer = new_account.clone();
+            }
+        }
+        events::emit_issuer_rebind(&old_account, &new_account, issuer_id);
+    }
+
+    /// Adds an IAH issuer: an account having tokens of all `classes` of the `issuer` is
+    /// a human. The issuer must be already registered. The `classes` list must not be empty;
+    /// it's sorted and deduplicated.
+    /// Must be called by the authority.
+    pub fn admin_add_iah_issuer(&mut self, issuer: AccountId, classes: Vec<ClassId>) {
+        self.assert_authority();
+        let classes = normalize_iah_classes(classes);
+        require!(
+            self.sbt_issuers.get(&issuer).is_some(),
+            "issuer is not registered"
+        );
+        require!(
+            self.iah_sbts.iter().all(|(i, _)| *i != issuer),
+            "issuer is already an IAH issuer"
+        );
+        self.iah_sbts.push((issuer, classes));
+    }
+
+    /// Removes the `issuer` from the IAH issuers. At least one IAH issuer must remain.
+    /// Must be called by the authority.
+    pub fn admin_remove_iah_issuer(&mut self, issuer: AccountId) {
+        self.assert_authority();
+        let len = self.iah_sbts.len();
+        self.iah_sbts.retain(|(i, _)| *i != issuer);
+        require!(self.iah_sbts.len() < len, "issuer is not an IAH issuer");
+        require!(
+            !self.iah_sbts.is_empty(),
+            "can't remove the last IAH issuer"
+        );
+    }
+
+    pub fn change_admin(&mut self, new_admin: AccountId) {
+        self.assert_authority();
+        self.authority = new_admin;
+    }
+
+    pub fn admin_set_authorized_flaggers(&mut self, authorized_flaggers: Vec<AccountId>) {
+        self.assert_authority();
+        self.authorized_flaggers.set(&authorized_flaggers);
+    }
+
+    /// Bans the `account` with the given `scope`, or updates the scope of an already banned
+    /// account. Banned accounts can't receive tokens nor do a soul transfer. Accounts banned
+    /// with `BanScope::Full` additionally have their tokens hidden from `sbt_tokens_by_owner`.
+    /// Emits `Ban` event if the account was not banned before.
+    pub fn admin_ban_with_scope(&mut self, account: AccountId, scope: BanScope) {
+        self.assert_authority();
+        if self.banlist.insert(&account) {
+            Nep393Event::Ban(vec![&account]).emit();
+        }
+        match scope {
+            BanScope::TransferOnly => self.ban_scope.remove(&account),
+            BanScope::Full => self.ban_scope.insert(&account, &scope),
+        };
+    }
+
+    /// Pauses (or unpauses) the `issuer`: a paused issuer can't mint nor renew tokens. Other
+    /// issuer operations (revoke, recover, ...) and the existing tokens are not affected.
+    /// Unlike removing an issuer, the pause is reversible and keeps the issuer registered.
+    /// Must be called by the authority.
+    pub fn admin_pause_issuer(&mut self, issuer: AccountId, paused: bool) {
+        self.assert_authority();
+        let issuer_id = self
+            .sbt_issuers
+            .get(&issuer)
+            .expect("issuer is not registered");
+        if paused {
+            self.paused_issuers.insert(&issuer_id, &());
+        } else {
+            self.paused_issuers.remove(&issuer_id);
+        }
+    }
+
+    /// Emergency deprecation of an issuer: sets `expires_at = now` for all existing tokens
+    /// of the `issuer`, without burning them (tokens stay in storage, but are not valid any
+    /// more). Already expired tokens are not modified.
+    /// The process is resumable: every call processes at most `limit` token IDs. Must be
+    /// called repeatedly until `true` is returned.
+    /// Returns the number of tokens expired in this call and a boolean: `true` if the process
+    /// has finished.
+    /// Emits `Revoke` event for the expired tokens.
+    pub fn admin_expire_issuer(&mut self, issuer: AccountId, limit: u32) -> (u32, bool) {
+        self.assert_authority();
+        require!(limit > 0, "limit must be bigger than 0");
+        let issuer_id = self.assert_issuer(&issuer);
+        let start = self.issuer_expire_cursor.get(&issuer_id).unwrap_or(1);
+        let last = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        let end = std::cmp::min(last.saturating_add(1), start.saturating_add(limit as u64));
+        let now = env::block_timestamp_ms();
+
+        let mut expired = Vec::new();
+        for token in start..end {
+            let key = IssuerTokenId::new(issuer_id, token);
+            if let Some(mut t) = self.issuer_tokens.get(&key) {
+                if !is_token_valid_at(&t, now) {
+                    continue;
+                }
+                let mut m = t.metadata.latest();
+                m.expires_at = Some(now);
+                t.metadata = m.into();
+                self.issuer_tokens.insert(&key, &t);
+                self.record_token_change(issuer_id, token, TokenField::Expiry);
+                expired.push(token);
+            }
+        }
+
+        let finished = end > last;
+        if finished {
+            self.issuer_expire_cursor.remove(&issuer_id);
+        } else {
+            self.issuer_expire_cursor.insert(&issuer_id, &end);
+        }
+        let num_expired = expired.len() as u32;
+        if !expired.is_empty() {
+            SbtTokensEvent {
+                issuer,
+                tokens: expired,
+                reason: Some(RevokeReason::ExpiredPolicy),
+                expires_at: None,
+                renewals: None,
+                event_seq: Some(self.next_event_seq()),
+            }
+            .emit_revoke();
+        }
+        (num_expired, finished)
+    }
+
+    /// Safety net to recover from corrupted supply counters: rescans the `issuer` tokens and
+    /// the balances, and rewrites the issuer supply, the class supplies and the issuer supply
+    /// and total supply of every owner found.
+    /// The process is resumable: every call processes at most `limit` token IDs. The first
+    /// call must use `cursor = None`, which restarts any ongoing rebuild of the issuer.
+    /// Subsequent calls must pass the returned cursor, until `true` is returned.
+    /// The issuer and class supplies are written at the end of the process.
+    /// NOTE: owners without any token of the issuer, and classes without tokens missing in
+    /// the `issuer_class_supplies` index, are not visited. The issuer should not mint or burn
+    /// tokens during the process.
+    /// Returns the next cursor and a boolean: `true` if the process has finished.
+    pub fn admin_rebuild_supplies(
+        &mut self,
+        issuer: AccountId,
+        cursor: Option<TokenId>,
+        limit: u32,
+    ) -> (TokenId, bool) {
+        self.assert_authority();
+        require!(limit > 0, "limit must be bigger than 0");
+        let issuer_id = self.assert_issuer(&issuer);
+        let mut state = match cursor {
+            None => SupplyRebuild {
+                cursor: 1,
+                total: 0,
+                classes: Vec::new(),
+            },
+            Some(cursor) => {
+                let state = self
+                    .supply_rebuild
+                    .get(&issuer_id)
+                    .expect("no ongoing supply rebuild for the issuer");
+                require!(
+                    state.cursor == cursor,
+                    format!("cursor mismatch, expected {}", state.cursor)
+                );
+                state
+            }
+        };
+        let last = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        let end = std::cmp::min(
+            last.saturating_add(1),
+            state.cursor.saturating_add(limit as u64),
+        );
+        for token in state.cursor..end {
+            if let Some(t) = self
+                .issuer_tokens
+                .get(&IssuerTokenId::new(issuer_id, token))
+            {
+                state.total += 1;
+                let class = t.metadata.class_id();
+                match state.classes.binary_search_by_key(&class, |(c, _)| *c) {
+                    Ok(i) => state.classes[i].1 += 1,
+                    Err(i) => state.classes.insert(i, (class, 1)),
+                }
+                self.rebuild_owner_supply(issuer_id, &t.owner);
+            }
+        }
+        state.cursor = end;
+        if end <= last {
+            self.supply_rebuild.insert(&issuer_id, &state);
+            return (end, false);
+        }
+
+        self.supply_rebuild.remove(&issuer_id);
+        self.supply_by_issuer.insert(&issuer_id, &state.total);
+        for class in self.issuer_classes.get(&issuer_id).unwrap_or_default() {
+            if state
+                .classes
+                .binary_search_by_key(&class, |(c, _)| *c)
+                .is_err()
+            {
+                self.set_class_supply(issuer_id, class, 0);
+            }
+        }
+        for (class, supply) in state.classes {
+            self.set_class_supply(issuer_id, class, supply);
+        }
+        (end, true)
+    }
+
+    /// Completes the `migrate`: backfills the owner total supplies, `total_souls` and the
+    /// `issuer_classes` index from the balances. The process is resumable: every call
+    /// processes at most `limit` balances and must be repeated until `true` is returned.
+    /// Until then, the transactions updating the supplies (mint, burn, soul transfer,
+    /// recover, ...) and the queries depending on the backfilled data (`total_souls`,
+    /// `issuer_class_supplies`, `check_id_invariants`) panic.
+    /// Logs `migrate_backfill_stats: {"souls":<number of token holders>}` when finished.
+    /// Returns the number of processed balances and a boolean: `true` if the process has
+    /// finished.
+    pub fn admin_migration_backfill(&mut self, limit: u32) -> (u32, bool) {
+        self.assert_authority();
+        require!(limit > 0, "limit must be bigger than 0");
+        let mut state = match self.migration_backfill.take() {
+            None => return (0, true),
+            Some(s) => s,
+        };
+        let iter: Box<dyn Iterator<Item = (BalanceKey, TokenId)>> = match state.cursor {
+            None => Box::new(self.balances.iter()),
+            Some(ref key) => Box::new(self.balances.iter_from(key.clone())),
+        };
+        let batch: Vec<BalanceKey> = iter.take(limit as usize).map(|(key, _)| key).collect();
+        let processed = batch.len() as u32;
+        // balances are ordered by owner, so we update each owner total once per batch.
+        let mut owners: Vec<(AccountId, u64)> = Vec::new();
+        let mut classes: BTreeMap<IssuerId, BTreeSet<ClassId>> = BTreeMap::new();
+        for key in &batch {
+            match owners.last_mut() {
+                Some((owner, n)) if *owner == key.owner => *n += 1,
+                _ => owners.push((key.owner.clone(), 1)),
+            }
+            classes
+                .entry(key.issuer_id)
+                .or_default()
+                .insert(key.class_id.0);
+        }
+        for (owner, n) in owners {
+            self.add_owner_total(&owner, n);
+        }
+        for (issuer_id, batch_classes) in classes {
+            let mut issuer_classes = self.issuer_classes.get(&issuer_id).unwrap_or_default();
+            for class in batch_classes {
+                if let Err(i) = issuer_classes.binary_search(&class) {
+                    issuer_classes.insert(i, class);
+                }
+            }
+            self.issuer_classes.insert(&issuer_id, &issuer_classes);
+        }
+        if processed < limit {
+            env::log_str(&format!(
+                r#"migrate_backfill_stats: {{"souls":{}}}"#,
+                self.total_souls
+            ));
+            return (processed, true);
+        }
+        state.cursor = batch.last().cloned();
+        self.migration_backfill = Some(state);
+        (processed, false)
+    }
+
+    /// Registers a `hook` contract to be notified when a soul transfer completes. The registry
+    /// will call `hook.on_soul_transfer({from, to})` with a fixed amount of gas, ignoring the
+    /// result. Every hook increases the soul transfer gas cost, hence max `MAX_SOUL_TX_HOOKS`
+    /// hooks can be registered.
+    /// Returns false if the `hook` was already registered.
+    pub fn admin_register_soul_tx_hook(&mut self, hook: AccountId) -> bool {
+        self.assert_authority();
+        let mut hooks = self.soul_tx_hooks.get().unwrap_or_default();
+        if hooks.contains(&hook) {
+            return false;
+        }
+        require!(
+            hooks.len() < MAX_SOUL_TX_HOOKS,
+            format!(
+                "max {} soul transfer hooks can be registered",
+                MAX_SOUL_TX_HOOKS
+            )
+        );
+        hooks.push(hook);
+        self.soul_tx_hooks.set(&hooks);
+        true
+    }
+
+    /// Returns false if the `hook` was not registered.
+    pub fn admin_unregister_soul_tx_hook(&mut self, hook: AccountId) -> bool {
+        self.assert_authority();
+        let mut hooks = self.soul_tx_hooks.get().unwrap_or_default();
+        let len = hooks.len();
+        hooks.retain(|h| h != &hook);
+        if hooks.len() == len {
+            return false;
+        }
+        self.soul_tx_hooks.set(&hooks);
+        true
+    }
+
+    /// Sets the max amount of tokens transferred in a single `sbt_soul_transfer` call.
+    /// Should be tuned to the network gas limits. Panics if `n == 0`.
+    pub fn admin_set_soul_tx_batch(&mut self, n: u32) {
+        self.assert_authority();
+        require!(n > 0, "soul transfer batch must be bigger than 0");
+        self.soul_tx_batch = n;
+    }
+
+    /// Returns true if account was added. Returns false if account was already authorized.
+    pub fn admin_add_authorized_flagger(&mut self, account: AccountId) -> bool {
+        self.assert_authority();
+        match self.authorized_flaggers.get() {
+            None => {
+                self.authorized_flaggers.set(&vec![account]);
+                true
+            }
+            Some(mut a) => {
+                if a.contains(&account) {
+                    false
+                } else {
+                    a.push(account);
+                    self.authorized_flaggers.set(&a);
+                    true
+                }
+            }
+        }
+    }
+
+    /// Sets a flag for every account in the `accounts` list, overwriting if needed.
+    /// Panics if a caller is not flagged.
+    /// Panics if any of the account is blacklisted.
+    pub fn admin_flag_accounts(
+        &mut self,
+        flag: AccountFlag,
+        accounts: Vec<AccountId>,
+        #[allow(unused_variables)] memo: String,
+    ) {
+        self.assert_authorized_flagger();
+        for a in &accounts {
+            self.assert_not_banned(a);
+            self.flagged.insert(a, &flag);
+        }
+        events::emit_iah_flag_accounts(flag, accounts);
+    }
+
+    /// removes flag from the provided account list.
+    /// Panics if an account is not currently flagged.
+    pub fn admin_unflag_accounts(
+        &mut self,
+        accounts: Vec<AccountId>,
+        #[allow(unused_variables)] memo: String,
+    ) {
+        self.assert_authorized_flagger();
+        for a in &accounts {
+            require!(self.flagged.remove(a).is_some());
+        }
+        events::emit_iah_unflag_accounts(accounts);
+    }
+
+    //
+    // Internal
+    //
+
+    /// Queries a given token. Panics if token doesn't exist
+    pub(crate) fn get_token(&self, issuer_id: IssuerId, token: TokenId) -> TokenData {
+        self.issuer_tokens
+            .get(&IssuerTokenId::new(issuer_id, token))
+            .unwrap_or_else(|| panic!("token {} not found", token))
+    }
+
+    pub(crate) fn assert_backfilled(&self) {
+        require!(
+            self.migration_backfill.is_none(),
+            "migration backfill in progress, see admin_migration_backfill"
+        );
+    }
+
+    /// sets the supply of the (issuer, class) pair and updates the `issuer_classes` index:
+    /// a class is added with the first token and removed when the supply hits 0.
+    pub(crate) fn set_class_supply(&mut self, issuer_id: IssuerId, class: ClassId, supply: u64) {
+        self.assert_backfilled();
+        let prev = self
+            .supply_by_class
+            .insert(&(issuer_id, class), &supply)
+            .unwrap_or(0);
+        if (prev == 0) == (supply == 0) {
+            return;
+        }
+        let mut classes = self.issuer_classes.get(&issuer_id).unwrap_or_default();
+        match classes.binary_search(&class) {
+            Ok(i) if supply == 0 => {
+                classes.remove(i);
+            }
+            Err(i) if supply > 0 => classes.insert(i, class),
+            _ => return,
+        }
+        if classes.is_empty() {
+            self.issuer_classes.remove(&issuer_id);
+        } else {
+            self.issuer_classes.insert(&issuer_id, &classes);
+        }
+    }
+
+    /// appends a `field` update to the token history, dropping the oldest entry when the
+    /// history has `MAX_TOKEN_HISTORY` entries. The history storage is prepaid at mint, see
+    /// `TOKEN_UPDATES_STORAGE`.
+    pub(crate) fn record_token_change(
+        &mut self,
+        issuer_id: IssuerId,
+        token: TokenId,
+        field: TokenField,
+    ) {
+        let key = IssuerTokenId::new(issuer_id, token);
+        let mut history = self.token_history.get(&key).unwrap_or_default();
+        if history.len() >= MAX_TOKEN_HISTORY {
+            history.remove(0);
+        }
+        history.push(TokenChange {
+            field,
+            at_ms: env::block_timestamp_ms(),
+        });
+        self.token_history.insert(&key, &history);
+    }
+
+    /// increments the event sequence counter and returns the `event_seq` for the next event.
+    pub(crate) fn next_event_seq(&mut self) -> u64 {
+        self.event_seq += 1;
+        self.event_seq
+    }
+
+    /// moves `moved` issuer tokens from the `owner` to the `recipient`, removes `burned` ones.
+    fn move_owner_supply(
+        &mut self,
+        owner: &AccountId,
+        recipient: &AccountId,
+        issuer_id: IssuerId,
+        moved: u64,
+        burned: u64,
+    ) {
+        if moved + burned == 0 {
+            return;
+        }
+        let key = &(owner.clone(), issuer_id);
+        let supply = self.supply_by_owner.get(key).unwrap_or(0) - moved - burned;
+        if supply == 0 {
+            self.supply_by_owner.remove(key);
+        } else {
+            self.supply_by_owner.insert(key, &supply);
+        }
+        if moved > 0 {
+            let key = &(recipient.clone(), issuer_id);
+            let supply_to = self.supply_by_owner.get(key).unwrap_or(0);
+            self.supply_by_owner.insert(key, &(moved + supply_to));
+        }
+    }
+
+    /// emits `Burn` event for the pinned tokens burned by a soul transfer.
+    fn emit_pinned_burn(&mut self, issuer_id: IssuerId, tokens: Vec<TokenId>) {
+        if tokens.is_empty() {
+            return;
+        }
+        SbtTokensEvent {
+            issuer: self.issuer_by_id(issuer_id),
+            tokens,
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(self.next_event_seq()),
+        }
+        .emit_burn();
+    }
+
+    /// adds `n` tokens to the `owner` total supply. Counts a new soul if the owner didn't
+    /// have any token.
+    pub(crate) fn add_owner_total(&mut self, owner: &AccountId, n: u64) {
+        self.assert_backfilled();
+        if n == 0 {
+            return;
+        }
+        let total = self.supply_by_owner_total.get(owner).unwrap_or(0);
+        if total == 0 {
+            self.total_souls += 1;
+        }
+        self.supply_by_owner_total.insert(owner, &(total + n));
+    }
+
+    /// subtracts `n` tokens from the `owner` total supply. Removes the soul if the owner
+    /// doesn't have any token left.
+    pub(crate) fn sub_owner_total(&mut self, owner: &AccountId, n: u64) {
+        self.assert_backfilled();
+        if n == 0 {
+            return;
+        }
+        let total = self.supply_by_owner_total.get(owner).unwrap_or(0);
+        require!(total >= n, "internal error: owner total supply underflow");
+        if total == n {
+            self.supply_by_owner_total.remove(owner);
+            self.total_souls -= 1;
+        } else {
+            self.supply_by_owner_total.insert(owner, &(total - n));
+        }
+    }
+
+    /// sets the `owner` supply of the issuer tokens and the owner total supply based on the
+    /// balances, and updates `total_souls` accordingly.
+    fn rebuild_owner_supply(&mut self, issuer_id: IssuerId, owner: &AccountId) {
+        self.assert_backfilled();
+        let mut supply = 0;
+        let mut new_total = 0;
+        for (key, _) in self
+            .balances
+            .iter_from(balance_key(owner.clone(), 0, ClassKey(0)))
+            .take_while(|(key, _)| key.owner == *owner)
+        {
+            new_total += 1;
+            if key.issuer_id == issuer_id {
+                supply += 1;
+            }
+        }
+        let key = &(owner.clone(), issuer_id);
+        if supply == 0 {
+            self.supply_by_owner.remove(key);
+        } else {
+            self.supply_by_owner.insert(key, &supply);
+        }
+        let total = self.supply_by_owner_total.get(owner).unwrap_or(0);
+        if total == new_total {
+            return;
+        }
+        if new_total == 0 {
+            self.supply_by_owner_total.remove(owner);
+        } else {
+            self.supply_by_owner_total.insert(owner, &new_total);
+        }
+        if total == 0 && new_total > 0 {
+            self.total_souls += 1;
+        } else if total > 0 && new_total == 0 {
+            self.total_souls -= 1;
+        }
+    }
+
+    /// updates the internal token counter based on how many tokens we want to mint (num), and
+    /// returns the first valid TokenId for newly minted tokens.
+    pub(crate) fn next_token_id(&mut self, issuer_id: IssuerId, num: u64) -> TokenId {
+        let tid = self.next_token_ids.get(&issuer_id).unwrap_or(0);
+        self.next_token_ids.insert(&issuer_id, &(tid + num));
+        tid + 1
+    }
+
+    #[inline]
+    pub(crate) fn assert_authorized_flagger(&self) {
+        let caller = env::predecessor_account_id();
+        let a = self.authorized_flaggers.get();
+        if a.is_none() || !a.unwrap().contains(&caller) {
+            env::panic_str("not authorized");
+        }
+    }
+
+    #[inline]
+    pub(crate) fn assert_not_banned(&self, owner: &AccountId) {
+        require!(
+            !self.banlist.contains(owner),
+            format!("account {} is banned", owner)
+        );
+    }
+
+    /// Calls `on_soul_transfer` on every registered soul transfer hook. Promises are detached,
+    /// so a failing hook doesn't affect the soul transfer.
+    fn notify_soul_tx_hooks(&self, from: &AccountId, to: &AccountId) {
+        let hooks = match self.soul_tx_hooks.get() {
+            None => return,
+            Some(h) => h,
+        };
+        let args = serde_json::to_vec(&SoulTransferHookArgs { from, to }).unwrap();
+        for h in hooks {
+            Promise::new(h).function_call(
+                "on_soul_transfer".to_owned(),
+                args.clone(),
+                0,
+                SOUL_TX_HOOK_GAS,
+            );
+        }
+    }
+
+    /// Panics if the attached deposit doesn't cover the storage increase since `storage_start`.
+    pub(crate) fn assert_storage_deposit(storage_start: u64) {
+        let storage_usage = env::storage_usage();
+        if storage_usage > storage_start {
+            let required_deposit =
+                (storage_usage - storage_start) as u128 * env::storage_byte_cost();
+            require!(
+                env::attached_deposit() >= required_deposit,
+                format!(
+                    "not enough NEAR storage deposit, required: {}",
+                    required_deposit
+                )
+            );
+        }
+    }
+
+    /// Same as `assert_storage_deposit`, but the part of the attached deposit not used to
+    /// cover the storage growth is refunded to the predecessor.
+    pub(crate) fn charge_storage_deposit(storage_start: u64) {
+        let storage_usage = env::storage_usage();
+        let required_deposit =
+            storage_usage.saturating_sub(storage_start) as u128 * env::storage_byte_cost();
+        let deposit = env::attached_deposit();
+        require!(
+            deposit >= required_deposit,
+            format!(
+                "not enough NEAR storage deposit, required: {}",
+                required_deposit
+            )
+        );
+        if deposit > required_deposit {
+            Promise::new(env::predecessor_account_id()).transfer(deposit - required_deposit);
+        }
+    }
+
+    /// Bounds the per token storage: `reference` can't be longer than `MAX_REFERENCE_LEN`
+    /// bytes and `reference_hash`, if provided, must be 32 or 64 bytes long.
+    pub(crate) fn assert_reference_size(
+        reference: &Option<String>,
+        reference_hash: &Option<Base64VecU8>,
+    ) {
+        if let Some(r) = reference {
+            require!(
+                r.len() <= MAX_REFERENCE_LEN,
+                format!("reference must be at most {} bytes", MAX_REFERENCE_LEN)
+            );
+        }
+        if let Some(h) = reference_hash {
+            require!(
+                h.0.len() == 32 || h.0.len() == 64,
+                "reference_hash must be 32 or 64 bytes"
+            );
+        }
+    }
+
+    /// Bounds the per token storage of the minted `metadata`: see `assert_reference_size`.
+    /// `attestation`, if provided, must be non empty and at most `MAX_ATTESTATION_LEN` bytes.
+    pub(crate) fn assert_metadata_size(metadata: &TokenMetadata) {
+        Self::assert_reference_size(&metadata.reference, &metadata.reference_hash);
+        if let Some(a) = &metadata.attestation {
+            require!(
+                !a.0.is_empty() && a.0.len() <= MAX_ATTESTATION_LEN,
+                format!("attestation must be 1 to {} bytes", MAX_ATTESTATION_LEN)
+            );
+        }
+    }
+
+    /// Requires the `reference_hash` to be a sha256 hash (32 bytes) when the `reference` is
+    /// set, unless the issuer opted out (see `set_reference_hash_opt_out`).
+    pub(crate) fn assert_reference_hash(
+        &self,
+        issuer_id: IssuerId,
+        reference: &Option<String>,
+        reference_hash: &Option<Base64VecU8>,
+    ) {
+        if let (Some(_), Some(h)) = (reference, reference_hash) {
+            require!(
+                h.0.len() == 32 || self.reference_hash_opt_out.contains_key(&issuer_id),
+                "reference_hash must be a 32 bytes sha256 hash"
+            );
+        }
+    }
+
+    /// note: use issuer_id() if you need issuer_id
+    /// Panics with the `ERR_NOT_ISSUER` code and the `issuer` account if the `issuer` is not
+    /// registered.
+    pub(crate) fn assert_issuer(&self, issuer: &AccountId) -> IssuerId {
+        // TODO: use Result rather than panic
+        match self.sbt_issuers.get(issuer) {
+            Some(id) => id,
+            None => env::panic_str(&format!(
+                "{}: must be called by a registered SBT Issuer, caller: {}",
+                ERR_NOT_ISSUER, issuer
+            )),
+        }
+    }
+
+    pub(crate) fn assert_issuer_not_paused(&self, issuer_id: IssuerId) {
+        require!(!self._is_issuer_paused(issuer_id), "issuer is paused");
+    }
+
+    pub(crate) fn issuer_by_id(&self, id: IssuerId) -> AccountId {
+        self.issuer_id_map
+            .get(&id)
+            .expect("internal error: inconsistent sbt issuer map")
+    }
+
+    /// Decrements the `caller` in flight `is_human_call` counter.
+    fn end_is_human_call(&mut self, caller: &AccountId) {
+        match self.ongoing_is_human_calls.get(caller).unwrap_or(0) {
+            0 | 1 => {
+                self.ongoing_is_human_calls.remove(caller);
+            }
+            n => {
+                self.ongoing_is_human_calls.insert(caller, &(n - 1));
+            }
+        }
+    }
+
+    pub(crate) fn assert_authority(&self) {
+        require!(
+            self.authority == env::predecessor_account_id(),
+            "not an admin"
+        )
+    }
+
+    fn _add_sbt_issuer(&mut self, issuer: &AccountId) -> bool {
+        if self.sbt_issuers.get(issuer).is_some() {
+            return false;
+        }
+        self.sbt_issuers.insert(issuer, &self.next_issuer_id);
+        let prev = self.issuer_id_map.insert(&self.next_issuer_id, issuer);
+        require!(prev.is_none(), "internal error: issuer id already assigned");
+        self.next_issuer_id += 1;
+        true
+    }
+
+    fn _sbt_renew(&mut self, issuer: AccountId, tokens: Vec<TokenId>, expires_at: u64) {
+        let issuer_id = self.assert_issuer(&issuer);
+        self.assert_issuer_not_paused(issuer_id);
+        self.renew_tokens(issuer, issuer_id, tokens, expires_at);
+    }
+
+    /// Sets `expires_at` of the issuer `tokens` and emits the `Renew` event.
+    fn renew_tokens(
+        &mut self,
+        issuer: AccountId,
+        issuer_id: IssuerId,
+        tokens: Vec<TokenId>,
+        expires_at: u64,
+    ) {
+        let mut renewed = Vec::with_capacity(tokens.len());
+        let mut renewals = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let mut t = self.get_token(issuer_id, token);
+            self.assert_not_banned(&t.owner);
+            // skip tokens which already have the requested expire time
+            if t.metadata.expires_at() == Some(expires_at) {
+                continue;
+            }
+            let mut m = t.metadata.latest();
+            renewals.push(TokenRenewal {
+                token,
+                old_expires_at: m.expires_at,
+                expires_at,
+            });
+            m.expires_at = Some(expires_at);
+            t.metadata = m.into();
+            self.issuer_tokens
+                .insert(&IssuerTokenId::new(issuer_id, token), &t);
+            self.record_token_change(issuer_id, token, TokenField::Expiry);
+            renewed.push(token);
+        }
+        if !renewed.is_empty() {
+            SbtTokensEvent {
+                issuer,
+                tokens: renewed,
+                reason: None,
+                expires_at: None,
+                renewals: Some(renewals),
+                event_seq: Some(self.next_event_seq()),
+            }
+            .emit_renew();
+        }
+    }
+
+    fn _sbt_mint(
+        &mut self,
+        issuer: &AccountId,
+        token_spec: Vec<(AccountId, Vec<TokenMetadata>)>,
+    ) -> Vec<TokenId> {
+        let issuer_id = self.assert_issuer(issuer);
+        self.assert_issuer_not_paused(issuer_id);
+        let mut num_tokens = 0;
+        for el in token_spec.iter() {
+            num_tokens += el.1.len() as u64;
+        }
+        let mut token = self.next_token_id(issuer_id, num_tokens);
+        let token_spec = token_spec
+            .into_iter()
+            .map(|(owner, metadatas)| {
+                let tokens = metadatas
+                    .into_iter()
+                    .map(|m| {
+                        token += 1;
+                        (token - 1, m)
+                    })
+                    .collect();
+                (owner, tokens)
+            })
+            .collect();
+        self.mint_tokens(issuer, issuer_id, token_spec)
+    }
+
+    /// Sets the `metadata` times not provided by the issuer: `issued_at` to `now` and
+    /// `expires_at` based on the issuer default expiry `policy`.
+    fn set_mint_times(metadata: &mut TokenMetadata, now: u64, policy: Option<ExpiryPolicy>) {
+        if metadata.issued_at.is_none() {
+            metadata.issued_at = Some(now);
+        }
+        if metadata.expires_at.is_none() {
+            metadata.expires_at = policy.and_then(|p| p.expires_at(now));
+        }
+    }
+
+    /// Mints tokens with already assigned IDs: `token_spec` is a list of pairs: owner and
+    /// list of (token ID, metadata). Returns the minted token IDs, in the `token_spec` order.
+    /// Checks the attached deposit covers the storage cost.
+    fn mint_tokens(
+        &mut self,
+        issuer: &AccountId,
+        issuer_id: IssuerId,
+        token_spec: Vec<(AccountId, Vec<(TokenId, TokenMetadata)>)>,
+    ) -> Vec<TokenId> {
+        let storage_start = env::storage_usage();
+        let storage_deposit = env::attached_deposit();
+        let mut num_tokens = 0;
+        let mut ret_token_ids = Vec::new();
+        let mut supply_by_class = HashMap::new();
+        let mut per_recipient: HashMap<AccountId, Vec<TokenId>> = HashMap::new();
+        let now = env::block_timestamp_ms();
+        let expiry_policy = self.expiry_policy.get(&issuer_id);
+
+        for (owner, metadatas) in token_spec {
+            // no need to check ongoing_soult_tx, because it will automatically ban the source account
+            self.assert_not_banned(&owner);
+
+            let recipient_tokens = per_recipient.entry(owner.clone()).or_default();
+            let metadatas_len = metadatas.len();
+
+            for (token, mut metadata) in metadatas {
+                require!(metadata.class > 0, "Class must be > 0");
+                Self::assert_metadata_size(&metadata);
+                self.assert_reference_hash(
+                    issuer_id,
+                    &metadata.reference,
+                    &metadata.reference_hash,
+                );
+                require!(
+                    self._is_class_allowed(issuer_id, metadata.class, &owner),
+                    format!(
+                        "{} is not allowed to receive SBT of class {}",
+                        owner, metadata.class
+                    )
+                );
+                Self::set_mint_times(&mut metadata, now, expiry_policy);
+                let prev = self.balances.insert(
+                    &balance_key(owner.clone(), issuer_id, ClassKey(metadata.class)),
+                    &token,
+                );
+                require!(
+                    prev.is_none(),
+                    format! {"{} already has SBT of class {}", owner, metadata.class}
+                );
+
+                // update supply by class
+                match supply_by_class.get_mut(&metadata.class) {
+                    None => {
+                        supply_by_class.insert(metadata.class, 1);
+                    }
+                    Some(s) => *s += 1,
+                };
+
+                let prev = self.issuer_tokens.insert(
+                    &IssuerTokenId::new(issuer_id, token),
+                    &TokenData {
+                        owner: owner.clone(),
+                        metadata: metadata.into(),
+                    },
+                );
+                // token IDs are never reused, see `check_id_invariants`.
+                require!(
+                    prev.is_none(),
+                    format!("internal error: token {} already exists", token)
+                );
+                recipient_tokens.push(token);
+                ret_token_ids.push(token);
+                num_tokens += 1;
+            }
+
+            // update supply by owner
+            self.add_owner_total(&owner, metadatas_len as u64);
+            let skey = (owner, issuer_id);
+            let sowner = self.supply_by_owner.get(&skey).unwrap_or(0) + metadatas_len as u64;
+            self.supply_by_owner.insert(&skey, &sowner);
+        }
+
+        for (cls, new_supply) in supply_by_class {
+            let s = self.supply_by_class.get(&(issuer_id, cls)).unwrap_or(0) + new_supply;
+            self.set_class_supply(issuer_id, cls, s);
+        }
+
+        let new_supply = self.supply_by_issuer.get(&issuer_id).unwrap_or(0) + num_tokens;
+        self.supply_by_issuer.insert(&issuer_id, &new_supply);
+
+        if self.compact_events.contains_key(&issuer_id) {
+            let mut tokens: Vec<TokenId> = per_recipient.values().flatten().copied().collect();
+            tokens.sort_unstable();
+            let event_seq = self.next_event_seq();
+            events::emit_mint_compact(issuer, per_recipient.len(), &tokens, event_seq);
+        } else {
+            let mut minted: Vec<(&AccountId, &Vec<TokenId>)> = per_recipient.iter().collect();
+            minted.sort_by(|a, b| a.0.cmp(b.0));
+            SbtMint {
+                issuer,
+                tokens: minted,
+                event_seq: Some(self.next_event_seq()),
+            }
+            .emit();
+        }
+
+        let storage_used = env::storage_usage() - storage_start;
+        // structured log (not an event) for cost tuning: operators can compare the attached
+        // deposit with the realized storage cost.
+        env::log_str(&format!(
+            r#"mint_stats: {{"tokens":{},"storage_bytes":{}}}"#,
+            num_tokens, storage_used
+        ));
+        let required_deposit =
+            (storage_used + num_tokens * TOKEN_UPDATES_STORAGE) as u128 * env::storage_byte_cost();
+        require!(
+            storage_deposit >= required_deposit,
+            format!(
+                "not enough NEAR storage deposit, required: {}",
+                required_deposit
+            )
+        );
+
+        ret_token_ids
+    }
+
+    /// Method to help parametrize the sbt_burn_all.
+    /// limit indicates the number of tokens that will be burned in one call
+    pub(crate) fn _sbt_burn_all(&mut self, limit: u32) -> bool {
+        let owner = env::predecessor_account_id();
+        require!(
+            !self.ongoing_soul_tx.contains_key(&owner),
+            "can't burn tokens while in soul_transfer"
+        );
+        let mut tokens_burned: u32 = 0;
+
+        let issuer_token_pair_vec =
+            self._sbt_tokens_by_owner(owner.clone(), None, None, Some(limit), Some(true));
+        for (issuer, tokens) in issuer_token_pair_vec.iter() {
+            let mut token_ids = Vec::new();
+            let issuer_id = self.assert_issuer(issuer);
+            let mut tokens_burned_per_issuer: u64 = 0;
+            for t in tokens.iter() {
+                token_ids.push(t.token);
+                self.issuer_tokens
+                    .remove(&IssuerTokenId::new(issuer_id, t.token));
+                self.token_history
+                    .remove(&IssuerTokenId::new(issuer_id, t.token));
+                let class_id = t.metadata.class;
+                self.balances
+                    .remove(&balance_key(owner.clone(), issuer_id, ClassKey(class_id)));
+
+                // update supply by class
+                let supply = self.supply_by_class.get(&(issuer_id, class_id)).unwrap();
+                self.set_class_supply(issuer_id, class_id, supply - 1);
+                tokens_burned_per_issuer += 1;
+                tokens_burned += 1;
+                if tokens_burned >= limit {
+                    break;
+                }
+            }
+
+            // update supply by owner
+            let key = (owner.clone(), issuer_id);
+            let mut supply = self.supply_by_owner.get(&key).unwrap();
+            supply -= tokens_burned_per_issuer;
+            self.supply_by_owner.insert(&key, &supply);
+            self.sub_owner_total(&owner, tokens_burned_per_issuer);
+
+            // update total supply by issuer
+            let mut supply = self.supply_by_issuer.get(&issuer_id).unwrap();
+            supply -= tokens_burned_per_issuer;
+            self.supply_by_issuer.insert(&issuer_id, &supply);
+
+            SbtTokensEvent {
+                issuer: issuer.to_owned(),
+                tokens: token_ids.clone(),
+                reason: None,
+                expires_at: None,
+                renewals: None,
+                event_seq: Some(self.next_event_seq()),
+            }
+            .emit_burn();
+            if tokens_burned >= limit {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Helper function for `sbt_revoke_by_owner`
+    /// `sbt_tokens_by_owner` without the ban scope check. Used internally by the registry
+    /// to handle tokens of accounts banned with `BanScope::Full`.
+    pub(crate) fn _sbt_tokens_by_owner(
+        &self,
+        account: AccountId,
+        issuer: Option<AccountId>,
+        from_class: Option<u64>,
+        limit: Option<u32>,
+        with_expired: Option<bool>,
+    ) -> Vec<(AccountId, Vec<OwnedToken>)> {
+        if from_class.is_some() {
+            require!(
+                issuer.is_some(),
+                "issuer must be defined if from_class is defined"
+            );
+        }
+        if self.ongoing_soul_tx.contains_key(&account) {
+            return vec![];
+        }
+
+        let issuer_id = match &issuer {
+            None => 0,
+            Some(addr) => self.assert_issuer(addr),
+        };
+        let from_class = from_class.unwrap_or(0);
+        // iter_from starts from exclusive "left end". We need to iteretare from one before.
+        let first_key = balance_key(account, issuer_id, ClassKey(from_class.saturating_sub(1)));
+        let limit = limit.unwrap_or(MAX_LIMIT);
+        require!(limit > 0, "limit must be bigger than 0");
+        self.owner_tokens_after(
+            first_key,
+            issuer.is_some(),
+            limit,
+            with_expired.unwrap_or(false),
+        )
+        .0
+    }
+
+    /// Lists at most `limit` (in total, across issuers) tokens of the `after.owner`, grouped
+    /// by issuer, starting after the `after` balance key (exclusive). Only the `after.issuer_id`
+    /// tokens are listed when `single_issuer` is set. When the `limit` is hit, additionally
+    /// returns the balance key of the last listed token.
+    fn owner_tokens_after(
+        &self,
+        after: BalanceKey,
+        single_issuer: bool,
+        mut limit: u32,
+        with_expired: bool,
+    ) -> (Vec<(AccountId, Vec<OwnedToken>)>, Option<BalanceKey>) {
+        let account = after.owner.clone();
+        let issuer_id = after.issuer_id;
+        let now = env::block_timestamp_ms();
+
+        let mut resp = Vec::new();
+        let mut tokens = Vec::new();
+        let mut prev_issuer = issuer_id;
+        let mut last_key = None;
+
+        for (key, token_id) in self.balances.iter_from(after) {
+            if key.owner != account {
+                break;
+            }
+            if prev_issuer != key.issuer_id {
+                if single_issuer {
+                    break;
+                }
+                if !tokens.is_empty() {
+                    let issuer = self.issuer_by_id(prev_issuer);
+                    resp.push((issuer, tokens));
+                    tokens = Vec::new();
+                }
+                prev_issuer = key.issuer_id;
+            }
+            let t: TokenData = self.get_token(key.issuer_id, token_id);
+            if !with_expired && !is_token_valid_at(&t, now) {
+                continue;
+            }
+            tokens.push(OwnedToken {
+                token: token_id,
+                metadata_version: t.metadata.version(),
+                metadata: t.metadata.latest(),
+            });
+            limit -= 1;
+            if limit == 0 {
+                last_key = Some(key);
+                break;
+            }
+        }
+        if prev_issuer != 0 && !tokens.is_empty() {
+            let issuer = self.issuer_by_id(prev_issuer);
+            resp.push((issuer, tokens));
+        }
+        (resp, last_key)
+    }
+
+    fn sbt_token_ids_by_owner(
+        &self,
+        account: AccountId,
+        issuer_id: u32,
+        limit: u32,
+    ) -> Vec<(TokenId, ClassId)> {
+        let first_key = balance_key(account.clone(), issuer_id, ClassKey(0));
+
+        assert!(limit > 0, "limit must be bigger than 0");
+
+        self.balances
+            .iter_from(first_key)
+            .take_while(|(key, _)| key.owner == account && key.issuer_id == issuer_id)
+            .map(|(key, token_id)| (token_id, key.class_id.0))
+            .take(limit as usize)
+            .collect()
+    }
+}
+
+/// Returns the sorted and deduplicated IAH `classes`. Panics if the list is empty.
+pub(crate) fn normalize_iah_classes(mut classes: Vec<ClassId>) -> Vec<ClassId> {
+    require!(!classes.is_empty(), "iah_classes must be a non empty list");
+    classes.sort_unstable();
+    classes.dedup();
+    classes
+}
+
+/// Returns the `is_human` grace period (default 0). Panics if it's bigger than
+/// `MAX_HUMAN_GRACE_MS`.
+fn assert_human_grace(grace_ms: Option<u64>) -> u64 {
+    let grace_ms = grace_ms.unwrap_or(0);
+    require!(
+        grace_ms <= MAX_HUMAN_GRACE_MS,
+        format!("grace_ms must be at most {}", MAX_HUMAN_GRACE_MS)
+    );
+    grace_ms
+}
+
+/// Validates the function name called by `is_human_call*`.
+fn validate_function_name(function: &str) -> Result<(), IsHumanCallErr> {
+    if function.is_empty() || function.len() > MAX_FUNCTION_NAME_LEN {
+        return Err(IsHumanCallErr::InvalidFunctionName);
+    }
+    Ok(())
+}
+
+/// Returns the gas to forward in `is_human_call*`: prepaid gas reduced by the gas `reserved`
+/// for the registry. Fails if it's less than `MIN_IS_HUMAN_CALL_GAS`.
+fn forwarded_gas(reserved: Gas) -> Result<Gas, IsHumanCallErr> {
+    let required = reserved + MIN_IS_HUMAN_CALL_GAS;
+    let prepaid = env::prepaid_gas();
+    if prepaid < required {
+        return Err(IsHumanCallErr::NotEnoughGas(required.0));
+    }
+    Ok(prepaid - reserved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Mul;
+
+    use cost::MILI_NEAR;
+    use near_sdk::json_types::Base64VecU8;
+    use near_sdk::test_utils::{self, VMContextBuilder};
+    use near_sdk::{testing_env, Balance, FunctionError, Gas, VMContext};
+    use sbt::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn alice() -> AccountId {
+        AccountId::new_unchecked("alice.near".to_string())
+    }
+
+    fn alice2() -> AccountId {
+        AccountId::new_unchecked("alice.nea".to_string())
+    }
+
+    fn bob() -> AccountId {
+        AccountId::new_unchecked("bob.near".to_string())
+    }
+
+    fn carol() -> AccountId {
+        AccountId::new_unchecked("carol.near".to_string())
+    }
+
+    fn dan() -> AccountId {
+        AccountId::new_unchecked("dan.near".to_string())
+    }
+
+    fn issuer1() -> AccountId {
+        AccountId::new_unchecked("sbt.n".to_string())
+    }
+
+    fn issuer2() -> AccountId {
+        AccountId::new_unchecked("sbt.ne".to_string())
+    }
+
+    fn issuer3() -> AccountId {
+        AccountId::new_unchecked("sbt.nea".to_string())
+    }
+
+    fn issuer4() -> AccountId {
+        AccountId::new_unchecked("sbt4.near".to_string())
+    }
+
+    fn admins_flagged() -> Vec<AccountId> {
+        vec![AccountId::new_unchecked("admin_flagged.near".to_string())]
+    }
+
+    #[inline]
+    fn fractal_mainnet() -> AccountId {
+        AccountId::new_unchecked("fractal.i-am-human.near".to_string())
+    }
+
+    fn admin() -> AccountId {
+        AccountId::new_unchecked("sbt.near".to_string())
+    }
+
+    fn mk_metadata(class: ClassId, expires_at: Option<u64>) -> TokenMetadata {
+        TokenMetadata {
+            class,
+            issued_at: Some(START),
+            expires_at,
+            reference: Some("abc".to_owned()),
+            reference_hash: Some(vec![61; 32].into()),
+            score: None,
+            attestation: None,
+        }
+    }
+
+    /// returns a token issued by `issuer1`.
+    fn mk_token(token: TokenId, owner: AccountId, metadata: TokenMetadata) -> Token {
+        mk_issuer_token(2, token, owner, metadata)
+    }
+
+    fn mk_issuer_token(
+        issuer_id: IssuerId,
+        token: TokenId,
+        owner: AccountId,
+        metadata: TokenMetadata,
+    ) -> Token {
+        Token {
+            token,
+            issuer_id,
+            owner,
+            metadata,
+            metadata_version: 1,
+        }
+    }
+
+    fn mk_owned_token(token: TokenId, metadata: TokenMetadata) -> OwnedToken {
+        OwnedToken {
+            token,
+            metadata,
+            metadata_version: 1,
+        }
+    }
+
+    /// returns logs without the `mint_stats` and `recover_stats` lines.
+    fn event_logs() -> Vec<String> {
+        test_utils::get_logs()
+            .into_iter()
+            .filter(|l| !l.starts_with("mint_stats: ") && !l.starts_with("recover_stats: "))
+            .collect()
+    }
+
+    fn mk_balance_key(owner: AccountId, issuer_id: IssuerId, class_id: ClassId) -> BalanceKey {
+        BalanceKey {
+            owner,
+            issuer_id,
+            class_id: ClassKey(class_id),
+        }
+    }
+
+    fn mk_batch_metadata(n: u64) -> Vec<TokenMetadata> {
+        let mut batch_metadata: Vec<TokenMetadata> = Vec::new();
+        for i in 1..=n {
+            batch_metadata.push(mk_metadata(i, Some(START + i)))
+        }
+        batch_metadata
+    }
+
+    fn max_gas() -> Gas {
+        Gas::ONE_TERA.mul(300)
+    }
+
+    const MSECOND: u64 = 1_000_000; // milisecond in ns
+    const START: u64 = 10;
+    const MINT_DEPOSIT: Balance = 9 * MILI_NEAR;
+
+    fn setup(predecessor: &AccountId, deposit: Balance) -> (VMContext, Contract) {
+        let mut ctx = VMContextBuilder::new()
+            .predecessor_account_id(admin())
             .block_timestamp(START * MSECOND) // multiplying by mili seconds for easier testing
             .is_view(false)
             .build();
-        if deposit > 0 {
-            ctx.attached_deposit = deposit
+        if deposit > 0 {
+            ctx.attached_deposit = deposit
+        }
+        testing_env!(ctx.clone());
+        let mut ctr = Contract::new(admin(), fractal_mainnet(), vec![1], admins_flagged());
+        ctr.admin_add_sbt_issuer(issuer1());
+        ctr.admin_add_sbt_issuer(issuer2());
+        ctr.admin_add_sbt_issuer(issuer3());
+        ctr.admin_set_authorized_flaggers([predecessor.clone()].to_vec());
+        ctx.predecessor_account_id = predecessor.clone();
+        testing_env!(ctx.clone());
+        (ctx, ctr)
+    }
+
+    #[test]
+    fn init_method() {
+        let ctr = Contract::new(admin(), fractal_mainnet(), vec![1], vec![]);
+        // make sure the iah_issuer has been set as an issuer
+        assert_eq!(1, ctr.assert_issuer(&fractal_mainnet()));
+    }
+
+    #[test]
+    fn init_iah_classes_normalized() {
+        let ctr = Contract::new(admin(), fractal_mainnet(), vec![3, 1, 3, 2, 1], vec![]);
+        assert_eq!(
+            ctr.iah_class_set(),
+            vec![(fractal_mainnet(), vec![1, 2, 3])]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "iah_classes must be a non empty list")]
+    fn init_iah_classes_empty() {
+        Contract::new(admin(), fractal_mainnet(), vec![], vec![]);
+    }
+
+    /// writes the `ctr` state in the v1.8.0 layout.
+    fn write_old_state(ctr: Contract) {
+        let old = migrate::OldState {
+            authority: ctr.authority,
+            sbt_issuers: ctr.sbt_issuers,
+            issuer_id_map: ctr.issuer_id_map,
+            ongoing_soul_tx: ctr.ongoing_soul_tx,
+            transfer_lock: ctr.transfer_lock,
+            banlist: ctr.banlist,
+            flagged: ctr.flagged,
+            authorized_flaggers: ctr.authorized_flaggers,
+            supply_by_owner: ctr.supply_by_owner,
+            supply_by_class: ctr.supply_by_class,
+            supply_by_issuer: ctr.supply_by_issuer,
+            balances: ctr.balances,
+            issuer_tokens: ctr.issuer_tokens,
+            next_token_ids: ctr.next_token_ids,
+            next_issuer_id: ctr.next_issuer_id,
+            iah_sbts: ctr.iah_sbts[0].clone(),
+        };
+        env::state_write(&old);
+    }
+
+    #[test]
+    fn humanity_stats() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 4 * MINT_DEPOSIT);
+        let expired = mk_metadata(1, Some(START));
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![expired]),
+            (carol(), vec![mk_metadata(1, None)]),
+            (dan(), vec![mk_metadata(2, None)]),
+        ]);
+        let mut stats = HumanityStats {
+            total_iah_holders: 3,
+            valid_iah_holders: None,
+            next_from_token: None,
+        };
+        assert_eq!(ctr.humanity_stats(None, None, None), stats);
+
+        ctx.block_timestamp = (START + 1) * MSECOND;
+        testing_env!(ctx);
+        stats.valid_iah_holders = Some(2);
+        assert_eq!(
+            ctr.humanity_stats(Some(fractal_mainnet()), None, None),
+            stats
+        );
+
+        // paging
+        stats.valid_iah_holders = Some(1);
+        stats.next_from_token = Some(3);
+        assert_eq!(
+            ctr.humanity_stats(Some(fractal_mainnet()), None, Some(2)),
+            stats
+        );
+        stats.next_from_token = None;
+        assert_eq!(
+            ctr.humanity_stats(Some(fractal_mainnet()), Some(3), Some(2)),
+            stats
+        );
+
+        // carol is banned
+        ctr.banlist.insert(&carol());
+        stats.valid_iah_holders = Some(1);
+        assert_eq!(
+            ctr.humanity_stats(Some(fractal_mainnet()), None, None),
+            stats
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "not an IAH issuer")]
+    fn humanity_stats_not_iah_issuer() {
+        let (_, ctr) = setup(&fractal_mainnet(), 0);
+        ctr.humanity_stats(Some(issuer1()), None, None);
+    }
+
+    #[test]
+    fn sbt_score() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let mut m1 = mk_metadata(1, None);
+        m1.score = Some(80);
+        let m2 = mk_metadata(2, None);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1.clone(), m2.clone()])]);
+
+        assert_eq!(ctr.sbt_score(issuer1(), tokens[0]), Some(80));
+        assert_eq!(ctr.sbt_score(issuer1(), tokens[1]), None);
+        assert_eq!(ctr.sbt_score(issuer1(), 10), None);
+        assert_eq!(ctr.sbt_score(issuer2(), tokens[0]), None);
+
+        // scored tokens use the V2 metadata schema
+        let t = ctr.sbt(issuer1(), tokens[0], None).unwrap();
+        assert_eq!(t.metadata, m1);
+        assert_eq!(t.metadata_version, 2);
+        let t = ctr.sbt(issuer1(), tokens[1], None).unwrap();
+        assert_eq!(t.metadata, m2);
+        assert_eq!(t.metadata_version, 1);
+        // score is not serialized when not set
+        let json = near_sdk::serde_json::to_string(&t.metadata).unwrap();
+        assert!(!json.contains("score"));
+        let m: TokenMetadata = near_sdk::serde_json::from_str(&json).unwrap();
+        assert_eq!(m.score, None);
+    }
+
+    #[test]
+    fn sbt_tokens_by_score() {
+        let (_, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        let scored = |class, score| {
+            let mut m = mk_metadata(class, None);
+            m.score = score;
+            m
+        };
+        ctr.sbt_mint(vec![
+            (alice(), vec![scored(1, Some(90)), scored(2, Some(95))]),
+            (bob(), vec![scored(1, None)]),
+            (carol(), vec![scored(1, Some(79))]),
+            (dan(), vec![scored(1, Some(80))]),
+        ]);
+
+        let ids = |tokens: Vec<Token>| tokens.iter().map(|t| t.token).collect::<Vec<_>>();
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_score(issuer1(), 1, 80, None, None)),
+            vec![1, 5]
+        );
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_score(issuer1(), 1, 0, None, None)),
+            vec![1, 4, 5]
+        );
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_score(issuer1(), 2, 80, None, None)),
+            vec![2]
+        );
+        assert!(ctr
+            .sbt_tokens_by_score(issuer1(), 1, 91, None, None)
+            .is_empty());
+
+        // paging
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_score(issuer1(), 1, 80, None, Some(4))),
+            vec![1]
+        );
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_score(issuer1(), 1, 80, Some(5), Some(4))),
+            vec![5]
+        );
+        assert!(ctr
+            .sbt_tokens_by_score(issuer1(), 1, 80, Some(6), None)
+            .is_empty());
+        assert!(ctr
+            .sbt_tokens_by_score(issuer2(), 1, 0, None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn sbt_attestation() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        let attestation: Base64VecU8 = vec![7; 64].into();
+        let mut m1 = mk_metadata(1, None);
+        m1.attestation = Some(attestation.clone());
+        let mut m2 = mk_metadata(2, None);
+        m2.attestation = Some(vec![1; MAX_ATTESTATION_LEN].into());
+        let tokens = ctr.sbt_mint(vec![
+            (alice(), vec![m1.clone(), mk_metadata(3, None)]),
+            (bob(), vec![m2]),
+        ]);
+
+        assert_eq!(ctr.sbt_attestation(issuer1(), tokens[0]), Some(attestation));
+        assert_eq!(ctr.sbt_attestation(issuer1(), tokens[1]), None);
+        assert_eq!(
+            ctr.sbt_attestation(issuer1(), tokens[2]).unwrap().0.len(),
+            MAX_ATTESTATION_LEN
+        );
+        assert_eq!(ctr.sbt_attestation(issuer1(), 10), None);
+        assert_eq!(ctr.sbt_attestation(issuer2(), tokens[0]), None);
+        let t = ctr.sbt(issuer1(), tokens[0], None).unwrap();
+        assert_eq!(t.metadata, m1);
+        assert_eq!(t.metadata_version, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "attestation must be 1 to 128 bytes")]
+    fn sbt_attestation_too_long() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let mut m = mk_metadata(1, None);
+        m.attestation = Some(vec![1; MAX_ATTESTATION_LEN + 1].into());
+        ctr.sbt_mint(vec![(alice(), vec![m])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "attestation must be 1 to 128 bytes")]
+    fn sbt_attestation_empty() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let mut m = mk_metadata(1, None);
+        m.attestation = Some(vec![].into());
+        ctr.sbt_mint(vec![(alice(), vec![m])]);
+    }
+
+    #[test]
+    fn token_metadata_v1_borsh_compatible() {
+        // tokens stored before the `score` field was introduced
+        let old = TokenData {
+            owner: alice(),
+            metadata: VerTokenMetadata::V1(sbt::TokenMetadataV1 {
+                class: 1,
+                issued_at: Some(START),
+                expires_at: None,
+                reference: None,
+                reference_hash: None,
+            }),
+        };
+        let t = TokenData::try_from_slice(&old.try_to_vec().unwrap()).unwrap();
+        assert_eq!(t.metadata.score(), None);
+        assert_eq!(t.metadata.version(), 1);
+        let m = t.metadata.latest();
+        assert_eq!((m.class, m.issued_at, m.score), (1, Some(START), None));
+
+        // metadata without score is stored with the V1 schema
+        let stored: VerTokenMetadata = m.into();
+        assert_eq!(stored.version(), 1);
+    }
+
+    #[test]
+    fn sbt_holds_classes() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![mk_metadata(1, None), mk_metadata(3, Some(START))],
+            ),
+            (bob(), vec![mk_metadata(2, None)]),
+        ]);
+
+        // partial holdings, input order is preserved, expired tokens are held
+        assert_eq!(
+            ctr.sbt_holds_classes(alice(), issuer1(), vec![3, 2, 1, 4, 1]),
+            vec![true, false, true, false, true]
+        );
+        assert_eq!(
+            ctr.sbt_holds_classes(bob(), issuer1(), vec![1, 2]),
+            vec![false, true]
+        );
+        assert_eq!(
+            ctr.sbt_holds_classes(alice(), issuer2(), vec![1]),
+            vec![false]
+        );
+        assert_eq!(
+            ctr.sbt_holds_classes(alice(), issuer4(), vec![1, 3]),
+            vec![false, false]
+        );
+        assert!(ctr.sbt_holds_classes(alice(), issuer1(), vec![]).is_empty());
+
+        // ongoing soul transfer
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert!(
+            !ctr._sbt_soul_transfer(carol(), 1, LockedPolicy::Skip)
+                .unwrap()
+                .1
+        );
+        assert_eq!(
+            ctr.sbt_holds_classes(alice(), issuer1(), vec![1, 3]),
+            vec![false, false]
+        );
+    }
+
+    #[test]
+    fn sbt_by_owner_class() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, Some(START + 10));
+        let m2 = mk_metadata(2, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1]), (bob(), vec![m2.clone()])]);
+        let m3 = mk_metadata(3, None);
+        ctr.sbt_mint(vec![(alice(), vec![m2.clone(), m3])]);
+
+        assert_eq!(
+            ctr.sbt_by_owner_class(alice(), issuer1(), 2),
+            Some(mk_token(3, alice(), m2.clone()))
+        );
+        assert_eq!(
+            ctr.sbt_by_owner_class(bob(), issuer1(), 2),
+            Some(mk_token(2, bob(), m2))
+        );
+        assert_eq!(ctr.sbt_by_owner_class(bob(), issuer1(), 1), None);
+        assert_eq!(ctr.sbt_by_owner_class(alice(), issuer2(), 1), None);
+        assert_eq!(ctr.sbt_by_owner_class(alice(), bob(), 1), None);
+
+        // ongoing soul transfer
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert!(
+            !ctr._sbt_soul_transfer(carol(), 1, LockedPolicy::Skip)
+                .unwrap()
+                .1
+        );
+        assert_eq!(ctr.sbt_by_owner_class(alice(), issuer1(), 2), None);
+        assert_eq!(
+            ctr.sbt_by_owner_class(carol(), issuer1(), 1).unwrap().token,
+            1
+        );
+    }
+
+    #[test]
+    fn dump_balances() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (bob(), vec![mk_metadata(1, None)]),
+            (alice(), vec![mk_metadata(2, None), mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        let (page1, next) = ctr.dump_balances(None, Some(3));
+        assert_eq!(
+            page1,
+            vec![(alice(), 2, 1, 3), (alice(), 2, 2, 2), (alice(), 3, 1, 1)]
+        );
+        let next = next.unwrap();
+        let (page2, next) = ctr.dump_balances(Some(next), Some(3));
+        assert_eq!(page2, vec![(bob(), 2, 1, 1)]);
+        assert_eq!(next, None);
+
+        // exact page size
+        let (all, next) = ctr.dump_balances(None, Some(4));
+        assert_eq!(all.len(), 4);
+        assert_eq!(next, None);
+        assert_eq!(ctr.dump_balances(None, None).0, all);
+    }
+
+    #[test]
+    fn balance_cursor_round_trip() {
+        let keys = [
+            mk_balance_key(alice(), 1, 1),
+            mk_balance_key(alice2(), IssuerId::MAX, ClassId::MAX),
+            mk_balance_key(bob(), 0, 0),
+        ];
+        for key in keys {
+            let cursor = encode_balance_cursor(&key);
+            assert!(decode_balance_cursor(&cursor) == Some(key));
+        }
+        assert_eq!(
+            encode_balance_cursor(&mk_balance_key(bob(), 2, 3)),
+            format!("{}00{:08x}{:016x}", "626f622e6e656172", 2, 3)
+        );
+        for bad in [
+            "",
+            "0",
+            "zz",
+            "626f6200",
+            "626f62000000000200000000000000",
+            "00",
+        ] {
+            assert!(decode_balance_cursor(bad).is_none(), "{}", bad);
+        }
+        // missing issuer and class ids
+        let mut cursor = encode_balance_cursor(&mk_balance_key(bob(), 2, 3));
+        cursor.push_str("00");
+        assert!(decode_balance_cursor(&cursor).is_none());
+    }
+
+    #[test]
+    fn balance_cursor_order() {
+        let (_, mut ctr) = setup(&issuer1(), 0);
+        // alice is a prefix of alice2, and big ids don't break the order.
+        for (owner, issuer_id, class_id) in [
+            (alice2(), 1, 1),
+            (alice(), 1 << 30, 1),
+            (alice(), 1, 1 << 60),
+            (alice(), 1, 2),
+            (bob(), 2, 1),
+            (alice(), 2, 1),
+        ] {
+            ctr.balances
+                .insert(&mk_balance_key(owner, issuer_id, class_id), &1);
+        }
+        let keys: Vec<BalanceKey> = ctr.balances.iter().map(|(k, _)| k).collect();
+        let cursors: Vec<String> = keys.iter().map(encode_balance_cursor).collect();
+        let mut sorted = cursors.clone();
+        sorted.sort();
+        assert_eq!(cursors, sorted);
+
+        // iter_from a decoded cursor continues right after the key
+        for (i, cursor) in cursors.iter().enumerate() {
+            let next: Vec<BalanceKey> = ctr
+                .balances
+                .iter_from(decode_balance_cursor(cursor).unwrap())
+                .map(|(k, _)| k)
+                .collect();
+            assert!(next == keys[i + 1..]);
+        }
+    }
+
+    #[test]
+    fn all_tokens() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![mk_metadata(1, None), mk_metadata(2, Some(START))],
+            ),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(carol(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_mint(vec![(dan(), vec![mk_metadata(1, None)])]);
+        // burned tokens leave holes
+        ctx.predecessor_account_id = carol();
+        testing_env!(ctx);
+        ctr.sbt_burn(issuer2(), vec![1], None);
+
+        let expected = vec![
+            (issuer1(), 1),
+            (issuer1(), 2),
+            (issuer1(), 3),
+            (issuer2(), 2),
+        ];
+        let ids = |page: &Vec<(AccountId, Token)>| -> Vec<(AccountId, TokenId)> {
+            page.iter().map(|(i, t)| (i.clone(), t.token)).collect()
+        };
+
+        let (all, next) = ctr.all_tokens(None, None);
+        assert_eq!(ids(&all), expected);
+        assert_eq!(next, None);
+        assert_eq!(all[2].1.owner, bob());
+        assert_eq!(all[3].1.issuer_id, 3);
+
+        // paging: 5 token IDs are scanned
+        for limit in 1..=5 {
+            let mut exported = Vec::new();
+            let mut cursor = None;
+            loop {
+                let (page, next) = ctr.all_tokens(cursor, Some(limit));
+                assert!(page.len() <= limit as usize);
+                exported.extend(ids(&page));
+                match next {
+                    None => break,
+                    Some(c) => cursor = Some(c),
+                }
+            }
+            assert_eq!(exported, expected, "limit {}", limit);
+        }
+
+        let (page, next) = ctr.all_tokens(None, Some(3));
+        assert_eq!(ids(&page), expected[..3]);
+        assert_eq!(next, Some(encode_token_cursor(2, 3)));
+        // the burned token is scanned, but not returned
+        let (page, next) = ctr.all_tokens(next, Some(1));
+        assert!(page.is_empty());
+        assert_eq!(next, Some(encode_token_cursor(3, 1)));
+    }
+
+    #[test]
+    fn token_cursor_round_trip() {
+        for (issuer_id, token) in [(1, 1), (IssuerId::MAX, TokenId::MAX), (0, 0)] {
+            let cursor = encode_token_cursor(issuer_id, token);
+            assert_eq!(decode_token_cursor(&cursor), Some((issuer_id, token)));
+        }
+        assert_eq!(encode_token_cursor(2, 3), format!("{:08x}{:016x}", 2, 3));
+        for bad in [
+            "",
+            "0",
+            "zz",
+            "0000000200000000000003",
+            "00000002000000000000000300",
+        ] {
+            assert!(decode_token_cursor(bad).is_none(), "{}", bad);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed cursor")]
+    fn all_tokens_malformed_cursor() {
+        let (_, ctr) = setup(&issuer1(), 0);
+        ctr.all_tokens(Some("2:3".to_string()), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed from_key")]
+    fn dump_balances_malformed_key() {
+        let (_, ctr) = setup(&issuer1(), 0);
+        ctr.dump_balances(Some("1:x:alice.near".to_string()), None);
+    }
+
+    #[test]
+    fn storage_key_newtypes_borsh_compatible() {
+        // the newtypes keep the layout of the plain IDs, so the stored keys don't change.
+        assert_eq!(
+            ClassKey(7).try_to_vec().unwrap(),
+            7u64.try_to_vec().unwrap()
+        );
+        assert_eq!(
+            TokenKey(7).try_to_vec().unwrap(),
+            7u64.try_to_vec().unwrap()
+        );
+        assert_eq!(
+            balance_key(alice(), 2, ClassKey(7)).try_to_vec().unwrap(),
+            (alice(), 2u32, 7u64).try_to_vec().unwrap()
+        );
+        assert_eq!(
+            IssuerTokenId::new(2, 7).try_to_vec().unwrap(),
+            (2u32, 7u64).try_to_vec().unwrap()
+        );
+
+        // ongoing_soul_tx records written before the SoulTxCursor type (as IssuerTokenId with
+        // the class ID in the `token` field) are readable.
+        let (_, ctr) = setup(&alice(), 0);
+        let mut old: LookupMap<AccountId, (IssuerId, u64)> =
+            LookupMap::new(StorageKey::OngoingSoultTx);
+        old.insert(&alice(), &(2, 7));
+        assert_eq!(
+            ctr.ongoing_soul_tx.get(&alice()),
+            Some(SoulTxCursor {
+                issuer_id: 2,
+                class_id: ClassKey(7),
+            })
+        );
+    }
+
+    #[test]
+    fn migrate_summary() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(carol(), vec![mk_metadata(1, None)])]);
+        let supplies: Vec<u64> = [issuer1(), issuer2(), issuer3()]
+            .into_iter()
+            .map(|i| ctr.sbt_supply(i))
+            .collect();
+        assert_eq!(supplies, vec![3, 1, 0]);
+        let issuers = ctr.sbt_issuers.len();
+        // the owner totals and issuer classes are not part of the old state
+        for a in [alice(), bob(), carol()] {
+            ctr.supply_by_owner_total.remove(&a);
+        }
+        for issuer_id in [2, 3] {
+            ctr.issuer_classes.remove(&issuer_id);
+        }
+
+        write_old_state(ctr);
+        testing_env!(ctx.clone());
+        let mut ctr = Contract::migrate();
+        assert_eq!(
+            test_utils::get_logs(),
+            vec![format!(
+                r#"migrate_stats: {{"issuers":{},"total_supply":4}}"#,
+                issuers
+            )]
+        );
+        let migrated: Vec<u64> = [issuer1(), issuer2(), issuer3()]
+            .into_iter()
+            .map(|i| ctr.sbt_supply(i))
+            .collect();
+        assert_eq!(migrated, supplies);
+
+        // the owner totals are computed by the resumable backfill
+        assert!(ctr.migration_backfill.is_some());
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx);
+        assert_eq!(ctr.admin_migration_backfill(2), (2, false));
+        assert_eq!(ctr.admin_migration_backfill(2), (2, false));
+        assert!(test_utils::get_logs().is_empty());
+        assert_eq!(ctr.admin_migration_backfill(2), (0, true));
+        assert_eq!(
+            test_utils::get_logs(),
+            vec![r#"migrate_backfill_stats: {"souls":3}"#.to_string()]
+        );
+        assert!(ctr.migration_backfill.is_none());
+        assert_eq!(ctr.total_souls(), 3);
+        assert_eq!(ctr.supply_by_owner_total.get(&alice()), Some(2));
+        assert_eq!(ctr.supply_by_owner_total.get(&bob()), Some(1));
+        assert_eq!(ctr.supply_by_owner_total.get(&carol()), Some(1));
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(1, 2), (2, 1)]
+        );
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer2(), None, None),
+            vec![(1, 1)]
+        );
+        assert!(ctr.check_id_invariants(None, None).0);
+        // no-op when finished
+        assert_eq!(ctr.admin_migration_backfill(2), (0, true));
+        assert_eq!(ctr.total_souls(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "migration backfill in progress, see admin_migration_backfill")]
+    fn migration_backfill_blocks_mint() {
+        let (ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.migration_backfill = Some(MigrationBackfill::default());
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "migration backfill in progress, see admin_migration_backfill")]
+    fn migration_backfill_blocks_total_souls() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.migration_backfill = Some(MigrationBackfill::default());
+        ctr.total_souls();
+    }
+
+    #[test]
+    #[should_panic(expected = "migrate: corrupted state, next_issuer_id must be > 0")]
+    fn migrate_corrupted_state() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.next_issuer_id = 0;
+        write_old_state(ctr);
+        Contract::migrate();
+    }
+
+    #[test]
+    fn admin_pause_issuer() {
+        let (mut ctx, mut ctr) = setup(&admin(), 2 * MINT_DEPOSIT);
+        ctr.admin_pause_issuer(issuer1(), true);
+        assert!(ctr.is_issuer_paused(issuer1()));
+        assert!(!ctr.is_issuer_paused(issuer2()));
+        // the pre-flight check agrees with the mint methods
+        assert_eq!(
+            ctr.can_mint(issuer1(), alice(), 1).reasons,
+            vec![MintSkipReason::IssuerPaused]
+        );
+        assert!(ctr.can_mint(issuer2(), alice(), 1).allowed);
+
+        // other issuers can mint
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        assert_eq!(
+            ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]),
+            vec![1]
+        );
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        ctr.admin_pause_issuer(issuer1(), false);
+        assert!(!ctr.is_issuer_paused(issuer1()));
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]),
+            vec![1]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "issuer is paused")]
+    fn admin_pause_issuer_mint() {
+        let (mut ctx, mut ctr) = setup(&admin(), MINT_DEPOSIT);
+        ctr.admin_pause_issuer(issuer1(), true);
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "issuer is paused")]
+    fn admin_pause_issuer_renew() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        ctr.admin_pause_issuer(issuer1(), true);
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_renew(vec![1], START + 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn admin_pause_issuer_not_admin() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.admin_pause_issuer(issuer1(), true);
+    }
+
+    #[test]
+    fn iah_class_set() {
+        let (_, ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        assert_eq!(ctr.iah_class_set(), ctr.iah_sbts);
+    }
+
+    #[test]
+    fn add_sbt_issuer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        // in setup we add 4 issuers, so the next id will be 5.
+        assert_eq!(5, ctr.next_issuer_id);
+        assert_eq!(1, ctr.assert_issuer(&fractal_mainnet()));
+        assert_eq!(2, ctr.assert_issuer(&issuer1()));
+        assert_eq!(3, ctr.assert_issuer(&issuer2()));
+        assert_eq!(4, ctr.assert_issuer(&issuer3()));
+
+        assert_eq!(fractal_mainnet(), ctr.issuer_by_id(1));
+        assert_eq!(issuer1(), ctr.issuer_by_id(2));
+        assert_eq!(issuer2(), ctr.issuer_by_id(3));
+        assert_eq!(issuer3(), ctr.issuer_by_id(4));
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx);
+        let ok = ctr.admin_add_sbt_issuer(issuer1());
+        assert!(
+            !ok,
+            "isser1 should be already added, so it should return false"
+        );
+        assert_eq!(5, ctr.next_issuer_id, "next_issuer_id should not change");
+        assert_eq!(
+            2,
+            ctr.assert_issuer(&issuer1()),
+            "issuer1 id should not change"
+        );
+    }
+
+    #[test]
+    fn mint_simple() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+
+        let minted_ids = ctr.sbt_mint(vec![
+            (alice(), vec![m1_1.clone()]),
+            (bob(), vec![m1_1.clone()]),
+        ]);
+        assert_eq!(minted_ids, vec![1, 2]);
+        assert_eq!(2, ctr.sbt_supply(issuer1()));
+        assert_eq!(0, ctr.sbt_supply(issuer2()));
+
+        let sbt1_1 = ctr.sbt(issuer1(), 1, None).unwrap();
+        let sbt1_1_e = mk_token(1, alice(), m1_1.clone());
+        let sbt1_2_e = mk_token(2, bob(), m1_1.clone());
+        assert_eq!(sbt1_1, sbt1_1_e);
+        let sbt1_2 = ctr.sbt(issuer1(), 2, None).unwrap();
+        assert_eq!(sbt1_2, sbt1_2_e);
+        assert!(ctr.sbt(issuer2(), 1, None).is_none());
+        assert!(ctr.sbt(issuer1(), 3, None).is_none());
+
+        let sbts = ctr.sbts(issuer1(), vec![1, 2], None);
+        assert_eq!(sbts, vec![Some(sbt1_1_e.clone()), Some(sbt1_2_e.clone())]);
+        assert_eq!(
+            ctr.sbt_classes(issuer1(), vec![1, 1]),
+            vec![Some(1), Some(1)]
+        );
+
+        let sbts = ctr.sbts(issuer1(), vec![2, 10, 3, 1], None);
+        assert_eq!(sbts, vec![Some(sbt1_2_e), None, None, Some(sbt1_1_e)]);
+        assert_eq!(
+            ctr.sbt_classes(issuer1(), vec![2, 10, 3, 1]),
+            vec![Some(1), None, None, Some(1)]
+        );
+
+        assert_eq!(1, ctr.sbt_supply_by_owner(alice(), issuer1(), None, None));
+        assert_eq!(
+            1,
+            ctr.sbt_supply_by_owner(alice(), issuer1(), Some(1), None)
+        );
+        assert_eq!(
+            0,
+            ctr.sbt_supply_by_owner(alice(), issuer1(), Some(2), None)
+        );
+
+        assert_eq!(1, ctr.sbt_supply_by_owner(bob(), issuer1(), None, None));
+        assert_eq!(1, ctr.sbt_supply_by_owner(bob(), issuer1(), Some(1), None));
+        assert_eq!(0, ctr.sbt_supply_by_owner(bob(), issuer1(), Some(2), None));
+
+        let alice_sbts = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        let expected = vec![(issuer1(), vec![mk_owned_token(1, m1_1.clone())])];
+        assert_eq!(alice_sbts, expected);
+
+        let bob_sbts = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
+        let expected = vec![(issuer1(), vec![mk_owned_token(2, m1_1)])];
+        assert_eq!(bob_sbts, expected);
+    }
+
+    #[test]
+    fn mint() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m1_2 = mk_metadata(1, Some(START + 12));
+        let m2_1 = mk_metadata(2, Some(START + 14));
+        let m4_1 = mk_metadata(4, Some(START + 16));
+
+        // mint an SBT to a user with same prefix as alice
+        let minted_ids = ctr.sbt_mint(vec![(alice2(), vec![m1_1.clone()])]);
+        assert_eq!(minted_ids, vec![1]);
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "mint",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[["{}",[1]]],"event_seq":1}}"#,
+                    issuer1(),
+                    alice2()
+                )
+            )
+        );
+
+        ctx.predecessor_account_id = issuer2();
+        ctx.attached_deposit = 4 * MINT_DEPOSIT;
+        testing_env!(ctx.clone());
+        let minted_ids = ctr.sbt_mint(vec![
+            (alice(), vec![m1_1.clone()]),
+            (bob(), vec![m1_2.clone()]),
+            (alice2(), vec![m1_1.clone()]),
+            (alice(), vec![m2_1.clone()]),
+        ]);
+        assert_eq!(minted_ids, vec![1, 2, 3, 4]);
+        assert_eq!(event_logs().len(), 1);
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "mint",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[["{}",[3]],["{}",[1,4]],["{}",[2]]],"event_seq":2}}"#,
+                    issuer2(),
+                    alice2(),
+                    alice(),
+                    bob()
+                )
+            )
+        );
+
+        // mint again for Alice
+        let minted_ids = ctr.sbt_mint(vec![(alice(), vec![m4_1.clone()])]);
+        assert_eq!(minted_ids, vec![5]);
+
+        // change the issuer and mint new tokens for alice
+        ctx.predecessor_account_id = issuer3();
+        ctx.attached_deposit = 2 * MINT_DEPOSIT;
+        testing_env!(ctx.clone());
+        let minted_ids = ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
+        // since we minted with different issuer, the new SBT should start with 1
+        assert_eq!(minted_ids, vec![1, 2]);
+
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 0), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 3);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 3), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 4), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 5), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer3(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer3(), 2), 1);
+
+        let mut supply_by_issuer = vec![1, 5, 2, 0];
+        assert_eq!(ctr.sbt_supply(issuer1()), supply_by_issuer[0]);
+        assert_eq!(ctr.sbt_supply(issuer2()), supply_by_issuer[1]);
+        assert_eq!(ctr.sbt_supply(issuer3()), supply_by_issuer[2]);
+        assert_eq!(ctr.sbt_supply(issuer4()), supply_by_issuer[3]);
+
+        assert_eq!(3, ctr.sbt_supply_by_owner(alice(), issuer2(), None, None));
+        assert_eq!(2, ctr.sbt_supply_by_owner(alice(), issuer3(), None, None));
+        assert_eq!(1, ctr.sbt_supply_by_owner(bob(), issuer2(), None, None));
+        assert_eq!(0, ctr.sbt_supply_by_owner(bob(), issuer3(), None, None));
+        assert_eq!(0, ctr.sbt_supply_by_owner(issuer2(), issuer2(), None, None));
+
+        let t2_all = vec![
+            mk_issuer_token(3, 1, alice(), m1_1.clone()),
+            mk_issuer_token(3, 2, bob(), m1_2),
+            mk_issuer_token(3, 3, alice2(), m1_1.clone()),
+            mk_issuer_token(3, 4, alice(), m2_1.clone()),
+            mk_issuer_token(3, 5, alice(), m4_1.clone()),
+        ];
+        let t3_1 = mk_issuer_token(4, 1, alice(), m1_1.clone());
+
+        assert_eq!(ctr.sbt(issuer2(), 1, None).unwrap(), t2_all[0]);
+        assert_eq!(ctr.sbt(issuer2(), 2, None).unwrap(), t2_all[1]);
+        assert_eq!(ctr.sbt(issuer2(), 3, None).unwrap(), t2_all[2]);
+        assert_eq!(ctr.sbt(issuer2(), 4, None).unwrap(), t2_all[3]);
+        assert_eq!(ctr.sbt(issuer3(), 1, None).unwrap(), t3_1);
+
+        // Token checks
+
+        let a_tokens = vec![
+            (issuer1(), vec![mk_owned_token(1, m1_1.clone())]),
+            (issuer2(), vec![mk_owned_token(3, m1_1.clone())]),
+        ];
+        assert_eq!(
+            &ctr.sbt_tokens_by_owner(alice2(), None, None, None, None),
+            &a_tokens
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice2(), Some(issuer1()), None, None, None),
+            vec![a_tokens[0].clone()],
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice2(), Some(issuer2()), None, None, None),
+            vec![a_tokens[1].clone()]
+        );
+
+        let alice_issuer2 = (
+            issuer2(),
+            vec![
+                mk_owned_token(1, m1_1.clone()),
+                mk_owned_token(4, m2_1.clone()),
+                mk_owned_token(5, m4_1.clone()),
+            ],
+        );
+        let alice_issuer3 = (
+            issuer3(),
+            vec![
+                mk_owned_token(1, m1_1.clone()),
+                mk_owned_token(2, m2_1.clone()),
+            ],
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![alice_issuer2.clone(), alice_issuer3.clone()]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None),
+            vec![alice_issuer2.clone()]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer3()), None, None, None),
+            vec![alice_issuer3.clone()]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(1), None, None),
+            vec![alice_issuer2]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(4), None, None),
+            vec![(issuer2(), vec![mk_owned_token(5, m4_1)])]
+        );
+
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), Some(5), None, None),
+            vec![]
+        );
+
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(5), None, None),
+            vec![]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer3()), Some(1), None, None),
+            vec![alice_issuer3.clone()]
+        );
+
+        // check by all tokens
+        assert_eq!(
+            ctr.sbt_tokens(issuer1(), Some(1), None, None),
+            vec![mk_token(1, alice2(), m1_1.clone())]
+        );
+        assert_eq!(ctr.sbt_tokens(issuer2(), None, None, None), t2_all,);
+        assert_eq!(ctr.sbt_tokens(issuer2(), None, Some(1), None), t2_all[..1]);
+        assert_eq!(ctr.sbt_tokens(issuer2(), None, Some(2), None), t2_all[..2]);
+        assert_eq!(
+            ctr.sbt_tokens(issuer2(), Some(2), Some(2), None),
+            t2_all[1..3]
+        );
+        assert_eq!(
+            ctr.sbt_tokens(issuer2(), Some(5), Some(5), None),
+            t2_all[4..5]
+        );
+        assert_eq!(ctr.sbt_tokens(issuer2(), Some(6), Some(2), None), vec![]);
+
+        //
+        // now let's test buring
+        //
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+
+        ctr.sbt_burn(issuer2(), vec![1, 5], Some("alice burning".to_owned()));
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "burn",
+                r#"{"issuer":"sbt.ne","tokens":[1,5],"event_seq":5}"#
+            )
+        );
+
+        supply_by_issuer[1] -= 2;
+        assert_eq!(ctr.sbt_supply(issuer1()), supply_by_issuer[0]);
+        assert_eq!(ctr.sbt_supply(issuer2()), supply_by_issuer[1]);
+        assert_eq!(ctr.sbt_supply(issuer3()), supply_by_issuer[2]);
+        assert_eq!(ctr.sbt_supply(issuer4()), supply_by_issuer[3]);
+
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1);
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer2(), Some(m2_1.class), None),
+            1
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer2(), Some(m1_1.class), None),
+            0
+        );
+
+        let alice_issuer2 = (issuer2(), vec![mk_owned_token(4, m2_1)]);
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![alice_issuer2.clone(), alice_issuer3]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None),
+            vec![alice_issuer2]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "bob.near is not a human")]
+    fn mint_iah() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
+        // issue IAH SBTs for alice
+        let m1_1 = mk_metadata(1, Some(START + 1)); // class=1 is IAH
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+
+        // alice is IAH verified, so mint_iah by issuer1 should work
+        let sbts = ctr.sbt_mint_iah(vec![(alice(), vec![m1_1.clone()])]);
+        assert!(!sbts.is_empty());
+
+        // bob doesn't have IAH SBTs -> the mint below panics.
+        ctr.sbt_mint_iah(vec![(bob(), vec![m1_1])]);
+    }
+
+    #[test]
+    fn soul_transfer1() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+
+        // test1: simple case: alice has one token and she owns alice2 account as well. She
+        // will do transfer from alice -> alice2
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+
+        // make soul transfer
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.sbt_soul_transfer(alice2(), None, None).unwrap(),
+            (3, true)
+        );
+
+        let log1 = mk_log_str("ban", &format!(r#"["{}"]"#, alice()));
+        let log2 = mk_log_str(
+            "soul_transfer",
+            &format!(
+                r#"{{"from":"{}","to":"{}","transferred":3,"skipped":0,"burned":0}}"#,
+                alice(),
+                alice2()
+            ),
+        );
+        assert_eq!(test_utils::get_logs(), vec![log1, log2].concat());
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None), 1);
+
+        assert!(ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice2(), None, None, None, None),
+            vec![
+                (
+                    issuer1(),
+                    vec![mk_owned_token(1, m1_1.clone()), mk_owned_token(2, m2_1)]
+                ),
+                (issuer2(), vec![mk_owned_token(1, m1_1)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn soul_transfer_with_continuation() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        // test1: simple case: alice has one token and she owns alice2 account as well. She
+        // will do transfer from alice -> alice2
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 11));
+        let m3_1 = mk_metadata(3, Some(START + 12));
+        let m4_1 = mk_metadata(4, Some(START + 13));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1])]);
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![m3_1, m4_1])]);
+
+        // make soul transfer
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 3, LockedPolicy::Skip)
+                .unwrap(),
+            (3, false)
+        );
+        // ban and soul_transfer_started
+        let log_started = format!(
+            r#"EVENT_JSON:{{"standard":"i_am_human","version":"1.0.0","event":"soul_transfer_started","data":{{"from":"{}","to":"{}"}}}}"#,
+            alice(),
+            alice2()
+        );
+        assert!(test_utils::get_logs().len() == 2);
+        assert_eq!(test_utils::get_logs()[1], log_started);
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 3, LockedPolicy::Skip)
+                .unwrap(),
+            (1, true)
+        );
+        // the start event is not emitted when resuming
+        assert!(test_utils::get_logs().len() == 3);
+
+        let log_soul_transfer = mk_log_str(
+            "soul_transfer",
+            &format!(
+                r#"{{"from":"{}","to":"{}","transferred":4,"skipped":0,"burned":0}}"#,
+                alice(),
+                alice2()
+            ),
+        );
+        assert_eq!(test_utils::get_logs()[2], log_soul_transfer[0]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None), 2);
+        assert!(ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+    }
+
+    /// alice gets issuer1 tokens of classes 1, 2 (pinned) and 3, and issuer2 token of class 1.
+    fn setup_pinned(ctx: &mut VMContext, ctr: &mut Contract) {
+        ctr.sbt_mint(vec![(
+            alice(),
+            vec![
+                mk_metadata(1, None),
+                mk_metadata(2, None),
+                mk_metadata(3, None),
+            ],
+        )]);
+        ctr.set_class_pinned(2, true);
+        assert!(ctr.is_class_pinned(issuer1(), 2));
+        assert!(!ctr.is_class_pinned(issuer1(), 1));
+        assert!(!ctr.is_class_pinned(issuer2(), 2));
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+    }
+
+    #[test]
+    fn soul_successor() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(
+            alice(),
+            vec![mk_metadata(1, None), mk_metadata(2, None)],
+        )]);
+        assert_eq!(ctr.soul_successor(alice()), None);
+
+        // alice -> alice2 in two calls: links are set only once the transfer completes
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        assert!(
+            !ctr._sbt_soul_transfer(alice2(), 2, LockedPolicy::Skip)
+                .unwrap()
+                .1
+        );
+        assert_eq!(ctr.soul_successor(alice()), None);
+        assert_eq!(ctr.soul_predecessor(alice2()), None);
+        assert!(
+            ctr._sbt_soul_transfer(alice2(), 2, LockedPolicy::Skip)
+                .unwrap()
+                .1
+        );
+        assert_eq!(ctr.soul_successor(alice()), Some(alice2()));
+        assert_eq!(ctr.soul_predecessor(alice2()), Some(alice()));
+
+        // alice2 -> bob: only one hop is returned
+        ctx.predecessor_account_id = alice2();
+        testing_env!(ctx);
+        assert!(
+            ctr._sbt_soul_transfer(bob(), 20, LockedPolicy::Skip)
+                .unwrap()
+                .1
+        );
+        assert_eq!(ctr.soul_successor(alice()), Some(alice2()));
+        assert_eq!(ctr.soul_successor(alice2()), Some(bob()));
+        assert_eq!(ctr.soul_predecessor(bob()), Some(alice2()));
+        assert_eq!(ctr.soul_predecessor(alice2()), Some(alice()));
+        assert_eq!(ctr.soul_predecessor(alice()), None);
+        assert_eq!(ctr.soul_successor(bob()), None);
+    }
+
+    #[test]
+    fn soul_transfer_locked_policy_skip() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        setup_pinned(&mut ctx, &mut ctr);
+
+        let policy = LockedPolicy::Skip;
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 2, policy).unwrap(),
+            (1, false)
+        );
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 2, policy).unwrap(),
+            (2, false)
+        );
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 2, policy).unwrap(),
+            (0, true)
+        );
+        let log_soul_transfer = mk_log_str(
+            "soul_transfer",
+            &format!(
+                r#"{{"from":"{}","to":"{}","transferred":3,"skipped":1,"burned":0}}"#,
+                alice(),
+                alice2()
+            ),
+        );
+        assert_eq!(test_utils::get_logs()[2], log_soul_transfer[0]);
+
+        assert_eq!(ctr.sbt(issuer1(), 2, None).unwrap().owner, alice());
+        assert_eq!(ctr.sbt(issuer1(), 3, None).unwrap().owner, alice2());
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None), 1);
+        assert_eq!(ctr.sbt_supply(issuer1()), 3);
+        assert_eq!(ctr.total_souls(), 2);
+        assert!(ctr.soul_tx_counts.get(&alice()).is_none());
+    }
+
+    #[test]
+    fn soul_transfer_locked_policy_burn() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        setup_pinned(&mut ctx, &mut ctr);
+
+        assert_eq!(
+            ctr.sbt_soul_transfer(alice2(), None, Some(LockedPolicy::Burn))
+                .unwrap(),
+            (3, true)
+        );
+        let log_burn = mk_log_str(
+            "burn",
+            &format!(r#"{{"issuer":"{}","tokens":[2],"event_seq":3}}"#, issuer1()),
+        );
+        let log_soul_transfer = mk_log_str(
+            "soul_transfer",
+            &format!(
+                r#"{{"from":"{}","to":"{}","transferred":3,"skipped":0,"burned":1}}"#,
+                alice(),
+                alice2()
+            ),
+        );
+        assert_eq!(
+            test_utils::get_logs()[1..],
+            [log_burn, log_soul_transfer].concat()
+        );
+
+        assert!(ctr.sbt(issuer1(), 2, None).is_none());
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None), 1);
+        assert_eq!(ctr.sbt_supply(issuer1()), 2);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 0);
+        assert_eq!(ctr.total_souls(), 1);
+    }
+
+    #[test]
+    fn soul_transfer_preserves_token_ids() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 10));
+        // bob token shifts alice token ids, so they are not equal to the class ids
+        ctr.sbt_mint(vec![(bob(), vec![m1_1.clone()])]);
+        let ids1 = ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
+        assert_eq!(ids1, vec![2, 3]);
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        let ids2 = ctr.sbt_mint(vec![(alice(), vec![m2_1.clone()])]);
+        assert_eq!(ids2, vec![1]);
+
+        // transfer with continuation: the ids must be preserved across the batches
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 2, LockedPolicy::Skip)
+                .unwrap(),
+            (2, false)
+        );
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), 2, LockedPolicy::Skip)
+                .unwrap(),
+            (1, true)
+        );
+
+        for (id, m) in ids1.iter().zip([m1_1, m2_1.clone()]) {
+            assert_eq!(
+                ctr.sbt(issuer1(), *id, None),
+                Some(mk_token(*id, alice2(), m))
+            );
+        }
+        assert_eq!(
+            ctr.sbt(issuer2(), ids2[0], None),
+            Some(mk_issuer_token(3, ids2[0], alice2(), m2_1))
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice2(), Some(issuer1()), None, None, None)[0]
+                .1
+                .iter()
+                .map(|t| t.token)
+                .collect::<Vec<_>>(),
+            ids1
+        );
+        // bob token is not affected
+        assert_eq!(ctr.sbt(issuer1(), 1, None).unwrap().owner, bob());
+    }
+
+    #[test]
+    fn soul_transfer_no_tokens_from_caller() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert!(!ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
+        assert!(ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+        // assert ban even is being emited after the caller with zero tokens has invoked the soul_transfer
+        let log_ban = mk_log_str("ban", &format!("[\"{}\"]", alice()));
+        assert_eq!(test_utils::get_logs(), log_ban);
+    }
+
+    fn soul_transfer_prepare() -> (VMContext, Contract) {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 150 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(110);
+        assert_eq!(batch_metadata.len(), 110);
+
+        // issuer_1
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 50);
+
+        // issuer_2
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[50..100].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 50);
+
+        // add more tokens to issuer_1
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(bob(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 20);
+
+        // mint non conflicting tokens
+        ctr.sbt_mint(vec![(alice2(), batch_metadata[100..].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 10);
+
+        testing_env!(ctx.clone()); // reset gas
+        ctr.sbt_mint(vec![(carol(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None, None), 20);
+
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(dan(), batch_metadata[..10].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None, None), 10);
+
+        (ctx, ctr)
+    }
+
+    #[test]
+    fn soul_transfer_limit() {
+        let (mut ctx, mut ctr) = soul_transfer_prepare();
+
+        // soul transfer alice->alice2
+        ctx.predecessor_account_id = alice();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        let limit: u32 = 20; //anything above this limit will fail due to exceeding maximum gas usage per call
+
+        let mut result = ctr
+            ._sbt_soul_transfer(alice2(), limit as usize, LockedPolicy::Skip)
+            .unwrap();
+        while !result.1 {
+            testing_env!(ctx.clone()); // reset gas
+            result = ctr
+                ._sbt_soul_transfer(alice2(), limit as usize, LockedPolicy::Skip)
+                .unwrap();
+        }
+
+        // check all the balances afterwards
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 60);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None), 50);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 20);
+        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None, None), 20);
+        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None, None), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "HostError(GasLimitExceeded)")]
+    fn soul_transfer_exceeded_gas_limit() {
+        let (mut ctx, mut ctr) = soul_transfer_prepare();
+
+        // soul transfer alice->alice2
+        ctx.predecessor_account_id = alice();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx);
+        let limit: u32 = 30;
+        ctr._sbt_soul_transfer(alice2(), limit as usize, LockedPolicy::Skip)
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "recipient already has an SBT of issuer=sbt.n, class=1; source_token_id=1"
+    )]
+    fn soul_transfer_conflict() {
+        let (mut ctx, mut ctr) = soul_transfer_prepare();
+
+        // alice1 already has SBT of (issuer1, 1), so let's mint it to alice2 and try soul_transfer
+        let batch_metadata = mk_batch_metadata(1);
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice2(), batch_metadata)]);
+
+        ctx.predecessor_account_id = alice();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx);
+        let limit: u32 = 30;
+        ctr._sbt_soul_transfer(alice2(), limit as usize, LockedPolicy::Skip)
+            .unwrap();
+    }
+
+    #[test]
+    fn soul_transfer_limit_basics() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 60 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(40);
+        assert!(batch_metadata.len() == 40);
+
+        // issuer_1
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 20);
+
+        // issuer_2
+        ctx.predecessor_account_id = issuer2();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[20..].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 20);
+
+        ctx.predecessor_account_id = alice();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+
+        let limit: usize = 10;
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), limit, LockedPolicy::Skip)
+                .unwrap(),
+            (limit as u32, false)
+        );
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), limit, LockedPolicy::Skip)
+                .unwrap(),
+            (limit as u32, false)
+        );
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), limit, LockedPolicy::Skip)
+                .unwrap(),
+            (limit as u32, false)
+        );
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), limit, LockedPolicy::Skip)
+                .unwrap(),
+            (limit as u32, false)
+        );
+
+        // resumed transfer but no more tokens to transfer
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr._sbt_soul_transfer(alice2(), limit, LockedPolicy::Skip)
+                .unwrap(),
+            (0, true)
+        );
+
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 20);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None), 20);
+    }
+
+    #[test]
+    fn soul_transfer_batch() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 10 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(7))]);
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        ctr.admin_set_soul_tx_batch(3);
+        assert_eq!(ctr.soul_tx_batch, 3);
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        assert_eq!(
+            ctr.sbt_soul_transfer(alice2(), None, None).unwrap(),
+            (3, false)
+        );
+        assert_eq!(
+            ctr.sbt_soul_transfer(alice2(), None, None).unwrap(),
+            (3, false)
+        );
+        assert_eq!(
+            ctr.sbt_soul_transfer(alice2(), None, None).unwrap(),
+            (1, true)
+        );
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn soul_transfer_batch_not_admin() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.admin_set_soul_tx_batch(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "soul transfer batch must be bigger than 0")]
+    fn soul_transfer_batch_zero() {
+        let (_, mut ctr) = setup(&admin(), MINT_DEPOSIT);
+        ctr.admin_set_soul_tx_batch(0);
+    }
+
+    #[test]
+    fn tokens_held() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 10 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(3))]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, Some(START))]),
+            (bob(), vec![mk_metadata(1, Some(START))]),
+        ]);
+
+        // start a soul transfer, so alice tokens are hidden from the public queries
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        ctr._sbt_soul_transfer(alice2(), 1, LockedPolicy::Skip)
+            .unwrap();
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true)),
+            vec![]
+        );
+
+        // a view, callable by anyone
+        ctx.predecessor_account_id = carol();
+        ctx.block_timestamp = (START + 1) * MSECOND;
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.tokens_held(alice(), None),
+            vec![(issuer1(), vec![2, 3]), (issuer2(), vec![1])]
+        );
+        assert_eq!(
+            ctr.tokens_held(alice(), Some(2)),
+            vec![(issuer1(), vec![2, 3])]
+        );
+        assert_eq!(ctr.tokens_held(bob(), None), vec![(issuer2(), vec![2])]);
+        assert_eq!(ctr.tokens_held(carol(), None), vec![]);
+    }
+
+    #[test]
+    fn tokens_held_limit_capped() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        // not a real transaction: raise the gas limit to fill the balances
+        ctx.prepaid_gas = Gas(u64::MAX);
+        let mut config = near_sdk::VMConfig::test();
+        config.limit_config.max_gas_burnt = u64::MAX;
+        testing_env!(
+            ctx,
+            config,
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![],
+        );
+        for class in 1..=MAX_LIMIT as u64 + 1 {
+            ctr.balances
+                .insert(&balance_key(alice(), 2, ClassKey(class)), &class);
+        }
+        let held = ctr.tokens_held(alice(), Some(MAX_LIMIT + 10));
+        assert_eq!(held[0].1.len(), MAX_LIMIT as usize);
+    }
+
+    #[test]
+    fn sbt_valid_only() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, Some(START + 10));
+        let m2 = mk_metadata(2, Some(START + 20));
+        ctr.sbt_mint(vec![(alice(), vec![m1.clone(), m2.clone()])]);
+        let t1 = mk_token(1, alice(), m1);
+        let t2 = mk_token(2, alice(), m2);
+
+        // token 1 expires exactly now: expired
+        ctx.block_timestamp = (START + 9) * MSECOND;
+        testing_env!(ctx.clone());
+        assert_eq!(ctr.sbt(issuer1(), 1, Some(true)), Some(t1.clone()));
+
+        ctx.block_timestamp = (START + 10) * MSECOND;
+        testing_env!(ctx);
+        assert_eq!(ctr.sbt(issuer1(), 1, Some(true)), None);
+        assert_eq!(ctr.sbt(issuer1(), 1, Some(false)), Some(t1.clone()));
+        assert_eq!(ctr.sbt(issuer1(), 1, None), Some(t1.clone()));
+        assert_eq!(ctr.sbt(issuer1(), 2, Some(true)), Some(t2.clone()));
+
+        assert_eq!(
+            ctr.sbts(issuer1(), vec![1, 2, 3], Some(true)),
+            vec![None, Some(t2.clone()), None]
+        );
+        assert_eq!(
+            ctr.sbts(issuer1(), vec![1, 2, 3], None),
+            vec![Some(t1), Some(t2), None]
+        );
+    }
+
+    #[test]
+    fn class_allowlist() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 10 * MINT_DEPOSIT);
+        assert!(ctr.is_class_allowed(issuer1(), 1, alice()));
+        assert!(!ctr.is_class_allowed(issuer4(), 1, alice()));
+
+        ctr.set_class_allowlist(1, vec![alice(), bob()]);
+        assert!(ctr.is_class_allowed(issuer1(), 1, alice()));
+        assert!(ctr.is_class_allowed(issuer1(), 1, bob()));
+        assert!(!ctr.is_class_allowed(issuer1(), 1, carol()));
+        // other classes and other issuers are not affected
+        assert!(ctr.is_class_allowed(issuer1(), 2, carol()));
+        assert!(ctr.is_class_allowed(issuer2(), 1, carol()));
+
+        // extend the allowlist
+        ctr.set_class_allowlist(1, vec![carol()]);
+        assert!(ctr.is_class_allowed(issuer1(), 1, carol()));
+
+        let tids = ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (carol(), vec![mk_metadata(1, None)]),
+            (dan(), vec![mk_metadata(2, None)]),
+        ]);
+        assert_eq!(tids, vec![1, 2, 3]);
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(dan(), vec![mk_metadata(1, None)])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "dan.near is not allowed to receive SBT of class 1")]
+    fn class_allowlist_rejected() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.set_class_allowlist(1, vec![alice()]);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (dan(), vec![mk_metadata(1, None)]),
+        ]);
+    }
+
+    #[test]
+    fn sbt_mint_at() {
+        let (ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        assert_eq!(
+            ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]),
+            vec![1]
+        );
+        testing_env!(ctx);
+        let tokens = ctr.sbt_mint_at(vec![
+            (10, bob(), mk_metadata(1, None)),
+            (5, alice(), mk_metadata(2, None)),
+            (7, bob(), mk_metadata(2, None)),
+        ]);
+        assert_eq!(tokens, vec![10, 5, 7]);
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "mint",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[["alice.near",[5]],["bob.near",[10,7]]],"event_seq":2}}"#,
+                    issuer1()
+                )
+            )
+        );
+        assert_eq!(ctr.sbt(issuer1(), 10, None).unwrap().owner, bob());
+        assert_eq!(ctr.sbt(issuer1(), 5, None).unwrap().metadata.class, 2);
+        assert_eq!(ctr.sbt_supply(issuer1()), 4);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 2);
+
+        // the counter is advanced after the biggest ID
+        assert_eq!(ctr.next_token_ids.get(&2), Some(10));
+        assert!(ctr.check_id_invariants(None, None).0);
+        assert_eq!(
+            ctr.sbt_mint(vec![(carol(), vec![mk_metadata(1, None)])]),
+            vec![11]
+        );
+        // gaps are never filled
+        assert_eq!(ctr.sbt(issuer1(), 2, None), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "token ID 1 is already used or reserved")]
+    fn sbt_mint_at_collision() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_mint_at(vec![(1, bob(), mk_metadata(1, None))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "token ID 3 is already used or reserved")]
+    fn sbt_mint_at_gap() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint_at(vec![(5, alice(), mk_metadata(1, None))]);
+        // IDs below the counter can't be used, even if they were never assigned
+        ctr.sbt_mint_at(vec![(3, bob(), mk_metadata(1, None))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "token ID 4 is already used or reserved")]
+    fn sbt_mint_at_duplicate() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint_at(vec![
+            (4, alice(), mk_metadata(1, None)),
+            (4, bob(), mk_metadata(1, None)),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough NEAR storage deposit")]
+    fn sbt_mint_at_deposit() {
+        let (_, mut ctr) = setup(&issuer1(), 0);
+        ctr.sbt_mint_at(vec![(4, alice(), mk_metadata(1, None))]);
+    }
+
+    #[test]
+    fn sbt_mint_report() {
+        let (_, mut ctr) = setup(&issuer1(), 10 * MINT_DEPOSIT);
+        ctr.set_class_allowlist(3, vec![alice()]);
+        ctr.banlist.insert(&dan());
+        ctr.sbt_mint(vec![(bob(), vec![mk_metadata(1, None)])]);
+
+        let outcomes = ctr.sbt_mint_report(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(3, None)]),
+            // bob already has class 1
+            (bob(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (carol(), vec![mk_metadata(3, None), mk_metadata(2, None)]),
+            (dan(), vec![mk_metadata(1, None)]),
+            // duplicate within the batch
+            (alice(), vec![mk_metadata(1, None)]),
+        ]);
+        assert_eq!(
+            outcomes,
+            vec![
+                MintOutcome::Minted(2),
+                MintOutcome::Minted(3),
+                MintOutcome::Skipped(MintSkipReason::DuplicateClass),
+                MintOutcome::Minted(4),
+                MintOutcome::Skipped(MintSkipReason::NotAllowed),
+                MintOutcome::Minted(5),
+                MintOutcome::Skipped(MintSkipReason::Banned),
+                MintOutcome::Skipped(MintSkipReason::DuplicateClass),
+            ]
+        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 5);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt(issuer1(), 5, None).unwrap().owner, carol());
+
+        // nothing to mint: no event
+        let outcomes = ctr.sbt_mint_report(vec![(dan(), vec![mk_metadata(2, None)])]);
+        assert_eq!(outcomes, vec![MintOutcome::Skipped(MintSkipReason::Banned)]);
+        // mint events of the two previous mints only
+        assert_eq!(event_logs().len(), 2);
+        assert_eq!(ctr.sbt_supply(issuer1()), 5);
+    }
+
+    #[test]
+    fn config() {
+        let ctx = VMContextBuilder::new()
+            .predecessor_account_id(admin())
+            .build();
+        testing_env!(ctx);
+        let mut ctr = Contract::new(admin(), fractal_mainnet(), vec![1, 3], vec![alice(), bob()]);
+        let mut expected = RegistryConfig {
+            authority: admin(),
+            authorized_flaggers: vec![alice(), bob()],
+            iah_sbts: vec![(fractal_mainnet(), vec![1, 3])],
+            soul_tx_batch: SOUL_TX_BATCH,
+            max_query_limit: MAX_LIMIT,
+        };
+        assert_eq!(ctr.config(), expected);
+
+        ctr.admin_set_soul_tx_batch(5);
+        ctr.admin_set_authorized_flaggers(vec![carol()]);
+        expected.soul_tx_batch = 5;
+        expected.authorized_flaggers = vec![carol()];
+        assert_eq!(ctr.config(), expected);
+    }
+
+    #[test]
+    fn storage_usage_bytes() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let before = ctr.storage_usage_bytes();
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        let usage = ctr.storage_usage_bytes();
+        assert!(usage > 0);
+        assert!(usage > before);
+        assert_eq!(
+            ctr.storage_balance(),
+            U128(usage as u128 * env::storage_byte_cost())
+        );
+    }
+
+    #[test]
+    fn can_mint() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let allowed = CanMintResult {
+            allowed: true,
+            reasons: vec![],
+        };
+        let blocked = |reasons| CanMintResult {
+            allowed: false,
+            reasons,
+        };
+        assert_eq!(ctr.can_mint(issuer1(), alice(), 1), allowed);
+
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert_eq!(
+            ctr.can_mint(issuer1(), alice(), 1),
+            blocked(vec![MintSkipReason::DuplicateClass])
+        );
+        // other class and other issuer are fine
+        assert_eq!(ctr.can_mint(issuer1(), alice(), 2), allowed);
+        assert_eq!(ctr.can_mint(issuer2(), alice(), 1), allowed);
+
+        ctr.set_class_allowlist(2, vec![bob()]);
+        assert_eq!(
+            ctr.can_mint(issuer1(), alice(), 2),
+            blocked(vec![MintSkipReason::NotAllowed])
+        );
+        assert_eq!(ctr.can_mint(issuer1(), bob(), 2), allowed);
+
+        ctr.banlist.insert(&bob());
+        assert_eq!(
+            ctr.can_mint(issuer1(), bob(), 2),
+            blocked(vec![MintSkipReason::Banned])
+        );
+
+        assert_eq!(
+            ctr.can_mint(issuer1(), alice(), 0),
+            blocked(vec![MintSkipReason::InvalidClass])
+        );
+        assert_eq!(
+            ctr.can_mint(issuer4(), bob(), 1),
+            blocked(vec![MintSkipReason::UnknownIssuer, MintSkipReason::Banned])
+        );
+    }
+
+    #[test]
+    fn can_mint_issuer_paused() {
+        let (mut ctx, mut ctr) = setup(&admin(), MINT_DEPOSIT);
+        ctr.admin_pause_issuer(issuer1(), true);
+        assert_eq!(
+            ctr.can_mint(issuer1(), alice(), 1),
+            CanMintResult {
+                allowed: false,
+                reasons: vec![MintSkipReason::IssuerPaused],
+            }
+        );
+        ctr.banlist.insert(&bob());
+        assert_eq!(
+            ctr.can_mint(issuer1(), bob(), 1).reasons,
+            vec![MintSkipReason::IssuerPaused, MintSkipReason::Banned]
+        );
+        // other issuers are not affected
+        assert!(ctr.can_mint(issuer2(), alice(), 1).allowed);
+
+        ctr.admin_pause_issuer(issuer1(), false);
+        assert!(ctr.can_mint(issuer1(), alice(), 1).allowed);
+
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Class must be > 0")]
+    fn sbt_mint_report_invalid_metadata() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint_report(vec![(alice(), vec![mk_metadata(0, None)])]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "ERR_NOT_ISSUER: must be called by a registered SBT Issuer, caller: alice.near"
+    )]
+    fn mint_not_issuer() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(bob(), vec![mk_metadata(1, None)])]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "ERR_NOT_ISSUER: must be called by a registered SBT Issuer, caller: bob.near"
+    )]
+    fn revoke_not_issuer() {
+        let (_, mut ctr) = setup(&bob(), MINT_DEPOSIT);
+        ctr.sbt_revoke(vec![1], true, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called by a registered SBT Issuer")]
+    fn class_allowlist_not_issuer() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.set_class_allowlist(1, vec![alice()]);
+    }
+
+    #[test]
+    fn queries_unknown_issuer() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        assert_eq!(ctr.sbt(issuer4(), 1, None), None);
+        assert_eq!(ctr.sbts(issuer4(), vec![1, 2], None), vec![None, None]);
+        assert_eq!(ctr.sbt_classes(issuer4(), vec![1, 2]), vec![None, None]);
+        assert_eq!(ctr.sbt_supply(issuer4()), 0);
+        assert_eq!(ctr.sbt_classes(issuer1(), vec![1, 2]), vec![Some(1), None]);
+    }
+
+    #[test]
+    fn is_banned_bulk() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.banlist.insert(&bob());
+        ctr.banlist.insert(&dan());
+        assert_eq!(
+            ctr.is_banned_bulk(vec![alice(), bob(), carol(), dan(), bob()]),
+            vec![false, true, false, true, true]
+        );
+        assert_eq!(ctr.is_banned_bulk(vec![]), Vec::<bool>::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "max 1000 accounts can be queried")]
+    fn is_banned_bulk_limit() {
+        let (_, ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.is_banned_bulk(vec![alice(); MAX_LIMIT as usize + 1]);
+    }
+
+    #[test]
+    fn token_issuer_id() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, None);
+        ctr.sbt_mint(vec![(alice(), vec![m1.clone()])]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![m1])]);
+
+        for issuer in [issuer1(), issuer2()] {
+            let issuer_id = ctr.assert_issuer(&issuer);
+            assert_eq!(
+                ctr.sbt(issuer.clone(), 1, None).unwrap().issuer_id,
+                issuer_id
+            );
+            assert_eq!(
+                ctr.sbts(issuer.clone(), vec![1], None)[0]
+                    .as_ref()
+                    .unwrap()
+                    .issuer_id,
+                issuer_id
+            );
+            assert_eq!(
+                ctr.sbt_tokens(issuer, None, None, None)[0].issuer_id,
+                issuer_id
+            );
+        }
+        assert_ne!(ctr.assert_issuer(&issuer1()), ctr.assert_issuer(&issuer2()));
+    }
+
+    #[test]
+    fn sbt_tokens_with_issuer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, None);
+        let m2 = mk_metadata(2, None);
+        ctr.sbt_mint(vec![(alice(), vec![m1.clone()]), (bob(), vec![m2.clone()])]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(carol(), vec![m1.clone()])]);
+
+        assert_eq!(
+            ctr.sbt_tokens_with_issuer(issuer1(), None, None, None),
+            vec![
+                (issuer1(), mk_token(1, alice(), m1.clone())),
+                (issuer1(), mk_token(2, bob(), m2))
+            ]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_with_issuer(issuer2(), None, None, None),
+            vec![(issuer2(), mk_issuer_token(3, 1, carol(), m1))]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_with_issuer(issuer4(), None, None, None),
+            vec![]
+        );
+    }
+
+    fn hook_calls() -> Vec<(AccountId, String, String)> {
+        test_utils::get_created_receipts()
+            .into_iter()
+            .flat_map(|r| {
+                r.actions.into_iter().filter_map(move |a| match a {
+                    near_sdk::mock::VmAction::FunctionCall {
+                        function_name,
+                        args,
+                        ..
+                    } => Some((
+                        r.receiver_id.clone(),
+                        function_name,
+                        String::from_utf8(args).unwrap(),
+                    )),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn soul_tx_hooks() {
+        let (mut ctx, mut ctr) = setup(&admin(), 3 * MINT_DEPOSIT);
+        let hook1 = AccountId::new_unchecked("hook1.near".to_string());
+        let hook2 = AccountId::new_unchecked("hook2.near".to_string());
+        assert!(ctr.admin_register_soul_tx_hook(hook1.clone()));
+        assert!(ctr.admin_register_soul_tx_hook(hook2.clone()));
+        assert!(!ctr.admin_register_soul_tx_hook(hook1.clone()));
+        assert_eq!(ctr.soul_tx_hooks(), vec![hook1.clone(), hook2.clone()]);
+
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(3))]);
+
+        // hooks are called only once the soul transfer completes
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        assert_eq!(
+            ctr._sbt_soul_transfer(bob(), 2, LockedPolicy::Skip)
+                .unwrap(),
+            (2, false)
+        );
+        assert_eq!(hook_calls(), vec![]);
+        assert_eq!(
+            ctr._sbt_soul_transfer(bob(), 2, LockedPolicy::Skip)
+                .unwrap(),
+            (1, true)
+        );
+        let args = r#"{"from":"alice.near","to":"bob.near"}"#.to_owned();
+        assert_eq!(
+            hook_calls(),
+            vec![
+                (hook1.clone(), "on_soul_transfer".to_owned(), args.clone()),
+                (hook2.clone(), "on_soul_transfer".to_owned(), args)
+            ]
+        );
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        assert!(ctr.admin_unregister_soul_tx_hook(hook1.clone()));
+        assert!(!ctr.admin_unregister_soul_tx_hook(hook1));
+        assert_eq!(ctr.soul_tx_hooks(), vec![hook2.clone()]);
+
+        ctx.predecessor_account_id = bob();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.sbt_soul_transfer(carol(), None, None).unwrap(),
+            (3, true)
+        );
+        assert_eq!(
+            hook_calls(),
+            vec![(
+                hook2,
+                "on_soul_transfer".to_owned(),
+                r#"{"from":"bob.near","to":"carol.near"}"#.to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "max 5 soul transfer hooks can be registered")]
+    fn soul_tx_hooks_limit() {
+        let (_, mut ctr) = setup(&admin(), MINT_DEPOSIT);
+        for i in 0..=MAX_SOUL_TX_HOOKS {
+            ctr.admin_register_soul_tx_hook(AccountId::new_unchecked(format!("hook{}.near", i)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn soul_tx_hooks_not_admin() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.admin_register_soul_tx_hook(bob());
+    }
+
+    #[test]
+    fn soul_transfer_to_self() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.sbt_soul_transfer(alice(), None, None),
+            Err(SoulTransferErr::SelfTransfer)
+        );
+        assert!(!ctr.is_banned(alice()));
+        assert!(test_utils::get_logs().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "from and to must be different accounts")]
+    fn sbt_recover_to_self() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_recover(alice(), alice());
+    }
+
+    #[test]
+    fn metadata_version() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert_eq!(ctr.supported_metadata_versions(), vec![1, 2]);
+        assert_eq!(ctr.sbt(issuer1(), 1, None).unwrap().metadata_version, 1);
+        assert_eq!(
+            ctr.sbt_tokens(issuer1(), None, None, None)[0].metadata_version,
+            1
+        );
+        let owned = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(owned[0].1[0].metadata_version, 1);
+    }
+
+    #[test]
+    fn admin_expire_issuer() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 5 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, Some(START + 100))]),
+            (carol(), vec![mk_metadata(1, Some(START))]),
+            (dan(), vec![mk_metadata(1, None)]),
+        ]);
+        // burn a token, to have a gap in the token IDs
+        ctx.predecessor_account_id = dan();
+        testing_env!(ctx.clone());
+        ctr.sbt_burn(fractal_mainnet(), vec![4], None);
+
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert!(ctr.is_human_bool(alice()));
+
+        let now = START + 10;
+        ctx.predecessor_account_id = admin();
+        ctx.block_timestamp = now * MSECOND;
+        testing_env!(ctx.clone());
+        // carol token (3) is already expired and not modified
+        assert_eq!(ctr.admin_expire_issuer(fractal_mainnet(), 2), (2, false));
+        assert_eq!(
+            test_utils::get_logs(),
+            mk_log_str(
+                "revoke",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[1,2],"reason":"expired_policy","event_seq":4}}"#,
+                    fractal_mainnet()
+                )
+            )
+        );
+        assert_eq!(ctr.admin_expire_issuer(fractal_mainnet(), 2), (0, true));
+        assert_eq!(ctr.issuer_expire_cursor.get(&1), None);
+
+        let t = ctr.sbt(fractal_mainnet(), 2, None).unwrap();
+        assert_eq!(t.metadata.expires_at, Some(now));
+        assert_eq!(
+            ctr.sbt(fractal_mainnet(), 3, None)
+                .unwrap()
+                .metadata
+                .expires_at,
+            Some(START)
+        );
+
+        ctx.block_timestamp = (now + 1) * MSECOND;
+        testing_env!(ctx);
+        assert_eq!(ctr.sbt(fractal_mainnet(), 1, Some(true)), None);
+        assert_eq!(ctr.sbt(fractal_mainnet(), 2, Some(true)), None);
+        assert!(!ctr.is_human_bool(alice()));
+        // other issuers are not affected
+        assert!(ctr.sbt(issuer1(), 1, Some(true)).is_some());
+        assert_eq!(ctr.sbt_supply(fractal_mainnet()), 3);
+    }
+
+    #[test]
+    fn admin_rebuild_supplies() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (bob(), vec![mk_metadata(1, None), mk_metadata(3, None)]),
+            (carol(), vec![mk_metadata(1, None)]),
+        ]);
+        ctr.sbt_revoke(vec![4], true, None);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        // corrupt the counters
+        ctr.supply_by_issuer.insert(&2, &10);
+        ctr.set_class_supply(2, 1, 0);
+        ctr.set_class_supply(2, 3, 5);
+        ctr.supply_by_owner.insert(&(alice(), 2), &7);
+        ctr.supply_by_owner.insert(&(carol(), 2), &0);
+        ctr.supply_by_owner_total.remove(&carol());
+        ctr.total_souls = 2;
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        assert_eq!(ctr.admin_rebuild_supplies(issuer1(), None, 2), (3, false));
+        assert_eq!(
+            ctr.admin_rebuild_supplies(issuer1(), Some(3), 2),
+            (5, false)
+        );
+        // issuer and class supplies are written at the end
+        assert_eq!(ctr.sbt_supply(issuer1()), 10);
+        assert_eq!(ctr.admin_rebuild_supplies(issuer1(), Some(5), 2), (6, true));
+
+        assert_eq!(ctr.sbt_supply(issuer1()), 4);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 3);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 3), 0);
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(1, 3), (2, 1)]
+        );
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None, None), 1);
+        assert_eq!(ctr.supply_by_owner_total.get(&alice()), Some(3));
+        assert_eq!(ctr.supply_by_owner_total.get(&carol()), Some(1));
+        assert_eq!(ctr.total_souls(), 3);
+        // other issuers are not affected
+        assert_eq!(ctr.sbt_supply(issuer2()), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1);
+
+        // rebuild is idempotent
+        assert_eq!(ctr.admin_rebuild_supplies(issuer1(), None, 10), (6, true));
+        assert_eq!(ctr.sbt_supply(issuer1()), 4);
+        assert_eq!(ctr.total_souls(), 3);
+    }
+
+    #[test]
+    fn admin_rebuild_supplies_zero_owner_supply() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        // corrupt the balances: the token is not indexed for the owner
+        ctr.balances.remove(&balance_key(alice(), 2, ClassKey(1)));
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx);
+        assert_eq!(ctr.admin_rebuild_supplies(issuer1(), None, 10), (2, true));
+        // zero supplies are removed, not stored
+        assert_eq!(ctr.supply_by_owner.get(&(alice(), 2)), None);
+        assert_eq!(ctr.supply_by_owner_total.get(&alice()), None);
+        assert_eq!(ctr.total_souls(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "cursor mismatch, expected 3")]
+    fn admin_rebuild_supplies_cursor_mismatch() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(3))]);
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx);
+        ctr.admin_rebuild_supplies(issuer1(), None, 2);
+        ctr.admin_rebuild_supplies(issuer1(), Some(2), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn admin_rebuild_supplies_not_admin() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.admin_rebuild_supplies(issuer1(), None, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn admin_expire_issuer_not_admin() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.admin_expire_issuer(issuer1(), 10);
+    }
+
+    #[test]
+    fn sbt_tokens_by_issued_range() {
+        let (_, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        let mut ms = mk_batch_metadata(5);
+        for (i, m) in ms.iter_mut().enumerate() {
+            m.issued_at = Some(100 * i as u64);
+        }
+        ctr.sbt_mint(vec![(alice(), ms[..2].to_vec()), (bob(), ms[2..].to_vec())]);
+        // sbt_mint always sets issued_at, but tokens minted before v1.6 may not have it.
+        let key = IssuerTokenId::new(2, 5);
+        let mut t = ctr.issuer_tokens.get(&key).unwrap();
+        let mut m = t.metadata.latest();
+        m.issued_at = None;
+        t.metadata = m.into();
+        ctr.issuer_tokens.insert(&key, &t);
+        let ids = |ts: Vec<Token>| -> Vec<TokenId> { ts.into_iter().map(|t| t.token).collect() };
+
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_issued_range(issuer1(), 0, 1000, None, None)),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_issued_range(issuer1(), 100, 200, None, None)),
+            vec![2, 3]
+        );
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_issued_range(issuer1(), 100, 100, None, None)),
+            vec![2]
+        );
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_issued_range(issuer1(), 0, 300, Some(2), Some(2))),
+            vec![2, 3]
+        );
+        assert!(ctr
+            .sbt_tokens_by_issued_range(issuer1(), 301, 400, None, None)
+            .is_empty());
+        assert!(ctr
+            .sbt_tokens_by_issued_range(issuer1(), 0, 300, Some(6), None)
+            .is_empty());
+        assert!(ctr
+            .sbt_tokens_by_issued_range(issuer4(), 0, 300, None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn check_id_invariants() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        assert!(ctr.check_id_invariants(None, None).0);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(2))]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(bob(), mk_batch_metadata(1))]);
+        assert!(ctr.check_id_invariants(None, None).0);
+
+        // break the token counter
+        ctr.next_token_ids.insert(&2, &1);
+        assert!(!ctr.check_id_invariants(None, None).0);
+        ctr.next_token_ids.insert(&2, &2);
+        assert!(ctr.check_id_invariants(None, None).0);
+
+        // paging over the ownership records
+        let (ok, next) = ctr.check_id_invariants(None, Some(2));
+        assert!(ok);
+        let (ok, next) = ctr.check_id_invariants(next, Some(2));
+        assert!(ok);
+        assert_eq!(next, None);
+
+        // token ID above the token counter
+        ctr.next_token_ids.insert(&2, &1);
+        ctr.issuer_tokens.remove(&IssuerTokenId::new(2, 2));
+        assert!(!ctr.check_id_invariants(None, None).0);
+        let (ok, next) = ctr.check_id_invariants(None, Some(1));
+        assert!(ok);
+        assert!(!ctr.check_id_invariants(next, Some(1)).0);
+        ctr.next_token_ids.insert(&2, &2);
+        assert!(ctr.check_id_invariants(None, None).0);
+
+        // break the supply counters
+        ctr.supply_by_issuer.insert(&2, &3);
+        assert!(!ctr.check_id_invariants(None, None).0);
+        ctr.supply_by_issuer.insert(&2, &2);
+        assert!(ctr.check_id_invariants(None, None).0);
+
+        // break the issuer counter
+        ctr.next_issuer_id = 3;
+        assert!(!ctr.check_id_invariants(None, None).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "internal error: token 2 already exists")]
+    fn mint_doesnt_overwrite_tokens() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(2))]);
+        ctr.next_token_ids.insert(&2, &1);
+        ctr.sbt_mint(vec![(bob(), mk_batch_metadata(1))]);
+    }
+
+    #[test]
+    fn mint_stats_log() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), mk_batch_metadata(2)),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        let logs = test_utils::get_logs();
+        assert_eq!(logs.len(), 2);
+        let stats = logs[1].strip_prefix("mint_stats: ").unwrap();
+        let stats: serde_json::Value = serde_json::from_str(stats).unwrap();
+        assert_eq!(stats["tokens"], 3);
+        assert!(stats["storage_bytes"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn sbt_supply_by_classes() {
+        let (_, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(3, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        assert_eq!(
+            ctr.sbt_supply_by_classes(issuer1(), vec![1, 2, 3, 1]),
+            vec![2, 0, 1, 2]
+        );
+        assert_eq!(ctr.sbt_supply_by_classes(issuer2(), vec![1, 2]), vec![0, 0]);
+        assert_eq!(ctr.sbt_supply_by_classes(issuer4(), vec![1, 2]), vec![0, 0]);
+        assert_eq!(
+            ctr.sbt_supply_by_classes(issuer1(), vec![]),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn sbt_supply_by_class_checked() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        assert_eq!(ctr.sbt_supply_by_class_checked(issuer1(), 1), Some(2));
+        // registered issuer, class without tokens
+        assert_eq!(ctr.sbt_supply_by_class_checked(issuer1(), 2), Some(0));
+        assert_eq!(ctr.sbt_supply_by_class_checked(issuer2(), 1), Some(0));
+        // unknown issuer
+        assert_eq!(ctr.sbt_supply_by_class_checked(issuer4(), 1), None);
+        assert_eq!(ctr.sbt_supply_by_class(issuer4(), 1), 0);
+    }
+
+    #[test]
+    fn ban_with_scope() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        let alice_tokens = vec![(issuer1(), vec![mk_owned_token(1, mk_metadata(1, None))])];
+        assert_eq!(ctr.ban_scope(alice()), None);
+
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        ctr.admin_ban_with_scope(alice(), BanScope::TransferOnly);
+        assert_eq!(
+            test_utils::get_logs(),
+            mk_log_str("ban", &format!(r#"["{}"]"#, alice()))
+        );
+        assert!(ctr.is_banned(alice()));
+        assert_eq!(ctr.ban_scope(alice()), Some(BanScope::TransferOnly));
+        // tokens are still visible
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            alice_tokens
+        );
+
+        // upgrade the ban scope, no new ban event
+        testing_env!(ctx.clone());
+        ctr.admin_ban_with_scope(alice(), BanScope::Full);
+        assert!(test_utils::get_logs().is_empty());
+        assert_eq!(ctr.ban_scope(alice()), Some(BanScope::Full));
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![]
+        );
+        // other queries are not affected
+        assert!(ctr.sbt(issuer1(), 1, None).is_some());
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+        assert!(!ctr.is_human_bool(alice()));
+
+        // downgrade the ban scope
+        ctr.admin_ban_with_scope(alice(), BanScope::TransferOnly);
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            alice_tokens
+        );
+
+        // ban scope doesn't block the issuer from revoking tokens
+        ctr.admin_ban_with_scope(bob(), BanScope::Full);
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        assert!(ctr.sbt_revoke_by_owner(bob(), true, None));
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "account bob.near is banned")]
+    fn ban_with_scope_blocks_mint() {
+        let (mut ctx, mut ctr) = setup(&admin(), MINT_DEPOSIT);
+        ctr.admin_ban_with_scope(bob(), BanScope::Full);
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(bob(), vec![mk_metadata(1, None)])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "from account is banned. Cannot start the transfer")]
+    fn ban_with_scope_blocks_soul_transfer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        ctr.admin_ban_with_scope(alice(), BanScope::TransferOnly);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_soul_transfer(bob(), None, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn ban_with_scope_not_admin() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.admin_ban_with_scope(bob(), BanScope::Full);
+    }
+
+    #[test]
+    fn sbt_tokens_paging_overflow() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert!(ctr
+            .sbt_tokens(issuer1(), Some(u64::MAX), Some(u32::MAX), None)
+            .is_empty());
+
+        ctr.next_token_ids.insert(&2, &u64::MAX);
+        for from_token in [u64::MAX, u64::MAX - 1, u64::MAX - 10] {
+            assert!(ctr
+                .sbt_tokens(issuer1(), Some(from_token), Some(u32::MAX), None)
+                .is_empty());
+            assert!(ctr
+                .sbt_tokens_by_issued_range(issuer1(), 0, u64::MAX, Some(from_token), Some(20))
+                .is_empty());
+        }
+        assert_eq!(ctr.sbt_tokens(issuer1(), None, Some(2), None).len(), 1);
+    }
+
+    #[test]
+    fn sbt_transfer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, None);
+        let m2 = mk_metadata(2, None);
+        ctr.sbt_mint(vec![(alice(), vec![m1.clone(), m2.clone()])]);
+        assert!(!ctr.is_class_transferable(issuer1(), 1));
+        ctr.set_class_transferable(1, true);
+        assert!(ctr.is_class_transferable(issuer1(), 1));
+        assert!(!ctr.is_class_transferable(issuer1(), 2));
+        assert!(!ctr.is_class_transferable(issuer2(), 1));
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        ctr.sbt_transfer(issuer1(), 1, bob());
+        assert_eq!(
+            test_utils::get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"i_am_human","version":"1.0.0","event":"sbt_transfer","data":{{"event_seq":2,"from":"{}","issuer":"{}","to":"{}","token":1}}}}"#,
+                alice(),
+                issuer1(),
+                bob()
+            )]
+        );
+        assert_eq!(
+            ctr.sbt(issuer1(), 1, None).unwrap(),
+            mk_token(1, bob(), m1.clone())
+        );
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 1);
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(bob(), None, None, None, None),
+            vec![(issuer1(), vec![mk_owned_token(1, m1)])]
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![(issuer1(), vec![mk_owned_token(2, m2)])]
+        );
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
+
+        // issuer can make the class soulbound again
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.set_class_transferable(1, false);
+        assert!(!ctr.is_class_transferable(issuer1(), 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "tokens of class 1 are not transferable")]
+    fn sbt_transfer_soulbound() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_transfer(issuer1(), 1, bob());
+    }
+
+    #[test]
+    fn sbt_transfer_last_token() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.set_class_transferable(1, true);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_transfer(issuer1(), 1, bob());
+        // the zero supply entry is removed
+        assert!(ctr.supply_by_owner.get(&(alice(), 2)).is_none());
+        assert_eq!(ctr.supply_by_owner.get(&(bob(), 2)), Some(1));
+        assert!(ctr.check_id_invariants(None, None).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't transfer tokens: owner has a transfer lock")]
+    fn sbt_transfer_locked() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.set_class_transferable(1, true);
+        ctr.transfer_lock.insert(&alice(), &(START + 10));
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_transfer(issuer1(), 1, bob());
+    }
+
+    #[test]
+    #[should_panic(expected = "can't transfer tokens: owner has an ongoing is_human_call")]
+    fn sbt_transfer_ongoing_is_human_call() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.set_class_transferable(1, true);
+        ctr.ongoing_is_human_calls.insert(&alice(), &1);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_transfer(issuer1(), 1, bob());
+    }
+
+    #[test]
+    #[should_panic(expected = "not an owner of tokenID=1")]
+    fn sbt_transfer_not_owner() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.set_class_transferable(1, true);
+        ctx.predecessor_account_id = bob();
+        testing_env!(ctx);
+        ctr.sbt_transfer(issuer1(), 1, carol());
+    }
+
+    #[test]
+    #[should_panic(expected = "bob.near already has SBT of class 1")]
+    fn sbt_transfer_recipient_has_class() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        ctr.set_class_transferable(1, true);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_transfer(issuer1(), 1, bob());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called by a registered SBT Issuer")]
+    fn set_class_transferable_not_issuer() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.set_class_transferable(1, true);
+    }
+
+    #[test]
+    fn sbt_reissue() {
+        let (ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, Some(START));
+        let m2 = mk_metadata(2, None);
+        ctr.sbt_mint(vec![(alice(), vec![m1]), (bob(), vec![m2.clone()])]);
+
+        // same class, new expiry
+        let m1_new = mk_metadata(1, Some(START + 100));
+        testing_env!(ctx.clone());
+        assert_eq!(ctr.sbt_reissue(1, m1_new.clone()), 3);
+        assert_eq!(
+            test_utils::get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"i_am_human","version":"1.0.0","event":"sbt_reissue","data":{{"event_seq":2,"issuer":"{}","new_token":3,"old_token":1,"owner":"{}"}}}}"#,
+                issuer1(),
+                alice()
+            )]
+        );
+        assert!(ctr.sbt(issuer1(), 1, None).is_none());
+        assert_eq!(
+            ctr.sbt(issuer1(), 3, None).unwrap(),
+            mk_token(3, alice(), m1_new.clone())
+        );
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![(issuer1(), vec![mk_owned_token(3, m1_new)])]
+        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
+
+        // different class
+        let m3 = mk_metadata(3, None);
+        assert_eq!(ctr.sbt_reissue(2, m3.clone()), 4);
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(bob(), None, None, None, None),
+            vec![(issuer1(), vec![mk_owned_token(4, m3)])]
+        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 3), 1);
+
+        // the default expiry policy applies
+        ctr.set_default_expiry_policy(ExpiryPolicy::FixedTtl(50));
+        assert_eq!(ctr.sbt_reissue(4, mk_metadata(5, None)), 5);
+        assert_eq!(
+            ctr.sbt(issuer1(), 5, None).unwrap().metadata.expires_at,
+            Some(START + 50)
+        );
+        assert!(ctr.check_id_invariants(None, None).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "alice.near already has SBT of class 2")]
+    fn sbt_reissue_class_conflict() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(
+            alice(),
+            vec![mk_metadata(1, None), mk_metadata(2, None)],
+        )]);
+        ctr.sbt_reissue(1, mk_metadata(2, None));
+    }
+
+    #[test]
+    #[should_panic(expected = "token 1 not found")]
+    fn sbt_reissue_other_issuer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_reissue(1, mk_metadata(1, None));
+    }
+
+    #[test]
+    fn event_seq() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        assert_eq!(ctr.last_event_seq(), 0);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert_eq!(ctr.last_event_seq(), 1);
+        ctr.sbt_mint(vec![(bob(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_renew(tokens.clone(), START + 100);
+        assert_eq!(ctr.last_event_seq(), 3);
+        // burn emits burn and revoke events
+        ctr.sbt_revoke(tokens, true, None);
+        assert_eq!(ctr.last_event_seq(), 5);
+
+        ctx.predecessor_account_id = bob();
+        testing_env!(ctx);
+        ctr.sbt_burn(issuer1(), vec![2], None);
+        assert_eq!(ctr.last_event_seq(), 6);
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "burn",
+                &format!(r#"{{"issuer":"{}","tokens":[2],"event_seq":6}}"#, issuer1())
+            )
+        );
+    }
+
+    #[test]
+    fn event_seq_sbt_transfer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.set_class_transferable(1, true);
+        assert_eq!(ctr.last_event_seq(), 1);
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        ctr.sbt_transfer(issuer1(), 1, bob());
+        assert_eq!(ctr.last_event_seq(), 2);
+        assert!(test_utils::get_logs()[0].contains(r#""event_seq":2"#));
+
+        ctx.predecessor_account_id = bob();
+        testing_env!(ctx);
+        ctr.sbt_burn(issuer1(), vec![1], None);
+        assert_eq!(ctr.last_event_seq(), 3);
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "burn",
+                &format!(r#"{{"issuer":"{}","tokens":[1],"event_seq":3}}"#, issuer1())
+            )
+        );
+    }
+
+    #[test]
+    fn banned_accounts() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        assert_eq!(ctr.banned_count(), 0);
+        assert!(ctr.banned_accounts(None, None).is_empty());
+
+        ctr.banlist.insert(&bob());
+        ctr.banlist.insert(&dan());
+        ctr.banlist.insert(&carol());
+        assert_eq!(ctr.banned_count(), 3);
+        assert_eq!(ctr.banned_accounts(None, None), vec![bob(), dan(), carol()]);
+        assert_eq!(ctr.banned_accounts(Some(1), Some(1)), vec![dan()]);
+        assert_eq!(ctr.banned_accounts(Some(2), Some(5)), vec![carol()]);
+        assert!(ctr.banned_accounts(Some(3), None).is_empty());
+    }
+
+    #[test]
+    fn recent_tokens() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        let mut ms = Vec::new();
+        for (cls, issued_at) in [
+            (1, START),
+            (2, START + 10),
+            (3, START + 20),
+            (4, START + 20),
+        ] {
+            let mut m = mk_metadata(cls, None);
+            m.issued_at = Some(issued_at);
+            ms.push(m);
+        }
+        ctr.sbt_mint(vec![(alice(), ms.clone())]);
+        // minted now, without custom issued_at
+        ctx.block_timestamp = (START + 30) * MSECOND;
+        testing_env!(ctx);
+        let mut m5 = mk_metadata(5, None);
+        m5.issued_at = None;
+        ctr.sbt_mint(vec![(alice(), vec![m5.clone()])]);
+        m5.issued_at = Some(START + 30);
+        let t5 = mk_token(5, alice(), m5);
+
+        // legacy token without issued_at
+        let key = IssuerTokenId::new(2, 3);
+        let mut m3 = ms[2].clone();
+        m3.issued_at = None;
+        ctr.issuer_tokens.insert(
+            &key,
+            &TokenData {
+                owner: alice(),
+                metadata: m3.into(),
+            },
+        );
+
+        let page = ctr.recent_tokens(issuer1(), START + 10, None, None);
+        assert_eq!(
+            page.tokens,
+            vec![
+                t5.clone(),
+                mk_token(4, alice(), ms[3].clone()),
+                mk_token(2, alice(), ms[1].clone())
+            ]
+        );
+        assert_eq!(page.next_from_token, None);
+
+        // paging: at most `limit` token IDs are scanned
+        let page = ctr.recent_tokens(issuer1(), START + 10, None, Some(2));
+        assert_eq!(
+            page.tokens,
+            vec![t5.clone(), mk_token(4, alice(), ms[3].clone())]
+        );
+        assert_eq!(page.next_from_token, Some(3));
+        // token 3 doesn't have issued_at, so it's skipped
+        let page = ctr.recent_tokens(issuer1(), START + 10, Some(3), Some(1));
+        assert!(page.tokens.is_empty());
+        assert_eq!(page.next_from_token, Some(2));
+        let page = ctr.recent_tokens(issuer1(), START + 10, Some(2), Some(2));
+        assert_eq!(page.tokens, vec![mk_token(2, alice(), ms[1].clone())]);
+        assert_eq!(page.next_from_token, None);
+
+        let page = ctr.recent_tokens(issuer1(), START + 30, None, None);
+        assert_eq!(page.tokens, vec![t5]);
+        assert_eq!(page.next_from_token, None);
+        assert!(ctr
+            .recent_tokens(issuer1(), START + 31, None, None)
+            .tokens
+            .is_empty());
+        let page = ctr.recent_tokens(issuer1(), 0, Some(100), None);
+        assert_eq!(page.tokens.len(), 4);
+        assert_eq!(page.next_from_token, None);
+        let page = ctr.recent_tokens(issuer1(), 0, None, Some(5));
+        assert_eq!(page.tokens.len(), 4);
+        assert_eq!(page.next_from_token, None);
+        let page = ctr.recent_tokens(issuer4(), 0, None, None);
+        assert!(page.tokens.is_empty());
+        assert_eq!(page.next_from_token, None);
+    }
+
+    #[test]
+    fn events_standard() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_mint(vec![(bob(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_renew(tokens.clone(), START + 100);
+        ctr.sbt_revoke(tokens, true, None);
+        ctr.sbt_recover(bob(), carol());
+        ctr.sbt_mint(vec![(dan(), vec![mk_metadata(1, None)])]);
+        // testing_env resets the logs
+        let mut logs = test_utils::get_logs();
+        ctx.predecessor_account_id = dan();
+        testing_env!(ctx.clone());
+        ctr.sbt_burn(issuer1(), vec![3], None);
+        logs.extend(test_utils::get_logs());
+
+        // issuer1 is an authorized flagger
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.admin_flag_accounts(AccountFlag::Verified, vec![alice()], "memo".to_owned());
+        logs.extend(test_utils::get_logs());
+
+        let mut events = Vec::new();
+        for l in logs.iter().filter(|l| l.starts_with("EVENT_JSON:")) {
+            let e: serde_json::Value = serde_json::from_str(&l["EVENT_JSON:".len()..]).unwrap();
+            let name = e["event"].as_str().unwrap().to_owned();
+            let (standard, version) = if name.starts_with("flag_") {
+                (events::IAH_STANDARD, events::IAH_VERSION)
+            } else {
+                (STANDARD_NAME, SPEC_VERSION)
+            };
+            assert_eq!(e["standard"], standard, "{}", l);
+            assert_eq!(e["version"], version, "{}", l);
+            events.push(name);
+        }
+        assert_eq!(
+            events,
+            vec![
+                "mint",
+                "mint",
+                "renew",
+                "burn",
+                "revoke",
+                "recover",
+                "mint",
+                "burn",
+                "flag_verified"
+            ]
+        );
+    }
+
+    #[test]
+    fn issuer_diversity() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        assert_eq!(ctr.issuer_diversity(alice()), 0);
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![mk_metadata(1, None), mk_metadata(2, Some(START))],
+            ),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = issuer3();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(4, None)])]);
+
+        assert_eq!(ctr.issuer_diversity(alice()), 3);
+        assert_eq!(ctr.issuer_diversity(bob()), 1);
+        assert_eq!(ctr.issuer_diversity(carol()), 0);
+
+        ctr.ongoing_soul_tx.insert(
+            &alice(),
+            &SoulTxCursor {
+                issuer_id: 1,
+                class_id: ClassKey(0),
+            },
+        );
+        assert_eq!(ctr.issuer_diversity(alice()), 0);
+    }
+
+    #[test]
+    fn empty_issuers() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        assert_eq!(
+            ctr.empty_issuers(None, None),
+            vec![fractal_mainnet(), issuer1(), issuer2(), issuer3()]
+        );
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert_eq!(
+            ctr.empty_issuers(None, None),
+            vec![fractal_mainnet(), issuer3()]
+        );
+        assert_eq!(ctr.empty_issuers(Some(2), Some(2)), vec![]);
+        assert_eq!(ctr.empty_issuers(Some(3), None), vec![issuer3()]);
+        assert_eq!(ctr.empty_issuers(Some(10), None), vec![]);
+
+        // all tokens burned
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_burn(issuer2(), vec![1], None);
+        assert_eq!(ctr.empty_issuers(Some(2), None), vec![issuer2(), issuer3()]);
+    }
+
+    #[test]
+    fn test_mk_log() {
+        let l = mk_log_str("abc", "[1,2,3]");
+        assert_eq!(
+            l,
+            vec![
+                r#"EVENT_JSON:{"standard":"nep393","version":"1.0.0","event":"abc","data":[1,2,3]}"#
+            ],
+        )
+    }
+
+    fn mk_log_str(event: &str, data: &str) -> Vec<String> {
+        vec![format!(
+            "EVENT_JSON:{{\"standard\":\"nep393\",\"version\":\"1.0.0\",\"event\":\"{}\",\"data\":{}}}",
+            event,data
+        )]
+    }
+
+    #[test]
+    fn compact_mint_events() {
+        let (ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        ctr.set_compact_events(true);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        let ids: Vec<u8> = [1_u64, 2, 3].iter().flat_map(|t| t.to_le_bytes()).collect();
+        let hash = near_sdk::base64::encode(env::sha256(&ids));
+        assert_eq!(
+            event_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"i_am_human","version":"1.0.0","event":"mint_compact","data":{{"event_seq":1,"issuer":"{}","owners":2,"tokens":3,"tokens_hash":"{}"}}}}"#,
+                issuer1(),
+                hash
+            )]
+        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 3);
+
+        // back to the NEP-393 mint event
+        testing_env!(ctx);
+        ctr.set_compact_events(false);
+        ctr.sbt_mint(vec![(carol(), vec![mk_metadata(1, None)])]);
+        assert_eq!(
+            event_logs(),
+            mk_log_str(
+                "mint",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[["{}",[4]]],"event_seq":2}}"#,
+                    issuer1(),
+                    carol()
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn check_tree_iterator() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.balances.insert(&mk_balance_key(alice2(), 1, 1), &101);
+        ctr.balances.insert(&mk_balance_key(alice(), 1, 1), &102);
+        ctr.balances.insert(&mk_balance_key(bob(), 1, 1), &103);
+
+        let bs: Vec<(BalanceKey, u64)> = ctr
+            .balances
+            .iter_from(mk_balance_key(alice(), 0, 0))
+            .collect();
+        assert_eq!(bs.len(), 2, "bob must be included in the prefix scan");
+        assert_eq!(
+            bs[0].0.owner,
+            alice(),
+            "alice must be first in the iterator"
+        );
+        assert_eq!(bs[0].1, 102, "alice must be first in the iterator");
+        assert_eq!(bs[1].0.owner, bob(), "bob must be second in the iterator");
+        assert_eq!(bs[1].1, 103, "alice must be first in the iterator");
+    }
+
+    #[test]
+    fn registry_renew_one_issuer() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+
+        // mint two tokens
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 11));
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 2);
+
+        // renvew the two tokens
+        let new_expire = START + 100;
+        ctr.sbt_renew(tokens, new_expire);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 2);
+        let m1_1_renewed = mk_metadata(1, Some(new_expire));
+        let m2_1_renewed = mk_metadata(2, Some(new_expire));
+
+        // assert the two tokens have been renewed (new expire_at)
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None),
+            vec![(
+                issuer1(),
+                vec![
+                    mk_owned_token(1, m1_1_renewed),
+                    mk_owned_token(2, m2_1_renewed)
+                ]
+            ),]
+        );
+    }
+
+    #[test]
+    fn registry_renew_multiple_issuers() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+
+        // mint two tokens by issuer1
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 11));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 2);
+
+        // mint two tokens by issuer2
+        let m1_2 = mk_metadata(1, Some(START + 10));
+        let m2_2: TokenMetadata = mk_metadata(2, Some(START + 12));
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        let tokens_issuer2 = ctr.sbt_mint(vec![(alice(), vec![m1_2, m2_2])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 2);
+
+        // renvew the two tokens
+        ctr.sbt_renew(tokens_issuer2, START + 100);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 2);
+        let m1_2_renewed = mk_metadata(1, Some(START + 100));
+        let m2_2_renewed = mk_metadata(2, Some(START + 100));
+
+        // assert tokens issued by issuer2 has been renewed (new expire_at)
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None),
+            vec![(
+                issuer2(),
+                vec![
+                    mk_owned_token(1, m1_2_renewed),
+                    mk_owned_token(2, m2_2_renewed)
+                ]
+            ),]
+        );
+
+        // assert tokens issued by issuer1 has not been renewed (new expire_at)
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None),
+            vec![(
+                issuer1(),
+                vec![mk_owned_token(1, m1_1), mk_owned_token(2, m2_1)]
+            ),]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn registry_renew_basics() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+
+        // mint two tokens
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+
+        // check if only the issuer can renew the tokens (should panic)
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_renew(tokens, START + 100);
+    }
+
+    #[test]
+    fn registry_renew_event() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+
+        // mint two tokens
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        ctr.sbt_renew(tokens.clone(), START + 100);
+        let log_mint = mk_log_str(
+            "mint",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[["{}",[1]]],"event_seq":1}}"#,
+                issuer1(),
+                alice()
+            ),
+        );
+        let log_renew = mk_log_str(
+            "renew",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[{}],"renewals":[{{"token":{},"old_expires_at":{},"expires_at":{}}}],"event_seq":2}}"#,
+                issuer1(),
+                tokens[0],
+                tokens[0],
+                START + 10,
+                START + 100
+            ),
+        );
+        let logs = event_logs();
+        assert_eq!(logs, vec![log_mint, log_renew].concat());
+
+        // indexers can compute the extension from the event
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[1].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        let renewal = &event["data"]["renewals"][0];
+        assert_eq!(renewal["token"], tokens[0]);
+        assert_eq!(renewal["old_expires_at"], START + 10);
+        assert_eq!(renewal["expires_at"], START + 100);
+
+        // not expiring token
+        let tokens = ctr.sbt_mint(vec![(bob(), vec![mk_metadata(1, None)])]);
+        ctr.sbt_renew(tokens.clone(), START + 100);
+        let logs = event_logs();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(logs[3].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+        let renewal = &event["data"]["renewals"][0];
+        assert_eq!(renewal["token"], tokens[0]);
+        assert!(renewal["old_expires_at"].is_null());
+        assert_eq!(renewal["expires_at"], START + 100);
+    }
+
+    #[test]
+    fn registry_renew_storage() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        // the token gets an expire time: the storage grows, but it was prepaid at mint.
+        ctx.attached_deposit = 0;
+        testing_env!(ctx);
+        let storage_start = env::storage_usage();
+        ctr.sbt_renew(tokens, START + 100);
+        let growth = env::storage_usage() - storage_start;
+        assert!(growth > 0);
+        assert!(growth <= TOKEN_UPDATES_STORAGE);
+    }
+
+    #[test]
+    fn sbt_renew_many() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(
+            alice(),
+            vec![
+                mk_metadata(1, None),
+                mk_metadata(2, None),
+                mk_metadata(3, Some(START + 10)),
+            ],
+        )]);
+
+        // tokens without expire time get one: the storage grows, the excess is refunded
+        ctx.attached_deposit = MINT_DEPOSIT;
+        testing_env!(ctx.clone());
+        let storage_start = env::storage_usage();
+        ctr.sbt_renew_many(vec![
+            (vec![tokens[0], tokens[1]], START + 100),
+            (vec![tokens[2]], START + 200),
+        ]);
+        let logs = test_utils::get_logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(
+            logs[1],
+            mk_log_str(
+                "renew",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[{}],"renewals":[{{"token":{},"old_expires_at":{},"expires_at":{}}}],"event_seq":3}}"#,
+                    issuer1(),
+                    tokens[2],
+                    tokens[2],
+                    START + 10,
+                    START + 200
+                ),
+            )[0]
+        );
+        assert!(env::storage_usage() > storage_start);
+        for (t, expires_at) in tokens.iter().zip([START + 100, START + 100, START + 200]) {
+            assert_eq!(
+                ctr.sbt(issuer1(), *t, None).unwrap().metadata.expires_at,
+                Some(expires_at)
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough NEAR storage deposit")]
+    fn sbt_renew_many_storage_deposit_fail() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        // the token gets an expire time: the storage grows and must be paid
+        ctx.attached_deposit = 0;
+        testing_env!(ctx);
+        ctr.sbt_renew_many(vec![(tokens, START + 100)]);
+    }
+
+    #[test]
+    fn sbt_renew_as() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(
+            alice(),
+            vec![mk_metadata(1, None), mk_metadata(2, None)],
+        )]);
+        assert!(!ctr.is_renewer(issuer1(), dan()));
+
+        ctr.approve_renewer(dan());
+        assert!(ctr.is_renewer(issuer1(), dan()));
+        assert!(!ctr.is_renewer(issuer2(), dan()));
+
+        ctx.predecessor_account_id = dan();
+        testing_env!(ctx.clone());
+        ctr.sbt_renew_as(issuer1(), vec![(tokens.clone(), START + 100)]);
+        for t in ctr.sbts(issuer1(), tokens.clone(), None) {
+            assert_eq!(t.unwrap().metadata.expires_at, Some(START + 100));
+        }
+        let logs = test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains(r#""event":"renew""#));
+        assert!(logs[0].contains(&format!(r#""issuer":"{}""#, issuer1())));
+
+        // revoke the approval
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.revoke_renewer(dan());
+        assert!(!ctr.is_renewer(issuer1(), dan()));
+    }
+
+    #[test]
+    #[should_panic(expected = "caller is not an approved renewer of the issuer")]
+    fn sbt_renew_as_not_approved() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        // approved by other issuer
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.approve_renewer(dan());
+
+        ctx.predecessor_account_id = dan();
+        testing_env!(ctx);
+        ctr.sbt_renew_as(issuer1(), vec![(tokens, START + 100)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "caller is not an approved renewer of the issuer")]
+    fn sbt_renew_as_revoked() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        ctr.approve_renewer(dan());
+        ctr.revoke_renewer(dan());
+
+        ctx.predecessor_account_id = dan();
+        testing_env!(ctx);
+        ctr.sbt_renew_as(issuer1(), vec![(tokens, START + 100)]);
+    }
+
+    #[test]
+    fn registry_renew_unchanged() {
+        let (ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 100));
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1])]);
+
+        // reset logs
+        testing_env!(ctx.clone());
+        // renew to the same expire time: no event should be emitted
+        ctr.sbt_renew(vec![tokens[1]], START + 100);
+        assert!(test_utils::get_logs().is_empty());
+
+        // only the token with a changed expire time should be in the event
+        ctr.sbt_renew(tokens.clone(), START + 100);
+        let log_renew = mk_log_str(
+            "renew",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[{}],"renewals":[{{"token":{},"old_expires_at":{},"expires_at":{}}}],"event_seq":2}}"#,
+                issuer1(),
+                tokens[0],
+                tokens[0],
+                START + 10,
+                START + 100
+            ),
+        );
+        assert_eq!(test_utils::get_logs(), log_renew);
+        assert_eq!(
+            ctr.sbt(issuer1(), tokens[0], None)
+                .unwrap()
+                .metadata
+                .expires_at,
+            Some(START + 100)
+        );
+    }
+
+    #[test]
+    fn total_souls() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        assert_eq!(ctr.total_souls(), 0);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 10));
+
+        ctr.sbt_mint(vec![
+            (alice(), vec![m1_1.clone(), m2_1.clone()]),
+            (bob(), vec![m1_1.clone()]),
+        ]);
+        assert_eq!(ctr.total_souls(), 2);
+
+        // alice gets a token from other issuer: still the same soul
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+        assert_eq!(ctr.total_souls(), 2);
+
+        // recover moves issuer1 alice tokens to carol: alice still holds the issuer2 token
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        ctr.sbt_recover(alice(), carol());
+        assert_eq!(ctr.total_souls(), 3);
+
+        // revoke without burn keeps the soul
+        ctr.sbt_revoke(vec![1], false, None);
+        assert_eq!(ctr.total_souls(), 3);
+
+        // burning the last alice token removes the soul
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        ctr.sbt_burn(issuer2(), vec![1], None);
+        assert_eq!(ctr.total_souls(), 2);
+
+        // carol burns only one token
+        ctx.predecessor_account_id = carol();
+        testing_env!(ctx.clone());
+        ctr.sbt_burn(issuer1(), vec![1], None);
+        assert_eq!(ctr.total_souls(), 2);
+
+        // soul transfer: carol -> dan
+        assert_eq!(ctr.sbt_soul_transfer(dan(), None, None).unwrap(), (1, true));
+        assert_eq!(ctr.total_souls(), 2);
+
+        // revoke with burn of the last bob token
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_revoke(vec![3], true, None);
+        assert_eq!(ctr.total_souls(), 1);
+        ctr.sbt_revoke_by_owner(dan(), true, None);
+        assert_eq!(ctr.total_souls(), 0);
+    }
+
+    #[test]
+    fn sbt_recover_basics() {
+        let (mut ctx, mut ctr) = setup(&issuer2(), 3 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1);
+
+        //issue tokens by a different issuer
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 2);
+
+        ctr.sbt_recover(alice(), bob());
+        let recover_log = mk_log_str(
+            "recover",
+            &format!(
+                r#"{{"issuer":"{}","old_owner":"{}","new_owner":"{}","event_seq":3}}"#,
+                issuer1(),
+                alice(),
+                bob()
+            ),
+        );
+        assert_eq!(event_logs().len(), 2);
+        assert_eq!(event_logs()[1], recover_log[0]);
+        assert!(!ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(bob()));
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1); //check if alice still holds the tokens issued by a different issuer
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(bob(), Some(issuer1()), None, None, None),
+            vec![(
+                issuer1(),
+                vec![
+                    mk_owned_token(1, m1_1.clone()),
+                    mk_owned_token(2, m2_1.clone())
+                ]
+            ),]
+        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 2);
+        assert_eq!(ctr.sbt_supply(issuer2()), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
+
+        assert_eq!(
+            ctr.sbt_tokens(issuer1(), None, None, None),
+            vec![
+                mk_token(1, bob(), m1_1.clone()),
+                mk_token(2, bob(), m2_1.clone())
+            ]
+        );
+        assert_eq!(
+            ctr.sbt(issuer1(), 1, None).unwrap(),
+            mk_token(1, bob(), m1_1.clone())
+        );
+        assert_eq!(
+            ctr.sbt(issuer1(), 2, None).unwrap(),
+            mk_token(2, bob(), m2_1)
+        );
+        assert_eq!(
+            ctr.sbt(issuer2(), 1, None).unwrap(),
+            mk_issuer_token(3, 1, alice(), m1_1)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough NEAR storage depost")]
+    fn sbt_recover_growing_storage_desposit_fail() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m1_2 = mk_metadata(2, Some(START + 10));
+        let m1_3 = mk_metadata(3, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![m1_1, m1_2, m1_3])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 3);
+
+        //set attached deposit to zero, should fail since the storage grows and we do not cover it
+        ctx.attached_deposit = 0;
+        testing_env!(ctx);
+        ctr._sbt_recover(alice(), bob(), 1);
+    }
+
+    #[test]
+    fn sbt_recover_growing_storage_desposit_pass() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1);
+
+        // storage will grow so need to attach deposit.
+        ctx.attached_deposit = MINT_DEPOSIT;
+        testing_env!(ctx);
+        ctr.sbt_recover(alice(), bob());
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None, None), 1);
+    }
+
+    /// Checks the balances invariants of the `owner` tokens of the given issuer: each
+    /// `balances` entry points to a token of the owner and of the entry class, and the
+    /// `supply_by_owner` equals the number of entries.
+    fn assert_balances_invariant(ctr: &Contract, owner: &AccountId, issuer_id: IssuerId) {
+        let entries: Vec<(BalanceKey, TokenId)> = ctr
+            .balances
+            .iter_from(mk_balance_key(owner.clone(), issuer_id, 0))
+            .take_while(|(k, _)| k.owner == *owner && k.issuer_id == issuer_id)
+            .collect();
+        for (key, token) in &entries {
+            let t = ctr.get_token(issuer_id, *token);
+            assert_eq!(t.owner, *owner);
+            assert_eq!(t.metadata.class_id(), key.class_id.0);
         }
+        let supply = ctr
+            .supply_by_owner
+            .get(&(owner.clone(), issuer_id))
+            .unwrap_or(0);
+        assert_eq!(supply, entries.len() as u64);
+    }
+
+    #[test]
+    fn sbt_recover_balances_invariant() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (alice2(), vec![mk_metadata(3, None)]),
+        ]);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(4, None)])]);
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        let mut ctr = Contract::new(admin(), fractal_mainnet(), vec![1], admins_flagged());
-        ctr.admin_add_sbt_issuer(issuer1());
-        ctr.admin_add_sbt_issuer(issuer2());
-        ctr.admin_add_sbt_issuer(issuer3());
-        ctr.admin_set_authorized_flaggers([predecessor.clone()].to_vec());
-        ctx.predecessor_account_id = predecessor.clone();
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        assert_eq!(ctr._sbt_recover(alice(), alice2(), 2), (2, false));
+        for owner in [alice(), alice2()] {
+            assert_balances_invariant(&ctr, &owner, 2);
+            assert_balances_invariant(&ctr, &owner, 3);
+        }
+        assert_eq!(ctr._sbt_recover(alice(), alice2(), 2), (1, true));
+        for owner in [alice(), alice2()] {
+            assert_balances_invariant(&ctr, &owner, 2);
+            assert_balances_invariant(&ctr, &owner, 3);
+        }
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 4);
+        // issuer2 tokens are not recovered
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "recipient already has an SBT of issuer=sbt.n, class=2; source_token_id=2"
+    )]
+    fn sbt_recover_conflict() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (alice2(), vec![mk_metadata(2, None)]),
+        ]);
+        ctr._sbt_recover(alice(), alice2(), 20);
+    }
+
+    #[test]
+    fn recover_stats_log() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(3))]);
+        assert_eq!(ctr._sbt_recover(alice(), bob(), 2), (2, false));
+        let logs = test_utils::get_logs();
+        let stats = logs
+            .iter()
+            .find_map(|l| l.strip_prefix("recover_stats: "))
+            .unwrap();
+        let stats: serde_json::Value = serde_json::from_str(stats).unwrap();
+        assert_eq!(stats["tokens"], 2);
+        assert_eq!(stats["completed"], false);
+        let gas_used = stats["gas_used"].as_u64().unwrap();
+        let token_gas = stats["token_gas"].as_u64().unwrap();
+        assert!(token_gas > 0 && gas_used >= 2 * token_gas);
+    }
+
+    #[test]
+    fn sbt_recover_with_continuation_basics() {
+        let (_, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 11));
+        let m3_1 = mk_metadata(3, Some(START + 12));
+        let m4_1 = mk_metadata(4, Some(START + 13));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1, m3_1, m4_1])]);
+
+        // sbt_recover
+        let mut result = ctr._sbt_recover(alice(), alice2(), 3);
+        assert_eq!((3, false), result);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 3);
+        assert!(event_logs().len() == 1);
+        result = ctr._sbt_recover(alice(), alice2(), 3);
+        assert_eq!((1, true), result);
+        assert!(event_logs().len() == 2);
+
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 4);
+    }
+
+    #[test]
+    fn sbt_revoke_class() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 6 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (bob(), vec![mk_metadata(1, Some(START + 100))]),
+            (carol(), vec![mk_metadata(1, Some(START))]),
+            (dan(), vec![mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        (ctx, ctr)
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        let now = START + 10;
+        ctx.predecessor_account_id = issuer1();
+        ctx.block_timestamp = now * MSECOND;
+        testing_env!(ctx.clone());
+        // token 2 is of other class, carol token (4) is already expired
+        assert_eq!(
+            ctr.sbt_revoke_class(1, 3, Some(RevokeReason::ExpiredPolicy)),
+            (2, false)
+        );
+        assert_eq!(
+            test_utils::get_logs(),
+            mk_log_str(
+                "revoke",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[1,3],"reason":"expired_policy","event_seq":3}}"#,
+                    issuer1()
+                )
+            )
+        );
+        assert_eq!(ctr.class_revoke_cursor.get(&(2, 1)), Some(4));
+        // other class cursor is independent
+        assert_eq!(ctr.sbt_revoke_class(2, 10, None), (1, true));
+        assert_eq!(ctr.sbt_revoke_class(1, 3, None), (1, true));
+        assert_eq!(ctr.class_revoke_cursor.get(&(2, 1)), None);
+        // a new call starts from the first token
+        assert_eq!(ctr.sbt_revoke_class(1, 10, None), (0, true));
+
+        // all the class tokens are invalid, but remain in storage
+        for (owner, token) in [(alice(), 1), (bob(), 3), (dan(), 5)] {
+            let t = ctr.sbt(issuer1(), token, None).unwrap();
+            assert_eq!(t.owner, owner);
+            assert_eq!(t.metadata.expires_at, Some(now));
+            assert_eq!(ctr.sbt(issuer1(), token, Some(true)), None);
+        }
+        assert_eq!(
+            ctr.sbt(issuer1(), 4, None).unwrap().metadata.expires_at,
+            Some(START)
+        );
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 4);
+        assert!(ctr
+            .sbt_tokens_by_owner(bob(), Some(issuer1()), None, None, None)
+            .is_empty());
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(bob(), Some(issuer1()), None, None, Some(true))[0]
+                .1
+                .len(),
+            1
+        );
+        // issuer2 tokens are not affected
+        assert_eq!(
+            ctr.sbt(issuer2(), 1, Some(true))
+                .unwrap()
+                .metadata
+                .expires_at,
+            None
+        );
     }
 
     #[test]
-    fn init_method() {
-        let ctr = Contract::new(admin(), fractal_mainnet(), vec![1], vec![]);
-        // make sure the iah_issuer has been set as an issuer
-        assert_eq!(1, ctr.assert_issuer(&fractal_mainnet()));
+    #[should_panic(expected = "must be called by a registered SBT Issuer")]
+    fn sbt_revoke_class_not_issuer() {
+        let (_, mut ctr) = setup(&alice(), 0);
+        ctr.sbt_revoke_class(1, 10, None);
+    }
+
+    #[test]
+    fn empty_tokens_noop() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, Some(START + 100))])]);
+        let seq = ctr.last_event_seq();
+
+        testing_env!(ctx.clone());
+        ctr.sbt_revoke(vec![], false, None);
+        ctr.sbt_revoke(vec![], true, None);
+        ctr.sbt_renew(vec![], START + 200);
+        ctr.sbt_renew_many(vec![(vec![], START + 200)]);
+        ctr.sbt_revoke_with_grace(vec![], 10, None);
+        assert!(test_utils::get_logs().is_empty());
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_burn(issuer1(), vec![], None);
+        assert!(test_utils::get_logs().is_empty());
+
+        assert_eq!(ctr.last_event_seq(), seq);
+        assert_eq!(ctr.sbt_supply(issuer1()), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called by a registered SBT Issuer")]
+    fn empty_tokens_revoke_not_issuer() {
+        let (_, mut ctr) = setup(&alice(), 0);
+        ctr.sbt_revoke(vec![], false, None);
+    }
+
+    #[test]
+    fn sbt_revoke() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 11));
+        let m3_1 = mk_metadata(3, Some(START + 21));
+
+        let current_timestamp = ctx.block_timestamp / MSECOND; // convert nano to mili seconds
+
+        let m1_1_revoked = mk_metadata(1, Some(current_timestamp));
+        let m2_1_revoked = mk_metadata(2, Some(current_timestamp));
+        let m3_1_revoked = mk_metadata(3, Some(current_timestamp));
+
+        let tokens_issuer_1 = ctr.sbt_mint(vec![(
+            alice(),
+            vec![m1_1.clone(), m2_1.clone(), m3_1.clone()],
+        )]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 3);
+
+        //issue tokens by a different issuer
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(bob(), vec![m1_1.clone(), m2_1.clone()])]);
+        ctr.sbt_mint(vec![(alice(), vec![m3_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None, None), 2);
+
+        //revoke tokens issued by issuer1
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_revoke(tokens_issuer_1, false, None);
+
+        let log_revoke = mk_log_str(
+            "revoke",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2,3],"reason":"unspecified","event_seq":4}}"#,
+                issuer1()
+            ),
+        );
+        assert_eq!(test_utils::get_logs().len(), 1);
+        assert_eq!(test_utils::get_logs()[0], log_revoke[0]);
+
+        assert_eq!(ctr.sbt_supply(issuer1()), 3);
+        assert_eq!(ctr.sbt_supply(issuer2()), 3);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 3);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None, None), 2);
+        // revoked tokens expire immediately (expires_at <= now)
+        assert!(ctr
+            .sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None)
+            .is_empty());
+        assert!(ctr.sbt_tokens(issuer1(), None, None, None).is_empty());
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, Some(true)),
+            vec![(
+                issuer1(),
+                vec![
+                    mk_owned_token(1, m1_1_revoked.clone()),
+                    mk_owned_token(2, m2_1_revoked.clone()),
+                    mk_owned_token(3, m3_1_revoked.clone()),
+                ]
+            ),]
+        );
+        assert_eq!(
+            ctr.sbt_tokens(issuer1(), None, None, Some(true)),
+            vec![
+                mk_token(1, alice(), m1_1_revoked),
+                mk_token(2, alice(), m2_1_revoked),
+                mk_token(3, alice(), m3_1_revoked)
+            ]
+        );
+        assert_eq!(
+            ctr.sbt_tokens(issuer2(), None, None, None),
+            vec![
+                mk_issuer_token(3, 1, bob(), m1_1),
+                mk_issuer_token(3, 2, bob(), m2_1),
+                mk_issuer_token(3, 3, alice(), m3_1)
+            ]
+        )
     }
 
     #[test]
-    fn iah_class_set() {
-        let (_, ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        assert_eq!(ctr.iah_class_set(), vec![ctr.iah_sbts]);
-    }
+    fn sbt_revoke_burn() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
 
-    #[test]
-    fn add_sbt_issuer() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        // in setup we add 4 issuers, so the next id will be 5.
-        assert_eq!(5, ctr.next_issuer_id);
-        assert_eq!(1, ctr.assert_issuer(&fractal_mainnet()));
-        assert_eq!(2, ctr.assert_issuer(&issuer1()));
-        assert_eq!(3, ctr.assert_issuer(&issuer2()));
-        assert_eq!(4, ctr.assert_issuer(&issuer3()));
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        let m2_1 = mk_metadata(2, Some(START + 11));
+        let m3_1 = mk_metadata(3, Some(START + 21));
 
-        assert_eq!(fractal_mainnet(), ctr.issuer_by_id(1));
-        assert_eq!(issuer1(), ctr.issuer_by_id(2));
-        assert_eq!(issuer2(), ctr.issuer_by_id(3));
-        assert_eq!(issuer3(), ctr.issuer_by_id(4));
+        let tokens_to_burn = ctr.sbt_mint(vec![
+            (alice(), vec![m1_1.clone(), m2_1.clone()]),
+            (bob(), vec![m1_1.clone()]),
+        ]);
 
-        ctx.predecessor_account_id = admin();
+        ctr.sbt_mint(vec![(alice(), vec![m3_1.clone()])]);
+
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 3);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 1);
+
+        //issue tokens by a different issuer
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(bob(), vec![m1_1.clone(), m2_1.clone()])]);
+        ctr.sbt_mint(vec![(alice(), vec![m3_1.clone()])]);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None, None), 2);
+
+        //revoke tokens issued by issuer1
+        ctx.predecessor_account_id = issuer1();
         testing_env!(ctx);
-        let ok = ctr.admin_add_sbt_issuer(issuer1());
-        assert!(
-            !ok,
-            "isser1 should be already added, so it should return false"
+        ctr.sbt_revoke(tokens_to_burn, true, None);
+
+        let log_burn = mk_log_str(
+            "burn",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2,3],"event_seq":5}}"#,
+                issuer1()
+            ),
         );
-        assert_eq!(5, ctr.next_issuer_id, "next_issuer_id should not change");
+        assert_eq!(test_utils::get_logs().len(), 2);
+        assert_eq!(test_utils::get_logs()[0], log_burn[0]);
+        assert_eq!(ctr.sbt_supply(issuer1()), 1);
+        assert_eq!(ctr.sbt_supply(issuer2()), 3);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 1);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None, None), 2);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 3), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 3), 1);
+
         assert_eq!(
-            2,
-            ctr.assert_issuer(&issuer1()),
-            "issuer1 id should not change"
+            ctr.sbt_tokens(issuer1(), None, None, None),
+            vec![mk_token(4, alice(), m3_1.clone())],
         );
+        assert_eq!(
+            ctr.sbt_tokens(issuer2(), None, None, None),
+            vec![
+                mk_issuer_token(3, 1, bob(), m1_1),
+                mk_issuer_token(3, 2, bob(), m2_1),
+                mk_issuer_token(3, 3, alice(), m3_1)
+            ]
+        )
     }
 
+    // sbt_ban
     #[test]
-    fn mint_simple() {
-        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+    fn sbt_soul_transfer_ban() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
         let m1_1 = mk_metadata(1, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert!(!ctr.is_banned(alice()));
 
-        let minted_ids = ctr.sbt_mint(vec![
-            (alice(), vec![m1_1.clone()]),
-            (bob(), vec![m1_1.clone()]),
-        ]);
-        assert_eq!(minted_ids, vec![1, 2]);
-        assert_eq!(2, ctr.sbt_supply(issuer1()));
-        assert_eq!(0, ctr.sbt_supply(issuer2()));
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
 
-        let sbt1_1 = ctr.sbt(issuer1(), 1).unwrap();
-        let sbt1_1_e = mk_token(1, alice(), m1_1.clone());
-        let sbt1_2_e = mk_token(2, bob(), m1_1.clone());
-        assert_eq!(sbt1_1, sbt1_1_e);
-        let sbt1_2 = ctr.sbt(issuer1(), 2).unwrap();
-        assert_eq!(sbt1_2, sbt1_2_e);
-        assert!(ctr.sbt(issuer2(), 1).is_none());
-        assert!(ctr.sbt(issuer1(), 3).is_none());
+        assert!(ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+    }
 
-        let sbts = ctr.sbts(issuer1(), vec![1, 2]);
-        assert_eq!(sbts, vec![Some(sbt1_1_e.clone()), Some(sbt1_2_e.clone())]);
+    #[test]
+    fn sbt_recover_limit() {
+        let (mut ctx, mut ctr) = setup(&issuer2(), 150 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(100);
+        assert!(batch_metadata.len() == 100);
+
+        // issuer_2
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 50);
+
+        // // add more tokens to issuer_2
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[50..].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 100);
+
+        // add more tokens to issuer_1
+        ctx.predecessor_account_id = issuer1();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(bob(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 20);
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice2(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 20);
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(carol(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None, None), 20);
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(dan(), batch_metadata[..10].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None, None), 10);
+
+        // sbt_recover alice->alice2
+        ctx.predecessor_account_id = issuer2();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        let limit: u32 = 20; //anything above this limit will fail due to exceeding maximum gas usage per call
+
+        let mut result = ctr._sbt_recover(alice(), alice2(), limit as usize);
+        while !result.1 {
+            ctx.prepaid_gas = max_gas();
+            testing_env!(ctx.clone());
+            result = ctr._sbt_recover(alice(), alice2(), limit as usize);
+        }
+
+        // check all the balances afterwards
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
         assert_eq!(
-            ctr.sbt_classes(issuer1(), vec![1, 1]),
-            vec![Some(1), Some(1)]
+            ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None),
+            100
         );
+    }
 
-        let sbts = ctr.sbts(issuer1(), vec![2, 10, 3, 1]);
-        assert_eq!(sbts, vec![Some(sbt1_2_e), None, None, Some(sbt1_1_e)]);
+    #[test]
+    fn sbt_recover_gas_yield() {
+        let (mut ctx, mut ctr) = setup(&issuer2(), 50 * MINT_DEPOSIT);
+        // tokens with large metadata
+        let batch_metadata: Vec<TokenMetadata> = mk_batch_metadata(20)
+            .into_iter()
+            .map(|mut m| {
+                m.reference = Some("x".repeat(MAX_REFERENCE_LEN));
+                m
+            })
+            .collect();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata)]);
+
+        // not enough gas to recover all the tokens: the call yields instead of failing
+        ctx.prepaid_gas = Gas::ONE_TERA.mul(100);
+        testing_env!(ctx.clone());
+        let (recovered, finished) = ctr._sbt_recover(alice(), bob(), 20);
+        assert!(!finished);
+        assert!(recovered > 0 && recovered < 20, "recovered: {}", recovered);
+        assert!(env::used_gas() < ctx.prepaid_gas);
         assert_eq!(
-            ctr.sbt_classes(issuer1(), vec![2, 10, 3, 1]),
-            vec![Some(1), None, None, Some(1)]
+            ctr.sbt_supply_by_owner(bob(), issuer2(), None, None),
+            recovered as u64
+        );
+        // no Recover event until the process is finished
+        assert!(event_logs().is_empty());
+
+        let mut total = recovered;
+        let mut finished = false;
+        while !finished {
+            testing_env!(ctx.clone());
+            let r = ctr._sbt_recover(alice(), bob(), 20);
+            assert!(r.0 > 0 || r.1);
+            total += r.0;
+            finished = r.1;
+        }
+        assert_eq!(total, 20);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None, None), 20);
+        for t in 1..=20 {
+            assert_eq!(ctr.sbt(issuer2(), t, None).unwrap().owner, bob());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "HostError(GasLimitExceeded)")]
+    fn sbt_recover_limit_exceeded() {
+        let (mut ctx, mut ctr) = setup(&issuer2(), 150 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(100);
+        assert!(batch_metadata.len() == 100);
+
+        // issuer_2
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 50);
+
+        // // add more tokens to issuer_2
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[50..].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 100);
+
+        // add more tokens to issuer_1
+        ctx.predecessor_account_id = issuer1();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(bob(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None, None), 20);
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice2(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None, None), 20);
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(carol(), batch_metadata[..20].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None, None), 20);
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(dan(), batch_metadata[..10].to_vec())]);
+        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None, None), 10);
+
+        // sbt_recover alice->alice2
+        ctx.predecessor_account_id = issuer2();
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        let limit: u32 = 25; // this value exceedes the gas limit allowed per call and should fail
+
+        let mut result = ctr._sbt_recover(alice(), alice2(), limit as usize);
+        while !result.1 {
+            ctx.prepaid_gas = max_gas();
+            testing_env!(ctx.clone());
+            result = ctr._sbt_recover(alice(), alice2(), limit as usize);
+        }
+
+        // check all the balances afterwards
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None, None), 0);
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice2(), issuer2(), None, None),
+            100
         );
+    }
+
+    #[test]
+    #[should_panic(expected = "from account is banned. Cannot start the transfer")]
+    fn sbt_soul_transfer_from_banned_account() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert!(!ctr.is_banned(alice()));
+
+        // ban the from account
+        ctr.banlist.insert(&alice());
+        assert!(ctr.is_banned(alice()));
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "account alice.nea is banned")]
+    fn sbt_soul_transfer_to_banned_account() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert!(!ctr.is_banned(alice()));
+
+        // ban the reciver account
+        ctr.banlist.insert(&alice2());
+        assert!(ctr.is_banned(alice2()));
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
+    }
+
+    #[test]
+    fn sbt_soul_transfer_ban_with_continuation() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 50 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(50);
+        ctr.sbt_mint(vec![(alice(), batch_metadata)]);
+        assert!(!ctr.is_banned(alice()));
 
-        assert_eq!(1, ctr.sbt_supply_by_owner(alice(), issuer1(), None));
-        assert_eq!(1, ctr.sbt_supply_by_owner(alice(), issuer1(), Some(1)));
-        assert_eq!(0, ctr.sbt_supply_by_owner(alice(), issuer1(), Some(2)));
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        assert!(!ctr.sbt_soul_transfer(alice2(), None, None).unwrap().1);
+
+        // assert the from account is banned after the first soul transfer execution
+        assert!(ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+
+        ctx.prepaid_gas = max_gas();
+        testing_env!(ctx);
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
+        assert!(ctr.sbt_soul_transfer(alice2(), None, None).unwrap().1);
 
-        assert_eq!(1, ctr.sbt_supply_by_owner(bob(), issuer1(), None));
-        assert_eq!(1, ctr.sbt_supply_by_owner(bob(), issuer1(), Some(1)));
-        assert_eq!(0, ctr.sbt_supply_by_owner(bob(), issuer1(), Some(2)));
+        // assert it stays banned after the soul transfer has been completed
+        assert!(ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
+    }
 
-        let alice_sbts = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        let expected = vec![(issuer1(), vec![mk_owned_token(1, m1_1.clone())])];
-        assert_eq!(alice_sbts, expected);
+    #[test]
+    fn sbt_recover_ban() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert!(!ctr.is_banned(alice()));
 
-        let bob_sbts = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
-        let expected = vec![(issuer1(), vec![mk_owned_token(2, m1_1)])];
-        assert_eq!(bob_sbts, expected);
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_recover(alice(), alice2());
+        // sbt_recover should not ban the source account
+        assert!(!ctr.is_banned(alice()));
+        assert!(!ctr.is_banned(alice2()));
     }
 
     #[test]
-    fn mint() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+    #[should_panic(expected = "account alice.near is banned")]
+    fn sbt_mint_to_banned_account() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
         let m1_1 = mk_metadata(1, Some(START + 10));
-        let m1_2 = mk_metadata(1, Some(START + 12));
-        let m2_1 = mk_metadata(2, Some(START + 14));
-        let m4_1 = mk_metadata(4, Some(START + 16));
 
-        // mint an SBT to a user with same prefix as alice
-        let minted_ids = ctr.sbt_mint(vec![(alice2(), vec![m1_1.clone()])]);
-        assert_eq!(minted_ids, vec![1]);
-        assert_eq!(
-            test_utils::get_logs(),
-            mk_log_str(
-                "mint",
-                &format!(
-                    r#"{{"issuer":"{}","tokens":[["{}",[1]]]}}"#,
-                    issuer1(),
-                    alice2()
-                )
-            )
-        );
+        //ban alice account
+        ctr.banlist.insert(&alice());
+        assert!(ctr.is_banned(alice()));
+
+        //try to mint to a banned account
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+    }
 
+    #[test]
+    fn sbt_tokens_of() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![mk_metadata(1, None), mk_metadata(2, Some(START + 10))],
+            ),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
         ctx.predecessor_account_id = issuer2();
-        ctx.attached_deposit = 4 * MINT_DEPOSIT;
         testing_env!(ctx.clone());
-        let minted_ids = ctr.sbt_mint(vec![
-            (alice(), vec![m1_1.clone()]),
-            (bob(), vec![m1_2.clone()]),
-            (alice2(), vec![m1_1.clone()]),
-            (alice(), vec![m2_1.clone()]),
-        ]);
-        assert_eq!(minted_ids, vec![1, 2, 3, 4]);
-        assert_eq!(test_utils::get_logs().len(), 1);
-        assert_eq!(
-            test_utils::get_logs(),
-            mk_log_str(
-                "mint",
-                &format!(
-                    r#"{{"issuer":"{}","tokens":[["{}",[3]],["{}",[1,4]],["{}",[2]]]}}"#,
-                    issuer2(),
-                    alice2(),
-                    alice(),
-                    bob()
-                )
-            )
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        let grouped = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None);
+        assert_eq!(grouped.len(), 1);
+        let tokens = ctr.sbt_tokens_of(issuer1(), alice());
+        assert_eq!(tokens, grouped[0].1);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(ctr.sbt_tokens_of(issuer2(), alice()).len(), 1);
+        assert!(ctr.sbt_tokens_of(issuer2(), bob()).is_empty());
+        assert!(ctr.sbt_tokens_of(issuer3(), alice()).is_empty());
+
+        // expired tokens are excluded
+        ctx.block_timestamp = (START + 10) * MSECOND;
+        testing_env!(ctx.clone());
+        let tokens = ctr.sbt_tokens_of(issuer1(), alice());
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token, 1);
+
+        // ongoing soul transfer
+        ctr.ongoing_soul_tx.insert(
+            &alice(),
+            &SoulTxCursor {
+                issuer_id: 0,
+                class_id: ClassKey(0),
+            },
         );
+        assert!(ctr.sbt_tokens_of(issuer1(), alice()).is_empty());
+    }
 
-        // mint again for Alice
-        let minted_ids = ctr.sbt_mint(vec![(alice(), vec![m4_1.clone()])]);
-        assert_eq!(minted_ids, vec![5]);
-
-        // change the issuer and mint new tokens for alice
-        ctx.predecessor_account_id = issuer3();
-        ctx.attached_deposit = 2 * MINT_DEPOSIT;
+    #[test]
+    fn sbt_tokens_by_owner_non_expired() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        ctx.block_timestamp = START * MSECOND; // 11 seconds
         testing_env!(ctx.clone());
-        let minted_ids = ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
-        // since we minted with different issuer, the new SBT should start with 1
-        assert_eq!(minted_ids, vec![1, 2]);
-
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 0), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 3);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 3), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 4), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 5), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer3(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer3(), 2), 1);
-
-        let mut supply_by_issuer = vec![1, 5, 2, 0];
-        assert_eq!(ctr.sbt_supply(issuer1()), supply_by_issuer[0]);
-        assert_eq!(ctr.sbt_supply(issuer2()), supply_by_issuer[1]);
-        assert_eq!(ctr.sbt_supply(issuer3()), supply_by_issuer[2]);
-        assert_eq!(ctr.sbt_supply(issuer4()), supply_by_issuer[3]);
-
-        assert_eq!(3, ctr.sbt_supply_by_owner(alice(), issuer2(), None));
-        assert_eq!(2, ctr.sbt_supply_by_owner(alice(), issuer3(), None));
-        assert_eq!(1, ctr.sbt_supply_by_owner(bob(), issuer2(), None));
-        assert_eq!(0, ctr.sbt_supply_by_owner(bob(), issuer3(), None));
-        assert_eq!(0, ctr.sbt_supply_by_owner(issuer2(), issuer2(), None));
-
-        let t2_all = vec![
-            mk_token(1, alice(), m1_1.clone()),
-            mk_token(2, bob(), m1_2),
-            mk_token(3, alice2(), m1_1.clone()),
-            mk_token(4, alice(), m2_1.clone()),
-            mk_token(5, alice(), m4_1.clone()),
-        ];
-        let t3_1 = mk_token(1, alice(), m1_1.clone());
 
-        assert_eq!(ctr.sbt(issuer2(), 1).unwrap(), t2_all[0]);
-        assert_eq!(ctr.sbt(issuer2(), 2).unwrap(), t2_all[1]);
-        assert_eq!(ctr.sbt(issuer2(), 3).unwrap(), t2_all[2]);
-        assert_eq!(ctr.sbt(issuer2(), 4).unwrap(), t2_all[3]);
-        assert_eq!(ctr.sbt(issuer3(), 1).unwrap(), t3_1);
+        let m1_1 = mk_metadata(1, Some(START + 1));
+        let m1_2 = mk_metadata(2, Some(START + 1));
+        let m1_3 = mk_metadata(3, Some(START + 100));
+        let m1_4 = mk_metadata(4, Some(START + 100));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1, m1_2, m1_3, m1_4])]);
 
-        // Token checks
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true));
+        assert_eq!(res[0].1.len(), 4);
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 4);
 
-        let a_tokens = vec![
-            (issuer1(), vec![mk_owned_token(1, m1_1.clone())]),
-            (issuer2(), vec![mk_owned_token(3, m1_1.clone())]),
-        ];
-        assert_eq!(
-            &ctr.sbt_tokens_by_owner(alice2(), None, None, None, None),
-            &a_tokens
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice2(), Some(issuer1()), None, None, None),
-            vec![a_tokens[0].clone()],
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice2(), Some(issuer2()), None, None, None),
-            vec![a_tokens[1].clone()]
-        );
+        let res = ctr.sbt_tokens(issuer1(), None, None, Some(true));
+        assert_eq!(res.len(), 4);
+        let res = ctr.sbt_tokens(issuer1(), None, None, Some(false));
+        assert_eq!(res.len(), 4);
+        let res = ctr.sbt_tokens(issuer1(), None, None, None);
+        assert_eq!(res.len(), 4);
 
-        let alice_issuer2 = (
-            issuer2(),
-            vec![
-                mk_owned_token(1, m1_1.clone()),
-                mk_owned_token(4, m2_1.clone()),
-                mk_owned_token(5, m4_1.clone()),
-            ],
-        );
-        let alice_issuer3 = (
-            issuer3(),
-            vec![
-                mk_owned_token(1, m1_1.clone()),
-                mk_owned_token(2, m2_1.clone()),
-            ],
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
-            vec![alice_issuer2.clone(), alice_issuer3.clone()]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None),
-            vec![alice_issuer2.clone()]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer3()), None, None, None),
-            vec![alice_issuer3.clone()]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(1), None, None),
-            vec![alice_issuer2]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(4), None, None),
-            vec![(issuer2(), vec![mk_owned_token(5, m4_1)])]
-        );
+        // fast forward so the first two sbts are expired
+        ctx.block_timestamp = (START + 50) * MSECOND;
+        testing_env!(ctx);
 
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), Some(5), None, None),
-            vec![]
-        );
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true));
+        assert_eq!(res[0].1.len(), 4);
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(false));
+        assert_eq!(res[0].1.len(), 2);
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 2);
 
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(5), None, None),
-            vec![]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer3()), Some(1), None, None),
-            vec![alice_issuer3.clone()]
-        );
+        let res = ctr.sbt_tokens(issuer1(), None, None, Some(true));
+        assert_eq!(res.len(), 4);
+        let res = ctr.sbt_tokens(issuer1(), None, None, Some(false));
+        assert_eq!(res.len(), 2);
+        let res = ctr.sbt_tokens(issuer1(), None, None, None);
+        assert_eq!(res.len(), 2);
+    }
 
-        // check by all tokens
-        assert_eq!(
-            ctr.sbt_tokens(issuer1(), Some(1), None, None),
-            vec![mk_token(1, alice2(), m1_1.clone())]
-        );
-        assert_eq!(ctr.sbt_tokens(issuer2(), None, None, None), t2_all,);
-        assert_eq!(ctr.sbt_tokens(issuer2(), None, Some(1), None), t2_all[..1]);
-        assert_eq!(ctr.sbt_tokens(issuer2(), None, Some(2), None), t2_all[..2]);
-        assert_eq!(
-            ctr.sbt_tokens(issuer2(), Some(2), Some(2), None),
-            t2_all[1..3]
-        );
-        assert_eq!(
-            ctr.sbt_tokens(issuer2(), Some(5), Some(5), None),
-            t2_all[4..5]
-        );
-        assert_eq!(ctr.sbt_tokens(issuer2(), Some(6), Some(2), None), vec![]);
+    #[test]
+    fn sbt_revoke_by_owner_consent() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, Some(START + 100))]),
+            (bob(), vec![mk_metadata(1, Some(START + 100))]),
+        ]);
+        ctr.set_revocation_consent_required(true);
+        assert!(ctr.is_revocation_consent_required(issuer1()));
+        assert!(!ctr.is_revocation_consent_required(issuer2()));
 
-        //
-        // now let's test buring
-        //
+        // alice gives the consent
         ctx.predecessor_account_id = alice();
-        testing_env!(ctx);
+        testing_env!(ctx.clone());
+        ctr.allow_revocation(issuer1());
+        assert!(ctr.has_revocation_consent(alice(), issuer1()));
+        assert!(!ctr.has_revocation_consent(bob(), issuer1()));
 
-        ctr.sbt_burn(issuer2(), vec![1, 5], Some("alice burning".to_owned()));
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx.clone());
+        assert!(ctr.sbt_revoke_by_owner(alice(), false, None));
         assert_eq!(
-            test_utils::get_logs(),
-            mk_log_str("burn", r#"{"issuer":"sbt.ne","tokens":[1,5]}"#)
+            ctr.sbt(issuer1(), 1, None).unwrap().metadata.expires_at,
+            Some(START)
         );
+        // the consent is cleared once all tokens are revoked
+        assert!(!ctr.has_revocation_consent(alice(), issuer1()));
 
-        supply_by_issuer[1] -= 2;
-        assert_eq!(ctr.sbt_supply(issuer1()), supply_by_issuer[0]);
-        assert_eq!(ctr.sbt_supply(issuer2()), supply_by_issuer[1]);
-        assert_eq!(ctr.sbt_supply(issuer3()), supply_by_issuer[2]);
-        assert_eq!(ctr.sbt_supply(issuer4()), supply_by_issuer[3]);
-
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 1);
-        assert_eq!(
-            ctr.sbt_supply_by_owner(alice(), issuer2(), Some(m2_1.class)),
-            1
-        );
-        assert_eq!(
-            ctr.sbt_supply_by_owner(alice(), issuer2(), Some(m1_1.class)),
-            0
-        );
+        // burn doesn't require the consent
+        assert!(ctr.sbt_revoke_by_owner(bob(), true, None));
+        assert_eq!(ctr.sbt(issuer1(), 2, None), None);
 
-        let alice_issuer2 = (issuer2(), vec![mk_owned_token(4, m2_1)]);
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
-            vec![alice_issuer2.clone(), alice_issuer3]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None),
-            vec![alice_issuer2]
-        );
+        // the policy is off
+        ctr.sbt_mint(vec![(carol(), vec![mk_metadata(1, Some(START + 100))])]);
+        ctr.set_revocation_consent_required(false);
+        assert!(ctr.sbt_revoke_by_owner(carol(), false, None));
     }
 
     #[test]
-    #[should_panic(expected = "bob.near is not a human")]
-    fn mint_iah() {
-        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
-        // issue IAH SBTs for alice
-        let m1_1 = mk_metadata(1, Some(START)); // class=1 is IAH
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
-
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx);
+    #[should_panic(expected = "owner consent required to revoke")]
+    fn sbt_revoke_by_owner_no_consent() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, Some(START + 100))])]);
+        ctr.set_revocation_consent_required(true);
+        ctr.sbt_revoke_by_owner(alice(), false, None);
+    }
 
-        // alice is IAH verified, so mint_iah by issuer1 should work
-        let sbts = ctr.sbt_mint_iah(vec![(alice(), vec![m1_1.clone()])]);
-        assert!(!sbts.is_empty());
+    #[test]
+    fn revoke_by_owner_preview() {
+        let (ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![
+                    mk_metadata(1, Some(START + 100)),
+                    mk_metadata(2, Some(START)),
+                    mk_metadata(3, None),
+                ],
+            ),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        assert_eq!(ctr.revoke_by_owner_preview(alice(), issuer1()), 3);
+        assert_eq!(ctr.revoke_by_owner_preview(alice(), issuer2()), 0);
+        assert_eq!(ctr.revoke_by_owner_preview(carol(), issuer1()), 0);
+        assert_eq!(ctr.revoke_by_owner_preview(alice(), issuer4()), 0);
 
-        // bob doesn't have IAH SBTs -> the mint below panics.
-        ctr.sbt_mint_iah(vec![(bob(), vec![m1_1])]);
+        let supply = ctr.sbt_supply(issuer1());
+        testing_env!(ctx);
+        assert!(ctr.sbt_revoke_by_owner(alice(), true, None));
+        assert_eq!(ctr.sbt_supply(issuer1()), supply - 3);
+        assert!(test_utils::get_logs()[0].contains(r#""tokens":[1,2,3]"#));
+        assert_eq!(ctr.revoke_by_owner_preview(alice(), issuer1()), 0);
     }
 
     #[test]
-    fn soul_transfer1() {
+    fn sbt_revoke_by_owner_basics() {
         let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
 
-        // test1: simple case: alice has one token and she owns alice2 account as well. She
-        // will do transfer from alice -> alice2
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
+        let m1_1 = mk_metadata(1, Some(START + 100));
+        let m1_2 = mk_metadata(2, Some(START + 100));
+        let m1_1_expired = mk_metadata(1, Some(START));
+        let m1_2_expired = mk_metadata(2, Some(START));
+
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m1_2.clone()])]);
 
         ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
 
-        // make soul transfer
-        ctx.predecessor_account_id = alice();
-        testing_env!(ctx);
-        assert_eq!(ctr.sbt_soul_transfer(alice2(), None).unwrap(), (3, true));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m1_2.clone()])]);
 
-        let log1 = mk_log_str("ban", &format!(r#"["{}"]"#, alice()));
-        let log2 = mk_log_str(
-            "soul_transfer",
-            &format!(r#"{{"from":"{}","to":"{}"}}"#, alice(), alice2()),
+        let res = ctr.sbt_revoke_by_owner(alice(), true, None);
+        assert!(res);
+
+        let log_burn = mk_log_str(
+            "burn",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2],"event_seq":3}}"#,
+                issuer2()
+            ),
         );
-        assert_eq!(test_utils::get_logs(), vec![log1, log2].concat());
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 2);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None), 1);
+        let log_revoke = mk_log_str(
+            "revoke",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2],"reason":"unspecified","event_seq":4}}"#,
+                issuer2()
+            ),
+        );
+        assert_eq!(event_logs().len(), 3);
+        assert_eq!(event_logs()[1], log_burn[0]);
+        assert_eq!(event_logs()[2], log_revoke[0]);
 
-        assert!(ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
+        // make sure the balances are updated correctly
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert!(res.len() == 1);
+        assert_eq!(res[0].1.len(), 2);
+        assert_eq!(ctr.sbt_supply(issuer1()), 2);
+        assert_eq!(ctr.sbt_supply(issuer2()), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 0);
 
         assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
-            vec![]
-        );
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice2(), None, None, None, None),
-            vec![
-                (
-                    issuer1(),
-                    vec![mk_owned_token(1, m1_1.clone()), mk_owned_token(2, m2_1)]
-                ),
-                (issuer2(), vec![mk_owned_token(1, m1_1)]),
-            ]
+            ctr.sbt_tokens(issuer1(), None, None, None),
+            vec![mk_token(1, alice(), m1_1), mk_token(2, alice(), m1_2),],
         );
-    }
-
-    #[test]
-    fn soul_transfer_with_continuation() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        // test1: simple case: alice has one token and she owns alice2 account as well. She
-        // will do transfer from alice -> alice2
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 11));
-        let m3_1 = mk_metadata(3, Some(START + 12));
-        let m4_1 = mk_metadata(4, Some(START + 13));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1])]);
+        assert!(ctr.sbt_tokens(issuer2(), None, None, None).is_empty());
 
-        ctx.predecessor_account_id = issuer2();
+        // revoke (not burn) tokens minted for alice from issuer1
+        ctx.predecessor_account_id = issuer1();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), vec![m3_1, m4_1])]);
+        assert_eq!(event_logs().len(), 0);
+        let res = ctr.sbt_revoke_by_owner(alice(), false, None);
+        assert!(res);
 
-        // make soul transfer
-        ctx.predecessor_account_id = alice();
+        let log_revoke = mk_log_str(
+            "revoke",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2],"reason":"unspecified","event_seq":5}}"#,
+                issuer1()
+            ),
+        );
+        assert_eq!(event_logs().len(), 1);
+        assert_eq!(event_logs()[0], log_revoke[0]);
+
+        // fast forward
+        ctx.block_timestamp = (START + 50) * MSECOND;
         testing_env!(ctx);
-        assert_eq!(ctr._sbt_soul_transfer(alice2(), 3).unwrap(), (3, false));
-        assert!(test_utils::get_logs().len() == 1);
-        assert_eq!(ctr._sbt_soul_transfer(alice2(), 3).unwrap(), (1, true));
-        assert!(test_utils::get_logs().len() == 2);
 
-        let log_soul_transfer = mk_log_str(
-            "soul_transfer",
-            &format!(r#"{{"from":"{}","to":"{}"}}"#, alice(), alice2()),
+        // make sure the balances are updated correctly
+        let res_with_expired = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert!(res_with_expired.is_empty());
+        let res_without_expired = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true));
+        assert!(res_without_expired.len() == 1);
+        assert_eq!(res_without_expired[0].1.len(), 2);
+        assert_eq!(ctr.sbt_supply(issuer1()), 2);
+        assert_eq!(ctr.sbt_supply(issuer2()), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 0);
+        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 0);
+
+        assert_eq!(
+            ctr.sbt_tokens(issuer1(), None, None, Some(true)),
+            vec![
+                mk_token(1, alice(), m1_1_expired),
+                mk_token(2, alice(), m1_2_expired),
+            ],
         );
-        assert_eq!(test_utils::get_logs()[1], log_soul_transfer[0]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 2);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None), 2);
-        assert!(ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
+        assert!(ctr.sbt_tokens(issuer1(), None, None, None).is_empty());
+        assert!(ctr.sbt_tokens(issuer2(), None, None, None).is_empty());
     }
 
     #[test]
-    fn soul_transfer_no_tokens_from_caller() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
-        ctx.predecessor_account_id = alice();
+    fn sbt_revoke_by_owner_batch() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
+
+        // mint tokens to alice and bob from issuer1
+        let batch_metadata = mk_batch_metadata(20);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[10..].to_vec())]);
+
+        // mint tokens to alice and bob from issuer2
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx);
-        assert!(!ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
-        assert!(ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
-        // assert ban even is being emited after the caller with zero tokens has invoked the soul_transfer
-        let log_ban = mk_log_str("ban", &format!("[\"{}\"]", alice()));
-        assert_eq!(test_utils::get_logs(), log_ban);
-    }
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[11..].to_vec())]);
 
-    fn soul_transfer_prepare() -> (VMContext, Contract) {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 150 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(110);
-        assert_eq!(batch_metadata.len(), 110);
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 10);
 
-        // issuer_1
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 50);
+        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 9);
 
-        // issuer_2
-        ctx.predecessor_account_id = issuer2();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[50..100].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 50);
+        assert_eq!(ctr.sbt_supply(issuer1()), 20);
+        assert_eq!(ctr.sbt_supply(issuer2()), 19);
 
-        // add more tokens to issuer_1
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(bob(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None), 20);
+        // revoke (burn) tokens minted for alice from issuer2
+        ctr.sbt_revoke_by_owner(alice(), true, None);
 
-        // mint non conflicting tokens
-        ctr.sbt_mint(vec![(alice2(), batch_metadata[100..].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 10);
+        // make sure the balances are updated correctly
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        // assert_eq!(res[1].1.len(), 0);
 
-        testing_env!(ctx.clone()); // reset gas
-        ctr.sbt_mint(vec![(carol(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None), 20);
+        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 9);
+
+        assert_eq!(ctr.sbt_supply(issuer1()), 20);
+        assert_eq!(ctr.sbt_supply(issuer2()), 9);
+    }
 
+    #[test]
+    fn sbt_revoke_by_owner_many_tokens() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 40 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(40);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[20..].to_vec())]);
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(dan(), batch_metadata[..10].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None), 10);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..5].to_vec())]);
 
-        (ctx, ctr)
+        // owner without tokens
+        assert!(ctr.sbt_revoke_by_owner(bob(), false, None));
+
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        assert!(!ctr.sbt_revoke_by_owner(alice(), false, None));
+        assert!(ctr.sbt_revoke_by_owner(alice(), false, None));
+        // all tokens are revoked (expired), so the next call is a no-op
+        assert!(ctr.sbt_revoke_by_owner(alice(), false, None));
+        assert_eq!(
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, Some(true))[0]
+                .1
+                .len(),
+            40
+        );
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].0, issuer2());
+        assert_eq!(res[0].1.len(), 5);
     }
 
     #[test]
-    fn soul_transfer_limit() {
-        let (mut ctx, mut ctr) = soul_transfer_prepare();
+    fn sbt_revoke_by_owner_benchmark() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
 
-        // soul transfer alice->alice2
-        ctx.predecessor_account_id = alice();
-        ctx.prepaid_gas = max_gas();
+        // mint tokens to alice and bob from issuer1
+        let batch_metadata = mk_batch_metadata(20);
+        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
+
+        // mint tokens to alice and bob from issuer2
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        let limit: u32 = 20; //anything above this limit will fail due to exceeding maximum gas usage per call
+        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
 
-        let mut result = ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap();
-        while !result.1 {
-            testing_env!(ctx.clone()); // reset gas
-            result = ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap();
-        }
+        // mint tokens to alice and bob from issuer3
+        ctx.predecessor_account_id = issuer3();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
 
-        // check all the balances afterwards
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 60);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None), 50);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None), 20);
-        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None), 20);
-        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None), 10);
-    }
+        // add 4th issuer
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        ctr.admin_add_sbt_issuer(issuer4());
 
-    #[test]
-    #[should_panic(expected = "HostError(GasLimitExceeded)")]
-    fn soul_transfer_exceeded_gas_limit() {
-        let (mut ctx, mut ctr) = soul_transfer_prepare();
+        // mint tokens to alice and bob from issuer4
+        ctx.predecessor_account_id = issuer4();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
 
-        // soul transfer alice->alice2
-        ctx.predecessor_account_id = alice();
-        ctx.prepaid_gas = max_gas();
+        // revoke (burn) tokens minted for alice from issuer2
+        ctx.predecessor_account_id = issuer2();
+        ctx.prepaid_gas = Gas::ONE_TERA.mul(115);
         testing_env!(ctx);
-        let limit: u32 = 30;
-        ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap();
+        let res = ctr.sbt_revoke_by_owner(alice(), true, None);
+        assert!(res);
+
+        // make sure the balances are updated correctly
+        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None);
+        assert_eq!(res.len(), 0);
     }
 
     #[test]
-    #[should_panic(
-        expected = "recipient already has an SBT of issuer=sbt.n, class=1; source_token_id=1"
-    )]
-    fn soul_transfer_conflict() {
-        let (mut ctx, mut ctr) = soul_transfer_prepare();
+    fn sbt_revoke_by_owner_burn_false() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
 
-        // alice1 already has SBT of (issuer1, 1), so let's mint it to alice2 and try soul_transfer
-        let batch_metadata = mk_batch_metadata(1);
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice2(), batch_metadata)]);
+        // mint tokens to alice and bob from issuer1
+        let batch_metadata = mk_batch_metadata(30);
+        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
 
-        ctx.predecessor_account_id = alice();
+        // revoke (burn = false) tokens minted for alice from issuer2
         ctx.prepaid_gas = max_gas();
+        testing_env!(ctx.clone());
+        let res = ctr.sbt_revoke_by_owner(alice(), false, None);
+        assert!(!res);
+        ctx.block_timestamp = (START + 1) * MSECOND;
+        testing_env!(ctx.clone());
+
+        let res = ctr.sbt_revoke_by_owner(alice(), false, None);
+        assert!(res);
+
+        ctx.block_timestamp = (START + 5) * MSECOND;
         testing_env!(ctx);
-        let limit: u32 = 30;
-        ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap();
+
+        // make sure the balances are updated correctly
+        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, Some(false));
+        assert_eq!(res.len(), 0);
     }
 
     #[test]
-    fn soul_transfer_limit_basics() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 60 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(40);
-        assert!(batch_metadata.len() == 40);
+    fn sbt_revoke_by_owner_limit() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 200 * MINT_DEPOSIT);
 
-        // issuer_1
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 20);
+        // mint tokens to alice from issuer1
+        let batch_metadata = mk_batch_metadata(100);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
 
-        // issuer_2
+        // mint tokens to alice from issuer2
         ctx.predecessor_account_id = issuer2();
         ctx.prepaid_gas = max_gas();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[20..].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 20);
-
-        ctx.predecessor_account_id = alice();
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-
-        let limit: usize = 10;
-        assert_eq!(
-            ctr._sbt_soul_transfer(alice2(), limit).unwrap(),
-            (limit as u32, false)
-        );
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
 
         ctx.prepaid_gas = max_gas();
         testing_env!(ctx.clone());
-        assert_eq!(
-            ctr._sbt_soul_transfer(alice2(), limit).unwrap(),
-            (limit as u32, false)
-        );
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 50);
+        assert_eq!(res[1].1.len(), 50);
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        assert_eq!(
-            ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap(),
-            (limit as u32, false)
-        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 50);
+        assert_eq!(ctr.sbt_supply(issuer2()), 50);
 
         ctx.prepaid_gas = max_gas();
         testing_env!(ctx.clone());
-        assert_eq!(
-            ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap(),
-            (limit as u32, false)
-        );
+        // revoke (burn) tokens minted for alice from issuer2
+        let res = ctr.sbt_revoke_by_owner(alice(), true, None);
+        assert!(!res);
 
-        // resumed transfer but no more tokens to transfer
         ctx.prepaid_gas = max_gas();
         testing_env!(ctx);
-        assert_eq!(
-            ctr._sbt_soul_transfer(alice2(), limit as usize).unwrap(),
-            (0, true)
-        );
-
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 20);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None), 20);
-    }
-
-    #[test]
-    fn test_mk_log() {
-        let l = mk_log_str("abc", "[1,2,3]");
-        assert_eq!(
-            l,
-            vec![
-                r#"EVENT_JSON:{"standard":"nep393","version":"1.0.0","event":"abc","data":[1,2,3]}"#
-            ],
-        )
-    }
-
-    fn mk_log_str(event: &str, data: &str) -> Vec<String> {
-        vec![format!(
-            "EVENT_JSON:{{\"standard\":\"nep393\",\"version\":\"1.0.0\",\"event\":\"{}\",\"data\":{}}}",
-            event,data
-        )]
-    }
-
-    #[test]
-    fn check_tree_iterator() {
-        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
-        ctr.balances.insert(&mk_balance_key(alice2(), 1, 1), &101);
-        ctr.balances.insert(&mk_balance_key(alice(), 1, 1), &102);
-        ctr.balances.insert(&mk_balance_key(bob(), 1, 1), &103);
-
-        let bs: Vec<(BalanceKey, u64)> = ctr
-            .balances
-            .iter_from(mk_balance_key(alice(), 0, 0))
-            .collect();
-        assert_eq!(bs.len(), 2, "bob must be included in the prefix scan");
-        assert_eq!(
-            bs[0].0.owner,
-            alice(),
-            "alice must be first in the iterator"
-        );
-        assert_eq!(bs[0].1, 102, "alice must be first in the iterator");
-        assert_eq!(bs[1].0.owner, bob(), "bob must be second in the iterator");
-        assert_eq!(bs[1].1, 103, "alice must be first in the iterator");
-    }
-
-    #[test]
-    fn registry_renew_one_issuer() {
-        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
-
-        // mint two tokens
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 11));
-        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 2);
+        // revoke (burn) tokens minted for alice from issuer2
+        let res = ctr.sbt_revoke_by_owner(alice(), true, None);
+        assert!(res);
 
-        // renvew the two tokens
-        let new_expire = START + 100;
-        ctr.sbt_renew(tokens, new_expire);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 2);
-        let m1_1_renewed = mk_metadata(1, Some(new_expire));
-        let m2_1_renewed = mk_metadata(2, Some(new_expire));
+        // make sure the balances are updated correctly
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 50);
 
-        // assert the two tokens have been renewed (new expire_at)
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None),
-            vec![(
-                issuer1(),
-                vec![
-                    mk_owned_token(1, m1_1_renewed),
-                    mk_owned_token(2, m2_1_renewed)
-                ]
-            ),]
-        );
+        assert_eq!(ctr.sbt_supply(issuer1()), 50);
+        assert_eq!(ctr.sbt_supply(issuer2()), 0);
     }
 
     #[test]
-    fn registry_renew_multiple_issuers() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
-
-        // mint two tokens by issuer1
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 11));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 2);
-
-        // mint two tokens by issuer2
-        let m1_2 = mk_metadata(1, Some(START + 10));
-        let m2_2: TokenMetadata = mk_metadata(2, Some(START + 12));
-        ctx.predecessor_account_id = issuer2();
-        testing_env!(ctx);
-        let tokens_issuer2 = ctr.sbt_mint(vec![(alice(), vec![m1_2, m2_2])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 2);
-
-        // renvew the two tokens
-        ctr.sbt_renew(tokens_issuer2, START + 100);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 2);
-        let m1_2_renewed = mk_metadata(1, Some(START + 100));
-        let m2_2_renewed = mk_metadata(2, Some(START + 100));
+    fn is_human() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
+        ctx.current_account_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
+        testing_env!(ctx.clone());
 
-        // assert tokens issued by issuer2 has been renewed (new expire_at)
-        assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None),
-            vec![(
-                issuer2(),
-                vec![
-                    mk_owned_token(1, m1_2_renewed),
-                    mk_owned_token(2, m2_2_renewed)
-                ]
-            ),]
-        );
+        let m1_1 = mk_metadata(1, Some(START + 1));
+        let m1_2 = mk_metadata(2, Some(START + 1));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        ctr.sbt_mint(vec![(bob(), vec![m1_2])]);
 
-        // assert tokens issued by issuer1 has not been renewed (new expire_at)
         assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None),
-            vec![(
-                issuer1(),
-                vec![mk_owned_token(1, m1_1), mk_owned_token(2, m2_1)]
-            ),]
+            ctr.is_human(alice(), None),
+            vec![(fractal_mainnet(), vec![1])]
         );
-    }
-
-    #[test]
-    #[should_panic]
-    fn registry_renew_basics() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
-
-        // mint two tokens
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 1);
+        assert_eq!(ctr.is_human(bob(), None), vec![]);
 
-        // check if only the issuer can renew the tokens (should panic)
-        ctx.predecessor_account_id = issuer2();
+        // step forward, so the tokens will expire
+        ctx.block_timestamp = (START + 1) * MSECOND;
         testing_env!(ctx);
-        ctr.sbt_renew(tokens, START + 100);
+        assert_eq!(ctr.is_human(alice(), None), vec![]);
+        assert_eq!(ctr.is_human(bob(), None), vec![]);
     }
 
     #[test]
-    fn registry_renew_event() {
-        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+    fn is_human_grace() {
+        let hour = 3_600_000;
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, Some(START + hour))])]);
+        let proof = vec![(fractal_mainnet(), vec![1])];
 
-        // mint two tokens
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        ctr.sbt_renew(tokens.clone(), START + 100);
-        let log_mint = mk_log_str(
-            "mint",
-            &format!(
-                r#"{{"issuer":"{}","tokens":[["{}",[1]]]}}"#,
-                issuer1(),
-                alice()
-            ),
-        );
-        let log_renew = mk_log_str(
-            "renew",
-            &format!(r#"{{"issuer":"{}","tokens":[{}]}}"#, issuer1(), tokens[0]),
-        );
-        assert_eq!(test_utils::get_logs(), vec![log_mint, log_renew].concat());
-    }
+        // the token expired 1 hour ago
+        ctx.block_timestamp = (START + 2 * hour) * MSECOND;
+        testing_env!(ctx.clone());
+        assert_eq!(ctr.is_human(alice(), None), vec![]);
+        assert_eq!(ctr.is_human(alice(), Some(0)), vec![]);
+        assert_eq!(ctr.is_human(alice(), Some(hour)), vec![]);
+        assert_eq!(ctr.is_human(alice(), Some(2 * hour)), proof);
+        assert_eq!(ctr.is_human(bob(), Some(2 * hour)), vec![]);
 
-    #[test]
-    fn sbt_recover_basics() {
-        let (mut ctx, mut ctr) = setup(&issuer2(), 3 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 1);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        match ctr.is_human_call(
+            bob(),
+            "function_name".to_string(),
+            "{}".to_string(),
+            None,
+            None,
+            None,
+            None,
+        ) {
+            Err(IsHumanCallErr::NotHuman) => (),
+            _ => panic!("expected NotHuman"),
+        }
+        assert!(ctr
+            .is_human_call(
+                bob(),
+                "function_name".to_string(),
+                "{}".to_string(),
+                None,
+                None,
+                None,
+                Some(2 * hour),
+            )
+            .is_ok());
 
-        //issue tokens by a different issuer
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx);
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m2_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 2);
+        // banned accounts are not humans, regardless of the grace
+        ctr.banlist.insert(&alice());
+        assert_eq!(ctr.is_human(alice(), Some(2 * hour)), vec![]);
+    }
 
-        ctr.sbt_recover(alice(), bob());
-        let recover_log = mk_log_str(
-            "recover",
-            &format!(
-                r#"{{"issuer":"{}","old_owner":"{}","new_owner":"{}"}}"#,
-                issuer1(),
+    #[test]
+    #[should_panic(expected = "grace_ms must be at most 604800000")]
+    fn is_human_grace_too_big() {
+        let (_, ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.is_human(alice(), Some(MAX_HUMAN_GRACE_MS + 1));
+    }
+
+    #[test]
+    fn human_proof() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
+        ctr.iah_sbts[0].1 = vec![3, 1];
+        ctr.sbt_mint(vec![
+            (
                 alice(),
-                bob()
+                vec![mk_metadata(1, Some(START + 1)), mk_metadata(3, None)],
             ),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        assert_eq!(ctr.human_proof(alice()), Some((fractal_mainnet(), 2, 3)));
+        assert_eq!(ctr.human_proof(bob()), None);
+
+        // ongoing soul transfer
+        ctr.ongoing_soul_tx.insert(
+            &alice(),
+            &SoulTxCursor {
+                issuer_id: 1,
+                class_id: ClassKey(0),
+            },
         );
-        assert_eq!(test_utils::get_logs().len(), 2);
-        assert_eq!(test_utils::get_logs()[1], recover_log[0]);
-        assert!(!ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(bob()));
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None), 2);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 1); //check if alice still holds the tokens issued by a different issuer
+        assert_eq!(ctr.human_proof(alice()), None);
+        ctr.ongoing_soul_tx.remove(&alice());
+
+        // class 1 token expired
+        ctx.block_timestamp = (START + 1) * MSECOND;
+        testing_env!(ctx);
+        assert_eq!(ctr.human_proof(alice()), None);
+    }
+
+    #[test]
+    fn expiry_boundary() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, Some(START + 5));
+        let m2 = mk_metadata(2, None);
+        ctr.sbt_mint(vec![(alice(), vec![m1.clone(), m2.clone()])]);
+
+        let td = |m: TokenMetadata| TokenData {
+            owner: alice(),
+            metadata: m.into(),
+        };
+        assert!(is_token_valid_at(&td(m1.clone()), START + 4));
+        assert!(!is_token_valid_at(&td(m1.clone()), START + 5));
+        assert!(is_token_valid_at(&td(m2.clone()), u64::MAX));
+
+        ctx.block_timestamp = (START + 4) * MSECOND;
+        testing_env!(ctx.clone());
+        assert!(ctr.sbt(fractal_mainnet(), 1, Some(true)).is_some());
+        assert_eq!(ctr.sbt_tokens(fractal_mainnet(), None, None, None).len(), 2);
         assert_eq!(
-            ctr.sbt_tokens_by_owner(bob(), Some(issuer1()), None, None, None),
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
             vec![(
-                issuer1(),
-                vec![
-                    mk_owned_token(1, m1_1.clone()),
-                    mk_owned_token(2, m2_1.clone())
-                ]
-            ),]
+                fractal_mainnet(),
+                vec![mk_owned_token(1, m1), mk_owned_token(2, m2.clone())]
+            )]
         );
-        assert_eq!(ctr.sbt_supply(issuer1()), 2);
-        assert_eq!(ctr.sbt_supply(issuer2()), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
-
         assert_eq!(
-            ctr.sbt_tokens(issuer1(), None, None, None),
-            vec![
-                mk_token(1, bob(), m1_1.clone()),
-                mk_token(2, bob(), m2_1.clone())
-            ]
+            ctr.is_human(alice(), None),
+            vec![(fractal_mainnet(), vec![1])]
         );
+
+        // expires_at == now: token is expired
+        ctx.block_timestamp = (START + 5) * MSECOND;
+        testing_env!(ctx);
+        assert!(ctr.sbt(fractal_mainnet(), 1, Some(true)).is_none());
+        assert_eq!(ctr.sbt_tokens(fractal_mainnet(), None, None, None).len(), 1);
         assert_eq!(
-            ctr.sbt(issuer1(), 1).unwrap(),
-            mk_token(1, bob(), m1_1.clone())
+            ctr.sbt_tokens_by_owner(alice(), None, None, None, None),
+            vec![(fractal_mainnet(), vec![mk_owned_token(2, m2)])]
         );
-        assert_eq!(ctr.sbt(issuer1(), 2).unwrap(), mk_token(2, bob(), m2_1));
-        assert_eq!(ctr.sbt(issuer2(), 1).unwrap(), mk_token(1, alice(), m1_1));
+        assert_eq!(ctr.is_human(alice(), None), vec![]);
     }
 
     #[test]
-    #[should_panic(expected = "not enough NEAR storage depost")]
-    fn sbt_recover_growing_storage_desposit_fail() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m1_2 = mk_metadata(2, Some(START + 10));
-        let m1_3 = mk_metadata(3, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 1);
-
+    fn is_human_multiple_iah_issuers() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
         ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), vec![m1_1, m1_2, m1_3])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 3);
+        ctr.sbt_mint(vec![
+            (bob(), vec![mk_metadata(2, None), mk_metadata(3, None)]),
+            (carol(), vec![mk_metadata(2, None)]),
+        ]);
+        assert_eq!(ctr.is_human(bob(), None), vec![]);
 
-        //set attached deposit to zero, should fail since the storage grows and we do not cover it
-        ctx.attached_deposit = 0;
+        ctx.predecessor_account_id = admin();
+        testing_env!(ctx.clone());
+        // classes are sorted and deduplicated
+        ctr.admin_add_iah_issuer(issuer2(), vec![3, 2, 3]);
+        assert_eq!(
+            ctr.iah_class_set(),
+            vec![(fractal_mainnet(), vec![1]), (issuer2(), vec![2, 3])]
+        );
+
+        // holding tokens from either IAH issuer proves humanity
+        assert_eq!(
+            ctr.is_human(alice(), None),
+            vec![(fractal_mainnet(), vec![1])]
+        );
+        assert_eq!(ctr.is_human(bob(), None), vec![(issuer2(), vec![1, 2])]);
+        assert_eq!(ctr.human_proof(bob()), Some((issuer2(), 1, 2)));
+        // carol doesn't have all required issuer2 classes
+        assert_eq!(ctr.is_human(carol(), None), vec![]);
+
+        ctr.admin_remove_iah_issuer(fractal_mainnet());
+        assert_eq!(ctr.iah_class_set(), vec![(issuer2(), vec![2, 3])]);
+        assert_eq!(ctr.is_human(alice(), None), vec![]);
+        assert_eq!(ctr.is_human(bob(), None), vec![(issuer2(), vec![1, 2])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't remove the last IAH issuer")]
+    fn admin_remove_last_iah_issuer() {
+        let (mut ctx, mut ctr) = setup(&admin(), 0);
+        ctx.predecessor_account_id = admin();
         testing_env!(ctx);
-        ctr._sbt_recover(alice(), bob(), 1);
+        ctr.admin_remove_iah_issuer(fractal_mainnet());
     }
 
     #[test]
-    fn sbt_recover_growing_storage_desposit_pass() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 1);
+    fn admin_rebind_issuer() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
+        let m1 = mk_metadata(1, Some(START + 10));
+        ctr.sbt_mint(vec![(alice(), vec![m1.clone()])]);
+        assert!(ctr.is_human_bool(alice()));
 
-        ctx.predecessor_account_id = issuer2();
+        let new_fractal = AccountId::new_unchecked("fractal-new.near".to_string());
+        ctx.predecessor_account_id = admin();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 1);
+        ctr.admin_rebind_issuer(fractal_mainnet(), new_fractal.clone());
+        assert_eq!(
+            test_utils::get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"i_am_human","version":"1.0.0","event":"issuer_rebind","data":{{"issuer_id":1,"new_account":"{}","old_account":"{}"}}}}"#,
+                new_fractal,
+                fractal_mainnet()
+            )]
+        );
+        assert_eq!(ctr.iah_class_set(), vec![(new_fractal.clone(), vec![1])]);
+        assert!(!ctr.sbt_issuers().contains(&fractal_mainnet()));
+        assert_eq!(ctr.sbt_supply(fractal_mainnet()), 0);
+
+        // tokens, supplies and humanity are preserved
+        assert_eq!(ctr.sbt_supply(new_fractal.clone()), 1);
+        let t = ctr.sbt(new_fractal.clone(), 1, None).unwrap();
+        assert_eq!((t.issuer_id, t.owner.clone()), (1, alice()));
+        assert_eq!(
+            ctr.is_human(alice(), None),
+            vec![(new_fractal.clone(), vec![1])]
+        );
 
-        // storage will grow so need to attach deposit.
-        ctx.attached_deposit = MINT_DEPOSIT;
-        testing_env!(ctx);
-        ctr.sbt_recover(alice(), bob());
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None), 1);
+        // the new account continues minting and renewing
+        ctx.predecessor_account_id = new_fractal.clone();
+        testing_env!(ctx.clone());
+        assert_eq!(ctr.sbt_mint(vec![(bob(), vec![m1])]), vec![2]);
+        ctr.sbt_renew(vec![1], START + 100);
+        assert_eq!(
+            ctr.sbt(new_fractal.clone(), 1, None)
+                .unwrap()
+                .metadata
+                .expires_at,
+            Some(START + 100)
+        );
+        assert_eq!(ctr.sbt_supply(new_fractal), 2);
     }
 
     #[test]
-    fn sbt_recover_with_continuation_basics() {
-        let (_, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 11));
-        let m3_1 = mk_metadata(3, Some(START + 12));
-        let m4_1 = mk_metadata(4, Some(START + 13));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1, m2_1, m3_1, m4_1])]);
+    #[should_panic(expected = "must be called by a registered SBT Issuer")]
+    fn admin_rebind_issuer_old_account() {
+        let (mut ctx, mut ctr) = setup(&admin(), 0);
+        let new_fractal = AccountId::new_unchecked("fractal-new.near".to_string());
+        ctr.admin_rebind_issuer(fractal_mainnet(), new_fractal);
 
-        // sbt_recover
-        let mut result = ctr._sbt_recover(alice(), alice2(), 3);
-        assert_eq!((3, false), result);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 3);
-        assert!(test_utils::get_logs().len() == 1);
-        result = ctr._sbt_recover(alice(), alice2(), 3);
-        assert_eq!((1, true), result);
-        assert!(test_utils::get_logs().len() == 2);
+        ctx.predecessor_account_id = fractal_mainnet();
+        testing_env!(ctx);
+        ctr.sbt_renew(vec![1], START + 200);
+    }
 
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 4);
+    #[test]
+    #[should_panic(expected = "new_account is already an issuer")]
+    fn admin_rebind_issuer_to_issuer() {
+        let (_, mut ctr) = setup(&admin(), 0);
+        ctr.admin_rebind_issuer(issuer1(), issuer2());
     }
 
     #[test]
-    fn sbt_revoke() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+    #[should_panic(expected = "old_account is not an issuer")]
+    fn admin_rebind_issuer_not_issuer() {
+        let (_, mut ctr) = setup(&admin(), 0);
+        ctr.admin_rebind_issuer(alice(), bob());
+    }
 
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 11));
-        let m3_1 = mk_metadata(3, Some(START + 21));
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn admin_rebind_issuer_not_admin() {
+        let (_, mut ctr) = setup(&issuer1(), 0);
+        ctr.admin_rebind_issuer(issuer1(), bob());
+    }
 
-        let current_timestamp = ctx.block_timestamp / MSECOND; // convert nano to mili seconds
+    #[test]
+    #[should_panic(expected = "not an admin")]
+    fn admin_add_iah_issuer_not_admin() {
+        let (_, mut ctr) = setup(&issuer1(), 0);
+        ctr.admin_add_iah_issuer(issuer1(), vec![1]);
+    }
 
-        let m1_1_revoked = mk_metadata(1, Some(current_timestamp));
-        let m2_1_revoked = mk_metadata(2, Some(current_timestamp));
-        let m3_1_revoked = mk_metadata(3, Some(current_timestamp));
+    #[test]
+    fn is_human_expires_at_none() {
+        let (_, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
 
-        let tokens_issuer_1 = ctr.sbt_mint(vec![(
-            alice(),
-            vec![m1_1.clone(), m2_1.clone(), m3_1.clone()],
-        )]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 3);
+        // make sure is_human works as expected when the expiratoin date is set to None (the token never expires).
+        let m1_1 = mk_metadata(1, None);
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
 
-        //issue tokens by a different issuer
-        ctx.predecessor_account_id = issuer2();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(bob(), vec![m1_1.clone(), m2_1.clone()])]);
-        ctr.sbt_mint(vec![(alice(), vec![m3_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None), 2);
+        assert_eq!(
+            ctr.is_human(alice(), None),
+            vec![(fractal_mainnet(), vec![1])]
+        );
+    }
 
-        //revoke tokens issued by issuer1
-        ctx.predecessor_account_id = issuer1();
+    #[test]
+    fn is_human_multiple_classes() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
+        ctr.iah_sbts[0].1 = vec![1, 3];
+        ctx.current_account_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
         testing_env!(ctx);
-        ctr.sbt_revoke(tokens_issuer_1, false);
 
-        let log_revoke = mk_log_str(
-            "revoke",
-            &format!(r#"{{"issuer":"{}","tokens":[1,2,3]}}"#, issuer1()),
-        );
-        assert_eq!(test_utils::get_logs().len(), 1);
-        assert_eq!(test_utils::get_logs()[0], log_revoke[0]);
+        let m1_1 = mk_metadata(1, Some(START + 1));
+        let m1_2 = mk_metadata(2, Some(START + 1));
+        let m1_3 = mk_metadata(3, Some(START + 1));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
+        ctr.sbt_mint(vec![(bob(), vec![m1_2.clone()])]);
+        ctr.sbt_mint(vec![(carol(), vec![m1_2, m1_1.clone()])]);
+        ctr.sbt_mint(vec![(dan(), vec![m1_3, m1_1])]);
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 3);
-        assert_eq!(ctr.sbt_supply(issuer2()), 3);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 3);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None), 2);
+        assert_eq!(ctr.is_human(alice(), None), vec![]);
+        assert_eq!(ctr.is_human(bob(), None), vec![]);
+        assert_eq!(ctr.is_human(carol(), None), vec![]);
         assert_eq!(
-            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None),
-            vec![(
-                issuer1(),
-                vec![
-                    mk_owned_token(1, m1_1_revoked.clone()),
-                    mk_owned_token(2, m2_1_revoked.clone()),
-                    mk_owned_token(3, m3_1_revoked.clone()),
-                ]
-            ),]
+            ctr.is_human(dan(), None),
+            vec![(fractal_mainnet(), vec![6, 5])]
         );
+    }
+
+    #[test]
+    fn sbt_tokens_by_owner_from_class_beyond() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None), mk_metadata(2, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+
+        // from_class is bigger than any alice class of issuer1: alice issuer2 tokens must
+        // not leak into the response.
+        for from_class in [3, 100, u64::MAX] {
+            assert_eq!(
+                ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), Some(from_class), None, None),
+                vec![]
+            );
+        }
+        // last issuer of the owner: bob tokens must not leak into the response.
         assert_eq!(
-            ctr.sbt_tokens(issuer1(), None, None, None),
-            vec![
-                mk_token(1, alice(), m1_1_revoked),
-                mk_token(2, alice(), m2_1_revoked),
-                mk_token(3, alice(), m3_1_revoked)
-            ]
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), Some(2), None, None),
+            vec![]
         );
         assert_eq!(
-            ctr.sbt_tokens(issuer2(), None, None, None),
-            vec![
-                mk_token(1, bob(), m1_1),
-                mk_token(2, bob(), m2_1),
-                mk_token(3, alice(), m3_1)
-            ]
-        )
+            ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), Some(2), None, None),
+            vec![(issuer1(), vec![mk_owned_token(2, mk_metadata(2, None))])]
+        );
     }
 
     #[test]
-    fn sbt_revoke_burn() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+    fn sbt_tokens_by_owner_per_issuer() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(30);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        let m2_1 = mk_metadata(2, Some(START + 11));
-        let m3_1 = mk_metadata(3, Some(START + 21));
+        ctx.predecessor_account_id = issuer3();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[10..20].to_vec())]);
 
-        let tokens_to_burn = ctr.sbt_mint(vec![
-            (alice(), vec![m1_1.clone(), m2_1.clone()]),
-            (bob(), vec![m1_1.clone()]),
-        ]);
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[20..].to_vec())]);
 
-        ctr.sbt_mint(vec![(alice(), vec![m3_1.clone()])]);
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 10);
+        assert_eq!(res[2].1.len(), 10);
+        // assert that returns always in the ascending order (not minting order)
+        assert_eq!(res[0].0, issuer1());
+        assert_eq!(res[1].0, issuer2());
+        assert_eq!(res[2].0, issuer3());
 
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 3);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None), 1);
+        let expected_tokens: Vec<u64> = (1..=10).collect();
 
-        //issue tokens by a different issuer
+        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None);
+        assert_eq!(res.len(), 1);
+        assert_eq!(
+            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
+            expected_tokens,
+        );
+        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None);
+        assert_eq!(res.len(), 1);
+        assert_eq!(
+            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
+            expected_tokens,
+        );
+        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer3()), None, None, None);
+        assert_eq!(res.len(), 1);
+        assert_eq!(
+            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
+            expected_tokens,
+        );
+
+        // mint more tokens for issuer1()
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[20..30].to_vec())]);
+        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].0, issuer1());
+        assert_eq!(
+            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
+            (1..=20).collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn sbt_tokens_by_owner_limit_across_issuers() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(3);
+        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
         ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(bob(), vec![m1_1.clone(), m2_1.clone()])]);
-        ctr.sbt_mint(vec![(alice(), vec![m3_1.clone()])]);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None), 2);
-
-        //revoke tokens issued by issuer1
-        ctx.predecessor_account_id = issuer1();
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..2].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[..1].to_vec())]);
+        ctx.predecessor_account_id = issuer3();
         testing_env!(ctx);
-        ctr.sbt_revoke(tokens_to_burn, true);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..1].to_vec())]);
 
-        let log_burn = mk_log_str(
-            "burn",
-            &format!(r#"{{"issuer":"{}","tokens":[1,2,3]}}"#, issuer1()),
+        let ids = |res: Vec<(AccountId, Vec<OwnedToken>)>| -> Vec<(AccountId, Vec<TokenId>)> {
+            res.into_iter()
+                .map(|(i, tokens)| (i, tokens.iter().map(|t| t.token).collect()))
+                .collect()
+        };
+
+        // the limit is hit in the middle of the issuer2 tokens: the partial list is returned.
+        assert_eq!(
+            ids(ctr.sbt_tokens_by_owner(alice(), None, None, Some(4), None)),
+            vec![(issuer1(), vec![1, 2, 3]), (issuer2(), vec![1])]
         );
-        assert_eq!(test_utils::get_logs().len(), 2);
-        assert_eq!(test_utils::get_logs()[0], log_burn[0]);
-        assert_eq!(ctr.sbt_supply(issuer1()), 1);
-        assert_eq!(ctr.sbt_supply(issuer2()), 3);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None), 1);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 1);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer2(), None), 2);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 3), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 3), 1);
 
+        let (page, cursor) = ctr.sbt_tokens_by_owner_page(alice(), None, Some(4), None);
         assert_eq!(
-            ctr.sbt_tokens(issuer1(), None, None, None),
-            vec![mk_token(4, alice(), m3_1.clone())],
+            ids(page),
+            vec![(issuer1(), vec![1, 2, 3]), (issuer2(), vec![1])]
         );
+        assert!(cursor.is_some());
+        // the next page resumes in the middle of the issuer2 tokens.
+        let (page, cursor) = ctr.sbt_tokens_by_owner_page(alice(), cursor, Some(4), None);
+        assert_eq!(ids(page), vec![(issuer2(), vec![2]), (issuer3(), vec![1])]);
+        assert_eq!(cursor, None);
+
+        // the limit is hit at the last owner token: no next page.
+        let (page, cursor) = ctr.sbt_tokens_by_owner_page(alice(), None, Some(6), None);
+        assert_eq!(page.iter().map(|(_, t)| t.len()).sum::<usize>(), 6);
+        assert_eq!(cursor, None);
+        let (page, cursor) = ctr.sbt_tokens_by_owner_page(bob(), None, Some(1), None);
+        assert_eq!(ids(page), vec![(issuer2(), vec![3])]);
+        assert_eq!(cursor, None);
+
+        // page by page
+        let mut cursor = None;
+        let mut all = Vec::new();
+        loop {
+            let (page, next) = ctr.sbt_tokens_by_owner_page(alice(), cursor, Some(1), None);
+            all.extend(ids(page));
+            if next.is_none() {
+                break;
+            }
+            cursor = next;
+        }
         assert_eq!(
-            ctr.sbt_tokens(issuer2(), None, None, None),
+            all,
             vec![
-                mk_token(1, bob(), m1_1),
-                mk_token(2, bob(), m2_1),
-                mk_token(3, alice(), m3_1)
+                (issuer1(), vec![1]),
+                (issuer1(), vec![2]),
+                (issuer1(), vec![3]),
+                (issuer2(), vec![1]),
+                (issuer2(), vec![2]),
+                (issuer3(), vec![1]),
             ]
-        )
+        );
     }
 
-    // sbt_ban
     #[test]
-    fn sbt_soul_transfer_ban() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert!(!ctr.is_banned(alice()));
-
-        ctx.predecessor_account_id = alice();
-        testing_env!(ctx);
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
-
-        assert!(ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
+    #[should_panic(expected = "malformed cursor")]
+    fn sbt_tokens_by_owner_page_other_account_cursor() {
+        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), mk_batch_metadata(2))]);
+        let (_, cursor) = ctr.sbt_tokens_by_owner_page(alice(), None, Some(1), None);
+        ctr.sbt_tokens_by_owner_page(bob(), cursor, Some(1), None);
     }
 
     #[test]
-    fn sbt_recover_limit() {
-        let (mut ctx, mut ctr) = setup(&issuer2(), 150 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(100);
-        assert!(batch_metadata.len() == 100);
-
-        // issuer_2
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 50);
+    fn sbt_token_ids_by_owner() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
+        let batch_metadata = mk_batch_metadata(20);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-        // // add more tokens to issuer_2
-        ctx.prepaid_gas = max_gas();
+        ctx.predecessor_account_id = issuer3();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[50..].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 100);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-        // add more tokens to issuer_1
-        ctx.predecessor_account_id = issuer1();
-        ctx.prepaid_gas = max_gas();
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(bob(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None), 20);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice2(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 20);
+        let alice_tokens: Vec<(u64, u64)> = (1..=20).map(|i| (i, i)).collect();
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(carol(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None), 20);
+        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer1()), 20);
+        assert_eq!(res, &alice_tokens[0..10]);
+        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer2()), 20);
+        assert_eq!(res, &alice_tokens[0..10]);
+        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer2()), 20);
+        assert_eq!(res, &alice_tokens[0..10]);
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(dan(), batch_metadata[..10].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None), 10);
+        // mint more tokens for issuer1()
+        ctx.predecessor_account_id = issuer1();
+        testing_env!(ctx);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[10..20].to_vec())]);
+        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer1()), 20);
+        assert_eq!(res, alice_tokens);
+    }
 
-        // sbt_recover alice->alice2
-        ctx.predecessor_account_id = issuer2();
-        ctx.prepaid_gas = max_gas();
+    #[test]
+    fn is_human_multiple_classes_with_expired_tokens() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
+        ctr.iah_sbts[0].1 = vec![1, 3];
+        ctx.current_account_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
         testing_env!(ctx.clone());
-        let limit: u32 = 20; //anything above this limit will fail due to exceeding maximum gas usage per call
 
-        let mut result = ctr._sbt_recover(alice(), alice2(), limit as usize);
-        while !result.1 {
-            ctx.prepaid_gas = max_gas();
-            testing_env!(ctx.clone());
-            result = ctr._sbt_recover(alice(), alice2(), limit as usize);
-        }
+        let m1_1 = mk_metadata(1, Some(START + 100));
+        let m1_2 = mk_metadata(2, Some(START + 100));
+        let m1_3 = mk_metadata(3, Some(START + 1));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1, m1_2, m1_3])]);
 
-        // check all the balances afterwards
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None), 100);
+        assert_eq!(
+            ctr.is_human(alice(), None),
+            vec![(fractal_mainnet(), vec![1, 3])]
+        );
+        // step forward, so token class==3 will expire
+        ctx.block_timestamp = (START + 1) * MSECOND;
+        testing_env!(ctx);
+        assert_eq!(ctr.is_human(alice(), None), vec![]);
     }
 
     #[test]
-    #[should_panic(expected = "HostError(GasLimitExceeded)")]
-    fn sbt_recover_limit_exceeded() {
-        let (mut ctx, mut ctr) = setup(&issuer2(), 150 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(100);
-        assert!(batch_metadata.len() == 100);
-
-        // issuer_2
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 50);
-
-        // // add more tokens to issuer_2
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[50..].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 100);
+    fn sbt_revoke_events() {
+        let (ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START));
+        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
 
-        // add more tokens to issuer_1
-        ctx.predecessor_account_id = issuer1();
-        ctx.prepaid_gas = max_gas();
+        // clear the events
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(bob(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(bob(), issuer1(), None), 20);
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice2(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer1(), None), 20);
+        // revoke (burn == false)
+        ctr.sbt_revoke(vec![tokens[0]], false, None);
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(carol(), batch_metadata[..20].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(carol(), issuer1(), None), 20);
+        let log_revoke = |seq| {
+            mk_log_str(
+                "revoke",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[1],"reason":"unspecified","event_seq":{}}}"#,
+                    fractal_mainnet(),
+                    seq
+                ),
+            )
+        };
+        let log_burn = mk_log_str(
+            "burn",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1],"event_seq":3}}"#,
+                fractal_mainnet()
+            ),
+        );
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(dan(), batch_metadata[..10].to_vec())]);
-        assert_eq!(ctr.sbt_supply_by_owner(dan(), issuer1(), None), 10);
+        // check only revoke event is emitted
+        assert_eq!(test_utils::get_logs().len(), 1);
+        assert_eq!(test_utils::get_logs(), log_revoke(2));
 
-        // sbt_recover alice->alice2
-        ctx.predecessor_account_id = issuer2();
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        let limit: u32 = 25; // this value exceedes the gas limit allowed per call and should fail
+        // clear the events
+        testing_env!(ctx);
 
-        let mut result = ctr._sbt_recover(alice(), alice2(), limit as usize);
-        while !result.1 {
-            ctx.prepaid_gas = max_gas();
-            testing_env!(ctx.clone());
-            result = ctr._sbt_recover(alice(), alice2(), limit as usize);
-        }
+        // revoke (burn == true)
+        ctr.sbt_revoke(tokens, true, None);
 
-        // check all the balances afterwards
-        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer2(), None), 0);
-        assert_eq!(ctr.sbt_supply_by_owner(alice2(), issuer2(), None), 100);
+        // check both burn and revoke events are emitted
+        assert_eq!(test_utils::get_logs().len(), 2); // -> only 1 event is emmited
+        assert_eq!(
+            test_utils::get_logs(),
+            vec![log_burn, log_revoke(4)].concat()
+        );
+        // -> missing revoke event
     }
 
     #[test]
-    #[should_panic(expected = "from account is banned. Cannot start the transfer")]
-    fn sbt_soul_transfer_from_banned_account() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert!(!ctr.is_banned(alice()));
-
-        // ban the from account
-        ctr.banlist.insert(&alice());
-        assert!(ctr.is_banned(alice()));
+    fn sbt_revoke_with_grace() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, Some(START + 10))]),
+            (carol(), vec![mk_metadata(1, Some(START + 5000))]),
+        ]);
+        testing_env!(ctx.clone());
+        ctr.sbt_revoke_with_grace(tokens.clone(), 100, Some(RevokeReason::UserRequest));
+        assert_eq!(
+            test_utils::get_logs(),
+            mk_log_str(
+                "revoke",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[1,3],"reason":"user_request","expires_at":{},"event_seq":2}}"#,
+                    issuer1(),
+                    START + 100
+                ),
+            )
+        );
+        // all tokens already expire before the end of the grace period: no event
+        testing_env!(ctx.clone());
+        ctr.sbt_revoke_with_grace(tokens.clone(), 200, None);
+        assert!(test_utils::get_logs().is_empty());
+        let expires_at =
+            |ctr: &Contract, t| ctr.sbt(issuer1(), t, None).unwrap().metadata.expires_at;
+        assert_eq!(expires_at(&ctr, tokens[0]), Some(START + 100));
+        // the revoke doesn't extend the token validity
+        assert_eq!(expires_at(&ctr, tokens[1]), Some(START + 10));
+        assert_eq!(expires_at(&ctr, tokens[2]), Some(START + 100));
+
+        // tokens are valid during the grace period
+        ctx.block_timestamp = (START + 99) * MSECOND;
+        testing_env!(ctx.clone());
+        assert!(ctr.sbt(issuer1(), tokens[0], Some(true)).is_some());
+        assert!(ctr.sbt(issuer1(), tokens[2], Some(true)).is_some());
+        assert!(ctr.sbt(issuer1(), tokens[1], Some(true)).is_none());
 
-        ctx.predecessor_account_id = alice();
+        // and invalid after
+        ctx.block_timestamp = (START + 100) * MSECOND;
         testing_env!(ctx);
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
+        for t in tokens {
+            assert!(ctr.sbt(issuer1(), t, Some(true)).is_none());
+        }
     }
 
     #[test]
-    #[should_panic(expected = "account alice.nea is banned")]
-    fn sbt_soul_transfer_to_banned_account() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert!(!ctr.is_banned(alice()));
+    fn sbt_revoke_reason() {
+        let (ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        let tokens = ctr.sbt_mint(vec![
+            (alice(), mk_batch_metadata(4)),
+            (bob(), mk_batch_metadata(1)),
+        ]);
 
-        // ban the reciver account
-        ctr.banlist.insert(&alice2());
-        assert!(ctr.is_banned(alice2()));
+        let reasons = [
+            (RevokeReason::Unspecified, "unspecified"),
+            (RevokeReason::ExpiredPolicy, "expired_policy"),
+            (RevokeReason::Fraud, "fraud"),
+            (RevokeReason::UserRequest, "user_request"),
+        ];
+        for (i, (reason, name)) in reasons.into_iter().enumerate() {
+            testing_env!(ctx.clone());
+            ctr.sbt_revoke(vec![tokens[i]], false, Some(reason));
+            let log = mk_log_str(
+                "revoke",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[{}],"reason":"{}","event_seq":{}}}"#,
+                    issuer1(),
+                    tokens[i],
+                    name,
+                    i + 2
+                ),
+            );
+            assert_eq!(test_utils::get_logs(), log);
+        }
 
-        ctx.predecessor_account_id = alice();
+        // revoke by owner with burn: the reason is set only in the revoke event
         testing_env!(ctx);
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
+        assert!(ctr.sbt_revoke_by_owner(bob(), true, Some(RevokeReason::Fraud)));
+        let logs = [
+            mk_log_str(
+                "burn",
+                &format!(r#"{{"issuer":"{}","tokens":[5],"event_seq":6}}"#, issuer1()),
+            ),
+            mk_log_str(
+                "revoke",
+                &format!(
+                    r#"{{"issuer":"{}","tokens":[5],"reason":"fraud","event_seq":7}}"#,
+                    issuer1()
+                ),
+            ),
+        ]
+        .concat();
+        assert_eq!(test_utils::get_logs(), logs);
     }
 
     #[test]
-    fn sbt_soul_transfer_ban_with_continuation() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 50 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(50);
-        ctr.sbt_mint(vec![(alice(), batch_metadata)]);
-        assert!(!ctr.is_banned(alice()));
+    fn sbt_burn_all_more_users() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
 
-        ctx.predecessor_account_id = alice();
+        // mint tokens to alice and bob from issuer1
+        let batch_metadata = mk_batch_metadata(20);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[10..].to_vec())]);
+
+        // mint tokens to alice and bob from issuer2
+        ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        assert!(!ctr.sbt_soul_transfer(alice2(), None).unwrap().1);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[11..].to_vec())]);
+
+        // mint tokens to alice and bob from issuer3
+        ctx.predecessor_account_id = issuer3();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[10..].to_vec())]);
+
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 10);
+        assert_eq!(res[2].1.len(), 10);
+
+        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 9);
+        assert_eq!(res[2].1.len(), 10);
 
-        // assert the from account is banned after the first soul transfer execution
-        assert!(ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
+        assert_eq!(ctr.sbt_supply(issuer1()), 20);
+        assert_eq!(ctr.sbt_supply(issuer2()), 19);
+        assert_eq!(ctr.sbt_supply(issuer3()), 20);
 
-        ctx.prepaid_gas = max_gas();
+        // alice burn all her tokens from all the issuers
+        ctx.predecessor_account_id = alice();
         testing_env!(ctx);
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
-        assert!(ctr.sbt_soul_transfer(alice2(), None).unwrap().1);
+        let res = ctr._sbt_burn_all(20);
+        assert!(!res);
+        let res = ctr._sbt_burn_all(20);
+        assert!(res); // make sure that after the second call true is returned (all tokens have been burned)
 
-        // assert it stays banned after the soul transfer has been completed
-        assert!(ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
-    }
+        // make sure the balances are updated correctly
+        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
+        assert!(res.is_empty());
 
-    #[test]
-    fn sbt_recover_ban() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert!(!ctr.is_banned(alice()));
+        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 10);
+        assert_eq!(res[1].1.len(), 9);
+        assert_eq!(res[2].1.len(), 10);
 
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx);
-        ctr.sbt_recover(alice(), alice2());
-        // sbt_recover should not ban the source account
-        assert!(!ctr.is_banned(alice()));
-        assert!(!ctr.is_banned(alice2()));
+        assert_eq!(ctr.sbt_supply(issuer1()), 10);
+        assert_eq!(ctr.sbt_supply(issuer2()), 9);
+        assert_eq!(ctr.sbt_supply(issuer3()), 10);
     }
 
     #[test]
-    #[should_panic(expected = "account alice.near is banned")]
-    fn sbt_mint_to_banned_account() {
-        let (_, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START + 10));
+    fn sbt_burn_all_basics() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
 
-        //ban alice account
-        ctr.banlist.insert(&alice());
-        assert!(ctr.is_banned(alice()));
+        // mint tokens to alice and bob from issuer1
+        let batch_metadata = mk_batch_metadata(20);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-        //try to mint to a banned account
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-    }
+        // mint tokens to alice and bob from issuer2
+        ctx.predecessor_account_id = issuer2();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-    #[test]
-    fn sbt_tokens_by_owner_non_expired() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
-        ctx.block_timestamp = START * MSECOND; // 11 seconds
+        // mint tokens to alice and bob from issuer3
+        ctx.predecessor_account_id = issuer3();
         testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
-        let m1_1 = mk_metadata(1, Some(START));
-        let m1_2 = mk_metadata(2, Some(START));
-        let m1_3 = mk_metadata(3, Some(START + 100));
-        let m1_4 = mk_metadata(4, Some(START + 100));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1, m1_2, m1_3, m1_4])]);
+        // alice burn all her tokens from all the issuers
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        loop {
+            if ctr._sbt_burn_all(10) {
+                break;
+            }
+        }
 
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true));
-        assert_eq!(res[0].1.len(), 4);
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 4);
+        // check if the logs are correct
+        assert_eq!(test_utils::get_logs().len(), 3);
 
-        let res = ctr.sbt_tokens(issuer1(), None, None, Some(true));
-        assert_eq!(res.len(), 4);
-        let res = ctr.sbt_tokens(issuer1(), None, None, Some(false));
-        assert_eq!(res.len(), 4);
-        let res = ctr.sbt_tokens(issuer1(), None, None, None);
-        assert_eq!(res.len(), 4);
+        let log_burn_issuer_1 = mk_log_str(
+            "burn",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2,3,4,5,6,7,8,9,10],"event_seq":4}}"#,
+                issuer1()
+            ),
+        );
 
-        // fast forward so the first two sbts are expired
-        ctx.block_timestamp = (START + 50) * MSECOND;
-        testing_env!(ctx);
+        let log_burn_issuer_2 = mk_log_str(
+            "burn",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2,3,4,5,6,7,8,9,10],"event_seq":5}}"#,
+                issuer2()
+            ),
+        );
 
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true));
-        assert_eq!(res[0].1.len(), 4);
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(false));
-        assert_eq!(res[0].1.len(), 2);
+        let log_burn_issuer_3 = mk_log_str(
+            "burn",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[1,2,3,4,5,6,7,8,9,10],"event_seq":6}}"#,
+                issuer3()
+            ),
+        );
+
+        assert_eq!(test_utils::get_logs()[0], log_burn_issuer_1[0]);
+        assert_eq!(test_utils::get_logs()[1], log_burn_issuer_2[0]);
+        assert_eq!(test_utils::get_logs()[2], log_burn_issuer_3[0]);
+
+        // make sure the balances are updated correctly
         let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 2);
+        assert!(res.is_empty());
 
-        let res = ctr.sbt_tokens(issuer1(), None, None, Some(true));
-        assert_eq!(res.len(), 4);
-        let res = ctr.sbt_tokens(issuer1(), None, None, Some(false));
-        assert_eq!(res.len(), 2);
-        let res = ctr.sbt_tokens(issuer1(), None, None, None);
-        assert_eq!(res.len(), 2);
+        assert_eq!(ctr.sbt_supply(issuer1()), 0);
+        assert_eq!(ctr.sbt_supply(issuer2()), 0);
+        assert_eq!(ctr.sbt_supply(issuer3()), 0);
+        for i in 1..=10 {
+            print!("{}", i);
+            assert_eq!(ctr.sbt_supply_by_class(issuer1(), i), 0);
+        }
+        for i in 1..=10 {
+            assert_eq!(ctr.sbt_supply_by_class(issuer2(), i), 0);
+        }
+        for i in 1..=10 {
+            assert_eq!(ctr.sbt_supply_by_class(issuer3(), i), 0);
+        }
     }
 
     #[test]
-    fn sbt_revoke_by_owner_basics() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
-
-        let m1_1 = mk_metadata(1, Some(START + 100));
-        let m1_2 = mk_metadata(2, Some(START + 100));
-        let m1_1_expired = mk_metadata(1, Some(START));
-        let m1_2_expired = mk_metadata(2, Some(START));
+    fn sbt_burn_all_limit() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 60 * MINT_DEPOSIT);
 
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m1_2.clone()])]);
+        // mint tokens to alice and bob from issuer1
+        let batch_metadata = mk_batch_metadata(40);
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[20..].to_vec())]);
 
+        // mint tokens to alice and bob from issuer2
         ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[20..].to_vec())]);
 
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone(), m1_2.clone()])]);
+        // mint tokens to alice and bob from issuer3
+        ctx.predecessor_account_id = issuer3();
+        testing_env!(ctx.clone());
+        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
+        ctr.sbt_mint(vec![(bob(), batch_metadata[20..].to_vec())]);
 
-        let res = ctr.sbt_revoke_by_owner(alice(), true);
-        assert!(res);
+        assert_eq!(ctr.sbt_supply(issuer1()), 40);
+        assert_eq!(ctr.sbt_supply(issuer2()), 40);
+        assert_eq!(ctr.sbt_supply(issuer3()), 40);
 
-        let log_burn = mk_log_str(
-            "burn",
-            &format!(r#"{{"issuer":"{}","tokens":[1,2]}}"#, issuer2()),
-        );
-        let log_revoke = mk_log_str(
-            "revoke",
-            &format!(r#"{{"issuer":"{}","tokens":[1,2]}}"#, issuer2()),
-        );
-        assert_eq!(test_utils::get_logs().len(), 3);
-        assert_eq!(test_utils::get_logs()[1], log_burn[0]);
-        assert_eq!(test_utils::get_logs()[2], log_revoke[0]);
+        // alice burn all her tokens from all the issuers
+        ctx.predecessor_account_id = alice();
+        loop {
+            ctx.prepaid_gas = max_gas();
+            testing_env!(ctx.clone());
+            if ctr._sbt_burn_all(39) {
+                //anything above 39 fails due to MaxGasLimitExceeded error
+                break;
+            }
+        }
 
         // make sure the balances are updated correctly
         let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert!(res.len() == 1);
-        assert_eq!(res[0].1.len(), 2);
-        assert_eq!(ctr.sbt_supply(issuer1()), 2);
-        assert_eq!(ctr.sbt_supply(issuer2()), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 0);
+        assert!(res.is_empty());
 
-        assert_eq!(
-            ctr.sbt_tokens(issuer1(), None, None, None),
-            vec![mk_token(1, alice(), m1_1), mk_token(2, alice(), m1_2),],
-        );
-        assert!(ctr.sbt_tokens(issuer2(), None, None, None).is_empty());
+        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
+        assert_eq!(res[0].1.len(), 20);
+        assert_eq!(res[1].1.len(), 20);
+        assert_eq!(res[2].1.len(), 20);
 
-        // revoke (not burn) tokens minted for alice from issuer1
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx.clone());
-        assert_eq!(test_utils::get_logs().len(), 0);
-        let res = ctr.sbt_revoke_by_owner(alice(), false);
-        assert!(res);
+        assert_eq!(ctr.sbt_supply(issuer1()), 20);
+        assert_eq!(ctr.sbt_supply(issuer2()), 20);
+        assert_eq!(ctr.sbt_supply(issuer3()), 20);
+    }
 
-        let log_revoke = mk_log_str(
-            "revoke",
-            &format!(r#"{{"issuer":"{}","tokens":[1,2]}}"#, issuer1()),
-        );
-        assert_eq!(test_utils::get_logs().len(), 1);
-        assert_eq!(test_utils::get_logs()[0], log_revoke[0]);
+    #[test]
+    fn sbt_update_token_references() {
+        let (ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
+        let m1_1 = mk_metadata(1, Some(START));
+        let tid1 = ctr.sbt_mint(vec![(bob(), vec![m1_1.clone()])])[0];
+        let tid2 = ctr.sbt_mint(vec![(alice(), vec![m1_1])])[0];
 
-        // fast forward
-        ctx.block_timestamp = (START + 50) * MSECOND;
+        let t1 = ctr.sbt(fractal_mainnet(), tid1, None).unwrap();
+        assert_eq!(t1.metadata.reference, Some("abc".to_owned()));
+
+        // reset logs
         testing_env!(ctx);
+        let r = Some("ipfs://abc123".to_owned());
+        let r_hash = Some(Base64VecU8(vec![1; 32]));
+        ctr.sbt_update_token_references(vec![(tid2, r.clone(), r_hash.clone())]);
 
-        // make sure the balances are updated correctly
-        let res_with_expired = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert!(res_with_expired.is_empty());
-        let res_without_expired = ctr.sbt_tokens_by_owner(alice(), None, None, None, Some(true));
-        assert!(res_without_expired.len() == 1);
-        assert_eq!(res_without_expired[0].1.len(), 2);
-        assert_eq!(ctr.sbt_supply(issuer1()), 2);
-        assert_eq!(ctr.sbt_supply(issuer2()), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 1), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer1(), 2), 1);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 1), 0);
-        assert_eq!(ctr.sbt_supply_by_class(issuer2(), 2), 0);
+        let t2 = ctr.sbt(fractal_mainnet(), tid2, None).unwrap();
+        assert_eq!(t2.metadata.reference, r);
+        assert_eq!(t2.metadata.reference_hash, r_hash);
 
-        assert_eq!(
-            ctr.sbt_tokens(issuer1(), None, None, Some(true)),
-            vec![
-                mk_token(1, alice(), m1_1_expired),
-                mk_token(2, alice(), m1_2_expired),
-            ],
+        let t1_2 = ctr.sbt(fractal_mainnet(), tid1, None).unwrap();
+        assert_eq!(t1, t1_2);
+
+        let log = mk_log_str(
+            "token_reference",
+            &format!(
+                r#"{{"issuer":"{}","tokens":[{}],"event_seq":3}}"#,
+                fractal_mainnet(),
+                tid2
+            ),
         );
-        assert!(ctr.sbt_tokens(issuer1(), None, None, None).is_empty());
-        assert!(ctr.sbt_tokens(issuer2(), None, None, None).is_empty());
+        assert_eq!(test_utils::get_logs(), log);
     }
 
     #[test]
-    fn sbt_revoke_by_owner_batch() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
-
-        // mint tokens to alice and bob from issuer1
-        let batch_metadata = mk_batch_metadata(20);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[10..].to_vec())]);
+    fn reference_size_limits() {
+        let (_, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        // 64 bytes hashes require the opt out
+        ctr.set_reference_hash_opt_out(true);
+        let mut m1 = mk_metadata(1, Some(START));
+        m1.reference = Some("a".repeat(MAX_REFERENCE_LEN));
+        let mut m2 = mk_metadata(2, Some(START));
+        m2.reference_hash = Some(vec![1; 64].into());
+        let tids = ctr.sbt_mint(vec![(alice(), vec![m1, m2])]);
+        assert_eq!(tids, vec![1, 2]);
+
+        ctr.sbt_update_token_references(vec![
+            (
+                1,
+                Some("a".repeat(MAX_REFERENCE_LEN)),
+                Some(vec![2; 64].into()),
+            ),
+            (2, None, None),
+        ]);
+        assert_eq!(
+            ctr.sbt(issuer1(), 2, None).unwrap().metadata.reference,
+            None
+        );
+    }
 
-        // mint tokens to alice and bob from issuer2
-        ctx.predecessor_account_id = issuer2();
-        testing_env!(ctx);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[11..].to_vec())]);
+    #[test]
+    fn reference_hash_sha256() {
+        let (_, mut ctr) = setup(&issuer1(), 4 * MINT_DEPOSIT);
+        let mut m1 = mk_metadata(1, Some(START));
+        m1.reference_hash = Some(vec![1; 32].into());
+        // hash without reference is only checked for the size
+        let mut m2 = mk_metadata(2, Some(START));
+        m2.reference = None;
+        m2.reference_hash = Some(vec![1; 64].into());
+        assert_eq!(ctr.sbt_mint(vec![(alice(), vec![m1, m2])]), vec![1, 2]);
+
+        let mut m3 = mk_metadata(3, Some(START));
+        m3.reference_hash = Some(vec![1; 64].into());
+        ctr.set_reference_hash_opt_out(true);
+        assert_eq!(ctr.sbt_mint(vec![(alice(), vec![m3])]), vec![3]);
+    }
 
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 10);
+    #[test]
+    #[should_panic(expected = "reference_hash must be a 32 bytes sha256 hash")]
+    fn mint_reference_hash_not_sha256() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        // opt out reverted
+        ctr.set_reference_hash_opt_out(true);
+        ctr.set_reference_hash_opt_out(false);
+        let mut m1 = mk_metadata(1, Some(START));
+        m1.reference_hash = Some(vec![1; 64].into());
+        ctr.sbt_mint(vec![(alice(), vec![m1])]);
+    }
 
-        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 9);
+    #[test]
+    #[should_panic(expected = "reference_hash must be a 32 bytes sha256 hash")]
+    fn update_token_references_hash_not_sha256() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, Some(START))])]);
+        ctr.sbt_update_token_references(vec![(
+            1,
+            Some("abc".to_owned()),
+            Some(vec![1; 64].into()),
+        )]);
+    }
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 20);
-        assert_eq!(ctr.sbt_supply(issuer2()), 19);
+    #[test]
+    #[should_panic(expected = "reference must be at most 512 bytes")]
+    fn mint_reference_too_long() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let mut m1 = mk_metadata(1, Some(START));
+        m1.reference = Some("a".repeat(MAX_REFERENCE_LEN + 1));
+        ctr.sbt_mint(vec![(alice(), vec![m1])]);
+    }
 
-        // revoke (burn) tokens minted for alice from issuer2
-        ctr.sbt_revoke_by_owner(alice(), true);
+    #[test]
+    #[should_panic(expected = "reference_hash must be 32 or 64 bytes")]
+    fn mint_reference_hash_wrong_size() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        let mut m1 = mk_metadata(1, Some(START));
+        m1.reference_hash = Some(vec![1; 33].into());
+        ctr.sbt_mint(vec![(alice(), vec![m1])]);
+    }
 
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        // assert_eq!(res[1].1.len(), 0);
+    #[test]
+    #[should_panic(expected = "reference must be at most 512 bytes")]
+    fn update_reference_too_long() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, Some(START))])]);
+        ctr.sbt_update_token_references(vec![(1, Some("a".repeat(MAX_REFERENCE_LEN + 1)), None)]);
+    }
 
-        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 9);
+    #[test]
+    #[should_panic(expected = "reference_hash must be 32 or 64 bytes")]
+    fn update_reference_hash_wrong_size() {
+        let (_, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, Some(START))])]);
+        ctr.sbt_update_token_references(vec![(1, None, Some(vec![1; 31].into()))]);
+    }
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 20);
-        assert_eq!(ctr.sbt_supply(issuer2()), 9);
+    #[test]
+    fn human_expires_at() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 7 * MINT_DEPOSIT);
+        ctr.iah_sbts[0].1 = vec![1, 3];
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![
+                    mk_metadata(1, Some(START + 20)),
+                    mk_metadata(3, Some(START + 10)),
+                ],
+            ),
+            (
+                bob(),
+                vec![mk_metadata(1, Some(START + 20)), mk_metadata(3, None)],
+            ),
+            (carol(), vec![mk_metadata(1, Some(START + 20))]),
+            (alice2(), vec![mk_metadata(1, None), mk_metadata(3, None)]),
+        ]);
+        assert_eq!(ctr.human_expires_at(alice()), Some(START + 10));
+        // bob holds an expiring and a non expiring IAH token
+        assert_eq!(ctr.human_expires_at(bob()), Some(START + 20));
+        // carol is not a human
+        assert_eq!(ctr.human_expires_at(carol()), None);
+        // alice2 IAH tokens never expire
+        assert_eq!(ctr.human_expires_at(alice2()), None);
+
+        // ongoing soul transfer
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        ctr._sbt_soul_transfer(dan(), 1, LockedPolicy::Skip)
+            .unwrap();
+        assert_eq!(ctr.human_expires_at(alice()), None);
     }
 
     #[test]
-    fn sbt_revoke_by_owner_benchmark() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
+    fn is_human_detailed() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, Some(START))]),
+            (carol(), vec![mk_metadata(1, None)]),
+        ]);
+        let proof = ctr.is_human_detailed(alice()).unwrap();
+        assert_eq!(proof, ctr.is_human(alice(), None));
+        assert_eq!(
+            near_sdk::serde_json::to_string(&proof).unwrap(),
+            format!(r#"[["{}",[1]]]"#, fractal_mainnet())
+        );
+        // expired token
+        assert_eq!(ctr.is_human_detailed(bob()), None);
+        assert_eq!(ctr.is_human_detailed(dan()), None);
 
-        // mint tokens to alice and bob from issuer1
-        let batch_metadata = mk_batch_metadata(20);
-        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
+        // ongoing soul transfer
+        ctx.predecessor_account_id = carol();
+        testing_env!(ctx);
+        ctr._sbt_soul_transfer(dan(), 1, LockedPolicy::Skip)
+            .unwrap();
+        assert_eq!(ctr.is_human_detailed(carol()), None);
+        // the recipient already got the token
+        assert_eq!(
+            ctr.is_human_detailed(dan()),
+            Some(vec![(fractal_mainnet(), vec![3])])
+        );
+    }
 
-        // mint tokens to alice and bob from issuer2
-        ctx.predecessor_account_id = issuer2();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
+    #[test]
+    fn count_unique_humans() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+            (carol(), vec![mk_metadata(1, None)]),
+        ]);
+        assert_eq!(ctr.count_unique_humans(vec![]), 0);
+        assert_eq!(
+            ctr.count_unique_humans(vec![alice(), bob(), alice(), dan(), bob()]),
+            2
+        );
 
-        // mint tokens to alice and bob from issuer3
-        ctx.predecessor_account_id = issuer3();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
+        // carol starts a soul transfer (with continuation): she is not counted
+        ctx.predecessor_account_id = carol();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr._sbt_soul_transfer(dan(), 1, LockedPolicy::Skip)
+                .unwrap(),
+            (1, false)
+        );
+        assert!(ctr.ongoing_soul_tx.contains_key(&carol()));
+        assert_eq!(ctr.count_unique_humans(vec![alice(), bob(), carol()]), 2);
+    }
 
-        // add 4th issuer
-        ctx.predecessor_account_id = admin();
+    #[test]
+    fn is_human_with_class() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 4 * MINT_DEPOSIT);
+        // alice and bob are humans
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
+        ctx.predecessor_account_id = issuer1();
         testing_env!(ctx.clone());
-        ctr.admin_add_sbt_issuer(issuer4());
+        // alice and carol hold the class 2 membership, dan holds an expiring one
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(2, None)]),
+            (carol(), vec![mk_metadata(2, None)]),
+            (dan(), vec![mk_metadata(2, Some(START + 10))]),
+        ]);
 
-        // mint tokens to alice and bob from issuer4
-        ctx.predecessor_account_id = issuer4();
+        // human and holds
+        assert!(ctr.is_human_with_class(alice(), issuer1(), 2));
+        // human and doesn't hold
+        assert!(!ctr.is_human_with_class(bob(), issuer1(), 2));
+        assert!(!ctr.is_human_with_class(alice(), issuer1(), 3));
+        assert!(!ctr.is_human_with_class(alice(), issuer2(), 2));
+        assert!(!ctr.is_human_with_class(alice(), issuer4(), 2));
+        // not human and holds
+        assert!(!ctr.is_human_with_class(carol(), issuer1(), 2));
+        // not human and doesn't hold
+        assert!(!ctr.is_human_with_class(carol(), issuer1(), 3));
+
+        // expired class token
+        ctr.sbt_mint(vec![(dan(), vec![mk_metadata(3, None)])]);
+        ctx.predecessor_account_id = fractal_mainnet();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
-
-        // revoke (burn) tokens minted for alice from issuer2
-        ctx.predecessor_account_id = issuer2();
-        ctx.prepaid_gas = Gas::ONE_TERA.mul(110);
+        ctr.sbt_mint(vec![(dan(), vec![mk_metadata(1, None)])]);
+        assert!(ctr.is_human_with_class(dan(), issuer1(), 2));
+        ctx.block_timestamp = (START + 10) * MSECOND;
         testing_env!(ctx);
-        let res = ctr.sbt_revoke_by_owner(alice(), true);
-        assert!(res);
-
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None);
-        assert_eq!(res.len(), 0);
+        assert!(!ctr.is_human_with_class(dan(), issuer1(), 2));
+        assert!(ctr.is_human_with_class(dan(), issuer1(), 3));
+
+        // ongoing soul transfer
+        ctr.ongoing_soul_tx.insert(
+            &alice(),
+            &SoulTxCursor {
+                issuer_id: 1,
+                class_id: ClassKey(0),
+            },
+        );
+        assert!(!ctr.is_human_with_class(alice(), issuer1(), 2));
     }
 
     #[test]
-    fn sbt_revoke_by_owner_burn_false() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
+    #[should_panic(expected = "max 100 accounts can be queried")]
+    fn count_unique_humans_limit() {
+        let (_, ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.count_unique_humans(vec![alice(); MAX_HUMANS_QUERY + 1]);
+    }
 
-        // mint tokens to alice and bob from issuer1
-        let batch_metadata = mk_batch_metadata(30);
-        ctr.sbt_mint(vec![(alice(), batch_metadata.clone())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata.clone())]);
+    #[test]
+    fn is_human_call_refund_to() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        // revoke (burn = false) tokens minted for alice from issuer2
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        let res = ctr.sbt_revoke_by_owner(alice(), false);
-        assert!(!res);
-        ctx.block_timestamp = (START + 1) * MSECOND;
+        // a relayer (bob) wants to get the refund
+        ctx.predecessor_account_id = alice();
         testing_env!(ctx.clone());
+        ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            "{}".to_string(),
+            None,
+            None,
+            Some(bob()),
+            None,
+        )
+        .unwrap();
+        let receipts = test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        match &receipts[1].actions[0] {
+            near_sdk::mock::VmAction::FunctionCall { args, .. } => {
+                let args: serde_json::Value = serde_json::from_slice(args).unwrap();
+                assert_eq!(args["refund_to"], bob().to_string());
+                assert_eq!(args["deposit"], MINT_DEPOSIT.to_string());
+            }
+            _ => panic!("expecting a function call"),
+        }
 
-        let res = ctr.sbt_revoke_by_owner(alice(), false);
-        assert!(res);
-
-        ctx.block_timestamp = (START + 5) * MSECOND;
-        testing_env!(ctx);
+        // the recipient call failed: the deposit goes to bob
+        ctx.predecessor_account_id = ctx.current_account_id.clone();
+        testing_env!(
+            ctx.clone(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        ctr.on_is_human_call(alice(), bob(), U128(MINT_DEPOSIT));
+        assert!(!ctr.has_ongoing_is_human_call(alice()));
+        let receipts = test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].receiver_id, bob());
+        match &receipts[0].actions[0] {
+            near_sdk::mock::VmAction::Transfer { deposit } => assert_eq!(*deposit, MINT_DEPOSIT),
+            _ => panic!("expecting a transfer"),
+        }
 
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, Some(false));
-        assert_eq!(res.len(), 0);
+        // successful recipient call: no refund
+        testing_env!(
+            ctx,
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"true".to_vec())],
+        );
+        ctr.on_is_human_call(alice(), bob(), U128(MINT_DEPOSIT));
+        assert!(test_utils::get_created_receipts().is_empty());
     }
 
     #[test]
-    fn sbt_revoke_by_owner_limit() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 200 * MINT_DEPOSIT);
-
-        // mint tokens to alice from issuer1
-        let batch_metadata = mk_batch_metadata(100);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
+    fn is_human_call_checked() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        // mint tokens to alice from issuer2
-        ctx.predecessor_account_id = issuer2();
-        ctx.prepaid_gas = max_gas();
+        ctx.predecessor_account_id = alice();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..50].to_vec())]);
+        ctr.is_human_call_checked(
+            bob(),
+            "function_name".to_string(),
+            "{}".to_string(),
+            None,
+            None,
+            Some(carol()),
+            None,
+        )
+        .unwrap();
+        assert!(ctr.has_ongoing_is_human_call(alice()));
+        let receipts = test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        match &receipts[1].actions[0] {
+            near_sdk::mock::VmAction::FunctionCall {
+                function_name,
+                args,
+                ..
+            } => {
+                assert_eq!(function_name, "on_is_human_call_checked");
+                let args: serde_json::Value = serde_json::from_slice(args).unwrap();
+                assert_eq!(args["refund_to"], carol().to_string());
+                assert_eq!(args["deposit"], MINT_DEPOSIT.to_string());
+            }
+            _ => panic!("expecting a function call"),
+        }
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 50);
-        assert_eq!(res[1].1.len(), 50);
+        // the recipient call failed: the deposit goes to carol, the result is returned
+        ctx.predecessor_account_id = ctx.current_account_id.clone();
+        testing_env!(
+            ctx.clone(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let res = ctr.on_is_human_call_checked(alice(), carol(), U128(MINT_DEPOSIT));
+        assert_eq!(
+            res,
+            IsHumanCallResult {
+                is_human: true,
+                callback_succeeded: false,
+                refunded: U128(MINT_DEPOSIT),
+            }
+        );
+        assert!(!ctr.has_ongoing_is_human_call(alice()));
+        let receipts = test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, carol());
+
+        // successful recipient call: no refund
+        testing_env!(
+            ctx,
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"true".to_vec())],
+        );
+        let res = ctr.on_is_human_call_checked(alice(), carol(), U128(MINT_DEPOSIT));
+        assert!(res.callback_succeeded);
+        assert_eq!(res.refunded, U128(0));
+        assert!(test_utils::get_created_receipts().is_empty());
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            r#"{"is_human":true,"callback_succeeded":true,"refunded":"0"}"#
+        );
+    }
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 50);
-        assert_eq!(ctr.sbt_supply(issuer2()), 50);
+    #[test]
+    fn is_human_call() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
 
-        ctx.prepaid_gas = max_gas();
-        testing_env!(ctx.clone());
-        // revoke (burn) tokens minted for alice from issuer2
-        let res = ctr.sbt_revoke_by_owner(alice(), true);
-        assert!(!res);
+        let m1_1 = mk_metadata(1, Some(START + 1));
+        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+        assert_eq!(
+            ctr.is_human(alice(), None),
+            vec![(fractal_mainnet(), vec![1])]
+        );
 
-        ctx.prepaid_gas = max_gas();
+        ctx.predecessor_account_id = alice();
         testing_env!(ctx);
-        // revoke (burn) tokens minted for alice from issuer2
-        let res = ctr.sbt_revoke_by_owner(alice(), true);
-        assert!(res);
-
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 50);
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 50);
-        assert_eq!(ctr.sbt_supply(issuer2()), 0);
+        ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            "{}".to_string(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
     }
 
     #[test]
-    fn is_human() {
-        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
-        ctx.current_account_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
-        testing_env!(ctx.clone());
+    fn is_human_call_fail() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
 
-        let m1_1 = mk_metadata(1, Some(START));
-        let m1_2 = mk_metadata(2, Some(START));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        ctr.sbt_mint(vec![(bob(), vec![m1_2])]);
+        match ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            "{}".to_string(),
+            None,
+            None,
+            None,
+            None,
+        ) {
+            Err(err) => assert_eq!(err, IsHumanCallErr::NotHuman),
+            Ok(_) => panic!("expecting Err(IsHumanCallErr::NotHuman)"),
+        };
+    }
 
-        assert_eq!(ctr.is_human(alice()), vec![(fractal_mainnet(), vec![1])]);
-        assert_eq!(ctr.is_human(bob()), vec![]);
+    #[test]
+    fn is_human_call_small_deposit() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        // step forward, so the tokens will expire
-        ctx.block_timestamp = (START + 1) * MSECOND;
+        // small deposits (eg: 1 yoctoNEAR) are forwarded to the recipient
+        ctx.predecessor_account_id = alice();
+        ctx.attached_deposit = 1;
         testing_env!(ctx);
-        assert_eq!(ctr.is_human(alice()), vec![]);
-        assert_eq!(ctr.is_human(bob()), vec![]);
+        ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            r#"{"nums":[200]}"#.to_string(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let receipts = test_utils::get_created_receipts();
+        match &receipts[0].actions[0] {
+            near_sdk::mock::VmAction::FunctionCall { deposit, .. } => assert_eq!(*deposit, 1),
+            _ => panic!("expecting a function call"),
+        }
     }
 
     #[test]
-    fn is_human_expires_at_none() {
-        let (_, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
-
-        // make sure is_human works as expected when the expiratoin date is set to None (the token never expires).
-        let m1_1 = mk_metadata(1, None);
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
+    fn sbt_supply_by_owner_count_expired() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(
+            alice(),
+            vec![
+                mk_metadata(1, Some(START + 10)),
+                mk_metadata(2, Some(START + 100)),
+                mk_metadata(3, None),
+            ],
+        )]);
 
-        assert_eq!(ctr.is_human(alice()), vec![(fractal_mainnet(), vec![1])]);
+        ctx.block_timestamp = (START + 50) * MSECOND;
+        testing_env!(ctx);
+        assert_eq!(ctr.sbt_supply_by_owner(alice(), issuer1(), None, None), 3);
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer1(), None, Some(true)),
+            3
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer1(), None, Some(false)),
+            2
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer1(), Some(1), None),
+            1
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer1(), Some(1), Some(false)),
+            0
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer1(), Some(2), Some(false)),
+            1
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(alice(), issuer1(), Some(4), Some(false)),
+            0
+        );
+        assert_eq!(
+            ctr.sbt_supply_by_owner(bob(), issuer1(), None, Some(false)),
+            0
+        );
     }
 
     #[test]
-    fn is_human_multiple_classes() {
-        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
-        ctr.iah_sbts.1 = vec![1, 3];
-        ctx.current_account_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
-        testing_env!(ctx);
+    fn issuer_class_supplies() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 5 * MINT_DEPOSIT);
+        assert!(ctr.issuer_class_supplies(issuer1(), None, None).is_empty());
+        assert!(ctr.issuer_class_supplies(issuer4(), None, None).is_empty());
+
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(5, None), mk_metadata(2, None)]),
+            (bob(), vec![mk_metadata(2, None), mk_metadata(9, None)]),
+            (carol(), vec![mk_metadata(5, None)]),
+        ]);
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(2, 2), (5, 2), (9, 1)]
+        );
+        // paging
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), Some(3), Some(1)),
+            vec![(5, 2)]
+        );
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), Some(5), None),
+            vec![(5, 2), (9, 1)]
+        );
+        assert!(ctr
+            .issuer_class_supplies(issuer1(), Some(10), None)
+            .is_empty());
+        // other issuers are not affected
+        assert!(ctr.issuer_class_supplies(issuer2(), None, None).is_empty());
+
+        // class is pruned when the supply hits 0
+        ctr.sbt_revoke(vec![4], true, None);
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(2, 2), (5, 2)]
+        );
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        ctr.sbt_burn(issuer1(), vec![1], None);
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(2, 2), (5, 1)]
+        );
 
-        let m1_1 = mk_metadata(1, Some(START));
-        let m1_2 = mk_metadata(2, Some(START));
-        let m1_3 = mk_metadata(3, Some(START));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1.clone()])]);
-        ctr.sbt_mint(vec![(bob(), vec![m1_2.clone()])]);
-        ctr.sbt_mint(vec![(carol(), vec![m1_2, m1_1.clone()])]);
-        ctr.sbt_mint(vec![(dan(), vec![m1_3, m1_1])]);
+        // reissue moves the token to the new class
+        ctx.predecessor_account_id = issuer1();
+        ctx.attached_deposit = MINT_DEPOSIT;
+        testing_env!(ctx.clone());
+        ctr.sbt_reissue(5, mk_metadata(7, None));
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(2, 2), (7, 1)]
+        );
 
-        assert_eq!(ctr.is_human(alice()), vec![]);
-        assert_eq!(ctr.is_human(bob()), vec![]);
-        assert_eq!(ctr.is_human(carol()), vec![]);
-        assert_eq!(ctr.is_human(dan()), vec![(fractal_mainnet(), vec![6, 5])]);
+        // revoke without burn doesn't change the supply
+        ctr.sbt_revoke(vec![2, 3], false, None);
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(2, 2), (7, 1)]
+        );
+        ctr.sbt_revoke(vec![2, 3], true, None);
+        assert_eq!(
+            ctr.issuer_class_supplies(issuer1(), None, None),
+            vec![(7, 1)]
+        );
     }
 
     #[test]
-    fn sbt_tokens_by_owner_per_issuer() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(30);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+    fn default_expiry_policy() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        assert_eq!(
+            ctr.default_expiry_policy(issuer1()),
+            Some(ExpiryPolicy::NeverExpire)
+        );
+        assert_eq!(ctr.default_expiry_policy(issuer4()), None);
 
-        ctx.predecessor_account_id = issuer3();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[10..20].to_vec())]);
+        // NeverExpire
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert_eq!(
+            ctr.sbt(issuer1(), 1, None).unwrap().metadata.expires_at,
+            None
+        );
 
-        ctx.predecessor_account_id = issuer2();
+        // FixedTtl
+        ctr.set_default_expiry_policy(ExpiryPolicy::FixedTtl(100));
+        assert_eq!(
+            ctr.default_expiry_policy(issuer1()),
+            Some(ExpiryPolicy::FixedTtl(100))
+        );
+        ctx.block_timestamp = (START + 5) * MSECOND;
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[20..].to_vec())]);
-
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res.len(), 3);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 10);
-        assert_eq!(res[2].1.len(), 10);
-        // assert that returns always in the ascending order (not minting order)
-        assert_eq!(res[0].0, issuer1());
-        assert_eq!(res[1].0, issuer2());
-        assert_eq!(res[2].0, issuer3());
-
-        let expected_tokens: Vec<u64> = (1..=10).collect();
-
-        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None);
-        assert_eq!(res.len(), 1);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(2, None)])]);
         assert_eq!(
-            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
-            expected_tokens,
+            ctr.sbt(issuer1(), 2, None).unwrap().metadata.expires_at,
+            Some(START + 105)
         );
-        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer2()), None, None, None);
-        assert_eq!(res.len(), 1);
+        // explicit expires_at overrides the policy
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(3, Some(START + 50))])]);
         assert_eq!(
-            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
-            expected_tokens,
+            ctr.sbt(issuer1(), 3, None).unwrap().metadata.expires_at,
+            Some(START + 50)
         );
-        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer3()), None, None, None);
-        assert_eq!(res.len(), 1);
+        // huge TTL doesn't overflow
         assert_eq!(
-            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
-            expected_tokens,
+            ExpiryPolicy::FixedTtl(u64::MAX).expires_at(START),
+            Some(u64::MAX)
         );
 
-        // mint more tokens for issuer1()
-        ctx.predecessor_account_id = issuer1();
-        testing_env!(ctx);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[20..30].to_vec())]);
-        let res = ctr.sbt_tokens_by_owner(alice(), Some(issuer1()), None, None, None);
-        assert_eq!(res.len(), 1);
-        assert_eq!(res[0].0, issuer1());
+        // FixedDate
+        ctr.set_default_expiry_policy(ExpiryPolicy::FixedDate(START + 1000));
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(4, None)])]);
         assert_eq!(
-            res[0].1.iter().map(|t| t.token).collect::<Vec<u64>>(),
-            (1..=20).collect::<Vec<u64>>()
+            ctr.sbt(issuer1(), 4, None).unwrap().metadata.expires_at,
+            Some(START + 1000)
         );
-    }
-
-    #[test]
-    fn sbt_token_ids_by_owner() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
-        let batch_metadata = mk_batch_metadata(20);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-
-        ctx.predecessor_account_id = issuer3();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
 
+        // other issuers are not affected
         ctx.predecessor_account_id = issuer2();
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-
-        let alice_tokens: Vec<(u64, u64)> = (1..=20).map(|i| (i, i)).collect();
-
-        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer1()), 20);
-        assert_eq!(res, &alice_tokens[0..10]);
-        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer2()), 20);
-        assert_eq!(res, &alice_tokens[0..10]);
-        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer2()), 20);
-        assert_eq!(res, &alice_tokens[0..10]);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+        assert_eq!(
+            ctr.sbt(issuer2(), 1, None).unwrap().metadata.expires_at,
+            None
+        );
 
-        // mint more tokens for issuer1()
+        // back to NeverExpire
         ctx.predecessor_account_id = issuer1();
         testing_env!(ctx);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[10..20].to_vec())]);
-        let res = ctr.sbt_token_ids_by_owner(alice(), ctr.assert_issuer(&issuer1()), 20);
-        assert_eq!(res, alice_tokens);
+        ctr.set_default_expiry_policy(ExpiryPolicy::NeverExpire);
+        assert_eq!(
+            ctr.default_expiry_policy(issuer1()),
+            Some(ExpiryPolicy::NeverExpire)
+        );
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(5, None)])]);
+        assert_eq!(
+            ctr.sbt(issuer1(), 5, None).unwrap().metadata.expires_at,
+            None
+        );
     }
 
     #[test]
-    fn is_human_multiple_classes_with_expired_tokens() {
-        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 150 * MINT_DEPOSIT);
-        ctr.iah_sbts.1 = vec![1, 3];
-        ctx.current_account_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
-        testing_env!(ctx.clone());
+    #[should_panic(expected = "must be called by a registered SBT Issuer")]
+    fn set_default_expiry_policy_not_issuer() {
+        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+        ctr.set_default_expiry_policy(ExpiryPolicy::FixedTtl(100));
+    }
 
-        let m1_1 = mk_metadata(1, Some(START + 100));
-        let m1_2 = mk_metadata(2, Some(START + 100));
-        let m1_3 = mk_metadata(3, Some(START));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1, m1_2, m1_3])]);
+    #[test]
+    fn sbt_tokens_page() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 7 * MINT_DEPOSIT);
+        let page = ctr.sbt_tokens_page(issuer1(), None, None, None);
+        assert!(page.tokens.is_empty());
+        assert_eq!(page.next_from_token, None);
+
+        let batch = mk_batch_metadata(7);
+        ctr.sbt_mint(vec![
+            (alice(), batch[..4].to_vec()),
+            (bob(), batch[4..].to_vec()),
+        ]);
+        // sparse ids: burn tokens 3, 4 and 5
+        ctr.sbt_revoke(vec![3, 4, 5], true, None);
 
-        assert_eq!(ctr.is_human(alice()), vec![(fractal_mainnet(), vec![1, 3])]);
-        // step forward, so token class==3 will expire
+        let mut pages = vec![];
+        let mut from_token = None;
+        loop {
+            let page = ctr.sbt_tokens_page(issuer1(), from_token, Some(2), None);
+            pages.push(page.tokens.iter().map(|t| t.token).collect::<Vec<_>>());
+            match page.next_from_token {
+                None => break,
+                next => from_token = next,
+            }
+        }
+        assert_eq!(pages, vec![vec![1, 2], vec![], vec![6], vec![7]]);
+
+        let page = ctr.sbt_tokens_page(issuer1(), Some(6), None, None);
+        assert_eq!(page.tokens.len(), 2);
+        assert_eq!(page.next_from_token, None);
+        let page = ctr.sbt_tokens_page(issuer1(), Some(8), None, None);
+        assert!(page.tokens.is_empty());
+        assert_eq!(page.next_from_token, None);
+
+        // expired tokens are skipped, but the paging continues
         ctx.block_timestamp = (START + 1) * MSECOND;
         testing_env!(ctx);
-        assert_eq!(ctr.is_human(alice()), vec![]);
+        ctr.sbt_revoke(vec![6], false, None);
+        let page = ctr.sbt_tokens_page(issuer1(), Some(5), Some(2), None);
+        assert!(page.tokens.is_empty());
+        assert_eq!(page.next_from_token, Some(7));
+        let page = ctr.sbt_tokens_page(issuer1(), Some(5), Some(2), Some(true));
+        assert_eq!(page.tokens.len(), 1);
     }
 
     #[test]
-    fn sbt_revoke_events() {
-        let (ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START));
-        let tokens = ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-
-        // clear the events
-        testing_env!(ctx.clone());
-
-        // revoke (burn == false)
-        ctr.sbt_revoke(vec![tokens[0]], false);
+    fn sbt_valid_tokens_by_class() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 6 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (
+                alice(),
+                vec![mk_metadata(1, Some(START + 10)), mk_metadata(2, None)],
+            ),
+            (
+                bob(),
+                vec![mk_metadata(1, Some(START + 100)), mk_metadata(2, None)],
+            ),
+            (carol(), vec![mk_metadata(1, None)]),
+            (dan(), vec![mk_metadata(1, Some(START + 100))]),
+        ]);
+        ctr.sbt_revoke(vec![6], true, None);
+        let ids = |ts: Vec<Token>| ts.iter().map(|t| t.token).collect::<Vec<_>>();
 
-        let log_revoke = mk_log_str(
-            "revoke",
-            &format!(r#"{{"issuer":"{}","tokens":[1]}}"#, fractal_mainnet()),
+        assert_eq!(
+            ids(ctr.sbt_valid_tokens_by_class(issuer1(), 1, None, None)),
+            vec![1, 3, 5]
         );
-        let log_burn = mk_log_str(
-            "burn",
-            &format!(r#"{{"issuer":"{}","tokens":[1]}}"#, fractal_mainnet()),
+        assert_eq!(
+            ids(ctr.sbt_valid_tokens_by_class(issuer1(), 2, None, None)),
+            vec![2, 4]
         );
+        assert!(ctr
+            .sbt_valid_tokens_by_class(issuer1(), 3, None, None)
+            .is_empty());
+        assert!(ctr
+            .sbt_valid_tokens_by_class(issuer4(), 1, None, None)
+            .is_empty());
+        // paging
+        assert_eq!(
+            ids(ctr.sbt_valid_tokens_by_class(issuer1(), 1, Some(2), Some(2))),
+            vec![3]
+        );
+        assert!(ctr
+            .sbt_valid_tokens_by_class(issuer1(), 1, Some(7), None)
+            .is_empty());
 
-        // check only revoke event is emitted
-        assert_eq!(test_utils::get_logs().len(), 1);
-        assert_eq!(test_utils::get_logs(), log_revoke);
-
-        // clear the events
+        // token 1 expires
+        ctx.block_timestamp = (START + 10) * MSECOND;
         testing_env!(ctx);
-
-        // revoke (burn == true)
-        ctr.sbt_revoke(tokens, true);
-
-        // check both burn and revoke events are emitted
-        assert_eq!(test_utils::get_logs().len(), 2); // -> only 1 event is emmited
-        assert_eq!(test_utils::get_logs(), vec![log_burn, log_revoke].concat());
-        // -> missing revoke event
+        assert_eq!(
+            ids(ctr.sbt_valid_tokens_by_class(issuer1(), 1, None, None)),
+            vec![3, 5]
+        );
     }
 
     #[test]
-    fn sbt_burn_all_more_users() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
-
-        // mint tokens to alice and bob from issuer1
-        let batch_metadata = mk_batch_metadata(20);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[10..].to_vec())]);
+    fn sbt_history() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, Some(START + 100))]),
+            (bob(), vec![mk_metadata(1, Some(START + 100))]),
+        ]);
+        assert!(ctr.sbt_history(issuer1(), 1).is_empty());
+        assert!(ctr.sbt_history(issuer1(), 3).is_empty());
+        assert!(ctr.sbt_history(issuer4(), 1).is_empty());
 
-        // mint tokens to alice and bob from issuer2
-        ctx.predecessor_account_id = issuer2();
+        ctx.block_timestamp = (START + 1) * MSECOND;
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[11..].to_vec())]);
-
-        // mint tokens to alice and bob from issuer3
-        ctx.predecessor_account_id = issuer3();
+        ctr.sbt_renew(vec![1], START + 200);
+        ctx.block_timestamp = (START + 2) * MSECOND;
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[10..].to_vec())]);
-
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 10);
-        assert_eq!(res[2].1.len(), 10);
-
-        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 9);
-        assert_eq!(res[2].1.len(), 10);
-
-        assert_eq!(ctr.sbt_supply(issuer1()), 20);
-        assert_eq!(ctr.sbt_supply(issuer2()), 19);
-        assert_eq!(ctr.sbt_supply(issuer3()), 20);
-
-        // alice burn all her tokens from all the issuers
-        ctx.predecessor_account_id = alice();
-        testing_env!(ctx);
-        let res = ctr._sbt_burn_all(20);
-        assert!(!res);
-        let res = ctr._sbt_burn_all(20);
-        assert!(res); // make sure that after the second call true is returned (all tokens have been burned)
-
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert!(res.is_empty());
-
-        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 10);
-        assert_eq!(res[1].1.len(), 9);
-        assert_eq!(res[2].1.len(), 10);
-
-        assert_eq!(ctr.sbt_supply(issuer1()), 10);
-        assert_eq!(ctr.sbt_supply(issuer2()), 9);
-        assert_eq!(ctr.sbt_supply(issuer3()), 10);
-    }
-
-    #[test]
-    fn sbt_burn_all_basics() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 20 * MINT_DEPOSIT);
-
-        // mint tokens to alice and bob from issuer1
-        let batch_metadata = mk_batch_metadata(20);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
-
-        // mint tokens to alice and bob from issuer2
-        ctx.predecessor_account_id = issuer2();
+        ctr.sbt_update_token_references(vec![(1, Some("def".to_owned()), None)]);
+        ctx.block_timestamp = (START + 3) * MSECOND;
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        ctr.sbt_revoke(vec![1], false, None);
+        ctr.sbt_recover(alice(), carol());
+        assert_eq!(
+            ctr.sbt_history(issuer1(), 1),
+            vec![
+                TokenChange {
+                    field: TokenField::Expiry,
+                    at_ms: START + 1
+                },
+                TokenChange {
+                    field: TokenField::Reference,
+                    at_ms: START + 2
+                },
+                TokenChange {
+                    field: TokenField::Expiry,
+                    at_ms: START + 3
+                },
+                TokenChange {
+                    field: TokenField::Recovery,
+                    at_ms: START + 3
+                },
+            ]
+        );
+        // other tokens are not affected
+        assert!(ctr.sbt_history(issuer1(), 2).is_empty());
 
-        // mint tokens to alice and bob from issuer3
-        ctx.predecessor_account_id = issuer3();
+        // reissue moves the history to the new token
+        ctx.attached_deposit = MINT_DEPOSIT;
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..10].to_vec())]);
+        let new_token = ctr.sbt_reissue(1, mk_metadata(2, Some(START + 100)));
+        assert!(ctr.sbt_history(issuer1(), 1).is_empty());
+        let history = ctr.sbt_history(issuer1(), new_token);
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[4].field, TokenField::Class);
 
-        // alice burn all her tokens from all the issuers
-        ctx.predecessor_account_id = alice();
+        // burn removes the history
+        ctx.predecessor_account_id = carol();
         testing_env!(ctx);
-        loop {
-            if ctr._sbt_burn_all(10) {
-                break;
-            }
-        }
+        ctr.sbt_burn(issuer1(), vec![new_token], None);
+        assert!(ctr.sbt_history(issuer1(), new_token).is_empty());
+    }
 
-        // check if the logs are correct
-        assert_eq!(test_utils::get_logs().len(), 3);
+    #[test]
+    fn sbt_history_cap() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        let log_burn_issuer_1 = mk_log_str(
-            "burn",
-            &format!(
-                r#"{{"issuer":"{}","tokens":[1,2,3,4,5,6,7,8,9,10]}}"#,
-                issuer1()
-            ),
-        );
+        let updates = MAX_TOKEN_HISTORY as u64 + 3;
+        for i in 1..=updates {
+            ctx.block_timestamp = (START + i) * MSECOND;
+            testing_env!(ctx.clone());
+            ctr.sbt_renew(vec![1], START + 100 + i);
+        }
+        let history = ctr.sbt_history(issuer1(), 1);
+        assert_eq!(history.len(), MAX_TOKEN_HISTORY);
+        // the oldest entries are dropped
+        assert_eq!(history[0].at_ms, START + 4);
+        assert_eq!(history[MAX_TOKEN_HISTORY - 1].at_ms, START + updates);
+    }
 
-        let log_burn_issuer_2 = mk_log_str(
-            "burn",
-            &format!(
-                r#"{{"issuer":"{}","tokens":[1,2,3,4,5,6,7,8,9,10]}}"#,
-                issuer2()
-            ),
-        );
+    #[test]
+    fn token_updates_storage() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        let log_burn_issuer_3 = mk_log_str(
-            "burn",
-            &format!(
-                r#"{{"issuer":"{}","tokens":[1,2,3,4,5,6,7,8,9,10]}}"#,
-                issuer3()
-            ),
-        );
+        // a full history and an added expire time fit in the storage prepaid at mint.
+        ctx.attached_deposit = 0;
+        testing_env!(ctx);
+        let storage_start = env::storage_usage();
+        for i in 1..=MAX_TOKEN_HISTORY as u64 {
+            ctr.sbt_renew(vec![1], START + i);
+        }
+        ctr.sbt_revoke(vec![1], false, None);
+        assert_eq!(ctr.sbt_history(issuer1(), 1).len(), MAX_TOKEN_HISTORY);
+        assert!(env::storage_usage() - storage_start <= TOKEN_UPDATES_STORAGE);
+    }
 
-        assert_eq!(test_utils::get_logs()[0], log_burn_issuer_1[0]);
-        assert_eq!(test_utils::get_logs()[1], log_burn_issuer_2[0]);
-        assert_eq!(test_utils::get_logs()[2], log_burn_issuer_3[0]);
+    #[test]
+    #[should_panic(expected = "not enough NEAR storage deposit")]
+    fn update_token_references_storage_deposit() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert!(res.is_empty());
+        // the reference grows: the storage must be paid
+        ctx.attached_deposit = 0;
+        testing_env!(ctx);
+        ctr.sbt_update_token_references(vec![(1, Some("a".repeat(MAX_REFERENCE_LEN)), None)]);
+    }
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 0);
-        assert_eq!(ctr.sbt_supply(issuer2()), 0);
-        assert_eq!(ctr.sbt_supply(issuer3()), 0);
-        for i in 1..=10 {
-            print!("{}", i);
-            assert_eq!(ctr.sbt_supply_by_class(issuer1(), i), 0);
-        }
-        for i in 1..=10 {
-            assert_eq!(ctr.sbt_supply_by_class(issuer2(), i), 0);
-        }
-        for i in 1..=10 {
-            assert_eq!(ctr.sbt_supply_by_class(issuer3(), i), 0);
+    #[test]
+    fn mint_cost_covers_max_metadata() {
+        let (mut ctx, mut ctr) = setup(&issuer1(), cost::mint_deposit(1));
+        // the longest account ID and metadata
+        let owner: AccountId = "a".repeat(64).parse().unwrap();
+        let m = TokenMetadata {
+            class: ClassId::MAX,
+            issued_at: Some(START),
+            expires_at: Some(START + 100),
+            reference: Some("a".repeat(MAX_REFERENCE_LEN)),
+            reference_hash: Some(vec![1; 64].into()),
+            score: Some(u32::MAX),
+            attestation: Some(vec![1; MAX_ATTESTATION_LEN].into()),
+        };
+        ctr.set_reference_hash_opt_out(true);
+        let storage_start = env::storage_usage();
+        ctr.sbt_mint(vec![(owner, vec![m])]);
+        let required = (env::storage_usage() - storage_start + TOKEN_UPDATES_STORAGE) as u128
+            * env::storage_byte_cost();
+        assert!(required <= cost::MINT_COST);
+
+        // the token updates fit in the prepaid storage
+        ctx.attached_deposit = 0;
+        testing_env!(ctx);
+        let storage_start = env::storage_usage();
+        for i in 1..=MAX_TOKEN_HISTORY as u64 {
+            ctr.sbt_renew(vec![1], START + 100 + i);
         }
+        assert!(env::storage_usage() - storage_start <= TOKEN_UPDATES_STORAGE);
     }
 
     #[test]
-    fn sbt_burn_all_limit() {
-        let (mut ctx, mut ctr) = setup(&issuer1(), 60 * MINT_DEPOSIT);
+    #[should_panic(expected = "not enough NEAR storage deposit")]
+    fn token_updates_storage_mint_deposit() {
+        // enough for the token storage, but not for the token updates storage.
+        let (_, mut ctr) = setup(&issuer1(), 700 * env::storage_byte_cost());
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
+    }
 
-        // mint tokens to alice and bob from issuer1
-        let batch_metadata = mk_batch_metadata(40);
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[20..].to_vec())]);
+    #[test]
+    fn is_human_call_not_enough_gas() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        // mint tokens to alice and bob from issuer2
-        ctx.predecessor_account_id = issuer2();
-        testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[20..].to_vec())]);
+        let payload = r#"{"nums":[200]}"#;
+        let args = format!(
+            r#"{{"caller":"{}","iah_proof":[["{}",[1]]],"payload":{}}}"#,
+            alice(),
+            fractal_mainnet(),
+            payload
+        );
+        // tight deposit: exactly the storage cost of the args
+        let deposit = args.len() as u128 * env::storage_byte_cost();
+        let required = IS_HUMAN_GAS + IS_HUMAN_CALLBACK_GAS + MIN_IS_HUMAN_CALL_GAS;
 
-        // mint tokens to alice and bob from issuer3
-        ctx.predecessor_account_id = issuer3();
+        ctx.predecessor_account_id = alice();
+        ctx.attached_deposit = deposit;
+        ctx.prepaid_gas = Gas(required.0 - 1);
         testing_env!(ctx.clone());
-        ctr.sbt_mint(vec![(alice(), batch_metadata[..20].to_vec())]);
-        ctr.sbt_mint(vec![(bob(), batch_metadata[20..].to_vec())]);
-
-        assert_eq!(ctr.sbt_supply(issuer1()), 40);
-        assert_eq!(ctr.sbt_supply(issuer2()), 40);
-        assert_eq!(ctr.sbt_supply(issuer3()), 40);
+        match ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            payload.to_string(),
+            None,
+            None,
+            None,
+            None,
+        ) {
+            Err(err) => assert_eq!(err, IsHumanCallErr::NotEnoughGas(required.0)),
+            Ok(_) => panic!("expecting Err(IsHumanCallErr::NotEnoughGas)"),
+        };
+        assert!(test_utils::get_created_receipts().is_empty());
+        // is_human_call_lock doesn't have a callback
+        let lock_required = IS_HUMAN_GAS + MIN_IS_HUMAN_CALL_GAS;
+        ctx.prepaid_gas = Gas(lock_required.0 - 1);
+        testing_env!(ctx.clone());
+        match ctr.is_human_call_lock(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            payload.to_string(),
+            MSECOND,
+            false,
+        ) {
+            Err(err) => assert_eq!(err, IsHumanCallErr::NotEnoughGas(lock_required.0)),
+            Ok(_) => panic!("expecting Err(IsHumanCallErr::NotEnoughGas)"),
+        };
+        assert!(test_utils::get_created_receipts().is_empty());
 
-        // alice burn all her tokens from all the issuers
-        ctx.predecessor_account_id = alice();
-        loop {
-            ctx.prepaid_gas = max_gas();
-            testing_env!(ctx.clone());
-            if ctr._sbt_burn_all(41) {
-                //anything above 41 fails due to MaxGasLimitExceeded error
-                break;
+        // tight gas and deposit are enough: the recipient gets the deposit and the min gas
+        ctx.prepaid_gas = required;
+        testing_env!(ctx);
+        ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            payload.to_string(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let receipts = test_utils::get_created_receipts();
+        // recipient call and the registry callback
+        assert_eq!(receipts.len(), 2);
+        match &receipts[0].actions[0] {
+            near_sdk::mock::VmAction::FunctionCall {
+                gas, deposit: d, ..
+            } => {
+                assert_eq!(*gas, MIN_IS_HUMAN_CALL_GAS);
+                assert_eq!(*d, deposit);
             }
+            _ => panic!("expecting a function call"),
         }
+    }
 
-        // make sure the balances are updated correctly
-        let res = ctr.sbt_tokens_by_owner(alice(), None, None, None, None);
-        assert!(res.is_empty());
-
-        let res = ctr.sbt_tokens_by_owner(bob(), None, None, None, None);
-        assert_eq!(res[0].1.len(), 20);
-        assert_eq!(res[1].1.len(), 20);
-        assert_eq!(res[2].1.len(), 20);
+    #[test]
+    fn is_human_call_invalid_function_name() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        assert_eq!(ctr.sbt_supply(issuer1()), 20);
-        assert_eq!(ctr.sbt_supply(issuer2()), 20);
-        assert_eq!(ctr.sbt_supply(issuer3()), 20);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        let ctr_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
+        let too_long = "f".repeat(MAX_FUNCTION_NAME_LEN + 1);
+        for function in ["".to_string(), too_long.clone()] {
+            match ctr.is_human_call(
+                ctr_id.clone(),
+                function,
+                "{}".to_string(),
+                None,
+                None,
+                None,
+                None,
+            ) {
+                Err(err) => assert_eq!(err, IsHumanCallErr::InvalidFunctionName),
+                Ok(_) => panic!("expecting Err(IsHumanCallErr::InvalidFunctionName)"),
+            };
+        }
+        match ctr.is_human_call_lock(ctr_id.clone(), too_long, "{}".to_string(), 0, false) {
+            Err(err) => assert_eq!(err, IsHumanCallErr::InvalidFunctionName),
+            Ok(_) => panic!("expecting Err(IsHumanCallErr::InvalidFunctionName)"),
+        };
+        assert!(test_utils::get_created_receipts().is_empty());
+        assert!(!ctr.has_ongoing_is_human_call(alice()));
+
+        // max length is accepted
+        let function = "f".repeat(MAX_FUNCTION_NAME_LEN);
+        assert!(ctr
+            .is_human_call(ctr_id, function, "{}".to_string(), None, None, None, None)
+            .is_ok());
     }
 
     #[test]
-    fn sbt_update_token_references() {
-        let (ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
-        let m1_1 = mk_metadata(1, Some(START));
-        let tid1 = ctr.sbt_mint(vec![(bob(), vec![m1_1.clone()])])[0];
-        let tid2 = ctr.sbt_mint(vec![(alice(), vec![m1_1])])[0];
+    fn is_human_call_expired() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(alice(), vec![mk_metadata(1, None)])]);
 
-        let t1 = ctr.sbt(fractal_mainnet(), tid1).unwrap();
-        assert_eq!(t1.metadata.reference, Some("abc".to_owned()));
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        match ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            "{}".to_string(),
+            Some(7),
+            Some(START - 1),
+            None,
+            None,
+        ) {
+            Err(err) => assert_eq!(err, IsHumanCallErr::Expired),
+            Ok(_) => panic!("expecting Err(IsHumanCallErr::Expired)"),
+        };
+        assert!(test_utils::get_created_receipts().is_empty());
+        assert!(!ctr.has_ongoing_is_human_call(alice()));
 
-        // reset logs
+        // valid until the current block time: nonce and valid_until_ms are forwarded
         testing_env!(ctx);
-        let r = Some("ipfs://abc123".to_owned());
-        let r_hash = Some(Base64VecU8(vec![1, 1, 2]));
-        ctr.sbt_update_token_references(vec![(tid2, r.clone(), r_hash.clone())]);
-
-        let t2 = ctr.sbt(fractal_mainnet(), tid2).unwrap();
-        assert_eq!(t2.metadata.reference, r);
-        assert_eq!(t2.metadata.reference_hash, r_hash);
-
-        let t1_2 = ctr.sbt(fractal_mainnet(), tid1).unwrap();
-        assert_eq!(t1, t1_2);
-
-        let log = mk_log_str(
-            "token_reference",
-            &format!(
-                r#"{{"issuer":"{}","tokens":[{}]}}"#,
-                fractal_mainnet(),
-                tid2
+        ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            "{}".to_string(),
+            Some(7),
+            Some(START),
+            None,
+            None,
+        )
+        .unwrap();
+        match &test_utils::get_created_receipts()[0].actions[0] {
+            near_sdk::mock::VmAction::FunctionCall { args, .. } => assert_eq!(
+                String::from_utf8(args.clone()).unwrap(),
+                format!(
+                    r#"{{"caller":"{}","iah_proof":[["{}",[1]]],"payload":{{}},"nonce":7,"valid_until_ms":{}}}"#,
+                    alice(),
+                    fractal_mainnet(),
+                    START
+                )
             ),
-        );
-        assert_eq!(test_utils::get_logs(), log);
+            _ => panic!("expecting a function call"),
+        }
     }
 
     #[test]
-    fn is_human_call() {
-        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
-
-        let m1_1 = mk_metadata(1, Some(START));
-        ctr.sbt_mint(vec![(alice(), vec![m1_1])]);
-        assert_eq!(ctr.is_human(alice()), vec![(fractal_mainnet(), vec![1])]);
+    fn is_human_call_ongoing() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![
+            (alice(), vec![mk_metadata(1, None)]),
+            (bob(), vec![mk_metadata(1, None)]),
+        ]);
 
         ctx.predecessor_account_id = alice();
-        testing_env!(ctx);
+        testing_env!(ctx.clone());
+        ctr.is_human_call(
+            AccountId::new_unchecked("registry.i-am-human.near".to_string()),
+            "function_name".to_string(),
+            "{}".to_string(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(ctr.has_ongoing_is_human_call(alice()));
+        assert!(!ctr.has_ongoing_is_human_call(bob()));
 
+        // soul transfer is blocked while the call is in flight
+        match ctr.sbt_soul_transfer(carol(), None, None) {
+            Err(err) => assert_eq!(err, SoulTransferErr::OngoingIsHumanCall),
+            Ok(_) => panic!("expecting Err(SoulTransferErr::OngoingIsHumanCall)"),
+        };
+
+        // concurrent call: the marker is cleared by the last callback
+        testing_env!(ctx.clone());
         ctr.is_human_call(
             AccountId::new_unchecked("registry.i-am-human.near".to_string()),
             "function_name".to_string(),
             "{}".to_string(),
+            None,
+            None,
+            None,
+            None,
         )
         .unwrap();
+
+        ctx.predecessor_account_id = ctx.current_account_id.clone();
+        testing_env!(
+            ctx.clone(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"true".to_vec())],
+        );
+        ctr.on_is_human_call(alice(), alice(), U128(0));
+        assert!(ctr.has_ongoing_is_human_call(alice()));
+        ctr.on_is_human_call(alice(), alice(), U128(0));
+        assert!(!ctr.has_ongoing_is_human_call(alice()));
+
+        // bob's tokens can be revoked, alice's after the callback as well
+        ctx.predecessor_account_id = fractal_mainnet();
+        testing_env!(ctx);
+        ctr.sbt_revoke(vec![1, 2], false, None);
+        assert!(!ctr.is_human_bool(alice()));
     }
 
     #[test]
-    fn is_human_call_fail() {
-        let (_, mut ctr) = setup(&alice(), MINT_DEPOSIT);
+    fn is_human_call_ongoing_failed() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 2 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(
+            alice(),
+            vec![mk_metadata(1, None), mk_metadata(2, None)],
+        )]);
 
-        match ctr.is_human_call(
+        // two calls in flight: is_human_call and is_human_call_checked
+        ctx.predecessor_account_id = alice();
+        for checked in [false, true] {
+            testing_env!(ctx.clone());
+            let ctr_id = AccountId::new_unchecked("registry.i-am-human.near".to_string());
+            let args = (ctr_id, "function_name".to_string(), "{}".to_string());
+            if checked {
+                ctr.is_human_call_checked(args.0, args.1, args.2, None, None, None, None)
+            } else {
+                ctr.is_human_call(args.0, args.1, args.2, None, None, None, None)
+            }
+            .unwrap();
+        }
+        assert!(ctr.has_ongoing_is_human_call(alice()));
+
+        // both recipient calls fail: the callbacks must clear the marker
+        ctx.predecessor_account_id = ctx.current_account_id.clone();
+        testing_env!(
+            ctx.clone(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        ctr.on_is_human_call(alice(), alice(), U128(0));
+        assert!(ctr.has_ongoing_is_human_call(alice()));
+        let res = ctr.on_is_human_call_checked(alice(), alice(), U128(0));
+        assert!(!res.callback_succeeded);
+        assert!(!ctr.has_ongoing_is_human_call(alice()));
+
+        // the account is not locked: tokens can be revoked and the soul can be transferred
+        ctx.predecessor_account_id = fractal_mainnet();
+        testing_env!(ctx.clone());
+        ctr.sbt_revoke(vec![1], false, None);
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.sbt_soul_transfer(carol(), None, None).unwrap(),
+            (2, true)
+        );
+    }
+
+    #[test]
+    fn is_human_call_ongoing_revoke() {
+        let (mut ctx, mut ctr) = setup(&fractal_mainnet(), 3 * MINT_DEPOSIT);
+        ctr.sbt_mint(vec![(
+            alice(),
+            vec![
+                mk_metadata(1, None),
+                mk_metadata(2, None),
+                mk_metadata(3, None),
+            ],
+        )]);
+
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx.clone());
+        ctr.is_human_call(
             AccountId::new_unchecked("registry.i-am-human.near".to_string()),
             "function_name".to_string(),
             "{}".to_string(),
-        ) {
-            Err(err) => assert_eq!(err, IsHumanCallErr::NotHuman),
-            Ok(_) => panic!("expecting Err(IsHumanCallErr::NotHuman)"),
-        };
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        // the issuer moderation is not blocked by the is_human_call in flight
+        ctx.predecessor_account_id = fractal_mainnet();
+        testing_env!(ctx.clone());
+        ctr.sbt_revoke(vec![1], true, None);
+        ctr.sbt_revoke(vec![2], false, None);
+        assert_eq!(ctr.sbt_revoke_class(3, 10, None), (1, true));
+        assert!(ctr.sbt_revoke_by_owner(alice(), false, None));
+        assert!(ctr.has_ongoing_is_human_call(alice()));
+
+        // the soul transfer is blocked
+        ctx.predecessor_account_id = alice();
+        testing_env!(ctx);
+        assert_eq!(
+            ctr.sbt_soul_transfer(carol(), None, None),
+            Err(SoulTransferErr::OngoingIsHumanCall)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "attach at least 1000 gas")]
+    fn is_human_call_not_enough_gas_msg() {
+        IsHumanCallErr::NotEnoughGas(1000).panic();
     }
 
     #[test]
@@ -3199,21 +10928,21 @@ mod tests {
     fn is_human_flagged() {
         let (_, mut ctr) = setup(&fractal_mainnet(), MINT_DEPOSIT);
 
-        let m1_1 = mk_metadata(1, Some(START));
+        let m1_1 = mk_metadata(1, Some(START + 1));
         ctr.sbt_mint(vec![(dan(), vec![m1_1])]);
         let human_proof = vec![(fractal_mainnet(), vec![1])];
         ctr.admin_flag_accounts(AccountFlag::Verified, [dan()].to_vec(), "memo".to_owned());
-        assert_eq!(ctr.is_human(dan()), human_proof);
+        assert_eq!(ctr.is_human(dan(), None), human_proof);
 
         ctr.admin_flag_accounts(
             AccountFlag::Blacklisted,
             [dan()].to_vec(),
             "memo".to_owned(),
         );
-        assert_eq!(ctr.is_human(dan()), vec![]);
+        assert_eq!(ctr.is_human(dan(), None), vec![]);
 
         ctr.admin_unflag_accounts([dan()].to_vec(), "memo".to_owned());
-        assert_eq!(ctr.is_human(dan()), human_proof);
+        assert_eq!(ctr.is_human(dan(), None), human_proof);
     }
 
     #[test]
@@ -3229,7 +10958,7 @@ mod tests {
         // make soul transfer
         ctx.predecessor_account_id = alice();
         testing_env!(ctx.clone());
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
 
         assert_eq!(
             ctr.flagged.get(&alice()),
@@ -3250,7 +10979,7 @@ mod tests {
         // transferring from blacklisted to verified account should fail
         ctx.predecessor_account_id = alice2();
         testing_env!(ctx);
-        ctr.sbt_soul_transfer(bob(), None).unwrap();
+        ctr.sbt_soul_transfer(bob(), None, None).unwrap();
     }
 
     #[test]
@@ -3265,7 +10994,7 @@ mod tests {
 
         ctx.predecessor_account_id = alice();
         testing_env!(ctx);
-        ctr.sbt_soul_transfer(alice2(), None).unwrap();
+        ctr.sbt_soul_transfer(alice2(), None, None).unwrap();
     }
 
     #[test]
@@ -3297,20 +11026,20 @@ mod tests {
         ctr.is_human_call_lock(bob(), fun(), payload(), lock_duration, false)
             .unwrap();
         assert_eq!(
-            ctr.sbt_soul_transfer(alice2(), None),
+            ctr.sbt_soul_transfer(alice2(), None, None),
             Err(SoulTransferErr::TransferLocked)
         );
         // at the lock_duration we should still fail
         ctx.block_timestamp += lock_duration * MSECOND;
         testing_env!(ctx.clone());
         assert_eq!(
-            ctr.sbt_soul_transfer(alice2(), None),
+            ctr.sbt_soul_transfer(alice2(), None, None),
             Err(SoulTransferErr::TransferLocked)
         );
         // add one more millisecond, now it transfer should work.
         ctx.block_timestamp += MSECOND;
         testing_env!(ctx.clone());
-        assert_eq!(ctr.sbt_soul_transfer(alice2(), None), Ok((1, true)));
+        assert_eq!(ctr.sbt_soul_transfer(alice2(), None, None), Ok((1, true)));
 
         //
         // Test 2: is_human_call_lock should extend the lock
@@ -3327,14 +11056,14 @@ mod tests {
         ctx.block_timestamp += (2 * lock_duration + 1) * MSECOND;
         testing_env!(ctx.clone());
         assert_eq!(
-            ctr.sbt_soul_transfer(carol(), None),
+            ctr.sbt_soul_transfer(carol(), None, None),
             Err(SoulTransferErr::TransferLocked)
         );
 
         // move forward, now it should work
         ctx.block_timestamp += lock_duration * MSECOND;
         testing_env!(ctx.clone());
-        assert_eq!(ctr.sbt_soul_transfer(carol(), None), Ok((1, true)));
+        assert_eq!(ctr.sbt_soul_transfer(carol(), None, None), Ok((1, true)));
 
         //
         // Test 3: is_human_call_lock should extend the lock only if it's bigger than the previous one
@@ -3348,6 +11077,6 @@ mod tests {
             .unwrap();
         ctx.block_timestamp += (lock_duration + 1) * MSECOND;
         testing_env!(ctx.clone());
-        assert_eq!(ctr.sbt_soul_transfer(dan(), None), Ok((1, true)));
+        assert_eq!(ctr.sbt_soul_transfer(dan(), None, None), Ok((1, true)));
     }
 }
@@ -1,12 +1,13 @@
 use crate::*;
 
-// registry/v1.6.0
-#[derive(BorshDeserialize, PanicOnDefault)]
+// registry/v1.8.0
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct OldState {
     pub authority: AccountId,
     pub sbt_issuers: UnorderedMap<AccountId, IssuerId>,
     pub issuer_id_map: LookupMap<IssuerId, AccountId>, // reverse index
-    pub(crate) ongoing_soul_tx: LookupMap<AccountId, IssuerTokenId>,
+    pub(crate) ongoing_soul_tx: LookupMap<AccountId, SoulTxCursor>,
+    pub(crate) transfer_lock: LookupMap<AccountId, u64>,
     pub(crate) banlist: UnorderedSet<AccountId>,
     pub(crate) flagged: LookupMap<AccountId, AccountFlag>,
     pub(crate) authorized_flaggers: LazyOption<Vec<AccountId>>,
@@ -27,26 +28,110 @@ impl Contract {
     // #[allow(dead_code)]
     pub fn migrate() -> Self {
         let old_state: OldState = env::state_read().expect("failed");
-        // new field in the smart contract :
-        // + transfer_lock: LookupMap<AccountId, u64>,
+        require!(
+            old_state.next_issuer_id > 0,
+            "migrate: corrupted state, next_issuer_id must be > 0"
+        );
+        // new fields in the smart contract :
+        // + soul_tx_batch: u32,
+        // + allowlisted_classes: LookupMap<(IssuerId, ClassId), ()>,
+        // + class_allowlist: LookupMap<(IssuerId, ClassId, AccountId), ()>,
+        // + soul_tx_hooks: LazyOption<Vec<AccountId>>,
+        // + issuer_expire_cursor: LookupMap<IssuerId, TokenId>,
+        // + ban_scope: LookupMap<AccountId, BanScope>,
+        // + transferable_classes: LookupMap<(IssuerId, ClassId), ()>,
+        // + event_seq: u64,
+        // + supply_by_owner_total: LookupMap<AccountId, u64>,
+        // + total_souls: u64,
+        // + reference_hash_opt_out: LookupMap<IssuerId, ()>,
+        // + token_history: LookupMap<IssuerTokenId, Vec<TokenChange>>,
+        // + issuer_classes: LookupMap<IssuerId, Vec<ClassId>>,
+        // + ongoing_is_human_calls: LookupMap<AccountId, u32>,
+        // + expiry_policy: LookupMap<IssuerId, ExpiryPolicy>,
+        // + supply_rebuild: LookupMap<IssuerId, SupplyRebuild>,
+        // + pinned_classes: LookupMap<(IssuerId, ClassId), ()>,
+        // + soul_tx_counts: LookupMap<AccountId, SoulTransferCounts>,
+        // + paused_issuers: LookupMap<IssuerId, ()>,
+        // + revocation_consent_required: LookupMap<IssuerId, ()>,
+        // + revocation_consents: LookupMap<(AccountId, IssuerId), ()>,
+        // + compact_events: LookupMap<IssuerId, ()>,
+        // + soul_successors: LookupMap<AccountId, AccountId>,
+        // + soul_predecessors: LookupMap<AccountId, AccountId>,
+        // + renewers: LookupMap<(IssuerId, AccountId), ()>,
+        // + class_revoke_cursor: LookupMap<(IssuerId, ClassId), TokenId>,
+        // + migration_backfill: Option<MigrationBackfill>,
+        // ~ iah_sbts: Vec<(AccountId, Vec<ClassId>)>,
+
+        // Past events were not sequenced, so we start from a baseline bigger than any
+        // possible amount of past Mint events: the total number of minted tokens.
+        let event_seq = old_state
+            .sbt_issuers
+            .values()
+            .map(|id| old_state.next_token_ids.get(&id).unwrap_or(0))
+            .sum();
+
+        // sanity check summary: the supplies are moved verbatim, so they must match the
+        // pre-migration queries.
+        let total_supply: u64 = old_state
+            .sbt_issuers
+            .values()
+            .map(|id| old_state.supply_by_issuer.get(&id).unwrap_or(0))
+            .sum();
+        env::log_str(&format!(
+            r#"migrate_stats: {{"issuers":{},"total_supply":{}}}"#,
+            old_state.sbt_issuers.len(),
+            total_supply
+        ));
 
         Self {
             authority: old_state.authority.clone(),
             sbt_issuers: old_state.sbt_issuers,
             issuer_id_map: old_state.issuer_id_map,
-            transfer_lock: LookupMap::new(StorageKey::TransferLock),
+            transfer_lock: old_state.transfer_lock,
             banlist: old_state.banlist,
             supply_by_owner: old_state.supply_by_owner,
             supply_by_class: old_state.supply_by_class,
+            issuer_classes: LookupMap::new(StorageKey::IssuerClasses),
             supply_by_issuer: old_state.supply_by_issuer,
+            // computed by the resumable `admin_migration_backfill`, the balances scan would
+            // not fit into a single transaction.
+            supply_by_owner_total: LookupMap::new(StorageKey::SupplyByOwnerTotal),
+            total_souls: 0,
+            migration_backfill: Some(MigrationBackfill::default()),
             balances: old_state.balances,
             issuer_tokens: old_state.issuer_tokens,
             next_token_ids: old_state.next_token_ids,
             next_issuer_id: old_state.next_issuer_id,
             ongoing_soul_tx: old_state.ongoing_soul_tx,
-            iah_sbts: old_state.iah_sbts,
+            iah_sbts: vec![(
+                old_state.iah_sbts.0,
+                normalize_iah_classes(old_state.iah_sbts.1),
+            )],
             flagged: old_state.flagged,
             authorized_flaggers: old_state.authorized_flaggers,
+            soul_tx_batch: SOUL_TX_BATCH,
+            allowlisted_classes: LookupMap::new(StorageKey::AllowlistedClasses),
+            class_allowlist: LookupMap::new(StorageKey::ClassAllowlist),
+            soul_tx_hooks: LazyOption::new(StorageKey::SoulTxHooks, None),
+            issuer_expire_cursor: LookupMap::new(StorageKey::IssuerExpireCursor),
+            ban_scope: LookupMap::new(StorageKey::BanScope),
+            transferable_classes: LookupMap::new(StorageKey::TransferableClasses),
+            pinned_classes: LookupMap::new(StorageKey::PinnedClasses),
+            soul_tx_counts: LookupMap::new(StorageKey::SoulTxCounts),
+            paused_issuers: LookupMap::new(StorageKey::PausedIssuers),
+            revocation_consent_required: LookupMap::new(StorageKey::RevocationConsentRequired),
+            revocation_consents: LookupMap::new(StorageKey::RevocationConsents),
+            reference_hash_opt_out: LookupMap::new(StorageKey::ReferenceHashOptOut),
+            token_history: LookupMap::new(StorageKey::TokenHistory),
+            ongoing_is_human_calls: LookupMap::new(StorageKey::OngoingIsHumanCalls),
+            expiry_policy: LookupMap::new(StorageKey::ExpiryPolicy),
+            supply_rebuild: LookupMap::new(StorageKey::SupplyRebuild),
+            compact_events: LookupMap::new(StorageKey::CompactEvents),
+            soul_successors: LookupMap::new(StorageKey::SoulSuccessors),
+            soul_predecessors: LookupMap::new(StorageKey::SoulPredecessors),
+            renewers: LookupMap::new(StorageKey::Renewers),
+            class_revoke_cursor: LookupMap::new(StorageKey::ClassRevokeCursor),
+            event_seq,
         }
     }
 }
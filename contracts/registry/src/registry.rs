@@ -4,7 +4,7 @@ use near_sdk::{json_types::Base64VecU8, near_bindgen, AccountId};
 
 use crate::*;
 
-const MAX_LIMIT: u32 = 1000;
+pub(crate) const MAX_LIMIT: u32 = 1000;
 const MAX_REVOKE_PER_CALL: u32 = 25;
 
 #[near_bindgen]
@@ -13,37 +13,58 @@ impl SBTRegistry for Contract {
      * QUERIES
      **********/
 
-    /// returns the token, if it does not exist returns None
-    fn sbt(&self, issuer: AccountId, token: TokenId) -> Option<Token> {
-        let issuer_id = self.assert_issuer(&issuer);
+    /// returns the token, if it does not exist (or the issuer is not registered) returns None.
+    /// If `valid_only` is true (default false), returns None for expired tokens.
+    fn sbt(&self, issuer: AccountId, token: TokenId, valid_only: Option<bool>) -> Option<Token> {
+        let issuer_id = self.sbt_issuers.get(&issuer)?;
+        let now = env::block_timestamp_ms();
+        let valid_only = valid_only.unwrap_or(false);
         self.issuer_tokens
-            .get(&IssuerTokenId { issuer_id, token })
-            .map(|td| td.to_token(token))
+            .get(&IssuerTokenId::new(issuer_id, token))
+            .filter(|td| !valid_only || is_token_valid_at(td, now))
+            .map(|td| td.to_token(issuer_id, token))
     }
 
     /// Get the information about list of token IDs issued by the SBT `issuer`.
     /// If token ID is not found, `None` is set in the specific return index.
-    fn sbts(&self, issuer: AccountId, tokens: Vec<TokenId>) -> Vec<Option<Token>> {
-        let issuer_id = self.assert_issuer(&issuer);
+    /// If `valid_only` is true (default false), `None` is set for expired tokens.
+    /// If the issuer is not registered, all return values are `None`.
+    fn sbts(
+        &self,
+        issuer: AccountId,
+        tokens: Vec<TokenId>,
+        valid_only: Option<bool>,
+    ) -> Vec<Option<Token>> {
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return tokens.iter().map(|_| None).collect(),
+            Some(id) => id,
+        };
+        let now = env::block_timestamp_ms();
+        let valid_only = valid_only.unwrap_or(false);
         tokens
             .into_iter()
             .map(|token| {
                 self.issuer_tokens
-                    .get(&IssuerTokenId { issuer_id, token })
-                    .map(|td| td.to_token(token))
+                    .get(&IssuerTokenId::new(issuer_id, token))
+                    .filter(|td| !valid_only || is_token_valid_at(td, now))
+                    .map(|td| td.to_token(issuer_id, token))
             })
             .collect()
     }
 
     /// Query class ID for each token ID issued by the SBT `issuer`.
     /// If token ID is not found, `None` is set in the specific return index.
+    /// If the issuer is not registered, all return values are `None`.
     fn sbt_classes(&self, issuer: AccountId, tokens: Vec<TokenId>) -> Vec<Option<ClassId>> {
-        let issuer_id = self.assert_issuer(&issuer);
+        let issuer_id = match self.sbt_issuers.get(&issuer) {
+            None => return vec![None; tokens.len()],
+            Some(id) => id,
+        };
         tokens
             .into_iter()
             .map(|token| {
                 self.issuer_tokens
-                    .get(&IssuerTokenId { issuer_id, token })
+                    .get(&IssuerTokenId::new(issuer_id, token))
                     .map(|td| td.metadata.class_id())
             })
             .collect()
@@ -69,11 +90,14 @@ impl SBTRegistry for Contract {
 
     /// returns total supply of SBTs for a given owner.
     /// If class is specified, returns only owner supply of the given class -- must be 0 or 1.
+    /// If `count_expired` is false (default true), expired tokens are not counted: the owner
+    /// tokens are iterated to check their expire time.
     fn sbt_supply_by_owner(
         &self,
         account: AccountId,
         issuer: AccountId,
         class: Option<ClassId>,
+        count_expired: Option<bool>,
     ) -> u64 {
         // we don't check banlist because we should still enable banned accounts to query their tokens
         if self.ongoing_soul_tx.contains_key(&account) {
@@ -85,11 +109,36 @@ impl SBTRegistry for Contract {
             None => return 0,
             Some(id) => id,
         };
-        if let Some(class_id) = class {
-            return match self
+        if !count_expired.unwrap_or(true) {
+            let now = env::block_timestamp_ms();
+            let is_valid = |token: TokenId| {
+                self.issuer_tokens
+                    .get(&IssuerTokenId::new(issuer_id, token))
+                    .map_or(false, |t| is_token_valid_at(&t, now))
+            };
+            if let Some(class_id) = class {
+                return match self
+                    .balances
+                    .get(&balance_key(account, issuer_id, ClassKey(class_id)))
+                {
+                    Some(token) if is_valid(token) => 1,
+                    _ => 0,
+                };
+            }
+            return self
                 .balances
-                .contains_key(&balance_key(account, issuer_id, class_id))
-            {
+                .iter_from(balance_key(account.clone(), issuer_id, ClassKey(0)))
+                .take_while(|(key, _)| key.owner == account && key.issuer_id == issuer_id)
+                .filter(|(_, token)| is_valid(*token))
+                .count() as u64;
+        }
+
+        if let Some(class_id) = class {
+            return match self.balances.contains_key(&balance_key(
+                account,
+                issuer_id,
+                ClassKey(class_id),
+            )) {
                 true => 1,
                 _ => 0,
             };
@@ -124,17 +173,23 @@ impl SBTRegistry for Contract {
         if max_id < from_token {
             return vec![];
         }
-        max_id = std::cmp::min(max_id + 1, from_token + limit as u64);
+        max_id = std::cmp::min(
+            max_id.saturating_add(1),
+            from_token.saturating_add(limit as u64),
+        );
 
         let now = env::block_timestamp_ms();
         let non_expired = !with_expired.unwrap_or(false);
         let mut resp = Vec::new();
         for token in from_token..max_id {
-            if let Some(t) = self.issuer_tokens.get(&IssuerTokenId { issuer_id, token }) {
-                if non_expired && t.metadata.expires_at().unwrap_or(now) < now {
+            if let Some(t) = self
+                .issuer_tokens
+                .get(&IssuerTokenId::new(issuer_id, token))
+            {
+                if non_expired && !is_token_valid_at(&t, now) {
                     continue;
                 }
-                resp.push(t.to_token(token))
+                resp.push(t.to_token(issuer_id, token))
             }
         }
         resp
@@ -148,6 +203,11 @@ impl SBTRegistry for Contract {
     /// Returns list of pairs: `(Issuer address, list of token IDs)`.
     /// If `with_expired` is set to `true` then all the tokens are returned including expired ones
     /// otherwise only non-expired tokens are returned.
+    /// NOTE: `limit` is a total of the returned tokens across all issuers: when `issuer` is
+    /// not set, the last returned issuer list can be cut off mid-issuer. The next page can't
+    /// be requested with `issuer = None` (`from_class` requires `issuer`), use
+    /// `sbt_tokens_by_owner_page` to list all owner tokens with a cursor.
+    /// Returns an empty list for accounts banned with `BanScope::Full`.
     fn sbt_tokens_by_owner(
         &self,
         account: AccountId,
@@ -156,67 +216,11 @@ impl SBTRegistry for Contract {
         limit: Option<u32>,
         with_expired: Option<bool>,
     ) -> Vec<(AccountId, Vec<OwnedToken>)> {
-        if from_class.is_some() {
-            require!(
-                issuer.is_some(),
-                "issuer must be defined if from_class is defined"
-            );
-        }
-        // we don't check banlist because we should still enable banned accounts to query their tokens
-        if self.ongoing_soul_tx.contains_key(&account) {
+        // banned accounts can still query their tokens, unless banned with the `Full` scope.
+        if self.ban_scope.get(&account) == Some(BanScope::Full) {
             return vec![];
         }
-
-        let issuer_id = match &issuer {
-            None => 0,
-            Some(addr) => self.assert_issuer(addr),
-        };
-        let from_class = from_class.unwrap_or(0);
-        // iter_from starts from exclusive "left end". We need to iteretare from one before.
-        let first_key = balance_key(account.clone(), issuer_id, from_class.saturating_sub(1));
-        let now = env::block_timestamp_ms();
-        let with_expired = with_expired.unwrap_or(false);
-
-        let mut limit = limit.unwrap_or(MAX_LIMIT);
-        require!(limit > 0, "limit must be bigger than 0");
-
-        let mut resp = Vec::new();
-        let mut tokens = Vec::new();
-        let mut prev_issuer = issuer_id;
-
-        for (key, token_id) in self.balances.iter_from(first_key) {
-            if key.owner != account {
-                break;
-            }
-            if prev_issuer != key.issuer_id {
-                if issuer_id != 0 {
-                    break;
-                }
-                if !tokens.is_empty() {
-                    let issuer = self.issuer_by_id(prev_issuer);
-                    resp.push((issuer, tokens));
-                    tokens = Vec::new();
-                }
-                prev_issuer = key.issuer_id;
-            }
-            let t: TokenData = self.get_token(key.issuer_id, token_id);
-            if !with_expired && t.metadata.expires_at().unwrap_or(now) < now {
-                continue;
-            }
-            tokens.push(OwnedToken {
-                token: token_id,
-                metadata: t.metadata.v1(),
-            });
-            limit -= 1;
-            if limit == 0 {
-                break;
-            }
-        }
-        if prev_issuer != 0 && !tokens.is_empty() {
-            let issuer = self.issuer_by_id(prev_issuer);
-            resp.push((issuer, tokens));
-        }
-        resp
+        self._sbt_tokens_by_owner(account, issuer, from_class, limit, with_expired)
     }
 
     /// checks if an `account` was banned by the registry.
@@ -233,7 +237,8 @@ impl SBTRegistry for Contract {
     /// Each TokenMetadata must specify non zero `class`.
     /// Must be called by an SBT contract.
     /// Must emit `Mint` event.
-    /// Must provide enough NEAR to cover registry storage cost.
+    /// Must provide enough NEAR to cover registry storage cost, including the storage reserved
+    /// for the later token updates (`TOKEN_UPDATES_STORAGE` bytes per token).
     /// Panics with "out of gas" if token_spec vector is too long and not enough gas was
     /// provided.
     #[payable]
@@ -246,6 +251,9 @@ impl SBTRegistry for Contract {
     /// Must be called by a valid SBT issuer.
     /// Must emit `Recover` event once all the tokens have been recovered.
     /// Requires attaching enough tokens to cover the storage growth.
+    /// Panics if the new owner already has a token of the same class: after a recovery each
+    /// `(owner, issuer, class)` maps to at most one token and the owner supply equals the
+    /// number of owner tokens. The conflicting token must be firstly burned.
     /// Returns the amount of tokens recovered and a boolean: `true` if the whole
     /// process has finished, `false` when the process has not finished and should be
     /// continued by a subsequent call. User must keep calling the `sbt_recover` until `true`
@@ -258,9 +266,13 @@ impl SBTRegistry for Contract {
     /// sbt_renew will update the expire time of provided tokens.
     /// `expires_at` is a unix timestamp miliseconds.
     /// Must be called by an SBT contract.
-    /// Must emit `Renew` event.
+    /// Must emit `Renew` event. Tokens which already expire at `expires_at` are skipped and
+    /// not included in the event. If no token was changed (eg: empty `tokens` list), the event
+    /// is not emitted.
     /// Use `cost::renew_gas` to calculate expected amount of gas that should be assigned for this
     /// function
+    /// The storage growth (a token gets an expire time, token history) is prepaid at mint, see
+    /// `TOKEN_UPDATES_STORAGE`.
     fn sbt_renew(&mut self, tokens: Vec<TokenId>, expires_at: u64) {
         let issuer = env::predecessor_account_id();
         self._sbt_renew(issuer, tokens, expires_at);
@@ -269,11 +281,15 @@ impl SBTRegistry for Contract {
     /// Revokes SBT. If `burn==true`, the tokens are burned (removed). Otherwise, the token
     /// expire_at is set to now, making the token expired.
     /// Must be called by an SBT contract.
-    /// Must emit `Revoke` event.
+    /// Must emit `Revoke` event, with the `reason` (default `Unspecified`).
     /// Must also emit `Burn` event if the SBT tokens are burned (removed).
-    fn sbt_revoke(&mut self, tokens: Vec<TokenId>, burn: bool) {
+    /// An empty `tokens` list is a no-op: no event is emitted.
+    fn sbt_revoke(&mut self, tokens: Vec<TokenId>, burn: bool, reason: Option<RevokeReason>) {
         let issuer = env::predecessor_account_id();
         let issuer_id = self.assert_issuer(&issuer);
+        if tokens.is_empty() {
+            return;
+        }
         if burn {
             let mut revoked_per_class: HashMap<u64, u64> = HashMap::new();
             let mut revoked_per_owner: HashMap<AccountId, u64> = HashMap::new();
@@ -286,7 +302,7 @@ impl SBTRegistry for Contract {
                 let balance_key = &BalanceKey {
                     issuer_id,
                     owner: owner.clone(),
-                    class_id,
+                    class_id: ClassKey(class_id),
                 };
                 self.balances.remove(balance_key);
 
@@ -303,11 +319,14 @@ impl SBTRegistry for Contract {
 
                 // remove from issuer_tokens
                 self.issuer_tokens
-                    .remove(&IssuerTokenId { issuer_id, token });
+                    .remove(&IssuerTokenId::new(issuer_id, token));
+                self.token_history
+                    .remove(&IssuerTokenId::new(issuer_id, token));
             }
 
             // update supply by owner
             for (owner_id, tokens_revoked) in revoked_per_owner {
+                self.sub_owner_total(&owner_id, tokens_revoked);
                 let old_supply = self
                     .supply_by_owner
                     .get(&(owner_id.clone(), issuer_id))
@@ -319,8 +338,7 @@ impl SBTRegistry for Contract {
             // update supply by class
             for (class_id, tokens_revoked) in revoked_per_class {
                 let old_supply = self.supply_by_class.get(&(issuer_id, class_id)).unwrap();
-                self.supply_by_class
-                    .insert(&(issuer_id, class_id), &(old_supply - tokens_revoked));
+                self.set_class_supply(issuer_id, class_id, old_supply - tokens_revoked);
             }
 
             // update supply by issuer
@@ -332,6 +350,10 @@ impl SBTRegistry for Contract {
             SbtTokensEvent {
                 issuer: issuer.clone(),
                 tokens: tokens.clone(),
+                reason: None,
+                expires_at: None,
+                renewals: None,
+                event_seq: Some(self.next_event_seq()),
             }
             .emit_burn();
         } else {
@@ -340,14 +362,23 @@ impl SBTRegistry for Contract {
             for token in tokens.clone() {
                 // update expire date for all tokens to current_timestamp
                 let mut t = self.get_token(issuer_id, token);
-                let mut m = t.metadata.v1();
+                let mut m = t.metadata.latest();
                 m.expires_at = Some(current_timestamp_ms);
                 t.metadata = m.into();
                 self.issuer_tokens
-                    .insert(&IssuerTokenId { issuer_id, token }, &t);
+                    .insert(&IssuerTokenId::new(issuer_id, token), &t);
+                self.record_token_change(issuer_id, token, TokenField::Expiry);
             }
         }
-        SbtTokensEvent { issuer, tokens }.emit_revoke();
+        SbtTokensEvent {
+            issuer,
+            tokens,
+            reason: Some(reason.unwrap_or_default()),
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(self.next_event_seq()),
+        }
+        .emit_revoke();
     }
 
     /// Revokes owners SBTs issued by the caller either by burning or updating their expire
@@ -357,9 +388,17 @@ impl SBTRegistry for Contract {
     /// Retuns true if all the tokens were revoked, false otherwise.
     /// If false is returned issuer must call the method until true is returned
     /// Must be called by an SBT contract.
-    /// Must emit `Revoke` event.
+    /// Must emit `Revoke` event, with the `reason` (default `Unspecified`).
     /// Must also emit `Burn` event if the SBT tokens are burned (removed).
-    fn sbt_revoke_by_owner(&mut self, owner: AccountId, burn: bool) -> bool {
+    /// When the issuer requires the owner consent (`set_revocation_consent_required`), the
+    /// revoke without burning panics if the owner didn't call `allow_revocation`. The consent
+    /// is cleared once all the tokens are revoked.
+    fn sbt_revoke_by_owner(
+        &mut self,
+        owner: AccountId,
+        burn: bool,
+        reason: Option<RevokeReason>,
+    ) -> bool {
         let issuer = env::predecessor_account_id();
         let issuer_id = self.assert_issuer(&issuer);
 
@@ -376,7 +415,7 @@ impl SBTRegistry for Contract {
                 let balance_key = BalanceKey {
                     issuer_id,
                     owner: owner.clone(),
-                    class_id: *class_id,
+                    class_id: ClassKey(*class_id),
                 };
 
                 self.balances.remove(&balance_key);
@@ -387,10 +426,10 @@ impl SBTRegistry for Contract {
                     .and_modify(|key_value| *key_value += 1)
                     .or_insert(1);
 
-                self.issuer_tokens.remove(&IssuerTokenId {
-                    issuer_id,
-                    token: *token_id,
-                });
+                self.issuer_tokens
+                    .remove(&IssuerTokenId::new(issuer_id, *token_id));
+                self.token_history
+                    .remove(&IssuerTokenId::new(issuer_id, *token_id));
             }
 
             // Batch updates for supply values
@@ -401,6 +440,7 @@ impl SBTRegistry for Contract {
             let supply_owner = self.supply_by_owner.get(owner_key).unwrap_or(0);
             let new_supply_owner = supply_owner - supply_update;
             self.supply_by_owner.insert(owner_key, &new_supply_owner);
+            self.sub_owner_total(&owner, supply_update);
 
             // Update supply_by_issuer
             let supply_issuer = self.supply_by_issuer.get(&issuer_id).unwrap_or(0);
@@ -409,10 +449,11 @@ impl SBTRegistry for Contract {
 
             // Update supply_by_class
             for (class_id, tokens_revoked) in burned_per_class {
-                let class_key = &(issuer_id, class_id);
-                let supply_class = self.supply_by_class.get(class_key).unwrap_or(0);
-                let new_supply_class = supply_class - tokens_revoked;
-                self.supply_by_class.insert(class_key, &new_supply_class);
+                let supply_class = self
+                    .supply_by_class
+                    .get(&(issuer_id, class_id))
+                    .unwrap_or(0);
+                self.set_class_supply(issuer_id, class_id, supply_class - tokens_revoked);
             }
 
             let token_ids_burned: Vec<TokenId> = tokens_by_owner
@@ -423,30 +464,50 @@ impl SBTRegistry for Contract {
             SbtTokensEvent {
                 issuer: issuer.clone(),
                 tokens: token_ids_burned.clone(),
+                reason: None,
+                expires_at: None,
+                renewals: None,
+                event_seq: Some(self.next_event_seq()),
             }
             .emit_burn();
 
             SbtTokensEvent {
                 issuer: issuer.clone(),
                 tokens: token_ids_burned,
+                reason: Some(reason.unwrap_or_default()),
+                expires_at: None,
+                renewals: None,
+                event_seq: Some(self.next_event_seq()),
             }
             .emit_revoke();
 
             // Check if all tokens were burned
-            return self.sbt_supply_by_owner(owner.clone(), issuer, None) == 0;
+            return self.sbt_supply_by_owner(owner.clone(), issuer, None, None) == 0;
         }
 
-        let (_, non_expired_tokens) = self
-            .sbt_tokens_by_owner(
-                owner.clone(),
-                Some(issuer.clone()),
-                None,
-                Some(MAX_REVOKE_PER_CALL),
-                Some(false),
-            )
-            .pop()
-            .unwrap();
-
+        let consent_key = &(owner.clone(), issuer_id);
+        require!(
+            !self.revocation_consent_required.contains_key(&issuer_id)
+                || self.revocation_consents.contains_key(consent_key),
+            "owner consent required to revoke"
+        );
+
+        let mut groups = self._sbt_tokens_by_owner(
+            owner.clone(),
+            Some(issuer.clone()),
+            None,
+            Some(MAX_REVOKE_PER_CALL),
+            Some(false),
+        );
+        // the query is filtered by issuer, so at most one group is returned.
+        require!(
+            groups.len() <= 1,
+            "internal error: tokens of multiple issuers returned"
+        );
+        let non_expired_tokens = match groups.pop() {
+            None => return true,
+            Some((_, tokens)) => tokens,
+        };
         if non_expired_tokens.is_empty() {
             return true;
         }
@@ -464,21 +525,24 @@ impl SBTRegistry for Contract {
                 owner: owner.clone(),
                 metadata: t.metadata.into(),
             };
-            self.issuer_tokens.insert(
-                &IssuerTokenId {
-                    issuer_id,
-                    token: t.token,
-                },
-                &token_data,
-            );
+            self.issuer_tokens
+                .insert(&IssuerTokenId::new(issuer_id, t.token), &token_data);
+            self.record_token_change(issuer_id, t.token, TokenField::Expiry);
         }
 
         SbtTokensEvent {
             issuer,
             tokens: token_ids,
+            reason: Some(reason.unwrap_or_default()),
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(self.next_event_seq()),
         }
         .emit_revoke();
 
+        if is_finished {
+            self.revocation_consents.remove(consent_key);
+        }
         // Check if all tokens were revoked
         is_finished
     }
@@ -486,31 +550,34 @@ impl SBTRegistry for Contract {
     /// Allows issuer to update token metadata reference and reference_hash.
     /// * `updates` is a list of triples: (token ID, reference, reference base64-encoded sha256 hash).
     /// Must emit `token_reference` event.
-    /// Panics if any of the token IDs don't exist.
+    /// Panics if any of the token IDs don't exist, or if the reference (or hash) is too long.
+    /// Enough NEAR must be attached to cover the storage growth (eg: a longer reference).
+    #[payable]
     fn sbt_update_token_references(
         &mut self,
         updates: Vec<(TokenId, Option<String>, Option<Base64VecU8>)>,
     ) {
+        let storage_start = env::storage_usage();
         let issuer = env::predecessor_account_id();
         let issuer_id = self.assert_issuer(&issuer);
         let mut token_ids = vec![0; updates.len()];
-        let mut key = IssuerTokenId {
-            issuer_id,
-            token: 0,
-        };
+        let mut key = IssuerTokenId::new(issuer_id, 0);
         let mut idx = 0;
         #[allow(clippy::explicit_counter_loop)]
         for (tid, reference, reference_hash) in updates {
-            key.token = tid;
+            key.token = TokenKey(tid);
             let mut t = match self.issuer_tokens.get(&key) {
                 None => env::panic_str(&format!("token {} not found", tid)),
                 Some(t) => t,
             };
-            let mut m = t.metadata.v1();
+            Self::assert_reference_size(&reference, &reference_hash);
+            self.assert_reference_hash(issuer_id, &reference, &reference_hash);
+            let mut m = t.metadata.latest();
             m.reference = reference;
             m.reference_hash = reference_hash;
             t.metadata = m.into();
             self.issuer_tokens.insert(&key, &t);
+            self.record_token_change(issuer_id, tid, TokenField::Reference);
             token_ids[idx] = tid;
             idx += 1;
         }
@@ -518,7 +585,12 @@ impl SBTRegistry for Contract {
         SbtTokensEvent {
             issuer,
             tokens: token_ids,
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(self.next_event_seq()),
         }
         .emit_token_reference();
+        Self::assert_storage_deposit(storage_start);
     }
 }
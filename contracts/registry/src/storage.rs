@@ -1,12 +1,18 @@
+use std::fmt::Write;
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::serde_json::value::RawValue;
 use near_sdk::{AccountId, BorshStorageKey};
-use sbt::{ClassId, SBTs, TokenId};
+use sbt::{ClassId, ClassSet, SBTs, Token, TokenId};
 
 /// Issuer contract ID based on the SBT Contract address -> u16 map.
 pub type IssuerId = u32;
 
+/// Ownership record returned by `dump_balances`: (owner, issuer ID, class, token).
+pub type BalanceRecord = (AccountId, IssuerId, ClassId, TokenId);
+
 /// Helper structure for keys of the persistent collections.
 #[derive(BorshSerialize, BorshStorageKey)]
 pub enum StorageKey {
@@ -23,6 +29,29 @@ pub enum StorageKey {
     Flagged,
     AdminsFlagged,
     TransferLock,
+    AllowlistedClasses,
+    ClassAllowlist,
+    SoulTxHooks,
+    IssuerExpireCursor,
+    BanScope,
+    TransferableClasses,
+    SupplyByOwnerTotal,
+    ReferenceHashOptOut,
+    TokenHistory,
+    IssuerClasses,
+    OngoingIsHumanCalls,
+    ExpiryPolicy,
+    SupplyRebuild,
+    PinnedClasses,
+    SoulTxCounts,
+    PausedIssuers,
+    RevocationConsentRequired,
+    RevocationConsents,
+    CompactEvents,
+    SoulSuccessors,
+    SoulPredecessors,
+    Renewers,
+    ClassRevokeCursor,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, BorshStorageKey, Serialize, Deserialize, PartialEq)]
@@ -38,22 +67,366 @@ pub enum AccountFlag {
     GovBan,
 }
 
+/// Defines the effect of a ban.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum BanScope {
+    /// Default ban scope (used by soul transfers): the account can't receive new tokens
+    /// and can't do a soul transfer, but its tokens are still returned by the queries.
+    TransferOnly,
+    /// Same as `TransferOnly`, and additionally the account tokens are hidden from the
+    /// `sbt_tokens_by_owner` query.
+    Full,
+}
+
+/// Defines how `sbt_soul_transfer` handles tokens of pinned (not movable) classes.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum LockedPolicy {
+    /// pinned tokens are left in the source account (default).
+    #[default]
+    Skip,
+    /// pinned tokens are burned.
+    Burn,
+}
+
+/// Outcome of the tokens processed by an ongoing `sbt_soul_transfer`.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub(crate) struct SoulTransferCounts {
+    pub transferred: u32,
+    pub skipped: u32,
+    pub burned: u32,
+}
+
+/// Issuer default expire time of the newly minted tokens, applied when a token
+/// `expires_at` is not set.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum ExpiryPolicy {
+    /// tokens don't expire (default).
+    NeverExpire,
+    /// tokens expire after the given duration (in milliseconds) since the mint.
+    FixedTtl(u64),
+    /// tokens expire at the given unix time (in milliseconds).
+    FixedDate(u64),
+}
+
+impl ExpiryPolicy {
+    /// returns the expire time of a token minted at `now` (in milliseconds).
+    pub fn expires_at(&self, now: u64) -> Option<u64> {
+        match self {
+            ExpiryPolicy::NeverExpire => None,
+            ExpiryPolicy::FixedTtl(ttl) => Some(now.saturating_add(*ttl)),
+            ExpiryPolicy::FixedDate(date) => Some(*date),
+        }
+    }
+}
+
+/// Result of minting a single token by `sbt_mint_report`.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum MintOutcome {
+    Minted(TokenId),
+    Skipped(MintSkipReason),
+}
+
+/// Reason why `sbt_mint_report` didn't mint a token.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum MintSkipReason {
+    /// The recipient is banned.
+    Banned,
+    /// The recipient already has a token of the same class (or it's duplicated in the batch).
+    DuplicateClass,
+    /// The class is allowlisted and the recipient is not in the allowlist.
+    NotAllowed,
+    /// The issuer is not registered (only reported by `can_mint`).
+    UnknownIssuer,
+    /// The class is zero (only reported by `can_mint`).
+    InvalidClass,
+    /// The issuer is paused by the authority (only reported by `can_mint`, the mint methods
+    /// panic), see `admin_pause_issuer`.
+    IssuerPaused,
+}
+
+/// Result of the `can_mint` pre-flight check.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct CanMintResult {
+    /// true if the token can be minted.
+    pub allowed: bool,
+    /// all rules blocking the mint.
+    pub reasons: Vec<MintSkipReason>,
+}
+
+/// Result of the `is_human_call_checked` call.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct IsHumanCallResult {
+    /// always true: the call fails early if the caller is not a human.
+    pub is_human: bool,
+    /// true if the recipient call succeeded.
+    pub callback_succeeded: bool,
+    /// the deposit refunded to `refund_to` when the recipient call failed.
+    pub refunded: U128,
+}
+
+/// Page of tokens returned by the `sbt_tokens_page` query.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TokenPage {
+    pub tokens: Vec<Token>,
+    /// `from_token` for the next page, `None` when the end is reached.
+    pub next_from_token: Option<u64>,
+}
+
+/// Humanity metrics returned by the `humanity_stats` query.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct HumanityStats {
+    /// estimate based on the class supply counters (includes expired tokens).
+    pub total_iah_holders: u64,
+    /// holders with a valid humanity proof found in the scanned page, `None` when no scan
+    /// was requested.
+    pub valid_iah_holders: Option<u64>,
+    /// `from_token` for the next page of the scan, `None` when the end is reached.
+    pub next_from_token: Option<u64>,
+}
+
+/// Live registry configuration returned by the `config` query.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct RegistryConfig {
+    pub authority: AccountId,
+    pub authorized_flaggers: Vec<AccountId>,
+    /// IAH issuers with required classes, see `iah_class_set`.
+    pub iah_sbts: ClassSet,
+    /// max amount of tokens transferred in a single `sbt_soul_transfer` call.
+    pub soul_tx_batch: u32,
+    /// max `limit` accepted by the paginated queries.
+    pub max_query_limit: u32,
+}
+
+/// Token field updated after the mint, recorded in the token history.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum TokenField {
+    /// `reference` or `reference_hash` updated.
+    Reference,
+    /// class changed by `sbt_reissue` (the history is moved to the new token).
+    Class,
+    /// expire time updated by a renew or a revoke.
+    Expiry,
+    /// token recovered to a new owner.
+    Recovery,
+}
+
+/// Token history entry returned by the `sbt_history` query.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct TokenChange {
+    pub field: TokenField,
+    /// time of the update, in milliseconds.
+    pub at_ms: u64,
+}
+
+/// State of the `migrate` backfill, see `admin_migration_backfill`.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub(crate) struct MigrationBackfill {
+    /// last processed `balances` key, `None` before the first call.
+    pub cursor: Option<BalanceKey>,
+}
+
+/// State of an ongoing `admin_rebuild_supplies`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub(crate) struct SupplyRebuild {
+    /// next token to process.
+    pub cursor: TokenId,
+    /// amount of tokens found so far.
+    pub total: u64,
+    /// amount of tokens found so far per class, sorted by class.
+    pub classes: Vec<(ClassId, u64)>,
+}
+
+/// `ClassId` used in the registry storage keys. The newtype has the same Borsh and JSON
+/// representation as `ClassId`, but it can't be mixed up with a `TokenKey` (eg: passing a
+/// token ID to `balance_key`). The NEP-393 interface keeps the plain `ClassId`.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Clone,
+    Copy,
+    Default,
+)]
+#[serde(crate = "near_sdk::serde", transparent)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub(crate) struct ClassKey(pub ClassId);
+
+/// `TokenId` used in the registry storage keys, see `ClassKey`.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Clone,
+    Copy,
+    Default,
+)]
+#[serde(crate = "near_sdk::serde", transparent)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub(crate) struct TokenKey(pub TokenId);
+
+impl From<ClassKey> for ClassId {
+    fn from(c: ClassKey) -> Self {
+        c.0
+    }
+}
+
+impl From<TokenKey> for TokenId {
+    fn from(t: TokenKey) -> Self {
+        t.0
+    }
+}
+
 /// Composition of issuer address and token id used for indexing
 #[derive(BorshSerialize, BorshDeserialize)]
 pub(crate) struct IssuerTokenId {
     pub issuer_id: IssuerId,
-    pub token: TokenId,
+    pub token: TokenKey,
+}
+
+impl IssuerTokenId {
+    #[inline]
+    pub(crate) fn new(issuer_id: IssuerId, token: TokenId) -> Self {
+        Self {
+            issuer_id,
+            token: TokenKey(token),
+        }
+    }
+}
+
+/// Position of an ongoing soul transfer or recovery: the next `balances` entry to process
+/// is the first one of the owner with `(issuer_id, class_id)` or greater.
+/// It has the same Borsh representation as the `IssuerTokenId` previously used to store
+/// the class ID in its `token` field, so the `ongoing_soul_tx` records don't need a migration.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub(crate) struct SoulTxCursor {
+    pub issuer_id: IssuerId,
+    pub class_id: ClassKey,
+}
+
+impl SoulTxCursor {
+    pub(crate) fn balance_key(&self, owner: AccountId) -> BalanceKey {
+        balance_key(owner, self.issuer_id, self.class_id)
+    }
+}
+
+impl From<&BalanceKey> for SoulTxCursor {
+    fn from(key: &BalanceKey) -> Self {
+        Self {
+            issuer_id: key.issuer_id,
+            class_id: key.class_id,
+        }
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Eq, Ord, PartialEq, PartialOrd, Clone)]
 pub(crate) struct BalanceKey {
     pub owner: AccountId,
     pub issuer_id: IssuerId,
-    pub class_id: ClassId,
+    pub class_id: ClassKey,
+}
+
+/// Encodes the `key` as a stable cursor for the `balances` based paging (eg `dump_balances`).
+/// The cursor is the hex encoding of: owner account bytes, `0x00` separator, issuer ID (u32
+/// big endian) and class ID (u64 big endian). Account IDs can't contain `0x00` and hex
+/// preserves the byte order, so cursors are sorted in the same order as the `balances` keys.
+pub(crate) fn encode_balance_cursor(key: &BalanceKey) -> String {
+    let mut bytes = key.owner.as_bytes().to_vec();
+    bytes.push(0);
+    bytes.extend_from_slice(&key.issuer_id.to_be_bytes());
+    bytes.extend_from_slice(&key.class_id.0.to_be_bytes());
+    hex_encode(&bytes)
+}
+
+/// Decodes a cursor created by `encode_balance_cursor`. Returns `None` if the cursor is
+/// malformed.
+pub(crate) fn decode_balance_cursor(cursor: &str) -> Option<BalanceKey> {
+    let bytes = hex_decode(cursor)?;
+    let sep = bytes.iter().position(|b| *b == 0)?;
+    let (owner, ids) = (&bytes[..sep], &bytes[sep + 1..]);
+    if ids.len() != 12 {
+        return None;
+    }
+    let owner = std::str::from_utf8(owner).ok()?.parse().ok()?;
+    let issuer_id = IssuerId::from_be_bytes(ids[..4].try_into().ok()?);
+    let class_id = ClassId::from_be_bytes(ids[4..].try_into().ok()?);
+    Some(balance_key(owner, issuer_id, ClassKey(class_id)))
+}
+
+/// Encodes the `(issuer_id, token)` position of the `all_tokens` paging as a cursor: hex of
+/// the issuer ID (u32 big endian) and token ID (u64 big endian).
+pub(crate) fn encode_token_cursor(issuer_id: IssuerId, token: TokenId) -> String {
+    let mut bytes = issuer_id.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&token.to_be_bytes());
+    hex_encode(&bytes)
+}
+
+/// Decodes a cursor created by `encode_token_cursor`. Returns `None` if the cursor is
+/// malformed.
+pub(crate) fn decode_token_cursor(cursor: &str) -> Option<(IssuerId, TokenId)> {
+    let bytes = hex_decode(cursor)?;
+    if bytes.len() != 12 {
+        return None;
+    }
+    let issuer_id = IssuerId::from_be_bytes(bytes[..4].try_into().ok()?);
+    let token = TokenId::from_be_bytes(bytes[4..].try_into().ok()?);
+    Some((issuer_id, token))
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
 }
 
 #[inline]
-pub(crate) fn balance_key(owner: AccountId, issuer_id: IssuerId, class_id: ClassId) -> BalanceKey {
+pub(crate) fn balance_key(owner: AccountId, issuer_id: IssuerId, class_id: ClassKey) -> BalanceKey {
     BalanceKey {
         owner,
         issuer_id,
@@ -69,6 +442,11 @@ pub struct IsHumanCallbackArgs<'a> {
     pub caller: AccountId,
     pub iah_proof: SBTs,
     pub payload: &'a RawValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    /// time in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_until_ms: Option<u64>,
 }
 
 /// `is_human_call_lock` wrapper for passing the payload args to the callback.
@@ -83,6 +461,15 @@ pub struct IsHumanLockCallbackArgs<'a> {
     pub payload: &'a RawValue,
 }
 
+/// Arguments of the `on_soul_transfer` call made to the registered soul transfer hooks.
+#[derive(Serialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug,))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SoulTransferHookArgs<'a> {
+    pub from: &'a AccountId,
+    pub to: &'a AccountId,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,6 +487,8 @@ mod tests {
             caller: alice,
             iah_proof: vec![(issuer, vec![1, 2, 5])],
             payload: &RawValue::from_string(payload_str).unwrap(),
+            nonce: None,
+            valid_until_ms: None,
         };
 
         let args_str = serde_json::to_string(&args).unwrap();
@@ -162,6 +162,8 @@ async fn init(
         expires_at: None,
         reference: None,
         reference_hash: None,
+        score: None,
+        attestation: None,
     }];
 
     let iah_token_spec = vec![
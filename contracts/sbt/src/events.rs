@@ -1,4 +1,4 @@
-use near_sdk::serde::Serialize;
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{env, AccountId};
 
 use crate::SPEC_VERSION;
@@ -54,10 +54,18 @@ pub enum Nep393Event<'a> {
 
 impl Nep393Event<'_> {
     // todo: maybe move to NearEvent
+    /// Emits the event with the NEP-393 `standard` and `version` (`STANDARD_NAME` and
+    /// `SPEC_VERSION`).
     pub fn emit(self) {
+        self.emit_as(STANDARD_NAME, SPEC_VERSION)
+    }
+
+    /// Emits the event with a custom `standard` and `version`, for registries extending
+    /// the NEP-393 event schema.
+    pub fn emit_as(self, standard: &'static str, version: &'static str) {
         NearEvent {
-            standard: STANDARD_NAME,
-            version: SPEC_VERSION,
+            standard,
+            version,
             event: self,
         }
         .emit()
@@ -75,6 +83,10 @@ impl Nep393Event<'_> {
 pub struct SbtMint<'a> {
     pub issuer: &'a AccountId,
     pub tokens: Vec<(&'a AccountId, &'a Vec<TokenId>)>,
+    /// Global, monotonically increasing sequence number of the event, set by registries
+    /// supporting event ordering. Indexers can use it to detect missing events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_seq: Option<u64>,
 }
 impl SbtMint<'_> {
     pub fn emit(self) {
@@ -97,6 +109,10 @@ pub struct SbtRecover<'a> {
     pub issuer: &'a AccountId,
     pub old_owner: &'a AccountId,
     pub new_owner: &'a AccountId,
+    /// Global, monotonically increasing sequence number of the event, set by registries
+    /// supporting event ordering. Indexers can use it to detect missing events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_seq: Option<u64>,
 }
 
 impl SbtRecover<'_> {
@@ -105,17 +121,58 @@ impl SbtRecover<'_> {
     }
 }
 
+/// Reason of a token revocation, reported in the `Revoke` event.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(rename_all = "snake_case")]
+#[serde(crate = "near_sdk::serde")]
+pub enum RevokeReason {
+    #[default]
+    Unspecified,
+    /// Token expired due to the issuer or registry policy.
+    ExpiredPolicy,
+    Fraud,
+    /// Revoked on the owner request.
+    UserRequest,
+}
+
 /// A common structure for the following events:
 /// renew, revoke, burn.
 /// Arguments:
 /// * `issuer`: SBT smart contract initiating the SBT state change.
 /// * `tokens`: list of tokens concering the transaction emitting the event.
+/// * `reason`: revocation reason, only set in the `Revoke` event.
+/// * `expires_at`: new expire time of the tokens, only set in the `Revoke` event when the
+///   tokens are revoked with a grace period.
+/// * `renewals`: previous and new expire time of every renewed token, set in the `Renew` event
+///   by registries supporting it.
 #[derive(Serialize)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
 #[serde(crate = "near_sdk::serde")]
 pub struct SbtTokensEvent {
     pub issuer: AccountId, // SBT Contract account address
     pub tokens: Vec<TokenId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<RevokeReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renewals: Option<Vec<TokenRenewal>>,
+    /// Global, monotonically increasing sequence number of the event, set by registries
+    /// supporting event ordering. Indexers can use it to detect missing events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_seq: Option<u64>,
+}
+
+/// Expire time change of a renewed token, in unix time milliseconds.
+#[derive(Serialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenRenewal {
+    pub token: TokenId,
+    /// expire time before the renew, `None` if the token was not expiring.
+    pub old_expires_at: Option<u64>,
+    pub expires_at: u64,
 }
 
 impl SbtTokensEvent {
@@ -140,12 +197,20 @@ impl SbtTokensEvent {
 /// to `to`, and the `from` account is banned (can't receive any new SBT).
 /// Must be emitted by an SBT registry.
 /// Registry MUST also emit `Ban` whenever the soul transfer happens.
+/// Registries which don't move all tokens (e.g. pinned tokens), can report the amount of
+/// `transferred`, `skipped` and `burned` tokens.
 #[derive(Serialize)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq, Clone))]
 #[serde(crate = "near_sdk::serde")]
 pub struct SoulTransfer<'a> {
     pub from: &'a AccountId,
     pub to: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transferred: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burned: Option<u32>,
 }
 
 impl SoulTransfer<'_> {
@@ -155,7 +220,14 @@ impl SoulTransfer<'_> {
 }
 
 pub fn emit_soul_transfer(from: &AccountId, to: &AccountId) {
-    SoulTransfer { from, to }.emit();
+    SoulTransfer {
+        from,
+        to,
+        transferred: None,
+        skipped: None,
+        burned: None,
+    }
+    .emit();
 }
 
 /// Helper struct to be used in `NearEvent.event` to construct NEAR Event compatible payload
@@ -298,6 +370,7 @@ mod tests {
         let event = Nep393Event::Mint(SbtMint {
             issuer: &issuer,
             tokens: vec![(&bob, &bob1_tokens), (&bob, &bob2_tokens)],
+            event_seq: None,
         });
         event.emit();
         assert_eq!(expected, test_utils::get_logs()[0]);
@@ -313,6 +386,7 @@ mod tests {
             issuer: &issuer,
             old_owner: &bob,
             new_owner: &charlie,
+            event_seq: None,
         });
         event.emit();
         assert_eq!(expected, test_utils::get_logs()[0]);
@@ -324,6 +398,10 @@ mod tests {
         let e = SbtTokensEvent {
             issuer: sbt_issuer(),
             tokens: vec![21, 10, 888],
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: None,
         };
         let event = Nep393Event::Renew(e.clone());
         event.emit();
@@ -332,12 +410,46 @@ mod tests {
         assert_eq!(expected, test_utils::get_logs()[1]);
     }
 
+    #[test]
+    fn log_format_custom_standard() {
+        let expected = r#"EVENT_JSON:{"standard":"i_am_human","version":"1.1.0","event":"burn","data":{"issuer":"sbt.near","tokens":[3]}}"#;
+        Nep393Event::Burn(SbtTokensEvent {
+            issuer: sbt_issuer(),
+            tokens: vec![3],
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: None,
+        })
+        .emit_as("i_am_human", "1.1.0");
+        assert_eq!(expected, test_utils::get_logs()[0]);
+    }
+
+    #[test]
+    fn log_format_event_seq() {
+        let expected = r#"EVENT_JSON:{"standard":"nep393","version":"1.0.0","event":"renew","data":{"issuer":"sbt.near","tokens":[21],"event_seq":7}}"#;
+        SbtTokensEvent {
+            issuer: sbt_issuer(),
+            tokens: vec![21],
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(7),
+        }
+        .emit_renew();
+        assert_eq!(expected, test_utils::get_logs()[0]);
+    }
+
     #[test]
     fn log_format_revoke() {
         let expected = r#"EVENT_JSON:{"standard":"nep393","version":"1.0.0","event":"revoke","data":{"issuer":"sbt.near","tokens":[19853,1]}}"#;
         let e = SbtTokensEvent {
             issuer: sbt_issuer(),
             tokens: vec![19853, 1],
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: None,
         };
         let event = Nep393Event::Revoke(e.clone());
         event.emit();
@@ -346,12 +458,31 @@ mod tests {
         assert_eq!(expected, test_utils::get_logs()[1]);
     }
 
+    #[test]
+    fn log_format_revoke_reason() {
+        let expected = r#"EVENT_JSON:{"standard":"nep393","version":"1.0.0","event":"revoke","data":{"issuer":"sbt.near","tokens":[2],"reason":"expired_policy","event_seq":3}}"#;
+        SbtTokensEvent {
+            issuer: sbt_issuer(),
+            tokens: vec![2],
+            reason: Some(RevokeReason::ExpiredPolicy),
+            expires_at: None,
+            renewals: None,
+            event_seq: Some(3),
+        }
+        .emit_revoke();
+        assert_eq!(expected, test_utils::get_logs()[0]);
+    }
+
     #[test]
     fn log_format_burn() {
         let expected = r#"EVENT_JSON:{"standard":"nep393","version":"1.0.0","event":"burn","data":{"issuer":"sbt.near","tokens":[19853,12]}}"#;
         let e = SbtTokensEvent {
             issuer: sbt_issuer(),
             tokens: vec![19853, 12],
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: None,
         };
         let event = Nep393Event::Burn(e.clone());
         event.emit();
@@ -366,6 +497,10 @@ mod tests {
         let e = SbtTokensEvent {
             issuer: sbt_issuer(),
             tokens: vec![19853, 12],
+            reason: None,
+            expires_at: None,
+            renewals: None,
+            event_seq: None,
         };
         let event = Nep393Event::TokenReference(e.clone());
         event.emit();
@@ -392,6 +527,9 @@ mod tests {
         let e = SoulTransfer {
             from: &alice,
             to: &bob,
+            transferred: None,
+            skipped: None,
+            burned: None,
         };
         let event = Nep393Event::SoulTransfer(e.clone());
         event.emit();
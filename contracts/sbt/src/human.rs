@@ -0,0 +1,140 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde_json;
+use near_sdk::{env, require, AccountId, Gas, Promise, PromiseResult};
+
+use crate::{ext_registry, SBTs};
+
+/// Gas reserved for the registry `is_human` query made by `HumanGate::is_human`.
+pub const IS_HUMAN_QUERY_GAS: Gas = Gas(5 * Gas::ONE_TERA.0);
+
+/// Helper for contracts which need to check the IAH humanity of their callers.
+/// It supports two patterns:
+/// * `is_human_call`: the user calls `registry.is_human_call`, which forwards
+///   `caller` and `iah_proof` to the contract method. The method must start with
+///   `gate.require_human_call(&iah_proof)`.
+/// * `require_human` callback: the contract calls `gate.is_human(account)`, chains its own
+///   callback and reads the result with `is_human_result()` in that callback.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct HumanGate {
+    /// IAH registry.
+    pub registry: AccountId,
+}
+
+impl HumanGate {
+    pub fn new(registry: AccountId) -> Self {
+        Self { registry }
+    }
+
+    /// Panics if the method is not called by the registry through `is_human_call`, or if the
+    /// `iah_proof` is empty (the caller is not a human).
+    pub fn require_human_call(&self, iah_proof: &SBTs) {
+        require!(
+            env::predecessor_account_id() == self.registry,
+            "must be called by registry"
+        );
+        require!(!iah_proof.is_empty(), "not a human");
+        for (_, tokens) in iah_proof {
+            require!(
+                !tokens.is_empty(),
+                "bad response, expected non empty token list"
+            );
+        }
+    }
+
+    /// Queries the registry if the `account` is human. The result must be handled in a
+    /// callback chained with `then`, using `is_human_result`.
+    pub fn is_human(&self, account: AccountId) -> Promise {
+        ext_registry::ext(self.registry.clone())
+            .with_static_gas(IS_HUMAN_QUERY_GAS)
+            .is_human(account)
+    }
+}
+
+/// Reads the result of the `HumanGate::is_human` query in a callback. Returns false if the
+/// query failed or the account is not a human.
+pub fn is_human_result() -> bool {
+    require!(
+        env::promise_results_count() == 1,
+        "expected 1 promise result"
+    );
+    match env::promise_result(0) {
+        PromiseResult::Successful(value) => serde_json::from_slice::<SBTs>(&value)
+            .map(|proof| !proof.is_empty())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+
+    fn registry() -> AccountId {
+        AccountId::new_unchecked("registry.near".to_string())
+    }
+
+    fn alice() -> AccountId {
+        AccountId::new_unchecked("alice.near".to_string())
+    }
+
+    fn setup(predecessor: &AccountId, results: Vec<PromiseResult>) {
+        let ctx = VMContextBuilder::new()
+            .predecessor_account_id(predecessor.clone())
+            .build();
+        testing_env!(
+            ctx,
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results
+        );
+    }
+
+    #[test]
+    fn require_human_call() {
+        setup(&registry(), vec![]);
+        let gate = HumanGate::new(registry());
+        gate.require_human_call(&vec![(registry(), vec![1])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called by registry")]
+    fn require_human_call_not_registry() {
+        setup(&alice(), vec![]);
+        let gate = HumanGate::new(registry());
+        gate.require_human_call(&vec![(registry(), vec![1])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a human")]
+    fn require_human_call_empty_proof() {
+        setup(&registry(), vec![]);
+        let gate = HumanGate::new(registry());
+        gate.require_human_call(&vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad response, expected non empty token list")]
+    fn require_human_call_empty_tokens() {
+        setup(&registry(), vec![]);
+        let gate = HumanGate::new(registry());
+        gate.require_human_call(&vec![(registry(), vec![])]);
+    }
+
+    #[test]
+    fn is_human_result_values() {
+        let proof = serde_json::to_vec(&vec![(registry(), vec![1_u64])]).unwrap();
+        setup(&registry(), vec![PromiseResult::Successful(proof)]);
+        assert!(is_human_result());
+
+        setup(&registry(), vec![PromiseResult::Successful(b"[]".to_vec())]);
+        assert!(!is_human_result());
+
+        setup(&registry(), vec![PromiseResult::Failed]);
+        assert!(!is_human_result());
+    }
+}
@@ -1,10 +1,12 @@
 mod events;
+mod human;
 mod metadata;
 
 use near_sdk::json_types::Base64VecU8;
 use near_sdk::{ext_contract, AccountId};
 
 pub use crate::events::*;
+pub use crate::human::*;
 pub use crate::metadata::*;
 
 /// This spec can be treated like a version of the standard.
@@ -59,11 +61,18 @@ pub trait SBTRegistry {
      **********/
 
     /// Get the information about specific token ID issued by the `issuer` SBT contract.
-    fn sbt(&self, issuer: AccountId, token: TokenId) -> Option<Token>;
+    /// If `valid_only` is true (default false), returns `None` for expired tokens.
+    fn sbt(&self, issuer: AccountId, token: TokenId, valid_only: Option<bool>) -> Option<Token>;
 
     /// Get the information about list of token IDs issued by the SBT `issuer`.
     /// If token ID is not found, `None` is set in the specific return index.
-    fn sbts(&self, issuer: AccountId, token: Vec<TokenId>) -> Vec<Option<Token>>;
+    /// If `valid_only` is true (default false), `None` is set for expired tokens.
+    fn sbts(
+        &self,
+        issuer: AccountId,
+        token: Vec<TokenId>,
+        valid_only: Option<bool>,
+    ) -> Vec<Option<Token>>;
 
     /// Query class ID for each token ID issued by the SBT `issuer`.
     /// If token ID is not found, `None` is set in the specific return index.
@@ -81,11 +90,13 @@ pub trait SBTRegistry {
     /// Returns total supply of SBTs for a given owner. See `sbt_supply` for information about
     /// revoked tokens.
     /// If class is specified, returns only owner supply of the given class -- must be 0 or 1.
+    /// If `count_expired` is false (default true), expired tokens are not counted.
     fn sbt_supply_by_owner(
         &self,
         account: AccountId,
         issuer: AccountId,
         class: Option<ClassId>,
+        count_expired: Option<bool>,
     ) -> u64;
 
     /// Query sbt tokens issued by a given contract.
@@ -152,20 +163,27 @@ pub trait SBTRegistry {
 
     /// Revokes SBT by burning the token or updating its expire time.
     /// Must be called by an SBT contract.
-    /// Must emit `Revoke` event.
+    /// Must emit `Revoke` event with the revocation `reason` (default `Unspecified`).
     /// Must also emit `Burn` event if the SBT tokens are burned (removed).
-    fn sbt_revoke(&mut self, tokens: Vec<TokenId>, burn: bool);
+    fn sbt_revoke(&mut self, tokens: Vec<TokenId>, burn: bool, reason: Option<RevokeReason>);
 
     /// Revokes all owners SBTs issued by the caller either by burning or updating their expire time.
     /// Must be called by an SBT contract.
-    /// Must emit `Revoke` event.
+    /// Must emit `Revoke` event with the revocation `reason` (default `Unspecified`).
     /// Must also emit `Burn` event if the SBT tokens are burned (removed).
-    fn sbt_revoke_by_owner(&mut self, owner: AccountId, burn: bool) -> bool;
+    fn sbt_revoke_by_owner(
+        &mut self,
+        owner: AccountId,
+        burn: bool,
+        reason: Option<RevokeReason>,
+    ) -> bool;
 
     /// Allows issuer to update token metadata reference and reference_hash.
-    /// * `updates` is a list of triples: (token ID, reference, reference hash).
+    /// `updates` is a list of triples: (token ID, reference, reference hash).
     /// Must emit `token_reference` event.
     /// Panics if any of the token IDs don't exist.
+    /// Must attach enough NEAR to cover the storage growth (eg: a longer reference).
+    // #[payable]
     fn sbt_update_token_references(
         &mut self,
         updates: Vec<(TokenId, Option<String>, Option<Base64VecU8>)>,
@@ -183,7 +201,12 @@ trait ExtRegistry {
     // queries
 
     fn is_human(&self, account: AccountId) -> Vec<(AccountId, Vec<TokenId>)>;
-    fn sbt(&self, issuer: AccountId, token: TokenId) -> Option<Token>;
-    fn sbts(&self, issuer: AccountId, tokens: Vec<TokenId>) -> Vec<Option<Token>>;
+    fn sbt(&self, issuer: AccountId, token: TokenId, valid_only: Option<bool>) -> Option<Token>;
+    fn sbts(
+        &self,
+        issuer: AccountId,
+        tokens: Vec<TokenId>,
+        valid_only: Option<bool>,
+    ) -> Vec<Option<Token>>;
     fn sbt_classes(&self, issuer: AccountId, tokens: Vec<TokenId>) -> Vec<Option<ClassId>>;
 }
@@ -62,7 +62,21 @@ pub struct ClassMetadata {
 #[cfg_attr(test, derive(Debug, Clone))]
 #[serde(crate = "near_sdk::serde")]
 pub enum VerTokenMetadata {
-    V1(TokenMetadata),
+    V1(TokenMetadataV1),
+    /// V1 extended with `score` and `attestation`.
+    V2(TokenMetadata),
+}
+
+/// Token metadata schema V1: `TokenMetadata` without `score` and `attestation`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[cfg_attr(test, derive(Debug, Clone))]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadataV1 {
+    pub class: ClassId,
+    pub issued_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
 }
 
 /// TokenMetadata defines attributes for each SBT token.
@@ -84,31 +98,84 @@ pub struct TokenMetadata {
     pub reference: Option<String>,
     /// Base64-encoded sha256 hash of JSON from reference field. Required if `reference` is included.
     pub reference_hash: Option<Base64VecU8>,
+    /// Credential level or score (eg: reputation), for graded credentials. Tokens minted
+    /// before the field was introduced have no score.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<u32>,
+    /// Issuer signature or attestation hash of the credential, which third parties can verify
+    /// off chain against the issuer public key. Not verified by the registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Base64VecU8>,
 }
 
 impl VerTokenMetadata {
-    pub fn v1(self) -> TokenMetadata {
+    /// Returns the metadata in the latest schema.
+    pub fn latest(self) -> TokenMetadata {
         match self {
-            VerTokenMetadata::V1(x) => x,
+            VerTokenMetadata::V1(x) => TokenMetadata {
+                class: x.class,
+                issued_at: x.issued_at,
+                expires_at: x.expires_at,
+                reference: x.reference,
+                reference_hash: x.reference_hash,
+                score: None,
+                attestation: None,
+            },
+            VerTokenMetadata::V2(x) => x,
         }
     }
 
     pub fn class_id(&self) -> ClassId {
         match self {
             VerTokenMetadata::V1(x) => x.class,
+            VerTokenMetadata::V2(x) => x.class,
         }
     }
 
     pub fn expires_at(&self) -> Option<u64> {
         match self {
             VerTokenMetadata::V1(x) => x.expires_at,
+            VerTokenMetadata::V2(x) => x.expires_at,
+        }
+    }
+
+    pub fn issued_at(&self) -> Option<u64> {
+        match self {
+            VerTokenMetadata::V1(x) => x.issued_at,
+            VerTokenMetadata::V2(x) => x.issued_at,
+        }
+    }
+
+    pub fn score(&self) -> Option<u32> {
+        match self {
+            VerTokenMetadata::V1(_) => None,
+            VerTokenMetadata::V2(x) => x.score,
+        }
+    }
+
+    /// Returns the metadata schema version.
+    pub fn version(&self) -> u8 {
+        match self {
+            VerTokenMetadata::V1(_) => 1,
+            VerTokenMetadata::V2(_) => 2,
         }
     }
 }
 
+/// Metadata without `score` and `attestation` is stored using the V1 schema (compact),
+/// otherwise V2 is used.
 impl From<TokenMetadata> for VerTokenMetadata {
     fn from(m: TokenMetadata) -> Self {
-        VerTokenMetadata::V1(m)
+        if m.score.is_some() || m.attestation.is_some() {
+            return VerTokenMetadata::V2(m);
+        }
+        VerTokenMetadata::V1(TokenMetadataV1 {
+            class: m.class,
+            issued_at: m.issued_at,
+            expires_at: m.expires_at,
+            reference: m.reference,
+            reference_hash: m.reference_hash,
+        })
     }
 }
 
@@ -121,16 +188,29 @@ pub struct TokenData {
 }
 
 impl TokenData {
-    pub fn to_token(self, token: TokenId) -> Token {
-        let metadata: TokenMetadata = self.metadata.v1();
+    pub fn to_token(self, issuer_id: u32, token: TokenId) -> Token {
+        let metadata_version = self.metadata.version();
+        let metadata: TokenMetadata = self.metadata.latest();
         Token {
             token,
+            issuer_id,
             metadata,
+            metadata_version,
             owner: self.owner,
         }
     }
 }
 
+/// Returns true if the token is not expired at time `now` (in milliseconds).
+/// A token without expiry date is always valid. A token is expired when
+/// `expires_at <= now`, so a token expiring exactly at the current block time is expired.
+pub fn is_token_valid_at(t: &TokenData, now: u64) -> bool {
+    match t.metadata.expires_at() {
+        None => true,
+        Some(expires_at) => expires_at > now,
+    }
+}
+
 /// token data for sbt_tokens_by_owner response
 #[derive(Serialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -141,6 +221,8 @@ impl TokenData {
 pub struct OwnedToken {
     pub token: TokenId,
     pub metadata: TokenMetadata,
+    /// version of the metadata schema, clients can use it to branch on the metadata layout.
+    pub metadata_version: u8,
 }
 
 /// Full information about the token
@@ -152,8 +234,13 @@ pub struct OwnedToken {
 )]
 pub struct Token {
     pub token: TokenId,
+    /// numeric ID of the token issuer, assigned by the registry. Allows grouping tokens by
+    /// issuer without resolving the issuer account.
+    pub issuer_id: u32,
     pub owner: AccountId,
     pub metadata: TokenMetadata,
+    /// version of the metadata schema, clients can use it to branch on the metadata layout.
+    pub metadata_version: u8,
 }
 
 impl ContractMetadata {
@@ -189,3 +276,84 @@ impl TokenMetadata {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `TokenMetadata` layout before the `score` and `attestation` fields were added.
+    #[derive(BorshSerialize)]
+    struct LegacyTokenMetadata {
+        class: ClassId,
+        issued_at: Option<u64>,
+        expires_at: Option<u64>,
+        reference: Option<String>,
+        reference_hash: Option<Base64VecU8>,
+    }
+
+    #[derive(BorshSerialize)]
+    enum LegacyVerTokenMetadata {
+        V1(LegacyTokenMetadata),
+    }
+
+    #[derive(BorshSerialize)]
+    struct LegacyTokenData {
+        owner: AccountId,
+        metadata: LegacyVerTokenMetadata,
+    }
+
+    fn alice() -> AccountId {
+        AccountId::new_unchecked("alice.near".to_string())
+    }
+
+    #[test]
+    fn legacy_token_data_deserialize() {
+        let legacy = LegacyTokenData {
+            owner: alice(),
+            metadata: LegacyVerTokenMetadata::V1(LegacyTokenMetadata {
+                class: 2,
+                issued_at: Some(10),
+                expires_at: Some(20),
+                reference: Some("ref".to_string()),
+                reference_hash: Some(vec![1, 2, 3].into()),
+            }),
+        };
+        let bytes = legacy.try_to_vec().unwrap();
+        let t = TokenData::try_from_slice(&bytes).unwrap();
+        assert_eq!(t.owner, alice());
+        assert_eq!(t.metadata.version(), 1);
+        assert_eq!(t.metadata.class_id(), 2);
+        assert_eq!(t.metadata.expires_at(), Some(20));
+        let m = t.metadata.latest();
+        assert_eq!(
+            m,
+            TokenMetadata {
+                class: 2,
+                issued_at: Some(10),
+                expires_at: Some(20),
+                reference: Some("ref".to_string()),
+                reference_hash: Some(vec![1, 2, 3].into()),
+                score: None,
+                attestation: None,
+            }
+        );
+
+        // metadata without score and attestation is stored in the legacy layout
+        let t = TokenData {
+            owner: alice(),
+            metadata: m.clone().into(),
+        };
+        assert_eq!(t.try_to_vec().unwrap(), bytes);
+
+        // otherwise V2 is used
+        let mut m = m;
+        m.score = Some(5);
+        let t = TokenData {
+            owner: alice(),
+            metadata: m.clone().into(),
+        };
+        let t = TokenData::try_from_slice(&t.try_to_vec().unwrap()).unwrap();
+        assert_eq!(t.metadata.version(), 2);
+        assert_eq!(t.metadata.latest(), m);
+    }
+}
@@ -122,6 +122,8 @@ pub async fn registry_mint_iah_tokens(
         expires_at: None,
         reference: None,
         reference_hash: None,
+        score: None,
+        attestation: None,
     }];
     let mut iah_token_spec = Vec::new();
 